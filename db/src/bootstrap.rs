@@ -35,6 +35,10 @@ pub const TX0: i64 = 0x10000000;
 /// This is the start of the :db.part/user partition.
 pub const USER0: i64 = 0x10000;
 
+/// The span of entids reserved for each partition installed with `:db.install/partition`.  Each
+/// starts at a multiple of this, above every partition that exists when it is installed.
+pub const PARTITION_SIZE: i64 = 1 << 32;
+
 // Corresponds to the version of the :db.schema/core vocabulary.
 pub const CORE_SCHEMA_VERSION: u32 = 1;
 
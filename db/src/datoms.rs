@@ -0,0 +1,195 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Read datoms directly from the store's indices, and from the transaction log, for browsing and
+//! debugging.  Unlike queries, these functions expose the raw `[e a v tx]` structure.
+
+use std::fmt;
+use std::str::FromStr;
+
+use rusqlite;
+use rusqlite::types::{ToSql, ToSqlOutput};
+
+use db::TypedSQLValue;
+use errors::Result;
+use mentat_core::{
+    Entid,
+    HasSchema,
+    Schema,
+    TypedValue,
+};
+
+/// One of the four orders in which the store indexes its datoms.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DatomIndex {
+    /// Every datom, by entity.
+    EAVT,
+    /// Every datom, by attribute.
+    AEVT,
+    /// Datoms of attributes that are `:db/index true` or unique, by attribute and value.
+    AVET,
+    /// Datoms of `:db.type/ref` attributes, by value.  This is the reverse index.
+    VAET,
+}
+
+impl DatomIndex {
+    /// The columns that order this index, most significant first.  `v` is ordered by type, and
+    /// then by value within each type.
+    fn order_by(&self) -> &'static str {
+        match *self {
+            DatomIndex::EAVT => "e, a, value_type_tag, v, tx",
+            DatomIndex::AEVT => "a, e, value_type_tag, v, tx",
+            DatomIndex::AVET => "a, value_type_tag, v, e, tx",
+            DatomIndex::VAET => "v, a, e, tx",
+        }
+    }
+
+    /// A condition selecting the datoms this index covers.
+    fn covers(&self) -> &'static str {
+        match *self {
+            DatomIndex::EAVT | DatomIndex::AEVT => "1",
+            DatomIndex::AVET => "index_avet IS NOT 0",
+            DatomIndex::VAET => "index_vaet IS NOT 0",
+        }
+    }
+}
+
+impl fmt::Display for DatomIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            DatomIndex::EAVT => "eavt",
+            DatomIndex::AEVT => "aevt",
+            DatomIndex::AVET => "avet",
+            DatomIndex::VAET => "vaet",
+        })
+    }
+}
+
+impl FromStr for DatomIndex {
+    type Err = String;
+
+    /// Parses an index name, like `eavt` or `:aevt`, ignoring case.
+    fn from_str(s: &str) -> ::std::result::Result<DatomIndex, String> {
+        match s.trim_left_matches(':').to_lowercase().as_str() {
+            "eavt" => Ok(DatomIndex::EAVT),
+            "aevt" => Ok(DatomIndex::AEVT),
+            "avet" => Ok(DatomIndex::AVET),
+            "vaet" => Ok(DatomIndex::VAET),
+            _ => Err(format!("unknown index {}; expected one of eavt, aevt, avet or vaet", s)),
+        }
+    }
+}
+
+/// Constrains the datoms returned by `datoms`.  `None` matches anything.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DatomPattern {
+    pub e: Option<Entid>,
+    pub a: Option<Entid>,
+    pub v: Option<TypedValue>,
+    pub tx: Option<Entid>,
+}
+
+/// A datom, as stored.  Datoms read from the `datoms` table are always `added`; datoms read from
+/// the transaction log may be retractions.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub struct Datom {
+    pub e: Entid,
+    pub a: Entid,
+    pub v: TypedValue,
+    pub tx: Entid,
+    pub added: bool,
+}
+
+/// Returns the datoms in `index` that match `pattern`, in index order.  Fulltext values are
+/// returned as their text.
+pub fn datoms(conn: &rusqlite::Connection, index: DatomIndex, pattern: &DatomPattern) -> Result<Vec<Datom>> {
+    let mut conditions: Vec<&'static str> = vec![index.covers()];
+    let mut params: Vec<ToSqlOutput> = vec![];
+    if let Some(e) = pattern.e {
+        conditions.push("e = ?");
+        params.push(ToSqlOutput::from(e));
+    }
+    if let Some(a) = pattern.a {
+        conditions.push("a = ?");
+        params.push(ToSqlOutput::from(a));
+    }
+    if let Some(ref v) = pattern.v {
+        let (value, value_type_tag) = v.to_sql_value_pair();
+        conditions.push("value_type_tag = ? AND v = ?");
+        params.push(ToSqlOutput::from(value_type_tag));
+        params.push(value);
+    }
+    if let Some(tx) = pattern.tx {
+        conditions.push("tx = ?");
+        params.push(ToSqlOutput::from(tx));
+    }
+
+    let sql = format!("SELECT e, a, v, value_type_tag, tx FROM all_datoms WHERE {} ORDER BY {}",
+                      conditions.join(" AND "),
+                      index.order_by());
+    let mut stmt: rusqlite::Statement = conn.prepare(&sql)?;
+    let params: Vec<&ToSql> = params.iter().map(|p| p as &ToSql).collect();
+    let m: Result<Vec<Datom>> = stmt.query_and_then(&params, |row| {
+        let v: rusqlite::types::Value = row.get_checked(2)?;
+        let value_type_tag: i32 = row.get_checked(3)?;
+        Ok(Datom {
+            e: row.get_checked(0)?,
+            a: row.get_checked(1)?,
+            v: TypedValue::from_sql_value_pair(v, value_type_tag)?,
+            tx: row.get_checked(4)?,
+            added: true,
+        })
+    })?.collect();
+    m
+}
+
+/// Returns the datoms asserted and retracted by the transaction `tx`, ordered by `(e, a, v)`, with
+/// retractions before assertions.  Fulltext values are returned as their text.
+pub fn transaction_datoms(conn: &rusqlite::Connection, schema: &Schema, tx: Entid) -> Result<Vec<Datom>> {
+    let mut stmt: rusqlite::Statement = conn.prepare("SELECT e, a, v, value_type_tag, added FROM transactions WHERE tx = ? ORDER BY e, a, value_type_tag, v, added")?;
+    let mut fulltext: rusqlite::Statement = conn.prepare("SELECT text FROM fulltext_values WHERE rowid = ?")?;
+    let m: Result<Vec<Datom>> = stmt.query_and_then(&[&tx], |row| {
+        let a: Entid = row.get_checked(1)?;
+        let mut v: rusqlite::types::Value = row.get_checked(2)?;
+        let value_type_tag: i32 = row.get_checked(3)?;
+
+        // The log refers to fulltext values by their rowid, just like `datoms` does.
+        if schema.attribute_for_entid(a).map_or(false, |attribute| attribute.fulltext) {
+            if let rusqlite::types::Value::Integer(rowid) = v {
+                v = fulltext.query_row(&[&rowid], |row| row.get_checked(0))??;
+            }
+        }
+
+        Ok(Datom {
+            e: row.get_checked(0)?,
+            a: a,
+            v: TypedValue::from_sql_value_pair(v, value_type_tag)?,
+            tx: tx,
+            added: row.get_checked(4)?,
+        })
+    })?.collect();
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_from_str() {
+        assert_eq!("eavt".parse::<DatomIndex>(), Ok(DatomIndex::EAVT));
+        assert_eq!(":AVET".parse::<DatomIndex>(), Ok(DatomIndex::AVET));
+        assert!("evat".parse::<DatomIndex>().is_err());
+
+        for index in &[DatomIndex::EAVT, DatomIndex::AEVT, DatomIndex::AVET, DatomIndex::VAET] {
+            assert_eq!(index.to_string().parse::<DatomIndex>(), Ok(*index));
+        }
+    }
+}
@@ -126,24 +126,6 @@ lazy_static! {
         r#"CREATE UNIQUE INDEX idx_datoms_eavt ON datoms (e, a, value_type_tag, v)"#,
         r#"CREATE UNIQUE INDEX idx_datoms_aevt ON datoms (a, e, value_type_tag, v)"#,
 
-        // Opt-in index: only if a has :db/index true.
-        r#"CREATE UNIQUE INDEX idx_datoms_avet ON datoms (a, value_type_tag, v, e) WHERE index_avet IS NOT 0"#,
-
-        // Opt-in index: only if a has :db/valueType :db.type/ref.  No need for tag here since all
-        // indexed elements are refs.
-        r#"CREATE UNIQUE INDEX idx_datoms_vaet ON datoms (v, a, e) WHERE index_vaet IS NOT 0"#,
-
-        // Opt-in index: only if a has :db/fulltext true; thus, it has :db/valueType :db.type/string,
-        // which is not :db/valueType :db.type/ref.  That is, index_vaet and index_fulltext are mutually
-        // exclusive.
-        r#"CREATE INDEX idx_datoms_fulltext ON datoms (value_type_tag, v, a, e) WHERE index_fulltext IS NOT 0"#,
-
-        // TODO: possibly remove this index.  :db.unique/{value,identity} should be asserted by the
-        // transactor in all cases, but the index may speed up some of SQLite's query planning.  For now,
-        // it serves to validate the transactor implementation.  Note that tag is needed here to
-        // differentiate, e.g., keywords and strings.
-        r#"CREATE UNIQUE INDEX idx_datoms_unique_value ON datoms (a, value_type_tag, v) WHERE unique_value IS NOT 0"#,
-
         r#"CREATE TABLE transactions (e INTEGER NOT NULL, a SMALLINT NOT NULL, v BLOB NOT NULL, tx INTEGER NOT NULL, added TINYINT NOT NULL DEFAULT 1, value_type_tag SMALLINT NOT NULL)"#,
         r#"CREATE INDEX idx_transactions_tx ON transactions (tx, added)"#,
 
@@ -198,6 +180,35 @@ lazy_static! {
         r#"CREATE TABLE parts (part TEXT NOT NULL PRIMARY KEY, start INTEGER NOT NULL, idx INTEGER NOT NULL)"#,
         ]
     };
+
+    /// The opt-in indexes of the datoms table (version 1), by name, with the SQL statements that
+    /// create them.  These follow `V1_STATEMENTS`.  They're kept apart so that bulk loading can drop
+    /// those that the transactor doesn't need, and create them afresh once it's done.
+    #[cfg_attr(rustfmt, rustfmt_skip)]
+    static ref V1_SECONDARY_INDEXES: Vec<(&'static str, &'static str)> = { vec![
+        // Opt-in index: only if a has :db/index true.
+        ("idx_datoms_avet",
+         r#"CREATE UNIQUE INDEX IF NOT EXISTS idx_datoms_avet ON datoms (a, value_type_tag, v, e) WHERE index_avet IS NOT 0"#),
+
+        // Opt-in index: only if a has :db/valueType :db.type/ref.  No need for tag here since all
+        // indexed elements are refs.
+        ("idx_datoms_vaet",
+         r#"CREATE UNIQUE INDEX IF NOT EXISTS idx_datoms_vaet ON datoms (v, a, e) WHERE index_vaet IS NOT 0"#),
+
+        // Opt-in index: only if a has :db/fulltext true; thus, it has :db/valueType :db.type/string,
+        // which is not :db/valueType :db.type/ref.  That is, index_vaet and index_fulltext are mutually
+        // exclusive.
+        ("idx_datoms_fulltext",
+         r#"CREATE INDEX IF NOT EXISTS idx_datoms_fulltext ON datoms (value_type_tag, v, a, e) WHERE index_fulltext IS NOT 0"#),
+
+        // TODO: possibly remove this index.  :db.unique/{value,identity} should be asserted by the
+        // transactor in all cases, but the index may speed up some of SQLite's query planning.  For now,
+        // it serves to validate the transactor implementation.  Note that tag is needed here to
+        // differentiate, e.g., keywords and strings.
+        ("idx_datoms_unique_value",
+         r#"CREATE UNIQUE INDEX IF NOT EXISTS idx_datoms_unique_value ON datoms (a, value_type_tag, v) WHERE unique_value IS NOT 0"#),
+        ]
+    };
 }
 
 /// Set the SQLite user version.
@@ -221,6 +232,30 @@ fn get_user_version(conn: &rusqlite::Connection) -> Result<i32> {
         .chain_err(|| "Could not get_user_version")
 }
 
+/// The opt-in indexes that the transactor itself relies on: AVET resolves upserts and lookup refs,
+/// and the unique value index enforces `:db/unique` as each transaction is written.
+const TRANSACTOR_INDEXES: &'static [&'static str] = &["idx_datoms_avet", "idx_datoms_unique_value"];
+
+/// Drop the opt-in indexes of the datoms table that the transactor doesn't rely on.  Until they're
+/// created again, writing datoms is faster, but some queries might be much slower.
+pub fn drop_secondary_indexes(conn: &rusqlite::Connection) -> Result<()> {
+    for &(name, _) in (&V1_SECONDARY_INDEXES).iter() {
+        if !TRANSACTOR_INDEXES.contains(&name) {
+            conn.execute(&format!("DROP INDEX IF EXISTS {}", name), &[])?;
+        }
+    }
+    Ok(())
+}
+
+/// Create the opt-in indexes of the datoms table, should they not exist.
+pub fn create_secondary_indexes(conn: &rusqlite::Connection) -> Result<()> {
+    for &(name, statement) in (&V1_SECONDARY_INDEXES).iter() {
+        conn.execute(statement, &[])
+            .chain_err(|| format!("Could not create index {}", name))?;
+    }
+    Ok(())
+}
+
 /// Do just enough work that either `create_current_version` or sync can populate the DB.
 pub fn create_empty_current_version(conn: &mut rusqlite::Connection) -> Result<(rusqlite::Transaction, DB)> {
     let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
@@ -228,6 +263,7 @@ pub fn create_empty_current_version(conn: &mut rusqlite::Connection) -> Result<(
     for statement in (&V1_STATEMENTS).iter() {
         tx.execute(statement, &[])?;
     }
+    create_secondary_indexes(&tx)?;
 
     set_user_version(&tx, CURRENT_VERSION)?;
 
@@ -244,7 +280,6 @@ pub fn create_current_version(conn: &mut rusqlite::Connection) -> Result<DB> {
     // TODO: think more carefully about allocating new parts and bitmasking part ranges.
     // TODO: install these using bootstrap assertions.  It's tricky because the part ranges are implicit.
     // TODO: one insert, chunk into 999/3 sections, for safety.
-    // This is necessary: `transact` will only UPDATE parts, not INSERT them if they're missing.
     for (part, partition) in db.partition_map.iter() {
         // TODO: Convert "keyword" part to SQL using Value conversion.
         tx.execute("INSERT INTO parts VALUES (?, ?, ?)", &[part, &partition.start, &partition.index])?;
@@ -1006,25 +1041,26 @@ impl MentatStoring for rusqlite::Connection {
 /// Update the current partition map materialized view.
 // TODO: only update changed partitions.
 pub fn update_partition_map(conn: &rusqlite::Connection, partition_map: &PartitionMap) -> Result<()> {
-    let values_per_statement = 2;
+    let values_per_statement = 3;
     let max_vars = conn.limit(Limit::SQLITE_LIMIT_VARIABLE_NUMBER) as usize;
     let max_partitions = max_vars / values_per_statement;
     if partition_map.len() > max_partitions {
         bail!(ErrorKind::NotYetImplemented(format!("No more than {} partitions are supported", max_partitions)));
     }
 
-    // Like "UPDATE parts SET idx = CASE WHEN part = ? THEN ? WHEN part = ? THEN ? ELSE idx END".
-    let s = format!("UPDATE parts SET idx = CASE {} ELSE idx END",
-                    repeat("WHEN part = ? THEN ?").take(partition_map.len()).join(" "));
+    // Like "INSERT OR REPLACE INTO parts (part, start, idx) VALUES (?, ?, ?), (?, ?, ?)".  Replacing
+    // rather than updating persists any partitions installed by the transaction.
+    let s = format!("INSERT OR REPLACE INTO parts (part, start, idx) VALUES {}",
+                    repeat_values(values_per_statement, partition_map.len()));
 
     let params: Vec<&ToSql> = partition_map.iter().flat_map(|(name, partition)| {
         once(name as &ToSql)
+            .chain(once(&partition.start as &ToSql))
             .chain(once(&partition.index as &ToSql))
     }).collect();
 
-    // TODO: only cache the latest of these statements.  Changing the set of partitions isn't
-    // supported in the Clojure implementation at all, and might not be supported in Mentat soon,
-    // so this is very low priority.
+    // TODO: only cache the latest of these statements.  Installing a partition changes the number
+    // of partitions, but that's rare, so this is very low priority.
     let mut stmt = conn.prepare_cached(s.as_str())?;
     stmt.execute(&params[..])
         .map(|_c| ())
@@ -1035,10 +1071,14 @@ pub fn update_partition_map(conn: &rusqlite::Connection, partition_map: &Partiti
 ///
 /// This updates the "entids", "idents", and "schema" materialized views, copying directly from the
 /// "datoms" and "transactions" table as appropriate.
-pub fn update_metadata(conn: &rusqlite::Connection, _old_schema: &Schema, new_schema: &Schema, metadata_report: &metadata::MetadataReport) -> Result<()>
+pub fn update_metadata(conn: &rusqlite::Connection, old_schema: &Schema, new_schema: &Schema, metadata_report: &metadata::MetadataReport) -> Result<()>
 {
-    use metadata::AttributeAlteration::*;
+    update_metadata_views(conn, metadata_report)?;
+    update_metadata_datoms(conn, old_schema, new_schema, metadata_report)
+}
 
+/// Update just the "idents" and "schema" materialized views based on the given metadata report.
+fn update_metadata_views(conn: &rusqlite::Connection, metadata_report: &metadata::MetadataReport) -> Result<()> {
     // Populate the materialized view directly from datoms (and, potentially in the future,
     // transactions).  This might generalize nicely as we expand the set of materialized views.
     // TODO: consider doing this in fewer SQLite execute() invocations.
@@ -1051,14 +1091,46 @@ pub fn update_metadata(conn: &rusqlite::Connection, _old_schema: &Schema, new_sc
                      &[])?;
     }
 
-
     let mut stmt = conn.prepare(format!("INSERT INTO schema SELECT e, a, v, value_type_tag FROM datoms WHERE e = ? AND a IN {}", entids::SCHEMA_SQL_LIST.as_str()).as_str())?;
     for &entid in &metadata_report.attributes_installed {
         stmt.execute(&[&entid as &ToSql])?;
     }
 
     let mut delete_stmt = conn.prepare(format!("DELETE FROM schema WHERE e = ? AND a IN {}", entids::SCHEMA_SQL_LIST.as_str()).as_str())?;
-    let mut insert_stmt = conn.prepare(format!("INSERT INTO schema SELECT e, a, v, value_type_tag FROM datoms WHERE e = ? AND a IN {}", entids::SCHEMA_SQL_LIST.as_str()).as_str())?;
+    for &entid in metadata_report.attributes_altered.keys() {
+        delete_stmt.execute(&[&entid as &ToSql])?;
+        stmt.execute(&[&entid as &ToSql])?;
+    }
+
+    Ok(())
+}
+
+/// Rebuild the "idents" and "schema" materialized views from scratch, for the given `schema`.
+///
+/// This is equivalent to updating the views after each transaction that changed the metadata, but
+/// only needs to walk the datoms once.
+pub fn rebuild_metadata_views(conn: &rusqlite::Connection, schema: &Schema) -> Result<()> {
+    conn.execute("DELETE FROM idents", &[])?;
+    conn.execute(format!("INSERT INTO idents SELECT e, a, v, value_type_tag FROM datoms WHERE a IN {}", entids::IDENTS_SQL_LIST.as_str()).as_str(),
+                 &[])?;
+
+    conn.execute("DELETE FROM schema", &[])?;
+    let mut stmt = conn.prepare(format!("INSERT INTO schema SELECT e, a, v, value_type_tag FROM datoms WHERE e = ? AND a IN {}", entids::SCHEMA_SQL_LIST.as_str()).as_str())?;
+    for &entid in schema.attribute_map.keys() {
+        stmt.execute(&[&entid as &ToSql])?;
+    }
+
+    Ok(())
+}
+
+/// Update the datoms of altered attributes based on the given metadata report, without touching
+/// the materialized views.
+///
+/// Use this in place of `update_metadata` when the views will be rebuilt with
+/// `rebuild_metadata_views` later.
+pub fn update_metadata_datoms(conn: &rusqlite::Connection, _old_schema: &Schema, new_schema: &Schema, metadata_report: &metadata::MetadataReport) -> Result<()> {
+    use metadata::AttributeAlteration::*;
+
     let mut index_stmt = conn.prepare("UPDATE datoms SET index_avet = ? WHERE a = ?")?;
     let mut unique_value_stmt = conn.prepare("UPDATE datoms SET unique_value = ? WHERE a = ?")?;
     let mut cardinality_stmt = conn.prepare(r#"
@@ -1071,9 +1143,6 @@ SELECT EXISTS
         left.v <> right.v)"#)?;
 
     for (&entid, alterations) in &metadata_report.attributes_altered {
-        delete_stmt.execute(&[&entid as &ToSql])?;
-        insert_stmt.execute(&[&entid as &ToSql])?;
-
         let attribute = new_schema.require_attribute_for_entid(entid)?;
 
         for alteration in alterations {
@@ -1117,28 +1186,34 @@ SELECT EXISTS
 }
 
 pub trait PartitionMapping {
-    fn allocate_entid<S: ?Sized + Ord + Display>(&mut self, partition: &S) -> i64 where String: Borrow<S>;
-    fn allocate_entids<S: ?Sized + Ord + Display>(&mut self, partition: &S, n: usize) -> Range<i64> where String: Borrow<S>;
+    fn allocate_entid<S: ?Sized + Ord + Display>(&mut self, partition: &S) -> Result<i64> where String: Borrow<S>;
+    fn allocate_entids<S: ?Sized + Ord + Display>(&mut self, partition: &S, n: usize) -> Result<Range<i64>> where String: Borrow<S>;
     fn contains_entid(&self, entid: Entid) -> bool;
 }
 
 impl PartitionMapping for PartitionMap {
     /// Allocate a single fresh entid in the given `partition`.
-    fn allocate_entid<S: ?Sized + Ord + Display>(&mut self, partition: &S) -> i64 where String: Borrow<S> {
-        self.allocate_entids(partition, 1).start
+    fn allocate_entid<S: ?Sized + Ord + Display>(&mut self, partition: &S) -> Result<i64> where String: Borrow<S> {
+        Ok(self.allocate_entids(partition, 1)?.start)
     }
 
-    /// Allocate `n` fresh entids in the given `partition`.
-    fn allocate_entids<S: ?Sized + Ord + Display>(&mut self, partition: &S, n: usize) -> Range<i64> where String: Borrow<S> {
-        match self.get_mut(partition) {
-            Some(partition) => {
-                let idx = partition.index;
-                partition.index += n as i64;
-                idx..partition.index
-            },
+    /// Allocate `n` fresh entids in the given `partition`, failing if they would reach into the
+    /// partition that starts after it.
+    fn allocate_entids<S: ?Sized + Ord + Display>(&mut self, partition: &S, n: usize) -> Result<Range<i64>> where String: Borrow<S> {
+        let start = match self.get(partition) {
+            Some(p) => p.start,
             // This is a programming error.
             None => panic!("Cannot allocate entid from unknown partition: {}", partition),
+        };
+        let end = self.values().map(|p| p.start).filter(|&s| s > start).min();
+        let p = self.get_mut(partition).unwrap();
+        let idx = p.index;
+        let next = idx + n as i64;
+        if end.map_or(false, |end| next > end) {
+            bail!(ErrorKind::PartitionExhausted(partition.to_string()));
         }
+        p.index = next;
+        Ok(idx..next)
     }
 
     fn contains_entid(&self, entid: Entid) -> bool {
@@ -1463,6 +1538,150 @@ mod tests {
                           [200 :db.schema/attribute 101]]");
     }
 
+    #[test]
+    fn test_retract_attribute_and_entity() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/many]
+                                 [:db/add 111 :db/valueType :db.type/long]
+                                 [:db/add 111 :db/cardinality :db.cardinality/many]
+                                 [:db/add 222 :db/ident :test/component]
+                                 [:db/add 222 :db/valueType :db.type/ref]
+                                 [:db/add 222 :db/isComponent true]
+                                 [:db/add 333 :db/ident :test/ref]
+                                 [:db/add 333 :db/valueType :db.type/ref]
+                                 [:db/add 444 :db/ident :test/name]
+                                 [:db/add 444 :db/valueType :db.type/string]
+                                 [:db/add 444 :db/unique :db.unique/identity]
+                                 [:db/add 444 :db/index true]]");
+        assert_transact!(conn, "[[:db/add 300 :test/name \"parent\"]
+                                 [:db/add 300 :test/many 1]
+                                 [:db/add 300 :test/many 2]
+                                 [:db/add 300 :test/component 301]
+                                 [:db/add 301 :test/many 3]
+                                 [:db/add 301 :test/component 302]
+                                 [:db/add 302 :test/many 4]
+                                 [:db/add 400 :test/ref 300]
+                                 [:db/add 400 :test/many 5]
+                                 [:db/add 401 :test/ref 301]]");
+
+        // Every value of the attribute is retracted.
+        assert_transact!(conn, "[[:db/retractAttribute 300 :test/many]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[300 :test/many 1 ?tx false]
+                          [300 :test/many 2 ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Retracting an attribute without values changes nothing.
+        assert_transact!(conn, "[[:db/retractAttribute 300 :test/ref]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?tx :db/txInstant ?ms ?tx true]]");
+
+        // Retracting an entity retracts its components, recursively, and every reference to it or
+        // to its components.
+        assert_transact!(conn, "[[:db/retractEntity (lookup-ref :test/name \"parent\")]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[300 :test/component 301 ?tx false]
+                          [300 :test/name \"parent\" ?tx false]
+                          [301 :test/many 3 ?tx false]
+                          [301 :test/component 302 ?tx false]
+                          [302 :test/many 4 ?tx false]
+                          [400 :test/ref 300 ?tx false]
+                          [401 :test/ref 301 ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Entity 400 only lost its reference.
+        assert_transact!(conn, "[[:db/retractEntity 300]
+                                 [:db/retractAttribute 400 :test/ref]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?tx :db/txInstant ?ms ?tx true]]");
+        assert_transact!(conn, "[[:db/retractEntity 400]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[400 :test/many 5 ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // A tempid names the entity it upserts to.
+        assert_transact!(conn, "[[:db/add 500 :test/name \"other\"]
+                                 [:db/add 500 :test/many 6]]");
+        assert_transact!(conn, "[[:db/add \"t\" :test/name \"other\"]
+                                 [:db/retractAttribute \"t\" :test/many]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[500 :test/many 6 ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // A tempid that doesn't upsert names nothing to retract.
+        assert_transact!(conn, "[[:db/retractEntity \"t\"]
+                                 [:db/retractAttribute \"u\" :test/many]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?tx :db/txInstant ?ms ?tx true]]");
+
+        // The retracted entity must already exist.
+        assert_transact!(conn,
+                         "[[:db/retractEntity (lookup-ref :test/name \"nobody\")]]",
+                         Err("no entity found for lookup ref [a v]: (444, String(\"nobody\"))"));
+    }
+
+    #[test]
+    fn test_cas() {
+        let mut conn = TestConn::default();
+
+        assert_transact!(conn, "[[:db/add 111 :db/ident :test/one]
+                                 [:db/add 111 :db/valueType :db.type/long]
+                                 [:db/add 111 :db/cardinality :db.cardinality/one]
+                                 [:db/add 222 :db/ident :test/many]
+                                 [:db/add 222 :db/valueType :db.type/long]
+                                 [:db/add 222 :db/cardinality :db.cardinality/many]
+                                 [:db/add 333 :db/ident :test/unique]
+                                 [:db/add 333 :db/valueType :db.type/string]
+                                 [:db/add 333 :db/unique :db.unique/identity]
+                                 [:db/add 333 :db/index true]]");
+
+        // nil expects no value.
+        assert_transact!(conn, "[[:db/cas 300 :test/one nil 1]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[300 :test/one 1 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // A matching value is swapped.
+        assert_transact!(conn, "[[:db/cas 300 :test/one 1 2]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[300 :test/one 1 ?tx false]
+                          [300 :test/one 2 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // A mismatch fails the whole transaction, reporting the current value.
+        assert_transact!(conn,
+                         "[[:db/add 301 :test/one 10]
+                           [:db/cas 300 :test/one 1 3]]",
+                         Err("compare-and-swap failed for [300 111]: expected Some(Long(1)), found Some(Long(2))"));
+        assert_transact!(conn,
+                         "[[:db/cas 300 :test/one nil 3]]",
+                         Err("compare-and-swap failed for [300 111]: expected None, found Some(Long(2))"));
+        assert_transact!(conn, "[[:db/retractAttribute 301 :test/one]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?tx :db/txInstant ?ms ?tx true]]");
+
+        // Preconditions are checked after upserting.
+        assert_transact!(conn, "[[:db/add \"t\" :test/unique \"x\"]
+                                 [:db/add \"t\" :test/one 5]]");
+        assert_transact!(conn, "[[:db/add \"t\" :test/unique \"x\"]
+                                 [:db/cas \"t\" :test/one 5 6]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?e :test/one 5 ?tx false]
+                          [?e :test/one 6 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // A new entity has no value.
+        assert_transact!(conn, "[[:db/cas \"new\" :test/one nil 7]]");
+        assert_transact!(conn,
+                         "[[:db/cas \"new\" :test/one 7 8]]",
+                         Err("compare-and-swap failed for [65538 111]: expected Some(Long(7)), found None"));
+
+        assert_transact!(conn,
+                         "[[:db/cas 300 :test/many nil 1]]",
+                         Err("not yet implemented: Cannot :db/cas attribute 222 that is :db.cardinality/many"));
+    }
+
     // Unique is required!
     #[test]
     fn test_upsert_issue_538() {
@@ -1543,11 +1762,16 @@ mod tests {
         // Conflicting upserts fail.
         assert_transact!(conn, "[[:db/add \"t1\" :db/ident :name/Ivan]
                                 [:db/add \"t1\" :db/ident :name/Petr]]",
-                         Err("not yet implemented: Conflicting upsert: tempid \'t1\' resolves to more than one entid: 100, 101"));
+                         Err("conflicting upsert: tempid \'t1\' resolves to more than one entid: [1 :name/Ivan] -> 100, [1 :name/Petr] -> 101"));
 
-        // tempids in :db/retract that don't upsert fail.
-        assert_transact!(conn, "[[:db/retract \"t1\" :db/ident :name/Anonymous]]",
-                         Err("not yet implemented: [:db/retract ...] entity referenced tempid that did not upsert: t1"));
+        // tempids in :db/retract that don't upsert name entities that don't exist yet, so there's
+        // nothing to retract.  They aren't allocated.
+        let report = assert_transact!(conn, "[[:db/retract \"t1\" :db/ident :name/Anonymous]
+                                              [:db/retract 100 :db.schema/attribute \"t2\"]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?tx :db/txInstant ?ms ?tx true]]");
+        assert_matches!(tempids(&report),
+                        "{}");
 
         // tempids in :db/retract that do upsert are retracted.  The ref given doesn't exist, so the
         // assertion will be ignored.
@@ -1644,6 +1868,103 @@ mod tests {
                                  [:db/add 101 :db/cardinality :db.cardinality/many]]");
     }
 
+    #[test]
+    fn test_db_install_partition() {
+        let mut conn = TestConn::default();
+
+        // We can install a partition named by an ident asserted in the same transaction.
+        assert_transact!(conn, r#"[[:db/add "a" :db/ident :test.part/a]
+                                   [:db/add :db.part/db :db.install/partition "a"]]"#);
+        assert_eq!(conn.partition_map.get(":test.part/a"), Some(&Partition::new(1 << 32, 1 << 32)));
+
+        // Installing it again changes nothing.
+        assert_transact!(conn, "[[:db/add :db.part/db :db.install/partition :test.part/a]]");
+        assert_eq!(conn.partition_map.get(":test.part/a"), Some(&Partition::new(1 << 32, 1 << 32)));
+
+        // Tempids that ask for the partition are allocated in it, wherever else they appear.
+        let report = assert_transact!(conn, r#"[[:db/add (tempid :test.part/a "x") :db/doc "x"]
+                                                [:db/add "y" :db/doc "y"]
+                                                [:db/add "x" :db.schema/attribute "y"]]"#);
+        assert_matches!(tempids(&report),
+                        r#"{"x" 4294967296
+                            "y" 65537}"#);
+        assert_matches!(conn.last_transaction(),
+                        "[[65537 :db/doc \"y\" ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]
+                          [4294967296 :db/doc \"x\" ?tx true]
+                          [4294967296 :db.schema/attribute 65537 ?tx true]]");
+
+        // Each new partition starts above every existing partition.
+        assert_transact!(conn, r#"[[:db/add "b" :db/ident :test.part/b]
+                                   [:db/add :db.part/db :db.install/partition "b"]]"#);
+        assert_eq!(conn.partition_map.get(":test.part/b"), Some(&Partition::new(1 << 33, 1 << 33)));
+
+        // The partitions are persisted.
+        assert_eq!(read_partition_map(&conn.sqlite).expect("partition map"), conn.partition_map);
+
+        assert_transact!(conn,
+                         r#"[[:db/add (tempid :test.part/c "z") :db/doc "z"]]"#,
+                         Err("no partition installed with name: :test.part/c"));
+
+        // Only the transactor allocates schema entities and transactions.
+        assert_transact!(conn,
+                         r#"[[:db/add (tempid :db.part/db "z") :db/doc "z"]]"#,
+                         Err("tempids cannot be allocated in reserved partition: :db.part/db"));
+        assert_transact!(conn,
+                         r#"[[:db/add (tempid :db.part/tx "z") :db/doc "z"]]"#,
+                         Err("tempids cannot be allocated in reserved partition: :db.part/tx"));
+
+        assert_transact!(conn,
+                         r#"[[:db/add (tempid :test.part/a "z") :db/doc "z"]
+                             [:db/add (tempid :test.part/b "z") :db.schema/version 1]]"#,
+                         Err("tempid 'z' requested conflicting partitions: :test.part/a and :test.part/b"));
+
+        assert_transact!(conn,
+                         "[[:db/add :db.part/db :db.install/partition 100]]",
+                         Err("cannot install partition 100 without :db/ident"));
+
+        // Only :db.part/db installs partitions.
+        assert_transact!(conn,
+                         "[[:db/add 65536 :db.install/partition :test.part/a]]",
+                         Err("bad schema assertion: Partitions are installed with [:db.part/db :db.install/partition ...], not on entity 65536"));
+    }
+
+    #[test]
+    fn test_allocate_entids_up_to_next_partition() {
+        let mut partition_map = PartitionMap::default();
+        partition_map.insert(":test.part/a".to_string(), Partition::new(10, 18));
+        partition_map.insert(":test.part/b".to_string(), Partition::new(20, 20));
+
+        assert_eq!(partition_map.allocate_entids(":test.part/a", 2).expect("allocated"), 18..20);
+        assert_eq!(partition_map.allocate_entid(":test.part/a").unwrap_err().to_string(),
+                   "partition :test.part/a has no entids left to allocate");
+        assert_eq!(partition_map.get(":test.part/a"), Some(&Partition::new(10, 20)));
+
+        // The last partition has nothing above it.
+        assert_eq!(partition_map.allocate_entid(":test.part/b").expect("allocated"), 20);
+    }
+
+    #[test]
+    fn test_tx_metadata() {
+        let mut conn = TestConn::default();
+
+        // The transaction can describe itself, and other entities can refer to it.
+        let report = assert_transact!(conn, r#"[[:db/add :db/tx :db/doc "reason"]
+                                                [:db/add "e" :db.schema/attribute :db/tx]]"#);
+        let tx = report.tx_id;
+        assert_eq!(report.tx_metadata.len(), 1);
+        assert_eq!(report.tx_metadata.get(&entids::DB_DOC), Some(&vec![TypedValue::typed_string("reason")]));
+        assert_matches!(conn.last_transaction(),
+                        "[[65536 :db.schema/attribute ?tx ?tx true]
+                          [?tx :db/doc \"reason\" ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Without any, there's no metadata to report.
+        let report = assert_transact!(conn, "[[:db/add 65536 :db/doc \"other\"]]");
+        assert!(report.tx_metadata.is_empty());
+        assert!(report.tx_id > tx);
+    }
+
     #[test]
     fn test_db_alter() {
         let mut conn = TestConn::default();
@@ -2020,7 +2341,7 @@ mod tests {
         // We cannot resolve lookup refs that aren't :db/unique.
         assert_transact!(conn,
                          "[[:db/add (lookup-ref :test/not_unique :test/keyword) :test/not_unique :test/keyword]]",
-                         Err("not yet implemented: Cannot resolve (lookup-ref 333 :test/keyword) with attribute that is not :db/unique"));
+                         Err("cannot resolve (lookup-ref :test/not_unique :test/keyword): :test/not_unique is not :db/unique"));
 
         // We type check the lookup ref's value against the lookup ref's attribute.
         assert_transact!(conn,
@@ -2030,7 +2351,7 @@ mod tests {
         // Each lookup ref in the entity column must resolve
         assert_transact!(conn,
                          "[[:db/add (lookup-ref :test/unique_value \"unmatched string value\") :test/not_unique :test/keyword]]",
-                         Err("no entid found for ident: couldn\'t lookup [a v]: (111, String(\"unmatched string value\"))"));
+                         Err("no entity found for lookup ref [a v]: (111, String(\"unmatched string value\"))"));
     }
 
     #[test]
@@ -2091,7 +2412,7 @@ mod tests {
         // Each lookup ref in the value column must resolve
         assert_transact!(conn,
                          "[[:db/add \"t\" :test/ref (lookup-ref :test/unique_value \"unmatched string value\")]]",
-                         Err("no entid found for ident: couldn\'t lookup [a v]: (111, String(\"unmatched string value\"))"));
+                         Err("no entity found for lookup ref [a v]: (111, String(\"unmatched string value\"))"));
     }
 
     #[test]
@@ -2318,6 +2639,50 @@ mod tests {
         assert_matches!(tempids(&report),
                         "{}");
 
+        // Check that we can explode nested maps in reversed notation.  Each nested map is a new
+        // entity that refers to the outer entity, so it is never dangling.
+        let report = assert_transact!(conn, "[{:db/id \"c\" :test/many 13 :test/_dangling {:db/id \"p\" :test/many 14}}]");
+        assert_matches!(conn.last_transaction(),
+                        "[[65544 :test/many 13 ?tx true]
+                          [65545 :test/many 14 ?tx true]
+                          [65545 :test/dangling 65544 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+        assert_matches!(tempids(&report),
+                        "{\"c\" 65544
+                          \"p\" 65545}");
+
+        // Without :db/id, and nested more than once: a tree, built from the leaf up.
+        assert_transact!(conn, "[{:test/many 15 :test/_dangling {:test/many 16 :test/_dangling {:test/many 17}}}]");
+        assert_matches!(conn.last_transaction(),
+                        "[[?leaf :test/many 15 ?tx true]
+                          [?parent :test/many 16 ?tx true]
+                          [?parent :test/dangling ?leaf ?tx true]
+                          [?root :test/many 17 ?tx true]
+                          [?root :test/dangling ?parent ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // And in vectors, each element of which refers to the outer entity.
+        assert_transact!(conn, "[{:db/id 700 :test/_dangling [701 {:db/id \"q\" :test/many 18}]}]");
+        assert_matches!(conn.last_transaction(),
+                        "[[701 :test/dangling 700 ?tx true]
+                          [?q :test/many 18 ?tx true]
+                          [?q :test/dangling 700 ?tx true]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        // Retracting through a reversed attribute retracts each value's reference to the outer
+        // entity, whether the value is an atom or a vector.
+        assert_transact!(conn, "[[:db/retract 700 :test/_dangling 701]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[701 :test/dangling 700 ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
+
+        assert_transact!(conn, "[[:db/add 702 :test/dangling 700]
+                                 [:db/add 703 :test/dangling 700]]");
+        assert_transact!(conn, "[[:db/retract 700 :test/_dangling [702 703]]]");
+        assert_matches!(conn.last_transaction(),
+                        "[[702 :test/dangling 700 ?tx false]
+                          [703 :test/dangling 700 ?tx false]
+                          [?tx :db/txInstant ?ms ?tx true]]");
     }
 
     #[test]
@@ -2342,15 +2707,11 @@ mod tests {
         // nested value vectors, so we only test things that "get through" to the map notation
         // dynamic processor here.
 
-        // Verify that we can't explode reverse notation in map notation with nested value maps.
+        // Verify that we can't use reverse notation with non-:db.type/ref attributes, even with
+        // nested value maps.
         assert_transact!(conn,
-                         "[{:test/_dangling {:test/many 14}}]",
-                         Err("not yet implemented: Cannot explode map notation value in :attr/_reversed notation for attribute 444"));
-
-        // Verify that we can't explode reverse notation in map notation with nested value vectors.
-        assert_transact!(conn,
-                         "[{:test/_dangling [:test/many]}]",
-                         Err("not yet implemented: Cannot explode vector value in :attr/_reversed notation for attribute 444"));
+                         "[{:test/_unique {:test/many 14}}]",
+                         Err("not yet implemented: Cannot use :attr/_reversed notation for attribute 333 that is not :db/valueType :db.type/ref"));
 
         // Verify that we can't use reverse notation with non-:db.type/ref attributes.
         assert_transact!(conn,
@@ -2371,6 +2732,11 @@ mod tests {
         assert_transact!(conn,
                          "[{:test/_dangling 1.23}]",
                          Err("EDN value \'1.23\' is not the expected Mentat value type Ref"));
+
+        // Nested maps, even inside a vector, only make sense when asserting.
+        assert_transact!(conn,
+                         "[[:db/retract 700 :test/_dangling [{:test/many 1}]]]",
+                         Err("not yet implemented: Cannot explode nested map value in :db/retract for attribute 444"));
     }
 
     #[test]
@@ -14,7 +14,16 @@ use edn;
 use rusqlite;
 
 use mentat_tx_parser;
-use types::{Entid, ValueType};
+use db::TypedSQLValue;
+use types::{AVPair, Entid, TypedValue, ValueType};
+
+/// Formats the `[a v]` pairs of a conflicting upsert, and the entids they resolve to.
+fn format_upsert_conflicts(conflicts: &[(AVPair, Entid)]) -> String {
+    conflicts.iter()
+             .map(|&((a, ref v), e)| format!("[{} {}] -> {}", a, v.to_edn_value_pair().0, e))
+             .collect::<Vec<String>>()
+             .join(", ")
+}
 
 error_chain! {
     types {
@@ -75,6 +84,12 @@ error_chain! {
             display("no entid found for ident: {}", ident)
         }
 
+        /// No entity has the unique value of a lookup ref `[a v]`.
+        LookupRefNotFound(av: AVPair) {
+            description("no entity found for lookup ref")
+            display("no entity found for lookup ref [a v]: {:?}", av)
+        }
+
         /// An entid->ident mapping failed.
         /// We also use this error if you try to transact an entid that we didn't allocate,
         /// in part because we blow the stack in error_chain if we define a new enum!
@@ -83,6 +98,82 @@ error_chain! {
             display("unrecognized or no ident found for entid: {}", entid)
         }
 
+        /// A `:db/cas` found a value other than the one it expected.  `None` means no value.
+        CasFailed(e: Entid, a: Entid, expected: Option<TypedValue>, actual: Option<TypedValue>) {
+            description("compare-and-swap failed")
+            display("compare-and-swap failed for [{} {}]: expected {:?}, found {:?}", e, a, expected, actual)
+        }
+
+        /// A transaction called a transaction function that isn't registered.
+        UnrecognizedTxFunction(name: String) {
+            description("no transaction function registered with name")
+            display("no transaction function registered with name: {}", name)
+        }
+
+        /// Transaction functions called each other more than `depth` deep, most likely because one
+        /// returns a call to itself.
+        TxFunctionDepthExceeded(name: String, depth: usize) {
+            description("transaction functions nested too deeply")
+            display("transaction function {} nested more than {} calls deep", name, depth)
+        }
+
+        /// A `[:db/retract ...]` entity referenced a tempid that didn't upsert, and the transaction
+        /// was configured to fail rather than ignore it.
+        UnresolvedRetraction(tempid: String) {
+            description("[:db/retract ...] entity referenced tempid that did not upsert")
+            display("[:db/retract ...] entity referenced tempid that did not upsert: {}", tempid)
+        }
+
+        /// A tempid upserted to more than one entity, through the given `[a v]` pairs.
+        ConflictingUpsert(tempid: String, conflicts: Vec<(AVPair, Entid)>) {
+            description("conflicting upsert: tempid resolves to more than one entid")
+            display("conflicting upsert: tempid '{}' resolves to more than one entid: {}", tempid, format_upsert_conflicts(conflicts))
+        }
+
+        /// Merging a tempid's conflicting upserts would merge an entity that belongs to the schema:
+        /// one in `:db.part/db`, or one with a `:db/ident`.
+        UnmergeableEntity(tempid: String, entid: Entid) {
+            description("conflicting upsert would merge a schema entity")
+            display("cannot merge the entities tempid '{}' resolves to: {} is in :db.part/db or has a :db/ident", tempid, entid)
+        }
+
+        /// A lookup ref named an attribute that isn't `:db/unique`, so it could match many entities.
+        NonUniqueLookupRef(attribute: String, value: String) {
+            description("lookup ref attribute is not :db/unique")
+            display("cannot resolve (lookup-ref {} {}): {} is not :db/unique", attribute, value, attribute)
+        }
+
+        /// A tempid requested a partition that hasn't been installed.
+        UnrecognizedPartition(partition: String) {
+            description("no partition installed with name")
+            display("no partition installed with name: {}", partition)
+        }
+
+        /// A tempid requested to be allocated in `:db.part/db` or `:db.part/tx`, whose entids only
+        /// the transactor allocates.
+        ReservedPartition(partition: String) {
+            description("tempids cannot be allocated in reserved partition")
+            display("tempids cannot be allocated in reserved partition: {}", partition)
+        }
+
+        /// A partition has allocated every entid below the start of the next partition.
+        PartitionExhausted(partition: String) {
+            description("partition has no entids left to allocate")
+            display("partition {} has no entids left to allocate", partition)
+        }
+
+        /// A tempid requested more than one partition to be allocated in.
+        ConflictingTempIdPartitions(tempid: String, first: String, second: String) {
+            description("tempid requested conflicting partitions")
+            display("tempid '{}' requested conflicting partitions: {} and {}", tempid, first, second)
+        }
+
+        /// A transaction installed a partition without giving it a `:db/ident` to name it by.
+        UnnamedPartition(entid: Entid) {
+            description("cannot install partition without :db/ident")
+            display("cannot install partition {} without :db/ident", entid)
+        }
+
         ConflictingDatoms {
             description("conflicting datoms in tx")
             display("conflicting datoms in tx")
@@ -12,7 +12,10 @@
 
 //! Types used only within the transactor.  These should not be exposed outside of this crate.
 
-use std::collections::HashMap;
+use std::collections::{
+    BTreeMap,
+    HashMap,
+};
 use std::rc::Rc;
 
 use mentat_core::KnownEntid;
@@ -45,6 +48,23 @@ pub type TypedValueOr<T> = Either<TypedValue, T>;
 pub type TempIdHandle = Rc<TempId>;
 pub type TempIdMap = HashMap<TempIdHandle, KnownEntid>;
 
+/// Map tempids to the name of the partition each should be allocated in, should it not upsert.
+/// Tempids without an entry are allocated in `:db.part/user`.
+pub type TempIdPartitions = BTreeMap<TempId, String>;
+
+/// Record that `temp_id` should be allocated in `partition`.  A tempid can ask for the same
+/// partition any number of times, but can't ask for two different partitions.
+pub fn request_partition(partitions: &mut TempIdPartitions, temp_id: &TempId, partition: String) -> errors::Result<()> {
+    if let Some(existing) = partitions.get(temp_id) {
+        if *existing != partition {
+            bail!(ErrorKind::ConflictingTempIdPartitions(temp_id.to_string(), existing.clone(), partition));
+        }
+        return Ok(());
+    }
+    partitions.insert(temp_id.clone(), partition);
+    Ok(())
+}
+
 pub type LookupRef = Rc<AVPair>;
 
 /// Internal representation of an entid on its way to resolution.  We either have the simple case (a
@@ -90,8 +110,7 @@ pub fn replace_lookup_ref<T, U>(lookup_map: &AVMap, desired_or: Either<T, Lookup
                 LookupRefOrTempId::TempId(t) => Ok(Right(t)),
                 LookupRefOrTempId::LookupRef(av) => lookup_map.get(&*av)
                     .map(|x| lift(*x)).map(Left)
-                    // XXX TODO: fix this error kind!
-                    .ok_or_else(|| ErrorKind::UnrecognizedIdent(format!("couldn't lookup [a v]: {:?}", (*av).clone())).into()),
+                    .ok_or_else(|| ErrorKind::LookupRefNotFound((*av).clone()).into()),
             }
         }
     }
@@ -39,6 +39,7 @@ mod add_retract_alter_set;
 pub mod cache;
 pub mod db;
 mod bootstrap;
+pub mod datoms;
 pub mod debug;
 pub mod entids;
 pub mod errors;
@@ -47,6 +48,7 @@ mod metadata;
 mod schema;
 mod watcher;
 mod tx;
+pub mod tx_function;
 pub mod types;
 mod upsert_resolution;
 
@@ -81,14 +83,27 @@ pub use watcher::{
 };
 
 pub use tx::{
+    next_partition_start,
     transact,
     transact_terms,
+    transact_terms_with_options,
+    transact_with_options,
+    TxOptions,
+};
+
+pub use tx_function::{
+    TxFunction,
+    TxFunctionContext,
+    TxFunctions,
 };
 
 pub use types::{
     DB,
+    Partition,
     PartitionMap,
     TxReport,
+    UnresolvedRetractions,
+    UpsertConflicts,
 };
 
 pub fn to_namespaced_keyword(s: &str) -> Result<symbols::NamespacedKeyword> {
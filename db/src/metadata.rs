@@ -18,9 +18,9 @@
 //! - they can add (and, eventually, retract and alter) schema attributes using various `:db/*`
 //!   attributes;
 //!
-//! - eventually, they will be able to add (and possibly retract) entid partitions using a Mentat
-//!   equivalent (perhaps :db/partition or :db.partition/start) to Datomic's `:db.install/partition`
-//!   attribute.
+//! - they can add (but not yet retract) entid partitions using the `:db.install/partition`
+//!   attribute.  Partitions are installed by the transactor rather than by this module: see
+//!   `Tx::install_partition`.
 //!
 //! This module recognizes, validates, applies, and reports on these mutations.
 
@@ -46,14 +46,23 @@
 //! keep everything straight.
 
 use std::borrow::Cow;
+use std::cmp;
 use std::collections::{
     BTreeMap,
     BTreeSet,
     VecDeque,
 };
+use std::fmt;
 
 use std::rc::Rc;
 
+use bootstrap::PARTITION_SIZE;
+use datoms::{
+    self,
+    Datom,
+    DatomIndex,
+    DatomPattern,
+};
 use db;
 use db::{
     MentatStoring,
@@ -70,18 +79,21 @@ use internal_types::{
     LookupRefOrTempId,
     TempIdHandle,
     TempIdMap,
+    TempIdPartitions,
     Term,
     TermWithTempIds,
     TermWithTempIdsAndLookupRefs,
     TermWithoutTempIds,
     TypedValueOr,
     replace_lookup_ref,
+    request_partition,
 };
 
 use mentat_core::util::Either;
 
 use mentat_core::{
     DateTime,
+    HasSchema,
     KnownEntid,
     Schema,
     Utc,
@@ -109,18 +121,70 @@ use types::{
     AVPair,
     AVMap,
     Entid,
+    Partition,
     PartitionMap,
     TypedValue,
     TxReport,
+    UnresolvedRetractions,
+    UpsertConflicts,
     ValueType,
 };
 
+use tx_function::{
+    MAX_TX_FUNCTION_DEPTH,
+    TxFunctionContext,
+    TxFunctions,
+};
+
 use watcher::{
     TransactWatcher,
 };
 
 use upsert_resolution::Generation;
 
+/// A `:db/cas` precondition, checked once tempids have been resolved: `e` must have the value `old`
+/// for `a`, or no value at all if `old` is `None`.
+struct CasCheck<E> {
+    e: E,
+    a: Entid,
+    old: Option<TypedValue>,
+}
+
+/// A `[:db/retractEntity e]`, or a `[:db/retractAttribute e a]` if `a` is given, whose `e` is a
+/// tempid.  It is expanded once tempids have been resolved, since the tempid might upsert.
+struct TempIdRetraction {
+    e: TempIdHandle,
+    a: Option<Entid>,
+}
+
+/// How a transaction treats the entities it applies.
+#[derive(Clone, Copy, Default)]
+pub struct TxOptions<'a> {
+    /// The transaction functions that entities like `[:my.fn/name args...]` may call.
+    pub functions: Option<&'a TxFunctions>,
+
+    pub unresolved_retractions: UnresolvedRetractions,
+
+    pub upsert_conflicts: UpsertConflicts,
+
+    /// Leave the "idents" and "schema" materialized views as they are, rather than updating them
+    /// as the metadata changes.  Whoever sets this must rebuild them with
+    /// `db::rebuild_metadata_views` before the store is read again.
+    pub defer_metadata_views: bool,
+}
+
+impl<'a> fmt::Debug for TxOptions<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let functions: Option<Vec<&NamespacedKeyword>> = self.functions.map(|functions| functions.keys().collect());
+        f.debug_struct("TxOptions")
+         .field("functions", &functions)
+         .field("unresolved_retractions", &self.unresolved_retractions)
+         .field("upsert_conflicts", &self.upsert_conflicts)
+         .field("defer_metadata_views", &self.defer_metadata_views)
+         .finish()
+    }
+}
+
 /// A transaction on its way to being applied.
 #[derive(Debug)]
 pub struct Tx<'conn, 'a, W> where W: TransactWatcher {
@@ -146,6 +210,8 @@ pub struct Tx<'conn, 'a, W> where W: TransactWatcher {
 
     watcher: W,
 
+    options: TxOptions<'a>,
+
     /// The transaction ID of the transaction.
     tx_id: Entid,
 
@@ -167,15 +233,25 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             schema_for_mutation: Cow::Borrowed(schema_for_mutation),
             schema: schema,
             watcher: watcher,
+            options: TxOptions::default(),
             tx_id: tx_id,
             tx_instant: None,
         }
     }
 
+    pub fn with_options(mut self, options: TxOptions<'a>) -> Tx<'conn, 'a, W> {
+        self.options = options;
+        self
+    }
+
     /// Given a collection of tempids and the [a v] pairs that they might upsert to, resolve exactly
     /// which [a v] pairs do upsert to entids, and map each tempid that upserts to the upserted
     /// entid.  The keys of the resulting map are exactly those tempids that upserted.
-    pub(crate) fn resolve_temp_id_avs<'b>(&self, temp_id_avs: &'b [(TempIdHandle, AVPair)]) -> Result<TempIdMap> {
+    ///
+    /// A tempid whose [a v] pairs upsert to more than one entid fails the transaction, unless the
+    /// transaction merges such conflicts.  In that case the tempid maps to the lowest of the entids,
+    /// and each of the others is recorded in `merges` as being merged into it.
+    pub(crate) fn resolve_temp_id_avs<'b>(&self, temp_id_avs: &'b [(TempIdHandle, AVPair)], merges: &mut BTreeMap<Entid, Entid>) -> Result<TempIdMap> {
         if temp_id_avs.is_empty() {
             return Ok(TempIdMap::default());
         }
@@ -189,35 +265,163 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         // Lookup in the store.
         let av_map: AVMap = self.store.resolve_avs(&av_pairs[..])?;
 
-        // Map id->entid.
-        let mut temp_id_map: TempIdMap = TempIdMap::default();
+        // Map id->[a v]->entid.  BTreeMap rather than HashMap so that conflicts are reported
+        // deterministically.
+        let mut upserts: BTreeMap<TempIdHandle, BTreeMap<&AVPair, Entid>> = BTreeMap::default();
         for &(ref temp_id, ref av_pair) in temp_id_avs {
             if let Some(n) = av_map.get(&av_pair) {
-                if let Some(&KnownEntid(previous_n)) = temp_id_map.get(&*temp_id) {
-                    if *n != previous_n {
-                        // Conflicting upsert!  TODO: collect conflicts and give more details on what failed this transaction.
-                        bail!(ErrorKind::NotYetImplemented(format!("Conflicting upsert: tempid '{}' resolves to more than one entid: {:?}, {:?}", temp_id, previous_n, n))) // XXX
-                    }
+                upserts.entry(temp_id.clone()).or_insert_with(BTreeMap::default).insert(av_pair, *n);
+            }
+        }
+
+        // Map id->entid.
+        let mut temp_id_map: TempIdMap = TempIdMap::default();
+        for (temp_id, avs) in upserts {
+            let entids: BTreeSet<Entid> = avs.values().cloned().collect();
+            let n = *entids.iter().next().expect("at least one upserted entid");
+            if entids.len() > 1 {
+                match self.options.upsert_conflicts {
+                    UpsertConflicts::Fail => {
+                        let conflicts = avs.into_iter().map(|(av_pair, e)| (av_pair.clone(), e)).collect();
+                        bail!(ErrorKind::ConflictingUpsert(temp_id.to_string(), conflicts));
+                    },
+                    UpsertConflicts::Merge => {
+                        // Idents and the schema's own entities are never merged away, or into.
+                        let db_part = self.partition_map.get(":db.part/db");
+                        for &e in entids.iter() {
+                            if db_part.map_or(false, |part| part.contains_entid(e)) || self.schema.get_ident(e).is_some() {
+                                bail!(ErrorKind::UnmergeableEntity(temp_id.to_string(), e));
+                            }
+                        }
+                        for &other in entids.iter().skip(1) {
+                            merge_entids(merges, n, other);
+                        }
+                    },
                 }
-                temp_id_map.insert(temp_id.clone(), KnownEntid(*n));
             }
+            temp_id_map.insert(temp_id, KnownEntid(n));
         }
 
         Ok(temp_id_map)
     }
 
+    /// The terms that merge each entity in `merges` into the entity it is merged into: every datom
+    /// about a merged entity, and every datom that refers to one, is retracted and asserted again
+    /// about, or referring to, the surviving entity.
+    ///
+    /// `terms` are the transaction's own terms.  Their `:db.cardinality/one` assertions take
+    /// precedence over values being moved, as do the surviving entity's existing values.
+    fn merge_terms(&self, merges: &BTreeMap<Entid, Entid>, terms: &[TermWithoutTempIds]) -> Result<Vec<TermWithoutTempIds>> {
+        let mut merged: BTreeSet<Datom> = BTreeSet::new();
+        for &e in merges.keys() {
+            merged.extend(datoms::datoms(self.store, DatomIndex::EAVT, &DatomPattern { e: Some(e), ..Default::default() })?);
+            merged.extend(datoms::datoms(self.store, DatomIndex::VAET, &DatomPattern { v: Some(TypedValue::Ref(e)), ..Default::default() })?);
+        }
+
+        // The [e a] pairs that the transaction asserts.
+        let asserted: BTreeSet<(Entid, Entid)> = terms.iter().filter_map(|term| match term {
+            &Term::AddOrRetract(OpType::Add, KnownEntid(e), a, _) => Some((e, a)),
+            &Term::AddOrRetract(OpType::Retract, _, _, _) => None,
+        }).collect();
+
+        // The [e a] pairs of surviving entities that already have a value.
+        let mut occupied: BTreeSet<(Entid, Entid)> = BTreeSet::new();
+        let survivors: BTreeSet<Entid> = merges.keys().map(|&e| merged_entid(merges, e)).collect();
+        for e in survivors {
+            occupied.extend(datoms::datoms(self.store, DatomIndex::EAVT, &DatomPattern { e: Some(e), ..Default::default() })?
+                            .into_iter()
+                            .map(|datom| (datom.e, datom.a)));
+        }
+
+        let mut merge_terms = vec![];
+        let mut added: BTreeSet<(Entid, Entid, TypedValue)> = BTreeSet::new();
+        for datom in merged {
+            let e = merged_entid(merges, datom.e);
+            let v = match datom.v {
+                TypedValue::Ref(v) => TypedValue::Ref(merged_entid(merges, v)),
+                ref v => v.clone(),
+            };
+            merge_terms.push(Term::AddOrRetract(OpType::Retract, KnownEntid(datom.e), datom.a, datom.v));
+
+            let one = !self.schema.require_attribute_for_entid(datom.a)?.multival;
+            if one && (asserted.contains(&(e, datom.a)) || (e != datom.e && occupied.contains(&(e, datom.a)))) {
+                continue;
+            }
+            if e != datom.e {
+                occupied.insert((e, datom.a));
+            }
+            if added.insert((e, datom.a, v.clone())) {
+                merge_terms.push(Term::AddOrRetract(OpType::Add, KnownEntid(e), datom.a, v));
+            }
+        }
+        Ok(merge_terms)
+    }
+
+    /// Resolves the entity named by `:db/retractEntity` or `:db/retractAttribute`.  Such an entity
+    /// must already exist, so a lookup ref must resolve now; a tempid is left to upsert.
+    fn resolve_retracted_entity(&self, e: KnownEntidOr<LookupRefOrTempId>) -> Result<KnownEntidOr<TempIdHandle>> {
+        match e {
+            Either::Left(e) => Ok(Either::Left(e)),
+            Either::Right(LookupRefOrTempId::LookupRef(av)) => {
+                let av_map: AVMap = self.store.resolve_avs(&[&*av])?;
+                av_map.get(&*av)
+                      .map(|e| Either::Left(KnownEntid(*e)))
+                      .ok_or_else(|| ErrorKind::LookupRefNotFound((*av).clone()).into())
+            },
+            Either::Right(LookupRefOrTempId::TempId(tempid)) => Ok(Either::Right(tempid)),
+        }
+    }
+
+    /// The datoms that `[:db/retractAttribute e a]` retracts if `a` is given, and that
+    /// `[:db/retractEntity e]` retracts otherwise.
+    fn retracted_datoms(&self, e: KnownEntid, a: Option<Entid>) -> Result<BTreeSet<Datom>> {
+        match a {
+            Some(a) => Ok(datoms::datoms(self.store, DatomIndex::EAVT, &DatomPattern { e: Some(e.0), a: Some(a), ..Default::default() })?.into_iter().collect()),
+            None => self.retract_entity_datoms(e),
+        }
+    }
+
+    /// The datoms that `[:db/retractEntity e]` retracts: every datom about `e`, every datom that
+    /// refers to `e`, and the same for each entity that `e` refers to through a component attribute,
+    /// recursively.
+    fn retract_entity_datoms(&self, e: KnownEntid) -> Result<BTreeSet<Datom>> {
+        let mut retracted: BTreeSet<Datom> = BTreeSet::new();
+        let mut visited: BTreeSet<Entid> = BTreeSet::new();
+        let mut pending: Vec<Entid> = vec![e.0];
+        while let Some(e) = pending.pop() {
+            if !visited.insert(e) {
+                continue;
+            }
+            for datom in datoms::datoms(self.store, DatomIndex::EAVT, &DatomPattern { e: Some(e), ..Default::default() })? {
+                if let TypedValue::Ref(v) = datom.v {
+                    if self.schema.attribute_for_entid(datom.a).map_or(false, |attribute| attribute.component) {
+                        pending.push(v);
+                    }
+                }
+                retracted.insert(datom);
+            }
+            retracted.extend(datoms::datoms(self.store, DatomIndex::VAET, &DatomPattern { v: Some(TypedValue::Ref(e)), ..Default::default() })?);
+        }
+        Ok(retracted)
+    }
+
     /// Pipeline stage 1: convert `Entity` instances into `Term` instances, ready for term
     /// rewriting.
     ///
     /// The `Term` instances produce share interned TempId and LookupRef handles, and we return the
     /// interned handle sets so that consumers can ensure all handles are used appropriately.
-    fn entities_into_terms_with_temp_ids_and_lookup_refs<I>(&self, entities: I) -> Result<(Vec<TermWithTempIdsAndLookupRefs>, InternSet<TempId>, InternSet<AVPair>)> where I: IntoIterator<Item=Entity> {
+    ///
+    /// `:db/cas` entities become plain assertions, and their preconditions are returned separately.
+    /// So too are the partitions that tempids like `(tempid :my.part/name "t")` ask for, and the
+    /// `:db/retractEntity` and `:db/retractAttribute` entities that name tempids.
+    fn entities_into_terms_with_temp_ids_and_lookup_refs<I>(&self, entities: I) -> Result<(Vec<TermWithTempIdsAndLookupRefs>, Vec<CasCheck<KnownEntidOr<LookupRefOrTempId>>>, Vec<TempIdRetraction>, InternSet<TempId>, TempIdPartitions, InternSet<AVPair>)> where I: IntoIterator<Item=Entity> {
         struct InProcess<'a> {
             partition_map: &'a PartitionMap,
             schema: &'a Schema,
             mentat_id_count: i64,
             tx_id: KnownEntid,
             temp_ids: InternSet<TempId>,
+            temp_id_partitions: TempIdPartitions,
             lookup_refs: InternSet<AVPair>,
         }
 
@@ -229,6 +433,7 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                     mentat_id_count: 0,
                     tx_id,
                     temp_ids: InternSet::new(),
+                    temp_id_partitions: TempIdPartitions::default(),
                     lookup_refs: InternSet::new(),
                 }
             }
@@ -253,7 +458,8 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                 let lr_attribute: &Attribute = self.schema.require_attribute_for_entid(lr_a)?;
 
                 if lr_attribute.unique.is_none() {
-                    bail!(ErrorKind::NotYetImplemented(format!("Cannot resolve (lookup-ref {} {}) with attribute that is not :db/unique", lr_a, lookup_ref.v)))
+                    let name = self.schema.get_ident(lr_a).map_or_else(|| lr_a.to_string(), |ident| ident.to_string());
+                    bail!(ErrorKind::NonUniqueLookupRef(name, lookup_ref.v.to_string()))
                 }
 
                 let lr_typed_value: TypedValue = self.schema.to_typed_value(&lookup_ref.v, lr_attribute.value_type)?;
@@ -290,6 +496,11 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                         Ok(Either::Right(LookupRefOrTempId::TempId(self.intern_temp_id(e))))
                     },
 
+                    entmod::EntidOrLookupRefOrTempId::TempIdInPartition(e, partition) => {
+                        request_partition(&mut self.temp_id_partitions, &e, partition.to_string())?;
+                        Ok(Either::Right(LookupRefOrTempId::TempId(self.intern_temp_id(e))))
+                    },
+
                     entmod::EntidOrLookupRefOrTempId::LookupRef(ref lookup_ref) => {
                         Ok(Either::Right(LookupRefOrTempId::LookupRef(self.intern_lookup_ref(lookup_ref)?)))
                     },
@@ -340,11 +551,9 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                             entmod::AtomOrLookupRefOrVectorOrMapNotation::LookupRef(ref lookup_ref) =>
                                 Ok(Either::Right(LookupRefOrTempId::LookupRef(self.intern_lookup_ref(lookup_ref)?))),
 
-                            entmod::AtomOrLookupRefOrVectorOrMapNotation::Vector(_) =>
-                                bail!(ErrorKind::NotYetImplemented(format!("Cannot explode vector value in :attr/_reversed notation for attribute {}", forward_a))),
-
-                            entmod::AtomOrLookupRefOrVectorOrMapNotation::MapNotation(_) =>
-                                bail!(ErrorKind::NotYetImplemented(format!("Cannot explode map notation value in :attr/_reversed notation for attribute {}", forward_a))),
+                            // The caller explodes these into many entities.
+                            entmod::AtomOrLookupRefOrVectorOrMapNotation::Vector(_) |
+                            entmod::AtomOrLookupRefOrVectorOrMapNotation::MapNotation(_) => unreachable!(),
                         }
                     },
                 }
@@ -360,6 +569,13 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         deque.extend(entities);
 
         let mut terms: Vec<TermWithTempIdsAndLookupRefs> = Vec::with_capacity(deque.len());
+        let mut cas_checks: Vec<CasCheck<KnownEntidOr<LookupRefOrTempId>>> = vec![];
+        let mut retractions: Vec<TempIdRetraction> = vec![];
+
+        // For each transaction function call being expanded, the length the deque will shrink back
+        // to once the call's output (and everything that output explodes into) has been handled.
+        // Its length is the depth of nested calls.
+        let mut calls: Vec<usize> = vec![];
 
         while let Some(entity) = deque.pop_front() {
             match entity {
@@ -380,12 +596,133 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                     }
                 },
 
+                Entity::Cas { e, a, old, new } => {
+                    let a = in_process.entity_a_into_term_a(a)?;
+                    let attribute = self.schema.require_attribute_for_entid(a)?;
+                    if attribute.multival {
+                        bail!(ErrorKind::NotYetImplemented(format!("Cannot :db/cas attribute {} that is :db.cardinality/many", a)));
+                    }
+
+                    let old = if old.inner.is_nil() {
+                        None
+                    } else {
+                        Some(self.schema.to_typed_value(&old.without_spans(), attribute.value_type)?)
+                    };
+                    cas_checks.push(CasCheck {
+                        e: in_process.entity_e_into_term_e(e.clone())?,
+                        a: a,
+                        old: old,
+                    });
+
+                    // If the check passes, this is just an assertion.
+                    deque.push_front(Entity::AddOrRetract {
+                        op: OpType::Add,
+                        e: e,
+                        a: entmod::Entid::Entid(a),
+                        v: new,
+                    });
+                },
+
+                Entity::Call { function, args } => {
+                    let f = self.options.functions
+                                .and_then(|functions| functions.get(&function))
+                                .ok_or_else(|| ErrorKind::UnrecognizedTxFunction(function.to_string()))?;
+                    let context = TxFunctionContext::new(self.store, self.schema, self.tx_id);
+
+                    // Calls whose output has been used up don't enclose this one.
+                    let remaining = deque.len();
+                    while calls.last().map_or(false, |&end| end > remaining) {
+                        calls.pop();
+                    }
+                    if calls.len() >= MAX_TX_FUNCTION_DEPTH {
+                        bail!(ErrorKind::TxFunctionDepthExceeded(function.to_string(), MAX_TX_FUNCTION_DEPTH));
+                    }
+                    calls.push(remaining);
+
+                    // Transact the function's entities in its place, in the order it gave them.
+                    for entity in f.apply(&context, &args)?.into_iter().rev() {
+                        deque.push_front(entity);
+                    }
+                },
+
+                Entity::RetractAttribute { e, a } => {
+                    let e = in_process.entity_e_into_term_e(e)?;
+                    let e = self.resolve_retracted_entity(e)?;
+                    let a = in_process.entity_a_into_term_a(a)?;
+                    self.schema.require_attribute_for_entid(a)?;
+
+                    match e {
+                        Either::Left(e) => {
+                            for datom in self.retracted_datoms(e, Some(a))? {
+                                terms.push(Term::AddOrRetract(OpType::Retract, Either::Left(e), a, Either::Left(datom.v)));
+                            }
+                        },
+                        Either::Right(tempid) => retractions.push(TempIdRetraction { e: tempid, a: Some(a) }),
+                    }
+                },
+
+                Entity::RetractEntity { e } => {
+                    let e = in_process.entity_e_into_term_e(e)?;
+                    let e = self.resolve_retracted_entity(e)?;
+
+                    match e {
+                        Either::Left(e) => {
+                            for datom in self.retracted_datoms(e, None)? {
+                                terms.push(Term::AddOrRetract(OpType::Retract, Either::Left(KnownEntid(datom.e)), datom.a, Either::Left(datom.v)));
+                            }
+                        },
+                        Either::Right(tempid) => retractions.push(TempIdRetraction { e: tempid, a: None }),
+                    }
+                },
+
                 Entity::AddOrRetract { op, e, a, v } => {
                     if let Some(reversed_a) = a.unreversed() {
-                        let reversed_e = in_process.entity_v_into_term_e(v, &a)?;
-                        let reversed_a = in_process.entity_a_into_term_a(reversed_a)?;
-                        let reversed_v = in_process.entity_e_into_term_v(e)?;
-                        terms.push(Term::AddOrRetract(OpType::Add, reversed_e, reversed_a, reversed_v));
+                        match v {
+                            entmod::AtomOrLookupRefOrVectorOrMapNotation::Vector(vs) => {
+                                // Each value refers to `e`: asserted or retracted, like an atom.
+                                for vv in vs {
+                                    deque.push_front(Entity::AddOrRetract {
+                                        op: op.clone(),
+                                        e: e.clone(),
+                                        a: a.clone(),
+                                        v: vv,
+                                    });
+                                }
+                            },
+
+                            entmod::AtomOrLookupRefOrVectorOrMapNotation::MapNotation(mut map_notation) => {
+                                let forward_a = in_process.entity_a_into_term_a(reversed_a)?;
+                                if op != OpType::Add {
+                                    bail!(ErrorKind::NotYetImplemented(format!("Cannot explode nested map value in :db/retract for attribute {}", forward_a)));
+                                }
+                                if self.schema.require_attribute_for_entid(forward_a)?.value_type != ValueType::Ref {
+                                    bail!(ErrorKind::NotYetImplemented(format!("Cannot use :attr/_reversed notation for attribute {} that is not :db/valueType :db.type/ref", forward_a)))
+                                }
+
+                                // The nested map is an entity that refers to `e`.  It can always be
+                                // reached through that reference, so it can't be dangling.
+                                let db_id: entmod::EntidOrLookupRefOrTempId = mentat_tx_parser::remove_db_id(&mut map_notation)?.unwrap_or_else(|| in_process.allocate_mentat_id());
+                                let reversed_e = in_process.entity_e_into_term_e(db_id.clone())?;
+                                let reversed_v = in_process.entity_e_into_term_v(e)?;
+                                terms.push(Term::AddOrRetract(OpType::Add, reversed_e, forward_a, reversed_v));
+
+                                for (inner_a, inner_v) in map_notation {
+                                    deque.push_front(Entity::AddOrRetract {
+                                        op: OpType::Add,
+                                        e: db_id.clone(),
+                                        a: inner_a,
+                                        v: inner_v,
+                                    });
+                                }
+                            },
+
+                            v => {
+                                let reversed_e = in_process.entity_v_into_term_e(v, &a)?;
+                                let reversed_a = in_process.entity_a_into_term_a(reversed_a)?;
+                                let reversed_v = in_process.entity_e_into_term_v(e)?;
+                                terms.push(Term::AddOrRetract(op, reversed_e, reversed_a, reversed_v));
+                            },
+                        }
                     } else {
                         let a = in_process.entity_a_into_term_a(a)?;
                         let attribute = self.schema.require_attribute_for_entid(a)?;
@@ -394,6 +731,9 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                             entmod::AtomOrLookupRefOrVectorOrMapNotation::Atom(v) => {
                                 if attribute.value_type == ValueType::Ref && v.inner.is_text() {
                                     Either::Right(LookupRefOrTempId::TempId(in_process.intern_temp_id(v.inner.as_text().cloned().map(TempId::External).unwrap())))
+                                } else if attribute.value_type == ValueType::Ref && v.inner.as_namespaced_keyword().map_or(false, |k| k.namespace == "db" && k.name == "tx") {
+                                    // Special case: a reference to the current transaction.
+                                    Either::Left(TypedValue::Ref(self.tx_id))
                                 } else {
                                     // Here is where we do schema-aware typechecking: we either assert that
                                     // the given value is in the attribute's value set, or (in limited
@@ -460,19 +800,18 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                                 }
 
                                 for (inner_a, inner_v) in map_notation {
-                                    if let Some(reversed_a) = inner_a.unreversed() {
-                                        // We definitely have a reference.  The reference might be
-                                        // dangling (a bare entid, for example), but we don't yet
-                                        // support nested maps and reverse notation simultaneously
-                                        // (i.e., we don't accept {:reverse/_attribute {:nested map}})
-                                        // so we don't need to check that the nested map reference isn't
-                                        // dangling.
+                                    if inner_a.unreversed().is_some() {
+                                        // We definitely have a reference, from whatever entity the
+                                        // value names -- or from the nested map that it is -- to
+                                        // this one.  That makes this entity reachable.
                                         dangling = false;
 
-                                        let reversed_e = in_process.entity_v_into_term_e(inner_v, &inner_a)?;
-                                        let reversed_a = in_process.entity_a_into_term_a(reversed_a)?;
-                                        let reversed_v = in_process.entity_e_into_term_v(db_id.clone())?;
-                                        terms.push(Term::AddOrRetract(OpType::Add, reversed_e, reversed_a, reversed_v));
+                                        deque.push_front(Entity::AddOrRetract {
+                                            op: OpType::Add,
+                                            e: db_id.clone(),
+                                            a: inner_a,
+                                            v: inner_v,
+                                        });
                                     } else {
                                         let inner_a = in_process.entity_a_into_term_a(inner_a)?;
                                         let inner_attribute = self.schema.require_attribute_for_entid(inner_a)?;
@@ -503,7 +842,7 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                 },
             }
         };
-        Ok((terms, in_process.temp_ids, in_process.lookup_refs))
+        Ok((terms, cas_checks, retractions, in_process.temp_ids, in_process.temp_id_partitions, in_process.lookup_refs))
     }
 
     /// Pipeline stage 2: rewrite `Term` instances with lookup refs into `Term` instances without
@@ -530,23 +869,92 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
     where I: IntoIterator<Item=Entity>,
           W: TransactWatcher {
         // Pipeline stage 1: entities -> terms with tempids and lookup refs.
-        let (terms_with_temp_ids_and_lookup_refs, tempid_set, lookup_ref_set) = self.entities_into_terms_with_temp_ids_and_lookup_refs(entities)?;
+        let (terms_with_temp_ids_and_lookup_refs, cas_checks, retractions, tempid_set, temp_id_partitions, lookup_ref_set) = self.entities_into_terms_with_temp_ids_and_lookup_refs(entities)?;
 
         // Pipeline stage 2: resolve lookup refs -> terms with tempids.
         let lookup_ref_avs: Vec<&(i64, TypedValue)> = lookup_ref_set.inner.iter().map(|rc| &**rc).collect();
         let lookup_ref_map: AVMap = self.store.resolve_avs(&lookup_ref_avs[..])?;
 
         let terms_with_temp_ids = self.resolve_lookup_refs(&lookup_ref_map, terms_with_temp_ids_and_lookup_refs)?;
+        let cas_checks = cas_checks.into_iter().map(|check| -> Result<CasCheck<KnownEntidOr<TempIdHandle>>> {
+            Ok(CasCheck {
+                e: replace_lookup_ref(&lookup_ref_map, check.e, |x| KnownEntid(x))?,
+                a: check.a,
+                old: check.old,
+            })
+        }).collect::<Result<Vec<_>>>()?;
+
+        self.transact_terms_with_cas_checks(terms_with_temp_ids, tempid_set, temp_id_partitions, cas_checks, retractions)
+    }
+
+    pub fn transact_simple_terms<I>(&mut self, terms: I, tempid_set: InternSet<TempId>, temp_id_partitions: TempIdPartitions) -> Result<TxReport>
+    where I: IntoIterator<Item=TermWithTempIdsAndLookupRefs>,
+          W: TransactWatcher {
+        // Pipeline stage 2: resolve lookup refs -> terms with tempids.
+        let terms: Vec<TermWithTempIdsAndLookupRefs> = terms.into_iter().collect();
+        let lookup_refs: BTreeSet<LookupRef> = terms.iter().flat_map(lookup_refs_in_term).collect();
+        let terms = {
+            let lookup_ref_avs: Vec<&AVPair> = lookup_refs.iter().map(|rc| &**rc).collect();
+            let lookup_ref_map: AVMap = self.store.resolve_avs(&lookup_ref_avs[..])?;
+            self.resolve_lookup_refs(&lookup_ref_map, terms)?
+        };
 
-        self.transact_simple_terms(terms_with_temp_ids, tempid_set)
+        // Builders name the current transaction with `TempId::Tx`, which needs no resolving.
+        let mut tempid_set = tempid_set;
+        let tx_id = self.tx_id;
+        let terms: Vec<TermWithTempIds> = if tempid_set.inner.remove(&TempId::Tx) {
+            terms.into_iter().map(|term| term_with_tx_id(term, tx_id)).collect()
+        } else {
+            terms
+        };
+        self.transact_terms_with_cas_checks(terms, tempid_set, temp_id_partitions, vec![], vec![])
+    }
+
+    /// Fails with `CasFailed` unless `e` has the value `old` for `a`, or has no value if `old` is
+    /// `None`.  Nothing has been written yet, so this sees the store as it was before this
+    /// transaction.
+    fn check_cas(&self, e: KnownEntid, a: Entid, old: Option<TypedValue>) -> Result<()> {
+        let actual = datoms::datoms(self.store, DatomIndex::EAVT, &DatomPattern { e: Some(e.0), a: Some(a), ..Default::default() })?
+            .into_iter()
+            .next()
+            .map(|datom| datom.v);
+        if actual != old {
+            bail!(ErrorKind::CasFailed(e.0, a, old, actual));
+        }
+        Ok(())
+    }
+
+    /// Install the partition named by the `:db/ident` of `e`, allocating its entids from a fresh
+    /// range above every existing partition.  Installing an existing partition again does nothing.
+    fn install_partition(&mut self, e: Entid) -> Result<()> {
+        let name = self.schema_for_mutation.get_ident(e).ok_or(ErrorKind::UnnamedPartition(e))?.to_string();
+        if !self.partition_map.contains_key(&name) {
+            let start = next_partition_start(&self.partition_map);
+            self.partition_map.insert(name, Partition::new(start, start));
+        }
+        Ok(())
     }
 
-    pub fn transact_simple_terms<I>(&mut self, terms: I, tempid_set: InternSet<TempId>) -> Result<TxReport>
+    fn transact_terms_with_cas_checks<I>(&mut self, terms: I, tempid_set: InternSet<TempId>, temp_id_partitions: TempIdPartitions, cas_checks: Vec<CasCheck<KnownEntidOr<TempIdHandle>>>, retractions: Vec<TempIdRetraction>) -> Result<TxReport>
     where I: IntoIterator<Item=TermWithTempIds>,
           W: TransactWatcher {
+        // Partitions must be installed before tempids can ask to be allocated in them, and only
+        // the transactor allocates schema entities and transactions.
+        for partition in temp_id_partitions.values() {
+            if partition == ":db.part/db" || partition == ":db.part/tx" {
+                bail!(ErrorKind::ReservedPartition(partition.clone()));
+            }
+            if !self.partition_map.contains_key(partition) {
+                bail!(ErrorKind::UnrecognizedPartition(partition.clone()));
+            }
+        }
+
         // TODO: push these into an internal transaction report?
         let mut tempids: BTreeMap<TempId, KnownEntid> = BTreeMap::default();
 
+        // Entities that conflicting upserts merge, mapped to the entity each is merged into.
+        let mut merges: BTreeMap<Entid, Entid> = BTreeMap::default();
+
         // Pipeline stage 3: upsert tempids -> terms without tempids or lookup refs.
         // Now we can collect upsert populations.
         let (mut generation, inert_terms) = Generation::from(terms, &self.schema)?;
@@ -554,7 +962,7 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         // And evolve them forward.
         while generation.can_evolve() {
             // Evolve further.
-            let temp_id_map: TempIdMap = self.resolve_temp_id_avs(&generation.temp_id_avs()[..])?;
+            let temp_id_map: TempIdMap = self.resolve_temp_id_avs(&generation.temp_id_avs()[..], &mut merges)?;
             generation = generation.evolve_one_step(&temp_id_map);
 
             // Report each tempid that resolves via upsert.
@@ -568,7 +976,7 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                 // `e`) before we try to resolve the next set of `UpsertsE`.  That is, we'll never
                 // successfully upsert the same tempid in more than one generation step.  (We might
                 // upsert the same tempid to multiple entids via distinct `[a v]` pairs in a single
-                // generation step; in this case, the transaction will fail.)
+                // generation step; in this case, the transaction will fail or merge the entities.)
                 let previous = tempids.insert((*tempid).clone(), entid);
                 assert!(previous.is_none());
             }
@@ -577,14 +985,17 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         // Allocate entids for tempids that didn't upsert.  BTreeSet rather than HashSet so this is deterministic.
         let unresolved_temp_ids: BTreeSet<TempIdHandle> = generation.temp_ids_in_allocations();
 
-        // TODO: track partitions for temporary IDs.
-        let entids = self.partition_map.allocate_entids(":db.part/user", unresolved_temp_ids.len());
-
-        let temp_id_allocations: TempIdMap = unresolved_temp_ids.into_iter()
-                                                                .zip(entids.map(|e| KnownEntid(e)))
-                                                                .collect();
+        // Each is allocated in the partition it asked for, or in :db.part/user.
+        let mut temp_id_allocations: TempIdMap = TempIdMap::default();
+        for temp_id in unresolved_temp_ids {
+            let entid = match temp_id_partitions.get(&*temp_id) {
+                Some(partition) => self.partition_map.allocate_entid(partition.as_str())?,
+                None => self.partition_map.allocate_entid(":db.part/user")?,
+            };
+            temp_id_allocations.insert(temp_id, KnownEntid(entid));
+        }
 
-        let final_populations = generation.into_final_populations(&temp_id_allocations)?;
+        let final_populations = generation.into_final_populations(&temp_id_allocations, self.options.unresolved_retractions)?;
 
         // Report each tempid that is allocated.
         for (tempid, &entid) in &temp_id_allocations {
@@ -593,15 +1004,49 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             tempids.insert((**tempid).clone(), entid);
         }
 
-        // Verify that every tempid we interned either resolved or has been allocated.
-        assert_eq!(tempids.len(), tempid_set.inner.len());
+        // Expand retractions of whole entities and attributes named by tempids.  Like any other
+        // retraction, one whose tempid didn't upsert names nothing that exists.
+        let mut unresolved: BTreeSet<TempIdHandle> = final_populations.unresolved.clone();
+        let mut retraction_terms: Vec<TermWithoutTempIds> = vec![];
+        for retraction in retractions {
+            let e = tempids.get(&*retraction.e).cloned();
+            match e {
+                Some(e) => {
+                    if merges.contains_key(&e.0) || merges.values().any(|&into| into == e.0) {
+                        bail!(ErrorKind::NotYetImplemented(format!("Cannot retract entity or attribute of tempid '{}', which names a merged entity", retraction.e)));
+                    }
+                    for datom in self.retracted_datoms(e, retraction.a)? {
+                        retraction_terms.push(Term::AddOrRetract(OpType::Retract, KnownEntid(datom.e), datom.a, datom.v));
+                    }
+                },
+                None => {
+                    if self.options.unresolved_retractions == UnresolvedRetractions::Fail {
+                        bail!(ErrorKind::UnresolvedRetraction(retraction.e.to_string()));
+                    }
+                    unresolved.insert(retraction.e);
+                },
+            }
+        }
+
+        // Verify that every tempid we interned either resolved, has been allocated, or was only
+        // retracted.
+        assert_eq!(tempids.len() + unresolved.len(), tempid_set.inner.len());
         for tempid in &tempid_set.inner {
-            assert!(tempids.contains_key(&**tempid));
+            assert!(tempids.contains_key(&**tempid) || unresolved.contains(tempid));
+        }
+
+        // Now that every entity is known, check any :db/cas preconditions.
+        for check in cas_checks {
+            let e = match check.e {
+                Either::Left(e) => e,
+                Either::Right(tempid) => tempids[&*tempid],
+            };
+            self.check_cas(KnownEntid(merged_entid(&merges, e.0)), check.a, check.old)?;
         }
 
         // Any internal tempid has been allocated by the system and is a private implementation
         // detail; it shouldn't be exposed in the final transaction report.
-        let tempids = tempids.into_iter().filter_map(|(tempid, e)| tempid.into_external().map(|s| (s, e.0))).collect();
+        let tempids = tempids.into_iter().filter_map(|(tempid, e)| tempid.into_external().map(|s| (s, merged_entid(&merges, e.0)))).collect();
 
         // A transaction might try to add or retract :db/ident assertions or other metadata mutating
         // assertions , but those assertions might not make it to the store.  If we see a possible
@@ -610,9 +1055,27 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         // store.
         let mut tx_might_update_metadata = false;
 
-        let final_terms: Vec<TermWithoutTempIds> = [final_populations.resolved,
-                                                    final_populations.allocated,
-                                                    inert_terms.into_iter().map(|term| term.unwrap()).collect()].concat();
+        // Entities asserted with [:db/add :db.part/db :db.install/partition e].  They're installed
+        // once the metadata is updated, since their :db/ident might be asserted alongside.
+        let mut installed_partitions: Vec<Entid> = vec![];
+
+        // What the transaction asserts about itself, other than its :db/txInstant.
+        let mut tx_metadata: BTreeMap<Entid, Vec<TypedValue>> = BTreeMap::default();
+
+        let mut final_terms: Vec<TermWithoutTempIds> = [final_populations.resolved,
+                                                        final_populations.allocated,
+                                                        inert_terms.into_iter().map(|term| term.unwrap()).collect(),
+                                                        retraction_terms].concat();
+
+        if !merges.is_empty() {
+            // Upserts usually assert what's already true, but the surviving entity of a merge
+            // might not have every upserted value yet.  Everything the transaction says about a
+            // merged entity is said about the surviving entity instead.
+            final_terms.extend(final_populations.upserted);
+            final_terms = final_terms.into_iter().map(|term| merged_term(&merges, term)).collect();
+            let merge_terms = self.merge_terms(&merges, &final_terms)?;
+            final_terms.extend(merge_terms);
+        }
 
         let tx_instant;
 
@@ -646,6 +1109,15 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
 
                     let added = op == OpType::Add;
 
+                    if added && a == entids::DB_INSTALL_PARTITION {
+                        if e != entids::DB_PART_DB {
+                            bail!(ErrorKind::BadSchemaAssertion(format!("Partitions are installed with [:db.part/db :db.install/partition ...], not on entity {}", e)));
+                        }
+                        if let TypedValue::Ref(partition) = v {
+                            installed_partitions.push(partition);
+                        }
+                    }
+
                     // We take the last encountered :db/txInstant value.
                     // If more than one is provided, the transactor will fail.
                     if added &&
@@ -668,6 +1140,13 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
                         }
                     }
 
+                    if added && e == self.tx_id {
+                        let values = tx_metadata.entry(a).or_insert_with(Vec::new);
+                        if !values.contains(&v) {
+                            values.push(v.clone());
+                        }
+                    }
+
                     self.watcher.datom(op, e, a, &v);
 
                     let reduced = (e, a, attribute, v, added);
@@ -709,7 +1188,6 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
         self.store.commit_transaction(self.tx_id)?;
         }
 
-        db::update_partition_map(self.store, &self.partition_map)?;
         self.watcher.done(self.schema)?;
 
         if tx_might_update_metadata {
@@ -727,18 +1205,98 @@ impl<'conn, 'a, W> Tx<'conn, 'a, W> where W: TransactWatcher {
             if new_schema != *self.schema_for_mutation {
                 let old_schema = (*self.schema_for_mutation).clone(); // Clone the original Schema for comparison.
                 *self.schema_for_mutation.to_mut() = new_schema; // Store the new Schema.
-                db::update_metadata(self.store, &old_schema, &*self.schema_for_mutation, &metadata_report)?;
+                if self.options.defer_metadata_views {
+                    db::update_metadata_datoms(self.store, &old_schema, &*self.schema_for_mutation, &metadata_report)?;
+                } else {
+                    db::update_metadata(self.store, &old_schema, &*self.schema_for_mutation, &metadata_report)?;
+                }
             }
         }
 
+        for partition in installed_partitions {
+            self.install_partition(partition)?;
+        }
+        db::update_partition_map(self.store, &self.partition_map)?;
+
         Ok(TxReport {
             tx_id: self.tx_id,
             tx_instant,
             tempids: tempids,
+            tx_metadata: tx_metadata,
         })
     }
 }
 
+/// The first multiple of `PARTITION_SIZE` above every entid allocated in `partition_map`.
+pub fn next_partition_start(partition_map: &PartitionMap) -> i64 {
+    let last = partition_map.values().map(|partition| partition.index).max().unwrap_or(0);
+    (last / PARTITION_SIZE + 1) * PARTITION_SIZE
+}
+
+/// Records that the entities `e` and `f` are merged, into whichever of them -- or of the entities
+/// they are already merged into -- has the lowest entid.
+fn merge_entids(merges: &mut BTreeMap<Entid, Entid>, e: Entid, f: Entid) {
+    let (e, f) = (merged_entid(merges, e), merged_entid(merges, f));
+    if e != f {
+        merges.insert(cmp::max(e, f), cmp::min(e, f));
+    }
+}
+
+/// The entity that `e` is merged into, or `e` itself.
+fn merged_entid(merges: &BTreeMap<Entid, Entid>, e: Entid) -> Entid {
+    let mut e = e;
+    while let Some(&into) = merges.get(&e) {
+        e = into;
+    }
+    e
+}
+
+/// The lookup refs that `term` names in its entity or value position.
+fn lookup_refs_in_term(term: &TermWithTempIdsAndLookupRefs) -> Vec<LookupRef> {
+    match term {
+        &Term::AddOrRetract(_, ref e, _, ref v) => {
+            let mut lookup_refs = vec![];
+            if let &Either::Right(LookupRefOrTempId::LookupRef(ref lookup_ref)) = e {
+                lookup_refs.push(lookup_ref.clone());
+            }
+            if let &Either::Right(LookupRefOrTempId::LookupRef(ref lookup_ref)) = v {
+                lookup_refs.push(lookup_ref.clone());
+            }
+            lookup_refs
+        },
+    }
+}
+
+/// `term`, with any `TempId::Tx` handle replaced by the current transaction `tx_id`.
+fn term_with_tx_id(term: TermWithTempIds, tx_id: Entid) -> TermWithTempIds {
+    match term {
+        Term::AddOrRetract(op, e, a, v) => {
+            let e = match e {
+                Either::Right(ref temp_id) if **temp_id == TempId::Tx => Either::Left(KnownEntid(tx_id)),
+                e => e,
+            };
+            let v = match v {
+                Either::Right(ref temp_id) if **temp_id == TempId::Tx => Either::Left(TypedValue::Ref(tx_id)),
+                v => v,
+            };
+            Term::AddOrRetract(op, e, a, v)
+        },
+    }
+}
+
+/// `term`, about and referring to the entities that merged entities are merged into.
+fn merged_term(merges: &BTreeMap<Entid, Entid>, term: TermWithoutTempIds) -> TermWithoutTempIds {
+    match term {
+        Term::AddOrRetract(op, KnownEntid(e), a, v) => {
+            let v = match v {
+                TypedValue::Ref(v) => TypedValue::Ref(merged_entid(merges, v)),
+                v => v,
+            };
+            Term::AddOrRetract(op, KnownEntid(merged_entid(merges, e)), a, v)
+        },
+    }
+}
+
 /// Initialize a new Tx object with a new tx id and a tx instant. Kick off the SQLite conn, too.
 fn start_tx<'conn, 'a, W>(conn: &'conn rusqlite::Connection,
                        mut partition_map: PartitionMap,
@@ -746,7 +1304,7 @@ fn start_tx<'conn, 'a, W>(conn: &'conn rusqlite::Connection,
                        schema: &'a Schema,
                        watcher: W) -> Result<Tx<'conn, 'a, W>>
     where W: TransactWatcher {
-    let tx_id = partition_map.allocate_entid(":db.part/tx");
+    let tx_id = partition_map.allocate_entid(":db.part/tx")?;
 
     conn.begin_tx_application()?;
 
@@ -783,6 +1341,22 @@ pub fn transact<'conn, 'a, I, W>(conn: &'conn rusqlite::Connection,
     conclude_tx(tx, report)
 }
 
+/// Just like `transact`, but with the given `options`.
+pub fn transact_with_options<'conn, 'a, I, W>(conn: &'conn rusqlite::Connection,
+                                              partition_map: PartitionMap,
+                                              schema_for_mutation: &'a Schema,
+                                              schema: &'a Schema,
+                                              watcher: W,
+                                              options: TxOptions<'a>,
+                                              entities: I) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
+    where I: IntoIterator<Item=Entity>,
+          W: TransactWatcher {
+
+    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher)?.with_options(options);
+    let report = tx.transact_entities(entities)?;
+    conclude_tx(tx, report)
+}
+
 /// Just like `transact`, but accepts lower-level inputs to allow bypassing the parser interface.
 pub fn transact_terms<'conn, 'a, I, W>(conn: &'conn rusqlite::Connection,
                                        partition_map: PartitionMap,
@@ -790,11 +1364,30 @@ pub fn transact_terms<'conn, 'a, I, W>(conn: &'conn rusqlite::Connection,
                                        schema: &'a Schema,
                                        watcher: W,
                                        terms: I,
-                                       tempid_set: InternSet<TempId>) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
-    where I: IntoIterator<Item=TermWithTempIds>,
+                                       tempid_set: InternSet<TempId>,
+                                       temp_id_partitions: TempIdPartitions) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
+    where I: IntoIterator<Item=TermWithTempIdsAndLookupRefs>,
           W: TransactWatcher {
 
     let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher)?;
-    let report = tx.transact_simple_terms(terms, tempid_set)?;
+    let report = tx.transact_simple_terms(terms, tempid_set, temp_id_partitions)?;
+    conclude_tx(tx, report)
+}
+
+/// Just like `transact_terms`, but with the given `options`.
+pub fn transact_terms_with_options<'conn, 'a, I, W>(conn: &'conn rusqlite::Connection,
+                                                    partition_map: PartitionMap,
+                                                    schema_for_mutation: &'a Schema,
+                                                    schema: &'a Schema,
+                                                    watcher: W,
+                                                    options: TxOptions<'a>,
+                                                    terms: I,
+                                                    tempid_set: InternSet<TempId>,
+                                                    temp_id_partitions: TempIdPartitions) -> Result<(TxReport, PartitionMap, Option<Schema>, W)>
+    where I: IntoIterator<Item=TermWithTempIdsAndLookupRefs>,
+          W: TransactWatcher {
+
+    let mut tx = start_tx(conn, partition_map, schema_for_mutation, schema, watcher)?.with_options(options);
+    let report = tx.transact_simple_terms(terms, tempid_set, temp_id_partitions)?;
     conclude_tx(tx, report)
 }
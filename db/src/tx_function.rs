@@ -0,0 +1,118 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Transaction functions run inside the transactor.
+//!
+//! A transaction function is registered under a name like `:counter/increment`.  Transacting the
+//! entity `[:counter/increment 65536 :counter/value]` calls the function with the arguments
+//! `65536` and `:counter/value` and transacts the entities it returns in its place, all within the
+//! one SQLite transaction.  Functions see the store as it was before the current transaction, so
+//! reading a value and writing a new one based on it is atomic.
+//!
+//! That also means a function doesn't see what the rest of the transaction asserts, including the
+//! output of other calls: two `[:counter/increment 65536 :counter/value]` calls in one transaction
+//! both read the same value and both assert one more than it, so the counter goes up by one, not
+//! two.  Call such a function once per entity and attribute in each transaction.
+//!
+//! The `:db` namespace is reserved for the transactor's own operations.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use edn;
+use edn::NamespacedKeyword;
+use rusqlite;
+
+use mentat_core::{
+    Entid,
+    HasSchema,
+    Schema,
+    TypedValue,
+};
+use mentat_tx::entities::Entity;
+
+use datoms::{
+    self,
+    Datom,
+    DatomIndex,
+    DatomPattern,
+};
+use errors::{
+    ErrorKind,
+    Result,
+};
+
+/// How deeply transaction functions may call each other: the entities a function returns may
+/// themselves be calls, but only this many levels down.
+pub const MAX_TX_FUNCTION_DEPTH: usize = 64;
+
+/// A read view of the store, for a transaction function, as it was before the transaction began.
+pub struct TxFunctionContext<'a> {
+    sqlite: &'a rusqlite::Connection,
+    schema: &'a Schema,
+    tx_id: Entid,
+}
+
+impl<'a> TxFunctionContext<'a> {
+    pub(crate) fn new(sqlite: &'a rusqlite::Connection, schema: &'a Schema, tx_id: Entid) -> TxFunctionContext<'a> {
+        TxFunctionContext {
+            sqlite: sqlite,
+            schema: schema,
+            tx_id: tx_id,
+        }
+    }
+
+    /// The SQLite transaction being applied, for running queries.
+    pub fn sqlite(&self) -> &rusqlite::Connection {
+        self.sqlite
+    }
+
+    pub fn schema(&self) -> &Schema {
+        self.schema
+    }
+
+    /// The entid of the transaction being applied.
+    pub fn tx_id(&self) -> Entid {
+        self.tx_id
+    }
+
+    /// The datoms in `index` that match `pattern`.
+    pub fn datoms(&self, index: DatomIndex, pattern: &DatomPattern) -> Result<Vec<Datom>> {
+        datoms::datoms(self.sqlite, index, pattern)
+    }
+
+    /// Every value of the attribute `a` on the entity `e`.
+    pub fn values_for(&self, e: Entid, a: &NamespacedKeyword) -> Result<Vec<TypedValue>> {
+        let a: Entid = self.schema.get_entid(a).ok_or_else(|| ErrorKind::UnrecognizedIdent(a.to_string()))?.into();
+        Ok(self.datoms(DatomIndex::EAVT, &DatomPattern { e: Some(e), a: Some(a), ..Default::default() })?
+               .into_iter()
+               .map(|datom| datom.v)
+               .collect())
+    }
+
+    /// The value of the cardinality-one attribute `a` on the entity `e`, if it has one.
+    pub fn value_for(&self, e: Entid, a: &NamespacedKeyword) -> Result<Option<TypedValue>> {
+        Ok(self.values_for(e, a)?.into_iter().next())
+    }
+}
+
+/// A function that expands into the entities to transact in its place.
+pub trait TxFunction: Send + Sync {
+    fn apply(&self, context: &TxFunctionContext, args: &[edn::Value]) -> Result<Vec<Entity>>;
+}
+
+impl<F> TxFunction for F where F: Fn(&TxFunctionContext, &[edn::Value]) -> Result<Vec<Entity>> + Send + Sync {
+    fn apply(&self, context: &TxFunctionContext, args: &[edn::Value]) -> Result<Vec<Entity>> {
+        (*self)(context, args)
+    }
+}
+
+/// The transaction functions available to a transaction, by name.
+pub type TxFunctions = BTreeMap<NamespacedKeyword, Arc<TxFunction>>;
@@ -47,6 +47,10 @@ impl Partition {
 }
 
 /// Map partition names to `Partition` instances.
+///
+/// Partitions are entities, installed with `:db.install/partition`, but they're keyed here -- and
+/// in the `parts` table, in exports, and in tempid partition requests -- by their `:db/ident`.
+/// Keying by entid would mean migrating the `parts` table, so it waits for a SQL schema change.
 pub type PartitionMap = BTreeMap<String, Partition>;
 
 /// Represents the metadata required to query from, or apply transactions to, a Mentat store.
@@ -95,6 +99,51 @@ pub struct TxReport {
     ///
     /// Every string literal tempid presented to the transactor either resolves via upsert to an
     /// existing entid, or is allocated a new entid.  (It is possible for multiple distinct string
-    /// literal tempids to all unify to a single freshly allocated entid.)
+    /// literal tempids to all unify to a single freshly allocated entid.)  The exception is a tempid
+    /// that only appears in ignored `[:db/retract ...]` entities; see `UnresolvedRetractions`.
     pub tempids: BTreeMap<String, Entid>,
+
+    /// The values the transaction asserted about its own entity, like `[:db/add :db/tx a v]`, by
+    /// attribute.  This is provenance like an author or a reason; `:db/txInstant` is reported as
+    /// `tx_instant` instead.
+    pub tx_metadata: BTreeMap<Entid, Vec<TypedValue>>,
+}
+
+/// What the transactor does with a `[:db/retract ...]` entity that references a tempid that didn't
+/// upsert.  Such a tempid names an entity that doesn't exist yet, so there is nothing to retract.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub enum UnresolvedRetractions {
+    /// Drop the retraction, as if it had retracted a datom that isn't present.
+    Ignore,
+
+    /// Fail the transaction with `ErrorKind::UnresolvedRetraction`.
+    Fail,
+}
+
+impl Default for UnresolvedRetractions {
+    fn default() -> UnresolvedRetractions {
+        UnresolvedRetractions::Ignore
+    }
+}
+
+/// What the transactor does when a tempid upserts to more than one entity: for example, when
+/// `[[:db/add "t" :person/email "a@example.com"] [:db/add "t" :person/phone "555-1234"]]` names
+/// one existing person by email and another by phone.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
+pub enum UpsertConflicts {
+    /// Fail the transaction with `ErrorKind::ConflictingUpsert`.
+    Fail,
+
+    /// Merge the entities into the one with the lowest entid, which the tempid then names.  The
+    /// other entities' datoms, and datoms that refer to them, are moved to the surviving entity.
+    /// Where both have a value for a `:db.cardinality/one` attribute, the transaction's value wins,
+    /// then the surviving entity's.  Entities in `:db.part/db` or with a `:db/ident` are never
+    /// merged: the transaction fails with `ErrorKind::UnmergeableEntity` instead.
+    Merge,
+}
+
+impl Default for UpsertConflicts {
+    fn default() -> UpsertConflicts {
+        UpsertConflicts::Fail
+    }
 }
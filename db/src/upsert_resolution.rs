@@ -19,6 +19,7 @@ use errors;
 use errors::ErrorKind;
 use types::{
     AVPair,
+    UnresolvedRetractions,
 };
 use internal_types::{
     Population,
@@ -88,6 +89,10 @@ pub(crate) struct FinalPopulations {
 
     /// Allocations that required new entid allocations.
     pub allocated: Vec<TermWithoutTempIds>,
+
+    /// Tempids referenced only by `[:db/retract ...]` entities that were ignored because the
+    /// tempids didn't upsert.
+    pub unresolved: BTreeSet<TempIdHandle>,
 }
 
 impl Generation {
@@ -241,7 +246,10 @@ impl Generation {
 
     /// After evolution is complete, use the provided allocated entids to segment `self` into
     /// populations, each with no references to tempids.
-    pub(crate) fn into_final_populations(self, temp_id_map: &TempIdMap) -> errors::Result<FinalPopulations> {
+    ///
+    /// `[:db/retract ...]` entities that reference tempids that didn't upsert are dropped or fail,
+    /// according to `unresolved_retractions`.
+    pub(crate) fn into_final_populations(self, temp_id_map: &TempIdMap, unresolved_retractions: UnresolvedRetractions) -> errors::Result<FinalPopulations> {
         assert!(self.upserts_e.is_empty());
         assert!(self.upserts_ev.is_empty());
 
@@ -251,27 +259,34 @@ impl Generation {
         populations.resolved = self.resolved;
 
         for term in self.allocations {
+            // A retraction that references a tempid that didn't upsert can't match any datom.
+            let unresolved = unresolved_retracted_temp_ids(&term, temp_id_map);
+            if !unresolved.is_empty() {
+                if unresolved_retractions == UnresolvedRetractions::Fail {
+                    bail!(ErrorKind::UnresolvedRetraction(unresolved[0].to_string()));
+                }
+                populations.unresolved.extend(unresolved);
+                continue;
+            }
+
             let allocated = match term {
                 // TODO: consider require implementing require on temp_id_map.
                 Term::AddOrRetract(op, Right(t1), a, Right(t2)) => {
                     match (op, temp_id_map.get(&*t1), temp_id_map.get(&*t2)) {
                         (op, Some(&n1), Some(&n2)) => Term::AddOrRetract(op, n1, a, TypedValue::Ref(n2.0)),
-                        (OpType::Add, _, _) => unreachable!(), // This is a coding error -- every tempid in a :db/add entity should resolve or be allocated.
-                        (OpType::Retract, _, _) => bail!(ErrorKind::NotYetImplemented(format!("[:db/retract ...] entity referenced tempid that did not upsert: one of {}, {}", t1, t2))),
+                        _ => unreachable!(), // This is a coding error -- every tempid should resolve, be allocated, or be unresolved above.
                     }
                 },
                 Term::AddOrRetract(op, Right(t), a, Left(v)) => {
                     match (op, temp_id_map.get(&*t)) {
                         (op, Some(&n)) => Term::AddOrRetract(op, n, a, v),
-                        (OpType::Add, _) => unreachable!(), // This is a coding error.
-                        (OpType::Retract, _) => bail!(ErrorKind::NotYetImplemented(format!("[:db/retract ...] entity referenced tempid that did not upsert: {}", t))),
+                        _ => unreachable!(), // This is a coding error.
                     }
                 },
                 Term::AddOrRetract(op, Left(e), a, Right(t)) => {
                     match (op, temp_id_map.get(&*t)) {
                         (op, Some(&n)) => Term::AddOrRetract(op, e, a, TypedValue::Ref(n.0)),
-                        (OpType::Add, _) => unreachable!(), // This is a coding error.
-                        (OpType::Retract, _) => bail!(ErrorKind::NotYetImplemented(format!("[:db/retract ...] entity referenced tempid that did not upsert: {}", t))),
+                        _ => unreachable!(), // This is a coding error.
                     }
                 },
                 Term::AddOrRetract(_, Left(_), _, Left(_)) => unreachable!(), // This is a coding error -- these should not be in allocations.
@@ -282,3 +297,21 @@ impl Generation {
         Ok(populations)
     }
 }
+
+/// The tempids referenced by a `[:db/retract ...]` term that are not in `temp_id_map`.
+fn unresolved_retracted_temp_ids(term: &TermWithTempIds, temp_id_map: &TempIdMap) -> Vec<TempIdHandle> {
+    let mut unresolved = vec![];
+    if let &Term::AddOrRetract(OpType::Retract, ref e, _, ref v) = term {
+        if let &Right(ref t) = e {
+            if !temp_id_map.contains_key(t) {
+                unresolved.push(t.clone());
+            }
+        }
+        if let &Right(ref t) = v {
+            if !temp_id_map.contains_key(t) {
+                unresolved.push(t.clone());
+            }
+        }
+    }
+    unresolved
+}
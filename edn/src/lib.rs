@@ -16,8 +16,12 @@ extern crate pretty;
 extern crate uuid;
 
 #[cfg(feature = "serde_support")]
+#[macro_use]
 extern crate serde;
 
+#[cfg(feature = "serde_support")]
+extern crate serde_json;
+
 #[cfg(feature = "serde_support")]
 #[macro_use]
 extern crate serde_derive;
@@ -27,6 +31,11 @@ pub mod types;
 pub mod pretty_print;
 pub mod utils;
 pub mod matcher;
+pub mod tags;
+pub mod reader;
+
+#[cfg(feature = "serde_support")]
+pub mod serde_support;
 
 pub mod parse {
     include!(concat!(env!("OUT_DIR"), "/edn.rs"));
@@ -98,6 +98,8 @@ impl<'a> Matcher<'a> {
                     v.len() == p.len() &&
                     v.iter().all(|a| p.iter().any(|b| self.match_internal::<T>(a.0, b.0) && self.match_internal::<T>(a.1, b.1))) &&
                     p.iter().all(|b| v.iter().any(|a| self.match_internal::<T>(a.0, b.0) && self.match_internal::<T>(a.1, b.1))),
+                (&Tagged(ref vt, ref v), &Tagged(ref pt, ref p)) =>
+                    vt == pt && self.match_internal::<T>(v, p),
                 _ => value == pattern
             }
         }
@@ -0,0 +1,449 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Incremental reading of EDN from an `io::Read`.
+//!
+//! `parse::value` needs the whole input in memory, and builds the whole value at once. `Reader`
+//! instead scans its input for the boundaries of one form at a time, and only holds that form's
+//! text and value in memory. That makes it suitable for very large inputs, like a transaction
+//! that asserts millions of datoms.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{self, BufRead, BufReader};
+use std::str;
+
+use parse::{self, ParseError};
+use types::{Span, SpannedValue, ValueAndSpan};
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+
+    /// The form starting at `offset` isn't valid UTF-8.
+    InvalidUtf8 { offset: usize },
+
+    /// The input ended in the middle of a form, or before the end of the top-level vector.
+    UnexpectedEof { offset: usize },
+
+    /// A byte that can't appear at `offset`: a closing delimiter with no opening delimiter, a
+    /// form other than a vector when reading vector elements, or anything after that vector.
+    Unexpected { offset: usize, found: u8 },
+
+    /// The form starting at `offset` isn't valid EDN. The error's own position is relative to
+    /// the start of the form.
+    Parse { offset: usize, error: ParseError },
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        match *self {
+            ReadError::Io(ref e) => write!(f, "couldn't read EDN: {}", e),
+            ReadError::InvalidUtf8 { offset } => write!(f, "invalid UTF-8 in form at byte {}", offset),
+            ReadError::UnexpectedEof { offset } => write!(f, "unexpected end of input at byte {}", offset),
+            ReadError::Unexpected { offset, found } => write!(f, "unexpected '{}' at byte {}", (found as char).escape_default(), offset),
+            ReadError::Parse { offset, ref error } => write!(f, "couldn't parse form at byte {}: {}", offset, error),
+        }
+    }
+}
+
+impl Error for ReadError {
+    fn description(&self) -> &str {
+        match *self {
+            ReadError::Io(_) => "I/O error reading EDN",
+            ReadError::InvalidUtf8 { .. } => "invalid UTF-8",
+            ReadError::UnexpectedEof { .. } => "unexpected end of input",
+            ReadError::Unexpected { .. } => "unexpected character",
+            ReadError::Parse { .. } => "invalid EDN",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ReadError::Io(ref e) => Some(e),
+            ReadError::Parse { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(error: io::Error) -> ReadError {
+        ReadError::Io(error)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    /// Reading a sequence of top-level forms.
+    Forms,
+    /// Reading the elements of a single top-level vector, whose `[` we haven't seen yet.
+    BeforeVector,
+    /// Reading the elements of a single top-level vector.
+    InVector,
+    Done,
+}
+
+/// Reads EDN forms one at a time from an `io::Read`.
+///
+/// Each form is yielded with spans that are byte offsets into the whole input. Spans are `u32`s,
+/// so they wrap for inputs larger than 4GiB.
+///
+/// ```rust
+/// # use edn::reader::Reader;
+/// # use edn::Value;
+/// let input = "[:db/add 1 :db/doc \"one\"] ; a comment\n {:db/id 2}".as_bytes();
+/// let forms: Vec<Value> = Reader::new(input).map(|form| form.unwrap().without_spans()).collect();
+/// assert_eq!(forms.len(), 2);
+/// assert!(forms[0].is_vector());
+/// assert!(forms[1].is_map());
+/// ```
+pub struct Reader<R> {
+    input: BufReader<R>,
+    /// The number of bytes consumed from `input`.
+    offset: usize,
+    /// The text of the form being read.
+    form: Vec<u8>,
+    state: State,
+}
+
+fn is_whitespace(b: u8) -> bool {
+    match b {
+        b' ' | b'\t' | b'\r' | b'\n' | b',' => true,
+        _ => false,
+    }
+}
+
+fn is_delimiter(b: u8) -> bool {
+    match b {
+        b'"' | b';' | b'(' | b')' | b'[' | b']' | b'{' | b'}' => true,
+        b => is_whitespace(b),
+    }
+}
+
+/// Move every span in `value` `by` bytes to the right.
+fn shifted(value: ValueAndSpan, by: u32) -> ValueAndSpan {
+    let shift = |v| shifted(v, by);
+    let inner = match value.inner {
+        SpannedValue::Vector(vs) => SpannedValue::Vector(vs.into_iter().map(shift).collect()),
+        SpannedValue::List(vs) => SpannedValue::List(vs.into_iter().map(shift).collect()),
+        SpannedValue::Set(vs) => SpannedValue::Set(vs.into_iter().map(shift).collect()),
+        SpannedValue::Map(vs) => SpannedValue::Map(vs.into_iter().map(|(k, v)| (shift(k), shift(v))).collect()),
+        SpannedValue::Tagged(tag, v) => SpannedValue::Tagged(tag, Box::new(shift(*v))),
+        v => v,
+    };
+    ValueAndSpan {
+        inner: inner,
+        span: Span(value.span.0.wrapping_add(by), value.span.1.wrapping_add(by)),
+    }
+}
+
+impl<R> Reader<R> where R: io::Read {
+    /// Read each top-level form in `input`.
+    pub fn new(input: R) -> Reader<R> {
+        Reader::with_state(input, State::Forms)
+    }
+
+    /// Read each element of the single vector that makes up `input`, as in a transaction file.
+    pub fn vector_elements(input: R) -> Reader<R> {
+        Reader::with_state(input, State::BeforeVector)
+    }
+
+    fn with_state(input: R, state: State) -> Reader<R> {
+        Reader {
+            input: BufReader::new(input),
+            offset: 0,
+            form: Vec::new(),
+            state: state,
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, ReadError> {
+        Ok(self.input.fill_buf()?.first().cloned())
+    }
+
+    /// Consume the next byte, without adding it to the current form.
+    fn skip(&mut self) -> Result<Option<u8>, ReadError> {
+        let next = self.peek()?;
+        if next.is_some() {
+            self.input.consume(1);
+            self.offset += 1;
+        }
+        Ok(next)
+    }
+
+    /// Consume the next byte and add it to the current form.
+    fn take(&mut self) -> Result<u8, ReadError> {
+        match self.skip()? {
+            Some(b) => {
+                self.form.push(b);
+                Ok(b)
+            },
+            None => Err(ReadError::UnexpectedEof { offset: self.offset }),
+        }
+    }
+
+    /// Consume whitespace and comments, adding them to the current form if `keep` is set.
+    fn skip_whitespace(&mut self, keep: bool) -> Result<(), ReadError> {
+        let mut in_comment = false;
+        while let Some(b) = self.peek()? {
+            if in_comment {
+                in_comment = b != b'\n' && b != b'\r';
+            } else if b == b';' {
+                in_comment = true;
+            } else if !is_whitespace(b) {
+                break;
+            }
+            if keep {
+                self.take()?;
+            } else {
+                self.skip()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add bytes to the current form up to the next delimiter.
+    fn take_token(&mut self) -> Result<(), ReadError> {
+        while let Some(b) = self.peek()? {
+            if is_delimiter(b) {
+                break;
+            }
+            self.take()?;
+        }
+        Ok(())
+    }
+
+    /// Add the rest of a string, whose opening quote has been taken, to the current form.
+    fn take_string(&mut self) -> Result<(), ReadError> {
+        loop {
+            match self.take()? {
+                b'"' => return Ok(()),
+                b'\\' => { self.take()?; },
+                _ => {},
+            }
+        }
+    }
+
+    /// Add the rest of a collection, whose opening delimiter has been taken, to the current form.
+    /// Mismatched delimiters are left for the parser to reject.
+    fn take_collection(&mut self) -> Result<(), ReadError> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.take()? {
+                b'"' => self.take_string()?,
+                b'\\' => { self.take()?; },
+                b';' => {
+                    while let Some(b) = self.peek()? {
+                        if b == b'\n' || b == b'\r' {
+                            break;
+                        }
+                        self.take()?;
+                    }
+                },
+                b'(' | b'[' | b'{' => depth += 1,
+                b')' | b']' | b'}' => depth -= 1,
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the next form to the current form, which must start at a form. Returns `false` if the
+    /// only thing before the end of the input, or of the enclosing vector, was discarded with `#_`.
+    fn take_form(&mut self) -> Result<bool, ReadError> {
+        let offset = self.offset;
+        match self.peek()? {
+            None => return Err(ReadError::UnexpectedEof { offset: offset }),
+            Some(found @ b')') | Some(found @ b']') | Some(found @ b'}') =>
+                return Err(ReadError::Unexpected { offset: offset, found: found }),
+            _ => {},
+        }
+
+        match self.take()? {
+            b'"' => self.take_string()?,
+            b'(' | b'[' | b'{' => self.take_collection()?,
+            b'\\' => {
+                self.take()?;
+                self.take_token()?;
+            },
+            b'#' => match self.peek()? {
+                Some(b'{') => {
+                    self.take()?;
+                    self.take_collection()?;
+                },
+                Some(b'_') => {
+                    self.take()?;
+                    self.skip_whitespace(true)?;
+                    self.take_form()?;
+                    self.skip_whitespace(true)?;
+                    match self.peek()? {
+                        None | Some(b')') | Some(b']') | Some(b'}') => return Ok(false),
+                        _ => return self.take_form(),
+                    }
+                },
+                _ => {
+                    // A tag, like #inst or #myapp/Person, and the element it tags.
+                    self.take_token()?;
+                    self.skip_whitespace(true)?;
+                    if !self.take_form()? {
+                        return Err(ReadError::UnexpectedEof { offset: self.offset });
+                    }
+                },
+            },
+            _ => self.take_token()?,
+        }
+        Ok(true)
+    }
+
+    fn read_next(&mut self) -> Result<Option<ValueAndSpan>, ReadError> {
+        if self.state == State::BeforeVector {
+            self.skip_whitespace(false)?;
+            let offset = self.offset;
+            match self.skip()? {
+                Some(b'[') => self.state = State::InVector,
+                Some(found) => return Err(ReadError::Unexpected { offset: offset, found: found }),
+                None => return Err(ReadError::UnexpectedEof { offset: offset }),
+            }
+        }
+
+        loop {
+            if self.state == State::Done {
+                return Ok(None);
+            }
+
+            self.skip_whitespace(false)?;
+            match (self.state, self.peek()?) {
+                (State::InVector, None) => return Err(ReadError::UnexpectedEof { offset: self.offset }),
+                (_, None) => {
+                    self.state = State::Done;
+                    return Ok(None);
+                },
+                (State::InVector, Some(b']')) => {
+                    self.skip()?;
+                    self.skip_whitespace(false)?;
+                    let offset = self.offset;
+                    if let Some(found) = self.peek()? {
+                        return Err(ReadError::Unexpected { offset: offset, found: found });
+                    }
+                    self.state = State::Done;
+                    return Ok(None);
+                },
+                _ => {},
+            }
+
+            let start = self.offset;
+            self.form.clear();
+            if !self.take_form()? {
+                // Nothing but discarded forms; go around again to finish up.
+                continue;
+            }
+
+            let text = str::from_utf8(&self.form).map_err(|_| ReadError::InvalidUtf8 { offset: start })?;
+            let value = parse::value(text).map_err(|e| ReadError::Parse { offset: start, error: e })?;
+            return Ok(Some(shifted(value, start as u32)));
+        }
+    }
+}
+
+impl<R> Iterator for Reader<R> where R: io::Read {
+    type Item = Result<ValueAndSpan, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_next() {
+            Ok(next) => next.map(Ok),
+            Err(e) => {
+                // Don't try to resynchronize after an error.
+                self.state = State::Done;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use types::Value;
+
+    fn read_all(input: &str) -> Result<Vec<ValueAndSpan>, ReadError> {
+        Reader::new(input.as_bytes()).collect()
+    }
+
+    fn read_elements(input: &str) -> Result<Vec<ValueAndSpan>, ReadError> {
+        Reader::vector_elements(input.as_bytes()).collect()
+    }
+
+    fn without_spans(values: Vec<ValueAndSpan>) -> Vec<Value> {
+        values.into_iter().map(|v| v.without_spans()).collect()
+    }
+
+    #[test]
+    fn test_forms_match_parse() {
+        let input = r#" 1 :a/b "a \"string\" with ] in it" \] \space [1 (2 #{3}) {:x "}"}] ; [not a form
+                        #inst "2018-01-01T00:00:00Z" #_ 5 #myapp/tag [6] #_ [7] nil #_ 8"#;
+        let forms = read_all(input).expect("read");
+        assert_eq!(forms.len(), 9);
+
+        // Each form's span covers exactly its text, and re-parsing that text gives the same value
+        // with the same (relative) spans.
+        for form in forms {
+            let text = &input[form.span.0 as usize..form.span.1 as usize];
+            let reparsed = parse::value(text).expect("parse");
+            assert_eq!(shifted(reparsed, form.span.0), form);
+        }
+    }
+
+    #[test]
+    fn test_vector_elements() {
+        let input = "\n[[:db/add 1 :db/doc \"x\"]\n {:db/id 2}, #_ [:skipped] 3] ; done\n";
+        let elements = read_elements(input).expect("read");
+        assert_eq!(without_spans(elements.clone()),
+                   parse::value("[[:db/add 1 :db/doc \"x\"] {:db/id 2} 3]").unwrap().without_spans().into_vector().unwrap());
+        assert_eq!(elements[1].span, Span(27, 37));
+
+        assert_eq!(read_elements("[]").expect("read"), vec![]);
+        assert_eq!(read_elements("[#_ 1]").expect("read"), vec![]);
+    }
+
+    #[test]
+    fn test_errors() {
+        match read_all("[1 2") {
+            Err(ReadError::UnexpectedEof { offset: 4 }) => {},
+            x => panic!("expected UnexpectedEof, got {:?}", x),
+        }
+        match read_all("1 ]") {
+            Err(ReadError::Unexpected { offset: 2, found: b']' }) => {},
+            x => panic!("expected Unexpected, got {:?}", x),
+        }
+        match read_all("1 [:a :b) 3") {
+            Err(ReadError::Parse { offset: 2, .. }) => {},
+            x => panic!("expected Parse, got {:?}", x),
+        }
+        match read_elements("[1 2] 3") {
+            Err(ReadError::Unexpected { offset: 6, found: b'3' }) => {},
+            x => panic!("expected Unexpected, got {:?}", x),
+        }
+        match read_elements("{:a 1}") {
+            Err(ReadError::Unexpected { offset: 0, found: b'{' }) => {},
+            x => panic!("expected Unexpected, got {:?}", x),
+        }
+        match read_elements("[1 2") {
+            Err(ReadError::UnexpectedEof { offset: 4 }) => {},
+            x => panic!("expected UnexpectedEof, got {:?}", x),
+        }
+
+        // Iteration stops after an error.
+        let mut reader = Reader::new("1 ) 2".as_bytes());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+}
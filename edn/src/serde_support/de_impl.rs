@@ -0,0 +1,161 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use num::ToPrimitive;
+use serde::de::{
+    self,
+    DeserializeSeed,
+    IntoDeserializer,
+    Visitor,
+};
+use serde::de::value::{
+    MapDeserializer,
+    SeqDeserializer,
+};
+
+use types::Value;
+use utils;
+
+use super::Error;
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+fn visit_seq<'de, I, V>(values: I, visitor: V) -> Result<V::Value, Error>
+where I: Iterator<Item=Value>,
+      V: Visitor<'de> {
+    let mut seq = SeqDeserializer::new(values);
+    let result = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(result)
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Nil => visitor.visit_unit(),
+            Value::Boolean(v) => visitor.visit_bool(v),
+            Value::Integer(v) => visitor.visit_i64(v),
+            Value::BigInteger(v) => {
+                if let Some(i) = v.to_i64() {
+                    visitor.visit_i64(i)
+                } else if let Some(u) = v.to_u64() {
+                    visitor.visit_u64(u)
+                } else {
+                    visitor.visit_string(v.to_string())
+                }
+            },
+            Value::Float(v) => visitor.visit_f64(v.into_inner()),
+            Value::Text(v) => visitor.visit_string(v),
+            Value::Character(v) => visitor.visit_char(v),
+            Value::Instant(v) => visitor.visit_string(utils::rfc3339(&v)),
+            Value::Uuid(v) => visitor.visit_string(v.hyphenated().to_string()),
+            Value::PlainSymbol(v) => visitor.visit_string(v.0),
+            Value::NamespacedSymbol(v) => visitor.visit_string(v.to_string()),
+            Value::Keyword(v) => visitor.visit_string(v.0),
+            Value::NamespacedKeyword(v) => visitor.visit_string(format!("{}/{}", v.namespace, v.name)),
+            Value::Vector(vs) => visit_seq(vs.into_iter(), visitor),
+            Value::List(vs) => visit_seq(vs.into_iter(), visitor),
+            Value::Set(vs) => visit_seq(vs.into_iter(), visitor),
+            Value::Map(vs) => {
+                let mut map = MapDeserializer::new(vs.into_iter());
+                let result = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(result)
+            },
+            Value::Tagged(_, v) => de::Deserializer::deserialize_any(*v, visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self {
+            Value::Nil => visitor.visit_none(),
+            v => visitor.visit_some(v),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        match self {
+            v @ Value::Keyword(_) | v @ Value::Text(_) => visitor.visit_enum(EnumDeserializer { variant: v, content: None }),
+            Value::Map(vs) => {
+                if vs.len() != 1 {
+                    return Err(de::Error::invalid_length(vs.len(), &"a map with a single entry"));
+                }
+                let (variant, content) = vs.into_iter().next().unwrap();
+                visitor.visit_enum(EnumDeserializer { variant: variant, content: Some(content) })
+            },
+            v => Err(Error::new(format!("expected a keyword or a single-entry map, found {}", v))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf unit unit_struct
+        seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct EnumDeserializer {
+    variant: Value,
+    content: Option<Value>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, VariantDeserializer), Error> {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, VariantDeserializer { content: self.content }))
+    }
+}
+
+struct VariantDeserializer {
+    content: Option<Value>,
+}
+
+impl VariantDeserializer {
+    fn content(self) -> Result<Value, Error> {
+        self.content.ok_or_else(|| Error::new("expected variant content, found a unit variant"))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.content {
+            None | Some(Value::Nil) => Ok(()),
+            Some(v) => Err(Error::new(format!("expected a unit variant, found {}", v))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self.content()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self.content()?, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+        de::Deserializer::deserialize_any(self.content()?, visitor)
+    }
+}
@@ -0,0 +1,289 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! A mapping between EDN and JSON, for exchanging EDN values with clients that only speak JSON.
+//!
+//! Values that JSON can represent directly map to themselves:
+//!
+//! | EDN                       | JSON                      |
+//! |---------------------------|---------------------------|
+//! | `nil`                     | `null`                    |
+//! | `true`, `false`           | `true`, `false`           |
+//! | `42`                      | `42`                      |
+//! | `1.5`                     | `1.5`                     |
+//! | `"text"`                  | `"text"`                  |
+//! | `[1 2]`                   | `[1, 2]`                  |
+//! | `{"a" 1}`, `{:a/b 1}`     | `{"a": 1}`, `{":a/b": 1}` |
+//!
+//! Everything else becomes an object with a single key naming its type:
+//!
+//! | EDN                       | JSON                                         |
+//! |---------------------------|----------------------------------------------|
+//! | `:a/b`                    | `{"#keyword": "a/b"}`                        |
+//! | `a/b`                     | `{"#symbol": "a/b"}`                         |
+//! | `\c`                      | `{"#char": "c"}`                             |
+//! | `123N`                    | `{"#bigint": "123"}`                         |
+//! | `#f NaN`, `#f -Infinity`  | `{"#f": "NaN"}`, `{"#f": "-Infinity"}`       |
+//! | `#inst "…"`               | `{"#inst": "2018-01-01T00:00:00.000Z"}`      |
+//! | `#uuid "…"`               | `{"#uuid": "550e8400-…"}`                    |
+//! | `(1 2)`                   | `{"#list": [1, 2]}`                          |
+//! | `#{1 2}`                  | `{"#set": [1, 2]}`                           |
+//! | `#myapp/tag [1]`          | `{"#tag": ["myapp/tag", [1]]}`               |
+//! | `{1 2}`                   | `{"#map": [[1, 2]]}`                         |
+//!
+//! Maps use JSON objects when every key is a keyword, or a string that doesn't start with `:` or
+//! `#`, and `#map` otherwise. Object keys that start with `:` are read as keywords, so string and
+//! keyword keys stay distinct, and an object with a single key that starts with `#` always
+//! denotes one of the types above.
+//!
+//! The mapping is lossless, except that JSON numbers can't distinguish `1.0` from `1` for all
+//! consumers, and that many JSON parsers lose precision in integers beyond 2^53.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use num::BigInt;
+use ordered_float::OrderedFloat;
+use serde_json::{self, Map, Number};
+use uuid::Uuid;
+
+use symbols;
+use types::Value;
+use utils;
+
+use super::Error;
+
+fn tagged<T: Into<serde_json::Value>>(tag: &str, value: T) -> serde_json::Value {
+    let mut map = Map::new();
+    map.insert(tag.to_string(), value.into());
+    serde_json::Value::Object(map)
+}
+
+fn names(namespace: &str, name: &str) -> String {
+    format!("{}/{}", namespace, name)
+}
+
+/// Split `ns/name` or `name`, rejecting empty parts.
+fn split_name(s: &str) -> Result<(Option<&str>, &str), Error> {
+    let (namespace, name) = match s.find('/') {
+        Some(i) => (Some(&s[..i]), &s[i + 1..]),
+        None => (None, s),
+    };
+    if name.is_empty() || namespace.map_or(false, |ns| ns.is_empty()) {
+        return Err(Error::new(format!("invalid keyword or symbol name: {:?}", s)));
+    }
+    Ok((namespace, name))
+}
+
+fn keyword(s: &str) -> Result<Value, Error> {
+    Ok(match split_name(s)? {
+        (Some(namespace), name) => Value::NamespacedKeyword(symbols::NamespacedKeyword::new(namespace, name)),
+        (None, name) => Value::Keyword(symbols::Keyword::new(name)),
+    })
+}
+
+fn symbol(s: &str) -> Result<Value, Error> {
+    Ok(match split_name(s)? {
+        (Some(namespace), name) => Value::NamespacedSymbol(symbols::NamespacedSymbol::new(namespace, name)),
+        (None, name) => Value::PlainSymbol(symbols::PlainSymbol::new(name)),
+    })
+}
+
+/// The key to use for `key` in a JSON object, if there is one.
+fn object_key(key: &Value) -> Option<String> {
+    match *key {
+        Value::Text(ref s) if !s.starts_with(':') && !s.starts_with('#') => Some(s.clone()),
+        Value::Keyword(ref k) => Some(format!(":{}", k.0)),
+        Value::NamespacedKeyword(ref k) => Some(format!(":{}", names(&k.namespace, &k.name))),
+        _ => None,
+    }
+}
+
+fn array<'a, I>(values: I) -> serde_json::Value where I: Iterator<Item=&'a Value> {
+    serde_json::Value::Array(values.map(to_json).collect())
+}
+
+/// Convert `value` to JSON.
+pub fn to_json(value: &Value) -> serde_json::Value {
+    use serde_json::Value as Json;
+
+    match *value {
+        Value::Nil => Json::Null,
+        Value::Boolean(v) => Json::Bool(v),
+        Value::Integer(v) => Json::Number(v.into()),
+        Value::BigInteger(ref v) => tagged("#bigint", v.to_string()),
+        Value::Float(OrderedFloat(v)) => match Number::from_f64(v) {
+            Some(n) => Json::Number(n),
+            None if v.is_nan() => tagged("#f", "NaN"),
+            None if v > 0.0 => tagged("#f", "Infinity"),
+            None => tagged("#f", "-Infinity"),
+        },
+        Value::Text(ref v) => Json::String(v.clone()),
+        Value::Character(c) => tagged("#char", c.to_string()),
+        Value::Instant(ref v) => tagged("#inst", utils::rfc3339(v)),
+        Value::Uuid(ref v) => tagged("#uuid", v.hyphenated().to_string()),
+        Value::PlainSymbol(ref v) => tagged("#symbol", v.0.clone()),
+        Value::NamespacedSymbol(ref v) => tagged("#symbol", names(&v.namespace, &v.name)),
+        Value::Keyword(ref v) => tagged("#keyword", v.0.clone()),
+        Value::NamespacedKeyword(ref v) => tagged("#keyword", names(&v.namespace, &v.name)),
+        Value::Vector(ref vs) => array(vs.iter()),
+        Value::List(ref vs) => tagged("#list", array(vs.iter())),
+        Value::Set(ref vs) => tagged("#set", array(vs.iter().rev())),
+        Value::Map(ref vs) => {
+            let keys: Option<Vec<String>> = vs.keys().rev().map(object_key).collect();
+            match keys {
+                Some(keys) => Json::Object(keys.into_iter().zip(vs.values().rev().map(to_json)).collect()),
+                None => tagged("#map", vs.iter().rev()
+                                         .map(|(k, v)| Json::Array(vec![to_json(k), to_json(v)]))
+                                         .collect::<Vec<_>>()),
+            }
+        },
+        Value::Tagged(ref tag, ref v) => tagged("#tag", vec![Json::String(tag.clone()), to_json(v)]),
+    }
+}
+
+fn expect_str<'a>(tag: &str, json: &'a serde_json::Value) -> Result<&'a str, Error> {
+    json.as_str().ok_or_else(|| Error::new(format!("expected a string for {}, found {}", tag, json)))
+}
+
+fn expect_array<'a>(tag: &str, json: &'a serde_json::Value) -> Result<&'a Vec<serde_json::Value>, Error> {
+    json.as_array().ok_or_else(|| Error::new(format!("expected an array for {}, found {}", tag, json)))
+}
+
+fn from_tagged(tag: &str, json: &serde_json::Value) -> Result<Value, Error> {
+    let invalid = |what: &str| Error::new(format!("invalid {}: {}", what, json));
+    match tag {
+        "#bigint" => BigInt::from_str(expect_str(tag, json)?).map(Value::BigInteger).map_err(|_| invalid("#bigint")),
+        "#f" => match expect_str(tag, json)? {
+            "NaN" => Ok(Value::Float(OrderedFloat(::std::f64::NAN))),
+            "Infinity" => Ok(Value::Float(OrderedFloat(::std::f64::INFINITY))),
+            "-Infinity" => Ok(Value::Float(OrderedFloat(::std::f64::NEG_INFINITY))),
+            _ => Err(invalid("#f")),
+        },
+        "#char" => {
+            let s = expect_str(tag, json)?;
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(Value::Character(c)),
+                _ => Err(invalid("#char")),
+            }
+        },
+        "#inst" => DateTime::parse_from_rfc3339(expect_str(tag, json)?)
+            .map(|t| Value::Instant(t.with_timezone(&Utc)))
+            .map_err(|_| invalid("#inst")),
+        "#uuid" => Uuid::parse_str(expect_str(tag, json)?).map(Value::Uuid).map_err(|_| invalid("#uuid")),
+        "#symbol" => symbol(expect_str(tag, json)?),
+        "#keyword" => keyword(expect_str(tag, json)?),
+        "#list" => Ok(Value::List(expect_array(tag, json)?.iter().map(from_json).collect::<Result<_, _>>()?)),
+        "#set" => Ok(Value::Set(expect_array(tag, json)?.iter().map(from_json).collect::<Result<_, _>>()?)),
+        "#map" => {
+            let pairs = expect_array(tag, json)?.iter().map(|pair| -> Result<(Value, Value), Error> {
+                match pair.as_array() {
+                    Some(kv) if kv.len() == 2 => Ok((from_json(&kv[0])?, from_json(&kv[1])?)),
+                    _ => Err(invalid("#map")),
+                }
+            });
+            Ok(Value::Map(pairs.collect::<Result<_, _>>()?))
+        },
+        "#tag" => {
+            let parts = expect_array(tag, json)?;
+            match (parts.get(0).and_then(|t| t.as_str()), parts.get(1)) {
+                (Some(t), Some(v)) if parts.len() == 2 && !t.is_empty() => Ok(Value::Tagged(t.to_string(), Box::new(from_json(v)?))),
+                _ => Err(invalid("#tag")),
+            }
+        },
+        _ => Err(Error::new(format!("unknown type {} in {}", tag, json))),
+    }
+}
+
+/// Convert JSON produced by `to_json` back to EDN.
+pub fn from_json(json: &serde_json::Value) -> Result<Value, Error> {
+    use serde_json::Value as Json;
+
+    match *json {
+        Json::Null => Ok(Value::Nil),
+        Json::Bool(v) => Ok(Value::Boolean(v)),
+        Json::Number(ref n) => {
+            if let Some(i) = n.as_i64() {
+                Ok(Value::Integer(i))
+            } else if let Some(u) = n.as_u64() {
+                Ok(Value::BigInteger(BigInt::from_str(&u.to_string()).unwrap()))
+            } else {
+                Ok(Value::Float(OrderedFloat(n.as_f64().unwrap())))
+            }
+        },
+        Json::String(ref s) => Ok(Value::Text(s.clone())),
+        Json::Array(ref vs) => Ok(Value::Vector(vs.iter().map(from_json).collect::<Result<_, _>>()?)),
+        Json::Object(ref map) => {
+            if map.len() == 1 {
+                let (key, value) = map.iter().next().unwrap();
+                if key.starts_with('#') {
+                    return from_tagged(key, value);
+                }
+            }
+            let entries = map.iter().map(|(k, v)| -> Result<(Value, Value), Error> {
+                let key = if k.starts_with(':') { keyword(&k[1..])? } else { Value::Text(k.clone()) };
+                Ok((key, from_json(v)?))
+            });
+            Ok(Value::Map(entries.collect::<Result<_, _>>()?))
+        },
+    }
+}
+
+/// Convert `value` to JSON text.
+pub fn to_json_string(value: &Value) -> String {
+    to_json(value).to_string()
+}
+
+/// Read JSON text produced by `to_json_string` as EDN.
+pub fn from_json_str(text: &str) -> Result<Value, Error> {
+    let json: serde_json::Value = serde_json::from_str(text).map_err(|e| Error::new(e.to_string()))?;
+    from_json(&json)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use parse;
+
+    fn edn(s: &str) -> Value {
+        parse::value(s).expect("parsed").without_spans()
+    }
+
+    #[test]
+    fn test_json_shapes() {
+        assert_eq!(to_json_string(&edn(r#"[nil true 42 1.5 "text"]"#)), r#"[null,true,42,1.5,"text"]"#);
+        assert_eq!(to_json_string(&edn(":a/b")), r##"{"#keyword":"a/b"}"##);
+        assert_eq!(to_json_string(&edn("#{1}")), r##"{"#set":[1]}"##);
+        assert_eq!(to_json_string(&edn("{:a/b 1}")), r#"{":a/b":1}"#);
+        assert_eq!(to_json_string(&edn(r#"{":a" 1}"#)), r##"{"#map":[[":a",1]]}"##);
+        assert_eq!(to_json_string(&edn(r#"#inst "2018-01-01T10:00:00.5Z""#)), r##"{"#inst":"2018-01-01T10:00:00.500Z"}"##);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let value = edn(r#"[nil true -7 123456789012345678901234567890N 0.5 #f NaN #f -Infinity "s" \c
+                            #inst "2018-01-01T10:00:00.123Z" #uuid "550e8400-e29b-41d4-a716-446655440000"
+                            sym ns/sym :kw :ns/kw (1 2) #{:x} {:a 1 "b" 2} {":c" 3 [4] 5} {"#d" 6}
+                            #myapp/tag {:e [7]}]"#);
+        let json = to_json_string(&value);
+        assert_eq!(from_json_str(&json), Ok(value));
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        assert!(from_json_str(r##"{"#keyword": 1}"##).is_err());
+        assert!(from_json_str(r##"{"#keyword": "/"}"##).is_err());
+        assert!(from_json_str(r##"{"#char": "ab"}"##).is_err());
+        assert!(from_json_str(r##"{"#unknown": 1}"##).is_err());
+        assert!(from_json_str(r##"{"#map": [[1]]}"##).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! EDN as a serde data format, and a mapping between EDN and JSON.
+//!
+//! Rust values map onto EDN as follows:
+//!
+//! - `bool`, integers, floats, `char` and strings become the corresponding EDN scalars. `u64`s
+//!   too large for an `i64` become big integers.
+//! - `None` and `()` become `nil`; `Some(v)` becomes `v`.
+//! - Sequences, tuples and tuple structs become vectors.
+//! - Maps become maps, and structs become maps with keyword keys: `{:name "Fred" :age 42}`.
+//! - Unit variants become keywords, like `:Red`; other variants become single-entry maps from
+//!   the variant's keyword to its content, like `{:Rgb [255 0 0]}`.
+//!
+//! Reading accepts the same shapes. In addition, keywords and symbols can be read as strings
+//! (without the leading `:`), instants and UUIDs as their string forms, lists and sets as
+//! sequences, and a tagged element as the element it tags.
+//!
+//! ```rust
+//! # #[macro_use] extern crate serde_derive;
+//! # extern crate edn;
+//! # use edn::serde_support::{from_str, to_string};
+//! #[derive(Debug, PartialEq, Serialize, Deserialize)]
+//! struct Person {
+//!     name: String,
+//!     nicknames: Vec<String>,
+//! }
+//!
+//! # fn main() {
+//! let fred = Person { name: "Fred".to_string(), nicknames: vec!["Freddie".to_string()] };
+//! let text = to_string(&fred).unwrap();
+//! assert_eq!(text, "{:name \"Fred\" :nicknames [\"Freddie\"]}");
+//! assert_eq!(from_str::<Person>(&text).unwrap(), fred);
+//! # }
+//! ```
+
+use std::error;
+use std::fmt::{self, Display, Formatter};
+
+use serde::{de, ser};
+
+use parse;
+use types::Value;
+
+mod de_impl;
+mod ser_impl;
+pub mod json;
+
+pub use self::ser_impl::Serializer;
+
+/// An error serializing to or deserializing from EDN or JSON.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error(String);
+
+impl Error {
+    pub fn new<T: Into<String>>(message: T) -> Error {
+        Error(message.into())
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error(msg.to_string())
+    }
+}
+
+/// Convert `value` into an EDN `Value`.
+pub fn to_value<T: ?Sized + ser::Serialize>(value: &T) -> Result<Value, Error> {
+    value.serialize(Serializer)
+}
+
+/// Convert `value` into EDN text.
+pub fn to_string<T: ?Sized + ser::Serialize>(value: &T) -> Result<String, Error> {
+    to_value(value)?.to_pretty(120).map_err(|e| Error(e.to_string()))
+}
+
+/// Build a `T` from an EDN `Value`.
+pub fn from_value<T: de::DeserializeOwned>(value: Value) -> Result<T, Error> {
+    T::deserialize(value)
+}
+
+/// Build a `T` from EDN text.
+pub fn from_str<T: de::DeserializeOwned>(text: &str) -> Result<T, Error> {
+    let value = parse::value(text).map_err(|e| Error(e.to_string()))?;
+    from_value(value.without_spans())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use symbols;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Color {
+        Red,
+        Grey(u8),
+        Rgb(u8, u8, u8),
+        Named { name: String },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Shape {
+        sides: u32,
+        area: f64,
+        label: Option<String>,
+        initial: char,
+        colors: Vec<Color>,
+        tags: BTreeMap<String, i64>,
+        unit: (),
+    }
+
+    fn shape() -> Shape {
+        let mut tags = BTreeMap::new();
+        tags.insert("weight".to_string(), -3);
+        Shape {
+            sides: 4,
+            area: 2.0,
+            label: None,
+            initial: 's',
+            colors: vec![Color::Red, Color::Grey(128), Color::Rgb(1, 2, 3), Color::Named { name: "teal".to_string() }],
+            tags: tags,
+            unit: (),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = to_string(&shape()).expect("serialized");
+        assert_eq!(from_str::<Shape>(&text).expect("deserialized"), shape());
+
+        let value = to_value(&shape()).expect("serialized");
+        let expected = parse::value(r#"{:sides 4 :area 2.0 :label nil :initial \s
+                                         :colors [:Red {:Grey 128} {:Rgb [1 2 3]} {:Named {:name "teal"}}]
+                                         :tags {"weight" -3} :unit nil}"#).unwrap().without_spans();
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn test_scalars() {
+        assert_eq!(to_value(&u64::max_value()).unwrap().is_big_integer(), true);
+        assert_eq!(from_str::<u64>("18446744073709551615N"), Ok(u64::max_value()));
+        assert_eq!(from_str::<Option<i32>>("nil"), Ok(None));
+        assert_eq!(from_str::<Option<i32>>("5"), Ok(Some(5)));
+        assert_eq!(from_str::<String>(":foo/bar"), Ok("foo/bar".to_string()));
+        assert_eq!(from_str::<BTreeSet<i32>>("#{1 2}"), Ok(vec![1, 2].into_iter().collect()));
+        assert_eq!(from_str::<Vec<i32>>("(1 2)"), Ok(vec![1, 2]));
+        assert_eq!(from_str::<i32>("#myapp/wrapped 7"), Ok(7));
+        assert_eq!(to_value(&"text").unwrap(), Value::Text("text".to_string()));
+        assert_eq!(to_value(&Color::Red).unwrap(), Value::Keyword(symbols::Keyword::new("Red")));
+
+        assert!(from_str::<i32>("\"seven\"").is_err());
+        assert!(from_str::<Color>("{:Red nil :Grey 1}").is_err());
+        assert!(from_str::<Color>(":Blue").is_err());
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use types::Value;
+
+/// Reads the element of a tagged element into some other `Value`, or explains why it can't.
+pub type TagHandler = Box<Fn(Value) -> Result<Value, String>>;
+
+/// A registered `TagHandler` rejected the element it was given.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TagError {
+    pub tag: String,
+    pub message: String,
+}
+
+impl Display for TagError {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        write!(f, "couldn't read #{}: {}", self.tag, self.message)
+    }
+}
+
+impl Error for TagError {
+    fn description(&self) -> &str {
+        "couldn't read tagged element"
+    }
+}
+
+/// A registry of handlers for custom tags.
+///
+/// The parser reads any `#tag element` it doesn't have built-in support for into
+/// `Value::Tagged`. `resolve` replaces those with the output of the handler registered
+/// for their tag:
+///
+/// ```rust
+/// # use edn::parse;
+/// # use edn::tags::TagHandlers;
+/// # use edn::Value;
+/// let mut handlers = TagHandlers::new();
+/// handlers.register("myapp/double", |v| {
+///     v.as_integer().map(|i| Value::Integer(i * 2)).ok_or_else(|| "expected an integer".to_string())
+/// });
+///
+/// let value = parse::value("[#myapp/double 2 #other/tag 3]").unwrap().without_spans();
+/// assert_eq!(handlers.resolve(value).unwrap(),
+///            Value::Vector(vec![Value::Integer(4),
+///                               Value::Tagged("other/tag".to_string(), Box::new(Value::Integer(3)))]));
+/// ```
+#[derive(Default)]
+pub struct TagHandlers {
+    handlers: BTreeMap<String, TagHandler>,
+}
+
+impl TagHandlers {
+    pub fn new() -> TagHandlers {
+        TagHandlers::default()
+    }
+
+    /// Register `handler` for `tag`, given without its leading `#`. Replaces any handler
+    /// previously registered for `tag`.
+    pub fn register<T, F>(&mut self, tag: T, handler: F)
+    where T: Into<String>,
+          F: Fn(Value) -> Result<Value, String> + 'static {
+        self.handlers.insert(tag.into(), Box::new(handler));
+    }
+
+    pub fn is_registered(&self, tag: &str) -> bool {
+        self.handlers.contains_key(tag)
+    }
+
+    /// Recursively replace each tagged element whose tag has a registered handler with that
+    /// handler's output. Nested elements are resolved first, so a handler never sees a tagged
+    /// element that it could have had resolved for it. Tagged elements with no registered
+    /// handler are left as they are.
+    pub fn resolve(&self, value: Value) -> Result<Value, TagError> {
+        match value {
+            Value::Tagged(tag, element) => {
+                let element = self.resolve(*element)?;
+                match self.handlers.get(&tag) {
+                    Some(handler) => handler(element).map_err(|message| TagError {
+                        tag: tag,
+                        message: message,
+                    }),
+                    None => Ok(Value::Tagged(tag, Box::new(element))),
+                }
+            },
+            Value::Vector(vs) => Ok(Value::Vector(vs.into_iter().map(|v| self.resolve(v)).collect::<Result<_, _>>()?)),
+            Value::List(vs) => Ok(Value::List(vs.into_iter().map(|v| self.resolve(v)).collect::<Result<_, _>>()?)),
+            Value::Set(vs) => Ok(Value::Set(vs.into_iter().map(|v| self.resolve(v)).collect::<Result<_, _>>()?)),
+            Value::Map(vs) => {
+                let resolved = vs.into_iter().map(|(k, v)| -> Result<(Value, Value), TagError> {
+                    Ok((self.resolve(k)?, self.resolve(v)?))
+                });
+                Ok(Value::Map(resolved.collect::<Result<_, _>>()?))
+            },
+            v => Ok(v),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parse;
+
+    #[test]
+    fn test_resolve_nested() {
+        let mut handlers = TagHandlers::new();
+        handlers.register("wrap", |v| Ok(Value::Vector(vec![v])));
+        handlers.register("fail", |_| Err("always fails".to_string()));
+        assert!(handlers.is_registered("wrap"));
+        assert!(!handlers.is_registered("unknown"));
+
+        let value = parse::value("{:a #wrap #wrap 1 #unknown #wrap 2 nil}").unwrap().without_spans();
+        let expected = parse::value("{:a [[1]] #unknown [2] nil}").unwrap().without_spans();
+        assert_eq!(handlers.resolve(value), Ok(expected));
+
+        let value = parse::value("#{1 #fail 2}").unwrap().without_spans();
+        assert_eq!(handlers.resolve(value), Err(TagError {
+            tag: "fail".to_string(),
+            message: "always fails".to_string(),
+        }));
+    }
+}
@@ -25,6 +25,7 @@ use ordered_float::OrderedFloat;
 use uuid::Uuid;
 
 use symbols;
+use utils;
 
 /// Value represents one of the allowed values in an EDN string.
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -36,6 +37,7 @@ pub enum Value {
     BigInteger(BigInt),
     Float(OrderedFloat<f64>),
     Text(String),
+    Character(char),
     Uuid(Uuid),
     PlainSymbol(symbols::PlainSymbol),
     NamespacedSymbol(symbols::NamespacedSymbol),
@@ -52,6 +54,10 @@ pub enum Value {
     // See https://internals.rust-lang.org/t/implementing-hash-for-hashset-hashmap/3817/1
     Set(BTreeSet<Value>),
     Map(BTreeMap<Value, Value>),
+    /// A tagged element, like `#myapp/Person {:first "Fred"}`: the tag, without its leading `#`,
+    /// and the element it tags. Elements with built-in tags, like `#inst` and `#uuid`, are read
+    /// as their own variants instead. See `tags::TagHandlers` to read custom tags.
+    Tagged(String, Box<Value>),
 }
 
 /// `SpannedValue` is the parallel to `Value` but used in `ValueAndSpan`.
@@ -65,6 +71,7 @@ pub enum SpannedValue {
     BigInteger(BigInt),
     Float(OrderedFloat<f64>),
     Text(String),
+    Character(char),
     Uuid(Uuid),
     PlainSymbol(symbols::PlainSymbol),
     NamespacedSymbol(symbols::NamespacedSymbol),
@@ -74,6 +81,7 @@ pub enum SpannedValue {
     List(LinkedList<ValueAndSpan>),
     Set(BTreeSet<ValueAndSpan>),
     Map(BTreeMap<ValueAndSpan, ValueAndSpan>),
+    Tagged(String, Box<ValueAndSpan>),
 }
 
 /// Span represents the current offset (start, end) into the input string.
@@ -150,6 +158,7 @@ impl From<SpannedValue> for Value {
             SpannedValue::BigInteger(v) => Value::BigInteger(v),
             SpannedValue::Float(v) => Value::Float(v),
             SpannedValue::Text(v) => Value::Text(v),
+            SpannedValue::Character(v) => Value::Character(v),
             SpannedValue::Uuid(v) => Value::Uuid(v),
             SpannedValue::PlainSymbol(v) => Value::PlainSymbol(v),
             SpannedValue::NamespacedSymbol(v) => Value::NamespacedSymbol(v),
@@ -159,6 +168,7 @@ impl From<SpannedValue> for Value {
             SpannedValue::List(v) => Value::List(v.into_iter().map(|x| x.without_spans()).collect()),
             SpannedValue::Set(v) => Value::Set(v.into_iter().map(|x| x.without_spans()).collect()),
             SpannedValue::Map(v) => Value::Map(v.into_iter().map(|(x, y)| (x.without_spans(), y.without_spans())).collect()),
+            SpannedValue::Tagged(t, v) => Value::Tagged(t, Box::new(v.without_spans())),
         }
     }
 }
@@ -303,6 +313,7 @@ macro_rules! def_common_value_methods {
         def_is!(is_big_integer, $t::BigInteger(_));
         def_is!(is_float, $t::Float(_));
         def_is!(is_text, $t::Text(_));
+        def_is!(is_character, $t::Character(_));
         def_is!(is_uuid, $t::Uuid(_));
         def_is!(is_symbol, $t::PlainSymbol(_));
         def_is!(is_namespaced_symbol, $t::NamespacedSymbol(_));
@@ -312,6 +323,7 @@ macro_rules! def_common_value_methods {
         def_is!(is_list, $t::List(_));
         def_is!(is_set, $t::Set(_));
         def_is!(is_map, $t::Map(_));
+        def_is!(is_tagged, $t::Tagged(_, _));
 
         /// `as_nil` does not use the macro as it does not have an underlying
         /// value, and returns `Option<()>`.
@@ -327,6 +339,7 @@ macro_rules! def_common_value_methods {
         def_as_ref!(as_big_integer, $t::BigInteger, BigInt);
         def_as_ref!(as_ordered_float, $t::Float, OrderedFloat<f64>);
         def_as_ref!(as_text, $t::Text, String);
+        def_as!(as_character, $t::Character, char,);
         def_as_ref!(as_uuid, $t::Uuid, Uuid);
         def_as_ref!(as_symbol, $t::PlainSymbol, symbols::PlainSymbol);
         def_as_ref!(as_namespaced_symbol, $t::NamespacedSymbol, symbols::NamespacedSymbol);
@@ -344,6 +357,7 @@ macro_rules! def_common_value_methods {
         def_into!(into_ordered_float, $t::Float, OrderedFloat<f64>,);
         def_into!(into_float, $t::Float, f64, |v: OrderedFloat<f64>| v.into_inner());
         def_into!(into_text, $t::Text, String,);
+        def_into!(into_character, $t::Character, char,);
         def_into!(into_uuid, $t::Uuid, Uuid,);
         def_into!(into_symbol, $t::PlainSymbol, symbols::PlainSymbol,);
         def_into!(into_namespaced_symbol, $t::NamespacedSymbol, symbols::NamespacedSymbol,);
@@ -354,6 +368,16 @@ macro_rules! def_common_value_methods {
         def_into!(into_set, $t::Set, BTreeSet<$tchild>,);
         def_into!(into_map, $t::Map, BTreeMap<$tchild, $tchild>,);
 
+        /// `as_tagged` and `into_tagged` don't use the macros as they have
+        /// two underlying values: the tag and the tagged element.
+        pub fn as_tagged(&self) -> Option<(&String, &$tchild)> {
+            match *self { $t::Tagged(ref tag, ref v) => Some((tag, &**v)), _ => None }
+        }
+
+        pub fn into_tagged(self) -> Option<(String, $tchild)> {
+            match self { $t::Tagged(tag, v) => Some((tag, *v)), _ => None }
+        }
+
         def_from_option!(from_bigint, $t, $t::BigInteger, &str, |src: &str| src.parse::<BigInt>().ok());
         def_from!(from_float, $t, $t::Float, f64, |src: f64| OrderedFloat::from(src));
         def_from!(from_ordered_float, $t, $t::Float, OrderedFloat<f64>,);
@@ -375,15 +399,17 @@ macro_rules! def_common_value_methods {
                 $t::Float(_) => 4,
                 $t::Instant(_) => 5,
                 $t::Text(_) => 6,
-                $t::Uuid(_) => 7,
-                $t::PlainSymbol(_) => 8,
-                $t::NamespacedSymbol(_) => 9,
-                $t::Keyword(_) => 10,
-                $t::NamespacedKeyword(_) => 11,
-                $t::Vector(_) => 12,
-                $t::List(_) => 13,
-                $t::Set(_) => 14,
-                $t::Map(_) => 15,
+                $t::Character(_) => 7,
+                $t::Uuid(_) => 8,
+                $t::PlainSymbol(_) => 9,
+                $t::NamespacedSymbol(_) => 10,
+                $t::Keyword(_) => 11,
+                $t::NamespacedKeyword(_) => 12,
+                $t::Vector(_) => 13,
+                $t::List(_) => 14,
+                $t::Set(_) => 15,
+                $t::Map(_) => 16,
+                $t::Tagged(_, _) => 17,
             }
         }
 
@@ -396,6 +422,7 @@ macro_rules! def_common_value_methods {
                 $t::BigInteger(_) => false,
                 $t::Float(_) => false,
                 $t::Text(_) => false,
+                $t::Character(_) => false,
                 $t::Uuid(_) => false,
                 $t::PlainSymbol(_) => false,
                 $t::NamespacedSymbol(_) => false,
@@ -405,6 +432,7 @@ macro_rules! def_common_value_methods {
                 $t::List(_) => true,
                 $t::Set(_) => true,
                 $t::Map(_) => true,
+                $t::Tagged(_, _) => false,
             }
         }
 
@@ -434,6 +462,7 @@ macro_rules! def_common_value_ord {
             (&$t::BigInteger(ref a), &$t::BigInteger(ref b)) => b.cmp(a),
             (&$t::Float(ref a), &$t::Float(ref b)) => b.cmp(a),
             (&$t::Text(ref a), &$t::Text(ref b)) => b.cmp(a),
+            (&$t::Character(a), &$t::Character(b)) => b.cmp(&a),
             (&$t::Uuid(ref a), &$t::Uuid(ref b)) => b.cmp(a),
             (&$t::PlainSymbol(ref a), &$t::PlainSymbol(ref b)) => b.cmp(a),
             (&$t::NamespacedSymbol(ref a), &$t::NamespacedSymbol(ref b)) => b.cmp(a),
@@ -443,23 +472,22 @@ macro_rules! def_common_value_ord {
             (&$t::List(ref a), &$t::List(ref b)) => b.cmp(a),
             (&$t::Set(ref a), &$t::Set(ref b)) => b.cmp(a),
             (&$t::Map(ref a), &$t::Map(ref b)) => b.cmp(a),
+            (&$t::Tagged(ref ta, ref a), &$t::Tagged(ref tb, ref b)) => (tb, b).cmp(&(ta, a)),
             _ => $value.precedence().cmp(&$other.precedence())
         }
     }
 }
 
 /// Converts a Value or SpannedValue to string, given a formatter.
-// TODO: Make sure float syntax is correct, handle NaN and escaping.
-// See https://github.com/mozilla/mentat/issues/232
+/// The output parses back to an equal value.
 macro_rules! def_common_value_display {
     ( $t:tt, $value:expr, $f:expr ) => {
         match *$value {
             $t::Nil => write!($f, "nil"),
             $t::Boolean(v) => write!($f, "{}", v),
             $t::Integer(v) => write!($f, "{}", v),
-            $t::Instant(v) => write!($f, "{}", v),
+            $t::Instant(ref v) => write!($f, "#inst \"{}\"", utils::rfc3339(v)),
             $t::BigInteger(ref v) => write!($f, "{}N", v),
-            // TODO: make sure float syntax is correct.
             $t::Float(ref v) => {
                 if *v == OrderedFloat(f64::INFINITY) {
                     write!($f, "#f +Infinity")
@@ -468,11 +496,18 @@ macro_rules! def_common_value_display {
                 } else if *v == OrderedFloat(f64::NAN) {
                     write!($f, "#f NaN")
                 } else {
-                    write!($f, "{}", v)
+                    // Rust prints integral floats without a decimal point, which would read
+                    // back as an integer.
+                    let s = v.to_string();
+                    if s.contains(|c: char| c == '.' || c == 'e' || c == 'E') {
+                        write!($f, "{}", s)
+                    } else {
+                        write!($f, "{}.0", s)
+                    }
                 }
             }
-            // TODO: EDN escaping.
-            $t::Text(ref v) => write!($f, "\"{}\"", v),
+            $t::Text(ref v) => write!($f, "\"{}\"", utils::escape_text(v)),
+            $t::Character(c) => write!($f, "{}", utils::escape_character(c)),
             $t::Uuid(ref u) => write!($f, "#uuid \"{}\"", u.hyphenated().to_string()),
             $t::PlainSymbol(ref v) => v.fmt($f),
             $t::NamespacedSymbol(ref v) => v.fmt($f),
@@ -506,6 +541,7 @@ macro_rules! def_common_value_display {
                 }
                 write!($f, " }}")
             }
+            $t::Tagged(ref tag, ref v) => write!($f, "#{} {}", tag, v),
         }
     }
 }
@@ -10,6 +10,8 @@
 
 #![allow(dead_code)]
 
+use chrono::{DateTime, Utc};
+
 use types::Value;
 
 /// Merge the EDN `Value::Map` instance `right` into `left`.  Returns `None` if either `left` or
@@ -29,3 +31,62 @@ pub fn merge(left: &Value, right: &Value) -> Option<Value> {
         _ => None
     }
 }
+
+/// Format `instant` as an RFC 3339 timestamp in UTC, the way `#inst` reads it.
+pub fn rfc3339(instant: &DateTime<Utc>) -> String {
+    instant.format("%Y-%m-%dT%H:%M:%S%.fZ").to_string()
+}
+
+/// Escape `text` for printing between the double quotes of an EDN string, such that reading the
+/// result yields `text` again.
+pub fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            '\u{8}' => escaped.push_str("\\b"),
+            '\u{c}' => escaped.push_str("\\f"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Print `c` as an EDN character literal, like `\a`, `\newline` or `\u0000`.
+pub fn escape_character(c: char) -> String {
+    match c {
+        '\n' => "\\newline".to_string(),
+        '\r' => "\\return".to_string(),
+        ' ' => "\\space".to_string(),
+        '\t' => "\\tab".to_string(),
+        // Commas are whitespace in EDN.
+        c if c == ',' || c.is_whitespace() || c.is_control() => format!("\\u{:04x}", c as u32),
+        c => format!("\\{}", c),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape_text() {
+        assert_eq!(escape_text("plain é"), "plain é");
+        assert_eq!(escape_text("\"quoted\"\\"), "\\\"quoted\\\"\\\\");
+        assert_eq!(escape_text("a\tb\r\nc\u{0}"), "a\\tb\\r\\nc\\u0000");
+    }
+
+    #[test]
+    fn test_escape_character() {
+        assert_eq!(escape_character('c'), "\\c");
+        assert_eq!(escape_character('é'), "\\é");
+        assert_eq!(escape_character('\n'), "\\newline");
+        assert_eq!(escape_character(','), "\\u002c");
+        assert_eq!(escape_character('\u{7f}'), "\\u007f");
+    }
+}
@@ -79,12 +79,14 @@ fn_parse_into_value!(basedinteger);
 fn_parse_into_value!(integer);
 fn_parse_into_value!(float);
 fn_parse_into_value!(text);
+fn_parse_into_value!(character);
 fn_parse_into_value!(symbol);
 fn_parse_into_value!(keyword);
 fn_parse_into_value!(list);
 fn_parse_into_value!(vector);
 fn_parse_into_value!(set);
 fn_parse_into_value!(map);
+fn_parse_into_value!(tagged);
 fn_parse_into_value!(value);
 
 #[test]
@@ -337,6 +339,58 @@ fn test_span_text() {
     });
 }
 
+#[test]
+fn test_text_escapes() {
+    use self::Value::*;
+
+    assert_eq!(text(r#""\"quoted\"""#).unwrap(), Text("\"quoted\"".to_string()));
+    assert_eq!(text(r#""back\\slash""#).unwrap(), Text("back\\slash".to_string()));
+    assert_eq!(text(r#""\t\r\n\b\f""#).unwrap(), Text("\t\r\n\u{8}\u{c}".to_string()));
+    assert_eq!(text(r#""é\u0000""#).unwrap(), Text("é\u{0}".to_string()));
+    assert_eq!(text("\"é and \u{1F600}\"").unwrap(), Text("é and \u{1F600}".to_string()));
+
+    // Unknown escapes, surrogates and short unicode escapes are errors.
+    assert!(text(r#""\q""#).is_err());
+    assert!(text(r#""\ud800""#).is_err());
+    assert!(text(r#""\u00e""#).is_err());
+    assert!(text(r#""unterminated\""#).is_err());
+
+    assert_eq!(Text("a \"b\" \\ c\n".to_string()).to_string(), r#""a \"b\" \\ c\n""#);
+}
+
+#[test]
+fn test_character() {
+    use self::Value::*;
+
+    assert_eq!(character("\\c").unwrap(), Character('c'));
+    assert_eq!(character("\\é").unwrap(), Character('é'));
+    assert_eq!(character("\\\\").unwrap(), Character('\\'));
+    assert_eq!(character("\\\"").unwrap(), Character('"'));
+    assert_eq!(character("\\newline").unwrap(), Character('\n'));
+    assert_eq!(character("\\return").unwrap(), Character('\r'));
+    assert_eq!(character("\\space").unwrap(), Character(' '));
+    assert_eq!(character("\\tab").unwrap(), Character('\t'));
+    assert_eq!(character("\\u0041").unwrap(), Character('A'));
+    assert_eq!(character("\\u").unwrap(), Character('u'));
+    assert_eq!(character("\\n").unwrap(), Character('n'));
+
+    assert!(character("\\").is_err());
+    assert!(character("\\ ").is_err());
+    assert!(character("c").is_err());
+
+    assert_eq!(value("[\\a \\b]").unwrap(), Vector(vec![Character('a'), Character('b')]));
+    assert_eq!(Character('\n').to_string(), "\\newline");
+    assert_eq!(Character('x').to_string(), "\\x");
+}
+
+#[test]
+fn test_span_character() {
+    assert_eq!(parse::value(" \\newline ").unwrap(), ValueAndSpan {
+        inner: SpannedValue::Character('\n'),
+        span: Span(1, 9)
+    });
+}
+
 #[test]
 fn test_symbol() {
     assert_eq!(symbol("$").unwrap(), s_plain("$"));
@@ -1320,6 +1374,137 @@ fn test_spurious_commas() {
     assert_eq!(value("[3,,]"), result);
 }
 
+#[test]
+fn test_tagged() {
+    use self::Value::*;
+
+    assert_eq!(tagged("#myapp/Person {:first \"Fred\"}").unwrap(),
+               Tagged("myapp/Person".to_string(),
+                      Box::new(Map(BTreeMap::from_iter(vec![(k_plain("first"), Text("Fred".to_string()))])))));
+    assert_eq!(tagged("#foo.bar/baz [1]").unwrap(),
+               Tagged("foo.bar/baz".to_string(), Box::new(Vector(vec![Integer(1)]))));
+    assert_eq!(tagged("#point[1 2]").unwrap(),
+               Tagged("point".to_string(), Box::new(Vector(vec![Integer(1), Integer(2)]))));
+    assert_eq!(tagged("#a #b 1").unwrap(),
+               Tagged("a".to_string(), Box::new(Tagged("b".to_string(), Box::new(Integer(1))))));
+
+    // Tags with a built-in meaning are only read by their own rules.
+    assert_eq!(tagged("#foo 1").unwrap(), Tagged("foo".to_string(), Box::new(Integer(1))));
+    assert_eq!(tagged("#instant 1").unwrap(), Tagged("instant".to_string(), Box::new(Integer(1))));
+    assert!(value("#inst 1").is_err());
+    assert!(value("#uuid \"not-a-uuid\"").is_err());
+    assert!(value("#f 1").is_err());
+
+    // Tags must start with an alphabetic character and be followed by an element.
+    assert!(tagged("#1 2").is_err());
+    assert!(tagged("#foo").is_err());
+    assert!(value("[#foo]").is_err());
+
+    assert_eq!(value("#myapp/Person {:first \"Fred\"}").unwrap().to_string(),
+               "#myapp/Person { :first \"Fred\" }");
+}
+
+#[test]
+fn test_span_tagged() {
+    assert_eq!(parse::value(" #foo 1 ").unwrap(), ValueAndSpan {
+        inner: SpannedValue::Tagged("foo".to_string(), Box::new(ValueAndSpan {
+            inner: SpannedValue::Integer(1),
+            span: Span(6, 7),
+        })),
+        span: Span(1, 7),
+    });
+}
+
+#[test]
+fn test_discard() {
+    use self::Value::*;
+
+    assert_eq!(value("#_ 1 2"), Ok(Integer(2)));
+    assert_eq!(value("#_1 2"), Ok(Integer(2)));
+    assert_eq!(value("2 #_ 1"), Ok(Integer(2)));
+    assert_eq!(value("[1 #_ 2 3]"), Ok(Vector(vec![Integer(1), Integer(3)])));
+    assert_eq!(value("[1 #_ [2 3]]"), Ok(Vector(vec![Integer(1)])));
+    assert_eq!(value("[#_ #_ 1 2 3]"), Ok(Vector(vec![Integer(3)])));
+    assert_eq!(value("{:a 1 #_ :b #_ 2}"), Ok(Map(BTreeMap::from_iter(vec![(k_plain("a"), Integer(1))]))));
+    assert_eq!(value("(#_ #foo 1)"), Ok(List(LinkedList::new())));
+
+    // `#_` must be followed by an element.
+    assert!(value("[1 #_]").is_err());
+    assert!(value("#_").is_err());
+}
+
+/// A tiny deterministic xorshift generator, so the round-trip tests are reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % (n as u64)) as usize
+    }
+
+    fn choose<'a, T>(&mut self, xs: &'a [T]) -> &'a T {
+        &xs[self.below(xs.len())]
+    }
+}
+
+// Characters that need escaping or are otherwise awkward to print.
+const AWKWARD_CHARS: &'static [char] = &['a', 'Z', '0', ' ', ',', '"', '\\', '\n', '\r', '\t',
+                                         '\u{0}', '\u{8}', '\u{c}', '\u{7f}', '\u{a0}', 'é',
+                                         '\u{1F600}', 'n', 'u', '#', ';', '{', ']'];
+
+fn arbitrary_value(rng: &mut Rng, depth: usize) -> Value {
+    let names = ["a", "foo", "foo-bar", "x?", "*y*", "z_w", "<=>", "ab1"];
+    let kind = if depth == 0 { rng.below(13) } else { rng.below(18) };
+    match kind {
+        0 => Value::Nil,
+        1 => Value::Boolean(rng.below(2) == 0),
+        2 => Value::Integer(*rng.choose(&[0, 1, -1, 42, i64::max_value(), i64::min_value()])),
+        3 => Value::Integer(rng.next() as i64),
+        4 => Value::BigInteger((rng.next() as i64).to_bigint().unwrap() * 1000i64.to_bigint().unwrap()),
+        5 => Value::Float(OrderedFloat(*rng.choose(&[0.0, -0.0, 1.0, -2.5, 0.1, 1e21, 1e-7,
+                                                      f64::NAN, f64::INFINITY, f64::NEG_INFINITY]))),
+        6 => Value::Instant(Utc.timestamp((rng.next() % 4_000_000_000) as i64,
+                                           (rng.below(1000) * 1_000_000) as u32)),
+        7 => Value::Text((0..rng.below(8)).map(|_| *rng.choose(AWKWARD_CHARS)).collect()),
+        8 => Value::Character(*rng.choose(AWKWARD_CHARS)),
+        9 => Value::Uuid(*rng.choose(&[uuid::Uuid::parse_str("550e8400-e29b-41d4-a716-446655440000").unwrap(),
+                                       uuid::Uuid::nil()])),
+        10 => s_plain(rng.choose(&names)),
+        11 => k_plain(rng.choose(&names)),
+        12 => if rng.below(2) == 0 {
+            s_ns(rng.choose(&names), rng.choose(&names))
+        } else {
+            k_ns(rng.choose(&names), rng.choose(&names))
+        },
+        13 => Value::Vector((0..rng.below(4)).map(|_| arbitrary_value(rng, depth - 1)).collect()),
+        14 => Value::List((0..rng.below(4)).map(|_| arbitrary_value(rng, depth - 1)).collect()),
+        15 => Value::Set((0..rng.below(4)).map(|_| arbitrary_value(rng, depth - 1)).collect()),
+        16 => Value::Map((0..rng.below(4)).map(|_| (arbitrary_value(rng, depth - 1), arbitrary_value(rng, depth - 1))).collect()),
+        _ => Value::Tagged(rng.choose(&["myapp/Person", "t", "a.b/c-d"]).to_string(),
+                           Box::new(arbitrary_value(rng, depth - 1))),
+    }
+}
+
+#[test]
+fn test_print_parse_round_trip() {
+    let mut rng = Rng(0x2545F4914F6CDD1D);
+    for _ in 0..2000 {
+        let v = arbitrary_value(&mut rng, 3);
+
+        let printed = v.to_string();
+        assert_eq!(value(printed.as_str()).as_ref(), Ok(&v), "Display round trip of {}", printed);
+
+        let pretty = v.to_pretty(20).unwrap();
+        assert_eq!(value(pretty.as_str()).as_ref(), Ok(&v), "pretty round trip of {}", pretty);
+    }
+}
+
 #[test]
 fn test_utils_merge() {
     // Take BTreeMap instances, wrap into Value::Map instances.
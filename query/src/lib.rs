@@ -386,6 +386,8 @@ impl FromValue<PatternValuePlace> for PatternValuePlace {
             edn::SpannedValue::List(_) => None,
             edn::SpannedValue::Set(_) => None,
             edn::SpannedValue::Vector(_) => None,
+            edn::SpannedValue::Character(_) => None,
+            edn::SpannedValue::Tagged(_, _) => None,
         }
     }
 }
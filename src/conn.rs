@@ -10,13 +10,13 @@
 
 #![allow(dead_code)]
 
+use std::cmp;
+
 use std::fs::{
     File,
 };
 
-use std::io::{
-    Read,
-};
+use std::io::Write;
 
 use std::path::{
     Path,
@@ -52,15 +52,31 @@ use mentat_db::cache::{
     SQLiteAttributeCache,
 };
 
+use mentat_db::datoms::{
+    self,
+    Datom,
+    DatomIndex,
+    DatomPattern,
+};
 use mentat_db::db;
 use mentat_db::{
-    transact,
-    transact_terms,
+    next_partition_start,
+    transact_terms_with_options,
+    transact_with_options,
+    Partition,
     PartitionMap,
+    TxFunction,
+    TxFunctions,
+    TxOptions,
     TxReport,
+    UnresolvedRetractions,
+    UpsertConflicts,
 };
 
-use mentat_db::internal_types::TermWithTempIds;
+use mentat_db::internal_types::{
+    TempIdPartitions,
+    TermWithTempIdsAndLookupRefs,
+};
 
 use mentat_tx;
 
@@ -69,15 +85,32 @@ use mentat_tx::entities::TempId;
 use mentat_tx_parser;
 
 use mentat_tolstoy::Syncer;
+use mentat_tolstoy::local_only::LocalOnly;
+use mentat_tolstoy::schema::ensure_current_version;
+use mentat_tolstoy::syncer::{
+    NoopSyncProgress,
+    SyncProgress,
+    SyncReport,
+};
 
 use uuid::Uuid;
 
 use entity_builder::{
     InProgressBuilder,
+    TermsInPartitions,
 };
 
 use errors::*;
 
+use export;
+
+use tx_observer::{
+    self,
+    TxCommitted,
+    TxObservationService,
+    TxObserver,
+};
+
 use query::{
     Known,
     PreparedResult,
@@ -136,7 +169,12 @@ pub struct Conn {
     /// replaced on commit.
     metadata: Mutex<Metadata>,
 
-    // TODO: maintain set of change listeners or handles to transaction report queues. #298.
+    /// Observers to notify when an `InProgress` commits.
+    tx_observer_service: Mutex<TxObservationService>,
+
+    /// Transaction functions that entities may call.  Each `InProgress` takes a snapshot when it
+    /// begins, so registering a function doesn't affect transactions already in progress.
+    tx_functions: Mutex<Arc<TxFunctions>>,
 
     // TODO: maintain cache of query plans that could be shared across threads and invalidated when
     // the schema changes. #315.
@@ -189,7 +227,18 @@ pub trait Queryable {
 }
 
 pub trait Syncable {
-    fn sync(&mut self, server_uri: &String, user_uuid: &String) -> Result<()>;
+    fn sync(&mut self, server_uri: &String, user_uuid: &String) -> Result<SyncReport>;
+
+    /// Like `sync`, but reports each step to the provided `SyncProgress` as it happens.
+    fn sync_with_progress(&mut self, server_uri: &String, user_uuid: &String, progress: &mut SyncProgress) -> Result<SyncReport>;
+
+    /// Keep the values of `attribute` on this device: they won't be uploaded. Pass `false` to sync
+    /// them again.
+    fn set_local_only(&mut self, attribute: &NamespacedKeyword, local_only: bool) -> Result<()>;
+
+    /// Keep the entities in `partition` on this device: datoms about them, or referring to them,
+    /// won't be uploaded. Pass `false` to sync them again.
+    fn set_partition_local_only(&mut self, partition: &NamespacedKeyword, local_only: bool) -> Result<()>;
 }
 
 /// Represents an in-progress, not yet committed, set of changes to the store.
@@ -199,6 +248,7 @@ pub trait Syncable {
 pub struct InProgress<'a, 'c> {
     transaction: rusqlite::Transaction<'c>,
     mutex: &'a Mutex<Metadata>,
+    tx_observer_service: &'a Mutex<TxObservationService>,
     generation: u64,
     partition_map: PartitionMap,
     schema: Schema,
@@ -206,6 +256,40 @@ pub struct InProgress<'a, 'c> {
     cache: InProgressSQLiteAttributeCache,
 
     use_caching: bool,
+
+    tx_functions: Arc<TxFunctions>,
+
+    unresolved_retractions: UnresolvedRetractions,
+
+    upsert_conflicts: UpsertConflicts,
+
+    /// The transactions applied so far, for observers to hear about once they're committed.
+    applied: AppliedTransactions,
+}
+
+/// What an `InProgress` remembers of the transactions it applies.  Reports, with their tempids,
+/// are only kept if there were observers when it began, so that loading a large dump without
+/// observers doesn't hold every report until commit.
+enum AppliedTransactions {
+    Reports(Vec<TxReport>),
+    Ids(Vec<Entid>),
+}
+
+impl AppliedTransactions {
+    fn new(observed: bool) -> AppliedTransactions {
+        if observed {
+            AppliedTransactions::Reports(Vec::new())
+        } else {
+            AppliedTransactions::Ids(Vec::new())
+        }
+    }
+
+    fn push(&mut self, report: &TxReport) {
+        match *self {
+            AppliedTransactions::Reports(ref mut reports) => reports.push(report.clone()),
+            AppliedTransactions::Ids(ref mut ids) => ids.push(report.tx_id),
+        }
+    }
 }
 
 /// Represents an in-progress set of reads to the store. Just like `InProgress`,
@@ -365,19 +449,49 @@ impl<'a, 'c> InProgress<'a, 'c> {
         self.use_caching = yesno;
     }
 
-    pub fn transact_terms<I>(&mut self, terms: I, tempid_set: InternSet<TempId>) -> Result<TxReport> where I: IntoIterator<Item=TermWithTempIds> {
+    /// Choose what to do with `[:db/retract ...]` entities that reference tempids that don't
+    /// upsert.  By default they are ignored.
+    pub fn unresolved_retractions(&mut self, unresolved_retractions: UnresolvedRetractions) {
+        self.unresolved_retractions = unresolved_retractions;
+    }
+
+    /// Choose what to do when a tempid upserts to more than one entity.  By default the
+    /// transaction fails.
+    pub fn upsert_conflicts(&mut self, upsert_conflicts: UpsertConflicts) {
+        self.upsert_conflicts = upsert_conflicts;
+    }
+
+    pub fn transact_terms<I>(&mut self, terms: I, tempid_set: InternSet<TempId>) -> Result<TxReport> where I: IntoIterator<Item=TermWithTempIdsAndLookupRefs> {
+        self.transact_terms_in_partitions(terms, tempid_set, TempIdPartitions::default())
+    }
+
+    /// Just like `transact_terms`, but allocates the tempids in `temp_id_partitions` that don't
+    /// upsert in the partitions they name, rather than in `:db.part/user`.
+    pub fn transact_terms_in_partitions<I>(&mut self, terms: I, tempid_set: InternSet<TempId>, temp_id_partitions: TempIdPartitions) -> Result<TxReport> where I: IntoIterator<Item=TermWithTempIdsAndLookupRefs> {
+        self.transact_terms_with_views(terms, tempid_set, temp_id_partitions, false)
+    }
+
+    fn transact_terms_with_views<I>(&mut self, terms: I, tempid_set: InternSet<TempId>, temp_id_partitions: TempIdPartitions, defer_metadata_views: bool) -> Result<TxReport> where I: IntoIterator<Item=TermWithTempIdsAndLookupRefs> {
         let (report, next_partition_map, next_schema, _watcher) =
-            transact_terms(&self.transaction,
-                           self.partition_map.clone(),
-                           &self.schema,
-                           &self.schema,
-                           self.cache.transact_watcher(),
-                           terms,
-                           tempid_set)?;
+            transact_terms_with_options(&self.transaction,
+                                        self.partition_map.clone(),
+                                        &self.schema,
+                                        &self.schema,
+                                        self.cache.transact_watcher(),
+                                        TxOptions {
+                                            functions: None,
+                                            unresolved_retractions: self.unresolved_retractions,
+                                            upsert_conflicts: self.upsert_conflicts,
+                                            defer_metadata_views: defer_metadata_views,
+                                        },
+                                        terms,
+                                        tempid_set,
+                                        temp_id_partitions)?;
         self.partition_map = next_partition_map;
         if let Some(schema) = next_schema {
             self.schema = schema;
         }
+        self.applied.push(&report);
         Ok(report)
     }
 
@@ -391,16 +505,23 @@ impl<'a, 'c> InProgress<'a, 'c> {
         //    `Default::default` in those situations to extract the partition map, and so there
         //    would still be some cost.
         let (report, next_partition_map, next_schema, _watcher) =
-            transact(&self.transaction,
-                     self.partition_map.clone(),
-                     &self.schema,
-                     &self.schema,
-                     self.cache.transact_watcher(),
-                     entities)?;
+            transact_with_options(&self.transaction,
+                                  self.partition_map.clone(),
+                                  &self.schema,
+                                  &self.schema,
+                                  self.cache.transact_watcher(),
+                                  TxOptions {
+                                      functions: Some(&*self.tx_functions),
+                                      unresolved_retractions: self.unresolved_retractions,
+                                      upsert_conflicts: self.upsert_conflicts,
+                                      defer_metadata_views: false,
+                                  },
+                                  entities)?;
         self.partition_map = next_partition_map;
         if let Some(schema) = next_schema {
             self.schema = schema;
         }
+        self.applied.push(&report);
         Ok(report)
     }
 
@@ -410,12 +531,117 @@ impl<'a, 'c> InProgress<'a, 'c> {
         self.transact_entities(entities)
     }
 
+    /// Transact the vector of entities in the EDN file at `path` as a single transaction. The file
+    /// is read incrementally, but every entity is parsed before any is transacted, so the whole
+    /// file is held in memory; use `import_in_batches` for files too large for that.
     pub fn import<P>(&mut self, path: P) -> Result<TxReport>
     where P: AsRef<Path> {
-        let mut file = File::open(path)?;
-        let mut text: String = String::new();
-        file.read_to_string(&mut text)?;
-        self.transact(text.as_str())
+        let entities = edn::reader::Reader::vector_elements(File::open(path)?)
+            .map(|element| -> Result<mentat_tx::entities::Entity> {
+                Ok(mentat_tx_parser::Tx::parse_entity(&element?)?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.transact_entities(entities)
+    }
+
+    /// Like `import`, but transact at most `batch_size` entities at a time, so that files of any
+    /// size can be imported in bounded memory. Tempids are resolved within a batch: the same
+    /// tempid in two batches names two different entities. Returns the number of transactions.
+    pub fn import_in_batches<P>(&mut self, path: P, batch_size: usize) -> Result<usize>
+    where P: AsRef<Path> {
+        if batch_size == 0 {
+            bail!(ErrorKind::InvalidBatchSize(batch_size));
+        }
+
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut transactions = 0;
+        for element in edn::reader::Reader::vector_elements(File::open(path)?) {
+            batch.push(mentat_tx_parser::Tx::parse_entity(&element?)?);
+            if batch.len() == batch_size {
+                self.transact_entities(batch.drain(..))?;
+                transactions += 1;
+            }
+        }
+        if !batch.is_empty() {
+            self.transact_entities(batch)?;
+            transactions += 1;
+        }
+        Ok(transactions)
+    }
+
+    /// Transact each of `transactions` in turn, with the same result as transacting each with
+    /// `transact_terms_in_partitions`, but faster.  Each transaction still goes through the whole
+    /// transactor; what's saved is index and view maintenance.  While loading, the datoms table's
+    /// VAET and fulltext indexes are dropped, and the metadata views aren't maintained; afterwards,
+    /// the indexes are created afresh and the views are rebuilt once.  The AVET and unique value
+    /// indexes are kept, so upserts and lookup refs stay fast and a `:db/unique` violation fails
+    /// the transaction that causes it.  A failure is reported as `BulkLoadFailed`, naming the
+    /// transaction by its position in `transactions`.  The indexes and views are restored even if
+    /// loading fails, but the transactions before the failure remain: roll back to discard them.
+    /// Returns the report of each transaction.
+    pub fn bulk_load<I>(&mut self, transactions: I) -> Result<Vec<TxReport>>
+    where I: IntoIterator<Item=TermsInPartitions> {
+        db::drop_secondary_indexes(&self.transaction)?;
+
+        let mut reports = vec![];
+        let mut loaded: Result<()> = Ok(());
+        for (index, (terms, tempid_set, temp_id_partitions)) in transactions.into_iter().enumerate() {
+            match self.transact_terms_with_views(terms, tempid_set, temp_id_partitions, true).chain_err(|| ErrorKind::BulkLoadFailed(index)) {
+                Ok(report) => reports.push(report),
+                Err(e) => {
+                    loaded = Err(e);
+                    break;
+                },
+            }
+        }
+
+        // Even after a failure, since the caller might commit what did load.
+        let restored = db::rebuild_metadata_views(&self.transaction, &self.schema)
+            .and_then(|_| db::create_secondary_indexes(&self.transaction));
+        loaded?;
+        restored?;
+        Ok(reports)
+    }
+
+    /// Replay an export written by `Store::export` into this store, which must be empty. Entities
+    /// keep their entids and transactions keep their `:db/txInstant`. Returns the number of
+    /// transactions replayed.
+    pub fn restore<P>(&mut self, path: P) -> Result<usize>
+    where P: AsRef<Path> {
+        let mut forms = edn::reader::Reader::new(File::open(path)?);
+        let partitions = match forms.next() {
+            Some(header) => export::read_header(&header?.without_spans())?,
+            None => bail!(ErrorKind::InvalidExport("expected a header map".to_string())),
+        };
+        if export::has_transactions(&self.transaction)? {
+            bail!(ErrorKind::StoreNotEmpty);
+        }
+
+        // Claim every entid the exported store had allocated, so that the exported entids can be
+        // asserted directly and new entids don't collide with them.  Partitions that the exported
+        // store installed are created here, in the ranges that installing them gave them there:
+        // installed in order, each starts above every partition before it.
+        let mut partitions: Vec<(String, Entid)> = partitions.into_iter().collect();
+        partitions.sort_by_key(|&(_, index)| index);
+        for (name, index) in partitions {
+            if let Some(partition) = self.partition_map.get_mut(&name) {
+                partition.index = cmp::max(partition.index, index);
+                continue;
+            }
+            let start = next_partition_start(&self.partition_map);
+            if index < start {
+                bail!(ErrorKind::InvalidExport(format!("partition {} allocated {} below its start {}", name, index, start)));
+            }
+            self.partition_map.insert(name, Partition::new(start, index));
+        }
+
+        let mut transactions = 0;
+        for form in forms {
+            let entities = mentat_tx_parser::Tx::parse(&form?)?;
+            self.transact_entities(entities)?;
+            transactions += 1;
+        }
+        Ok(transactions)
     }
 
     pub fn rollback(self) -> Result<()> {
@@ -423,6 +649,34 @@ impl<'a, 'c> InProgress<'a, 'c> {
     }
 
     pub fn commit(self) -> Result<()> {
+        // Observers are told about the transactions' datoms, which we read from the log while we
+        // can still see them.
+        let observers = self.tx_observer_service.lock().unwrap().observers();
+        let mut committed = Vec::new();
+        if !observers.is_empty() {
+            match self.applied {
+                AppliedTransactions::Reports(ref reports) => {
+                    for report in reports.iter() {
+                        committed.push(TxCommitted {
+                            report: report.clone(),
+                            datoms: datoms::transaction_datoms(&self.transaction, &self.schema, report.tx_id)?,
+                        });
+                    }
+                },
+                // `Conn::begin_transaction` borrows the `Conn` mutably, so nobody can have
+                // registered an observer since we began.
+                AppliedTransactions::Ids(_) => {},
+            }
+        }
+
+        self.commit_metadata()?;
+
+        // No locks are held while observers are notified.
+        tx_observer::notify(&observers, &committed);
+        Ok(())
+    }
+
+    fn commit_metadata(self) -> Result<()> {
         // The mutex is taken during this entire method.
         let mut metadata = self.mutex.lock().unwrap();
 
@@ -476,6 +730,16 @@ impl<'a, 'c> InProgress<'a, 'c> {
             },
         }
     }
+
+    /// The datoms in `index` that match `pattern`, including those written by this transaction.
+    pub fn datoms(&self, index: DatomIndex, pattern: &DatomPattern) -> Result<Vec<Datom>> {
+        Ok(datoms::datoms(&self.transaction, index, pattern)?)
+    }
+
+    /// The datoms asserted and retracted by the transaction `tx`.
+    pub fn transaction_datoms(&self, tx: Entid) -> Result<Vec<Datom>> {
+        Ok(datoms::transaction_datoms(&self.transaction, &self.schema, tx)?)
+    }
 }
 
 impl Store {
@@ -510,6 +774,58 @@ impl Store {
                         direction,
                         CacheAction::Register)
     }
+
+    /// The datoms in `index` that match `pattern`.
+    pub fn datoms(&self, index: DatomIndex, pattern: &DatomPattern) -> Result<Vec<Datom>> {
+        Ok(datoms::datoms(&self.sqlite, index, pattern)?)
+    }
+
+    /// The datoms asserted and retracted by the transaction `tx`.
+    pub fn transaction_datoms(&self, tx: Entid) -> Result<Vec<Datom>> {
+        Ok(datoms::transaction_datoms(&self.sqlite, &self.conn.current_schema(), tx)?)
+    }
+
+    pub fn register_tx_function(&self, name: NamespacedKeyword, function: Arc<TxFunction>) -> Result<()> {
+        self.conn.register_tx_function(name, function)
+    }
+
+    pub fn unregister_tx_function(&self, name: &NamespacedKeyword) {
+        self.conn.unregister_tx_function(name);
+    }
+
+    /// Bulk load `transactions` and commit them: see `InProgress::bulk_load`.  If loading fails,
+    /// nothing is committed.
+    pub fn bulk_load<I>(&mut self, transactions: I) -> Result<Vec<TxReport>>
+    where I: IntoIterator<Item=TermsInPartitions> {
+        let mut in_progress = self.begin_transaction()?;
+        let reports = in_progress.bulk_load(transactions)?;
+        in_progress.commit()?;
+        Ok(reports)
+    }
+
+    /// Write the store's schema and data to `out`, in a form that `InProgress::restore` can replay
+    /// into an empty store. If `history` is set, every transaction is written, including
+    /// retractions; otherwise only current datoms are. Returns the number of transactions written.
+    /// Restoring gives transactions new ids, so this fails with `UnexportableTxRef` if a datom of
+    /// one transaction refers to another transaction.
+    pub fn export<W>(&self, out: &mut W, history: bool) -> Result<usize>
+    where W: Write {
+        let (partition_map, schema) = {
+            let metadata = self.conn.metadata.lock().unwrap();
+            (metadata.partition_map.clone(), metadata.schema.clone())
+        };
+        export::write_export(&self.sqlite, &schema, &partition_map, out, history)
+    }
+
+    /// Registers `observer` to be told about each transaction committed to this store, replacing
+    /// any observer already registered under `key`.
+    pub fn register_observer(&self, key: String, observer: Arc<TxObserver>) {
+        self.conn.register_observer(key, observer);
+    }
+
+    pub fn unregister_observer(&self, key: &str) {
+        self.conn.unregister_observer(key);
+    }
 }
 
 impl Queryable for Store {
@@ -553,9 +869,42 @@ pub enum CacheAction {
 }
 
 impl Syncable for Store {
-    fn sync(&mut self, server_uri: &String, user_uuid: &String) -> Result<()> {
+    fn sync(&mut self, server_uri: &String, user_uuid: &String) -> Result<SyncReport> {
+        self.sync_with_progress(server_uri, user_uuid, &mut NoopSyncProgress)
+    }
+
+    fn sync_with_progress(&mut self, server_uri: &String, user_uuid: &String, progress: &mut SyncProgress) -> Result<SyncReport> {
         let uuid = Uuid::parse_str(&user_uuid)?;
-        Ok(Syncer::flow(&mut self.sqlite, server_uri, &uuid)?)
+        Ok(Syncer::flow_with_progress(&mut self.sqlite, server_uri, &uuid, progress)?)
+    }
+
+    fn set_local_only(&mut self, attribute: &NamespacedKeyword, local_only: bool) -> Result<()> {
+        let entid = self.conn.current_schema()
+                             .attribute_for_ident(attribute)
+                             .ok_or_else(|| ErrorKind::UnknownAttribute(attribute.to_string()))?.1;
+
+        ensure_current_version(&mut self.sqlite)?;
+        let tx = self.sqlite.transaction()?;
+        LocalOnly::set_attribute(&tx, entid.into(), local_only)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn set_partition_local_only(&mut self, partition: &NamespacedKeyword, local_only: bool) -> Result<()> {
+        let name = partition.to_string();
+        // Other devices need the schema, and every transaction's own datoms.
+        if name == ":db.part/db" || name == ":db.part/tx" {
+            bail!(ErrorKind::ReservedLocalOnlyPartition(name));
+        }
+        if !self.conn.metadata.lock().unwrap().partition_map.contains_key(&name) {
+            bail!(ErrorKind::DbError(::mentat_db::ErrorKind::UnrecognizedPartition(name)));
+        }
+
+        ensure_current_version(&mut self.sqlite)?;
+        let tx = self.sqlite.transaction()?;
+        LocalOnly::set_partition(&tx, &name, local_only)?;
+        tx.commit()?;
+        Ok(())
     }
 }
 
@@ -564,6 +913,8 @@ impl Conn {
     fn new(partition_map: PartitionMap, schema: Schema) -> Conn {
         Conn {
             metadata: Mutex::new(Metadata::new(0, partition_map, Arc::new(schema), Default::default())),
+            tx_observer_service: Mutex::new(Default::default()),
+            tx_functions: Mutex::new(Default::default()),
         }
     }
 
@@ -696,15 +1047,22 @@ impl Conn {
              current.schema.clone(),
              current.attribute_cache.clone())
         };
+        let tx_functions = self.tx_functions.lock().unwrap().clone();
+        let observed = self.tx_observer_service.lock().unwrap().has_observers();
 
         Ok(InProgress {
             mutex: &self.metadata,
+            tx_observer_service: &self.tx_observer_service,
             transaction: tx,
             generation: current_generation,
             partition_map: current_partition_map,
             schema: (*current_schema).clone(),
             cache: InProgressSQLiteAttributeCache::from_cache(cache_cow),
             use_caching: true,
+            tx_functions: tx_functions,
+            unresolved_retractions: UnresolvedRetractions::default(),
+            upsert_conflicts: UpsertConflicts::default(),
+            applied: AppliedTransactions::new(observed),
         })
     }
 
@@ -750,6 +1108,30 @@ impl Conn {
         Ok(report)
     }
 
+    /// Registers `observer` to be notified of committed transactions, replacing any observer
+    /// already registered under `key`.
+    pub fn register_observer(&self, key: String, observer: Arc<TxObserver>) {
+        self.tx_observer_service.lock().unwrap().register(key, observer);
+    }
+
+    pub fn unregister_observer(&self, key: &str) {
+        self.tx_observer_service.lock().unwrap().deregister(key);
+    }
+
+    /// Registers `function` to be called by entities like `[:my.fn/name args...]`, replacing any
+    /// function already registered under `name`.  The `:db` namespace is reserved.
+    pub fn register_tx_function(&self, name: NamespacedKeyword, function: Arc<TxFunction>) -> Result<()> {
+        if name.namespace == "db" {
+            bail!(ErrorKind::ReservedTxFunctionName(name.to_string()));
+        }
+        Arc::make_mut(&mut *self.tx_functions.lock().unwrap()).insert(name, function);
+        Ok(())
+    }
+
+    pub fn unregister_tx_function(&self, name: &NamespacedKeyword) {
+        Arc::make_mut(&mut *self.tx_functions.lock().unwrap()).remove(name);
+    }
+
     /// Adds or removes the values of a given attribute to an in-memory cache.
     /// The attribute should be a namespaced string: e.g., `:foo/bar`.
     /// `cache_action` determines if the attribute should be added or removed from the cache.
@@ -803,6 +1185,8 @@ mod tests {
         PathBuf,
     };
 
+    use std::rc::Rc;
+
     use std::time::Instant;
 
     use mentat_core::{
@@ -823,6 +1207,11 @@ mod tests {
 
     use mentat_db::USER0;
 
+    use entity_builder::{
+        BuildTerms,
+        TermBuilder,
+    };
+
     #[test]
     fn test_transact_does_not_collide_existing_entids() {
         let mut sqlite = db::new_connection("").unwrap();
@@ -1044,8 +1433,107 @@ mod tests {
         let report = conn.transact(&mut sqlite, "[[:db/add \"u\" :db/ident :a/keyword]
                                                   [:db/add \"u\" :db/ident :b/keyword]]");
         match report.unwrap_err() {
-            Error(ErrorKind::DbError(::mentat_db::errors::ErrorKind::NotYetImplemented(_)), _) => { },
-            x => panic!("expected EDN parse error, got {:?}", x),
+            Error(ErrorKind::DbError(::mentat_db::errors::ErrorKind::ConflictingUpsert(tempid, conflicts)), _) => {
+                assert_eq!(tempid, "u");
+                assert_eq!(conflicts.len(), 2);
+            },
+            x => panic!("expected conflicting upsert, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_unresolved_retractions() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+        let t = "[[:db/retract \"t\" :db/ident :a/keyword]]";
+
+        // By default, retracting a tempid that doesn't upsert does nothing.
+        let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+        let report = in_progress.transact(t).expect("transacted successfully");
+        assert!(report.tempids.is_empty());
+
+        // But it can be made to fail the transaction.
+        in_progress.unresolved_retractions(UnresolvedRetractions::Fail);
+        match in_progress.transact(t).unwrap_err() {
+            Error(ErrorKind::DbError(::mentat_db::errors::ErrorKind::UnresolvedRetraction(tempid)), _) => {
+                assert_eq!(tempid, "t");
+            },
+            x => panic!("expected unresolved retraction, got {:?}", x),
+        }
+
+        // Retracting a whole entity or attribute is no different.
+        for t in &["[[:db/retractEntity \"t\"]]", "[[:db/retractAttribute \"t\" :db/ident]]"] {
+            match in_progress.transact(*t).unwrap_err() {
+                Error(ErrorKind::DbError(::mentat_db::errors::ErrorKind::UnresolvedRetraction(tempid)), _) => {
+                    assert_eq!(tempid, "t");
+                },
+                x => panic!("expected unresolved retraction, got {:?}", x),
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_conflicting_upserts() {
+        let mut sqlite = db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+        let mut in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+        in_progress.transact(r#"[
+            {:db/ident :person/email :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity}
+            {:db/ident :person/phone :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity}
+            {:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :person/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/many}
+        ]"#).expect("transacted schema");
+        let report = in_progress.transact(r#"[
+            {:db/id "a" :person/email "alice@example.com" :person/name "Alice"}
+            {:db/id "b" :person/phone "555-1234" :person/name "Bob"}
+            {:db/id "c" :person/email "carol@example.com" :person/friend "b"}
+        ]"#).expect("transacted people");
+        let (a, b, c) = (report.tempids["a"], report.tempids["b"], report.tempids["c"]);
+
+        in_progress.upsert_conflicts(UpsertConflicts::Merge);
+        let report = in_progress.transact(r#"[
+            [:db/add "t" :person/email "alice@example.com"]
+            [:db/add "t" :person/phone "555-1234"]
+        ]"#).expect("merged");
+        assert_eq!(report.tempids["t"], a);
+
+        {
+            let value = |e: Entid, name: &str| {
+                in_progress.lookup_value_for_attribute(e, &NamespacedKeyword::new("person", name)).expect("looked up")
+            };
+
+            // Alice has both identities, and keeps her own name.
+            assert_eq!(value(a, "email"), Some(TypedValue::typed_string("alice@example.com")));
+            assert_eq!(value(a, "phone"), Some(TypedValue::typed_string("555-1234")));
+            assert_eq!(value(a, "name"), Some(TypedValue::typed_string("Alice")));
+
+            // Bob is gone, and references to him are now references to Alice.
+            assert_eq!(value(b, "phone"), None);
+            assert_eq!(value(b, "name"), None);
+            assert_eq!(value(c, "friend"), Some(TypedValue::Ref(a)));
+        }
+
+        // Entities that belong to the schema are never merged.
+        let email = in_progress.attribute_for_ident(&kw!(:person/email)).expect("attribute").1;
+        in_progress.transact(r#"[{:db/ident :person/dana :person/email "dana@example.com"}]"#).expect("transacted Dana");
+        let dana = in_progress.transact(r#"[
+            [:db/add "t" :person/email "dana@example.com"]
+            [:db/add "t" :person/phone "555-1234"]
+        ]"#);
+        match dana.expect_err("Dana has an ident") {
+            Error(ErrorKind::DbError(::mentat_db::errors::ErrorKind::UnmergeableEntity(tempid, _)), _) => assert_eq!(tempid, "t"),
+            x => panic!("expected unmergeable entity, got {:?}", x),
+        }
+        let attribute = in_progress.transact(r#"[
+            [:db/add "t" :db/ident :person/email]
+            [:db/add "t" :person/phone "555-1234"]
+        ]"#);
+        match attribute.expect_err(":person/email has an ident") {
+            Error(ErrorKind::DbError(::mentat_db::errors::ErrorKind::UnmergeableEntity(tempid, e)), _) => {
+                assert_eq!(tempid, "t");
+                assert_eq!(KnownEntid(e), email);
+            },
+            x => panic!("expected unmergeable entity, got {:?}", x),
         }
     }
 
@@ -1197,6 +1685,134 @@ mod tests {
         fixtures.join(Path::new(rest))
     }
 
+    #[test]
+    fn test_import_in_batches() {
+        let mut store = Store::open("").expect("opened");
+        let mut in_progress = store.begin_transaction().expect("began");
+        let transactions = in_progress.import_in_batches(fixture_path("cities.schema"), 7).expect("transacted schema");
+
+        // 30 attributes, 7 at a time.
+        assert_eq!(transactions, 5);
+        assert!(in_progress.attribute_for_ident(&kw!(:community/name)).is_some());
+        assert!(in_progress.attribute_for_ident(&kw!(:district/region)).is_some());
+
+        match in_progress.import_in_batches(fixture_path("cities.schema"), 0).expect_err("empty batches") {
+            Error(ErrorKind::InvalidBatchSize(0), _) => (),
+            e => panic!("expected an invalid batch size, got {:?}", e),
+        }
+    }
+
+    fn datoms_index_count(store: &mut Store) -> i64 {
+        store.sqlite_mut()
+             .query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'index' AND name LIKE 'idx_datoms_%'", &[], |row| row.get(0))
+             .expect("counted")
+    }
+
+    #[test]
+    fn test_bulk_load() {
+        let mut store = Store::open("").expect("opened");
+        {
+            let mut in_progress = store.begin_transaction().expect("began");
+            in_progress.transact(r#"[
+                {:db/ident :test/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/value}
+                {:db/ident :test/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/many}
+            ]"#).expect("schema");
+            in_progress.commit().expect("committed");
+        }
+        let indexes = datoms_index_count(&mut store);
+
+        let (ident, name, friend) = {
+            let schema = store.conn().current_schema();
+            (schema.get_entid(&kw!(:db/ident)).expect(":db/ident"),
+             schema.get_entid(&kw!(:test/name)).expect(":test/name"),
+             schema.get_entid(&kw!(:test/friend)).expect(":test/friend"))
+        };
+
+        let mut builder = TermBuilder::new();
+        let x = builder.named_tempid("x".into());
+        let y = builder.named_tempid("y".into());
+        builder.add(x.clone(), name, TypedValue::typed_string("Alice")).expect("added");
+        builder.add(y.clone(), name, TypedValue::typed_string("Bob")).expect("added");
+        builder.add(y, friend, x).expect("added");
+        let people = builder.build_in_partitions().expect("built");
+
+        // This transaction changes the metadata, which the bulk load defers.
+        let mut builder = TermBuilder::new();
+        let z = builder.named_tempid("z".into());
+        builder.add(z.clone(), ident, TypedValue::Keyword(Rc::new(kw!(:test/carol)))).expect("added");
+        builder.add(z, name, TypedValue::typed_string("Carol")).expect("added");
+        let carol = builder.build_in_partitions().expect("built");
+
+        let reports = store.bulk_load(vec![people, carol]).expect("loaded");
+        assert_eq!(reports.len(), 2);
+        let (alice, bob, carol) = (reports[0].tempids["x"], reports[0].tempids["y"], reports[1].tempids["z"]);
+
+        assert_eq!(store.begin_read().expect("began").lookup_value_for_attribute(bob, &kw!(:test/friend)).expect("looked up"),
+                   Some(TypedValue::Ref(alice)));
+        assert_eq!(store.conn().current_schema().get_entid(&kw!(:test/carol)), Some(KnownEntid(carol)));
+
+        // The metadata views were rebuilt, and the indexes created again.
+        let materialized = db::read_db(store.sqlite_mut()).expect("read");
+        assert_eq!(materialized.schema, *store.conn().current_schema());
+        assert_eq!(datoms_index_count(&mut store), indexes);
+
+        // Uniqueness is enforced by the transaction that violates it, and a failed load commits
+        // nothing.
+        let mut builder = TermBuilder::new();
+        let w = builder.named_tempid("w".into());
+        builder.add(w, name, TypedValue::typed_string("Walter")).expect("added");
+        let walter = builder.build_in_partitions().expect("built");
+        let mut builder = TermBuilder::new();
+        let w = builder.named_tempid("w".into());
+        builder.add(w, name, TypedValue::typed_string("Alice")).expect("added");
+        match store.bulk_load(vec![walter, builder.build_in_partitions().expect("built")]) {
+            Err(Error(ErrorKind::BulkLoadFailed(1), _)) => (),
+            x => panic!("expected the second transaction to fail, got {:?}", x),
+        }
+        assert_eq!(datoms_index_count(&mut store), indexes);
+        assert_eq!(store.q_once("[:find (count ?e) . :where [?e :test/name _]]", None).expect("counted").results,
+                   QueryResults::Scalar(Some(TypedValue::Long(3))));
+
+        // After a transaction fails, the indexes are back, even if what did load is committed.
+        let mut builder = TermBuilder::new();
+        let v = builder.named_tempid("v".into());
+        builder.add(v, name, TypedValue::typed_string("Dave")).expect("added");
+        let dave = builder.build_in_partitions().expect("built");
+        let mut builder = TermBuilder::new();
+        let u = builder.named_tempid("u".into());
+        builder.add(u, KnownEntid(99999), TypedValue::typed_string("bogus")).expect("added");
+        let bogus = builder.build_in_partitions().expect("built");
+        {
+            let mut in_progress = store.begin_transaction().expect("began");
+            assert!(in_progress.bulk_load(vec![dave, bogus]).is_err());
+            in_progress.commit().expect("committed");
+        }
+        assert_eq!(datoms_index_count(&mut store), indexes);
+        assert_eq!(store.q_once("[:find (count ?e) . :where [?e :test/name _]]", None).expect("counted").results,
+                   QueryResults::Scalar(Some(TypedValue::Long(4))));
+    }
+
+    #[test]
+    fn test_reports_are_kept_only_for_observers() {
+        let mut store = Store::open("").expect("opened");
+        {
+            let mut in_progress = store.begin_transaction().expect("began");
+            let report = in_progress.transact("[{:db/doc \"unobserved\"}]").expect("transacted");
+            match in_progress.applied {
+                AppliedTransactions::Ids(ref ids) => assert_eq!(ids, &vec![report.tx_id]),
+                AppliedTransactions::Reports(_) => panic!("expected only tx ids without observers"),
+            }
+        }
+
+        store.register_observer("all".to_string(), Arc::new(TxObserver::all(|_, _| {})));
+        let mut in_progress = store.begin_transaction().expect("began");
+        let report = in_progress.transact("[{:db/doc \"observed\"}]").expect("transacted");
+        match in_progress.applied {
+            AppliedTransactions::Reports(ref reports) => assert_eq!(reports, &vec![report]),
+            AppliedTransactions::Ids(_) => panic!("expected reports with an observer"),
+        }
+    }
+
     #[test]
     fn test_prepared_query_with_cache() {
         let mut store = Store::open("").expect("opened");
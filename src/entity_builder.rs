@@ -53,26 +53,39 @@
 // We probably need both, but this file provides the latter. Unfortunately, Entity -- the input to
 // the transactor -- is intimately tied to EDN and to spanned values.
 
+use std::borrow::BorrowMut;
+use std::marker::PhantomData;
+
 use mentat_core::{
+    DateTime,
     HasSchema,
     KnownEntid,
     NamespacedKeyword,
     TypedValue,
+    Utc,
+    Uuid,
+    ValueType,
 };
 
 use mentat_core::intern_set::InternSet;
 use mentat_core::util::Either;
 
+use mentat_db;
 use mentat_db::{
     TxReport,
+    TypedSQLValue,
 };
 
 use mentat_db::internal_types::{
     KnownEntidOr,
+    LookupRef,
+    LookupRefOrTempId,
     TempIdHandle,
+    TempIdPartitions,
     Term,
-    TermWithTempIds,
+    TermWithTempIdsAndLookupRefs,
     TypedValueOr,
+    request_partition,
 };
 
 use mentat_tx::entities::{
@@ -89,28 +102,63 @@ use errors::{
     Result,
 };
 
-pub type Terms = (Vec<TermWithTempIds>, InternSet<TempId>);
+pub type Terms = (Vec<TermWithTempIdsAndLookupRefs>, InternSet<TempId>);
+
+/// Like `Terms`, but also naming the partition that tempids should be allocated in.
+pub type TermsInPartitions = (Vec<TermWithTempIdsAndLookupRefs>, InternSet<TempId>, TempIdPartitions);
 
 pub struct TermBuilder {
     tempids: InternSet<TempId>,
-    terms: Vec<TermWithTempIds>,
+    partitions: TempIdPartitions,
+    terms: Vec<TermWithTempIdsAndLookupRefs>,
 }
 
 pub struct EntityBuilder<T: BuildTerms + Sized> {
     builder: T,
-    entity: KnownEntidOr<TempIdHandle>,
+    entity: KnownEntidOr<LookupRefOrTempId>,
 }
 
 pub trait BuildTerms where Self: Sized {
     fn named_tempid(&mut self, name: String) -> TempIdHandle;
+    fn named_tempid_in_partition(&mut self, name: String, partition: &NamespacedKeyword) -> Result<TempIdHandle>;
+    fn tx_tempid(&mut self) -> TempIdHandle;
+    fn new_tempid(&mut self) -> TempIdHandle;
     fn describe_tempid(self, name: &str) -> EntityBuilder<Self>;
-    fn describe<E>(self, entity: E) -> EntityBuilder<Self> where E: IntoThing<KnownEntidOr<TempIdHandle>>;
+    fn describe_tx(self) -> EntityBuilder<Self>;
+    fn describe<E>(self, entity: E) -> EntityBuilder<Self> where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>;
     fn add<E, V>(&mut self, e: E, a: KnownEntid, v: V) -> Result<()>
-    where E: IntoThing<KnownEntidOr<TempIdHandle>>,
-          V: IntoThing<TypedValueOr<TempIdHandle>>;
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>>;
     fn retract<E, V>(&mut self, e: E, a: KnownEntid, v: V) -> Result<()>
-    where E: IntoThing<KnownEntidOr<TempIdHandle>>,
-          V: IntoThing<TypedValueOr<TempIdHandle>>;
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>>;
+
+    /// Add each of `vs` as a value of the `:db.cardinality/many` attribute `a`.  Builders that know
+    /// the schema fail with `NotCardinalityMany`, before adding anything, if `a` isn't.
+    fn add_many<E, V, I>(&mut self, e: E, a: KnownEntid, vs: I) -> Result<()>
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>>,
+          I: IntoIterator<Item=V> {
+        let e: KnownEntidOr<LookupRefOrTempId> = e.into_thing();
+        for v in vs {
+            self.add(e.clone(), a, v)?;
+        }
+        Ok(())
+    }
+
+    fn add_typed<E, V, T>(&mut self, e: E, a: &TypedAttribute<V>, v: T) -> Result<()>
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: AttributeValue,
+          T: Into<V> {
+        self.add(e, a.entid(), v.into().into_value())
+    }
+
+    fn retract_typed<E, V, T>(&mut self, e: E, a: &TypedAttribute<V>, v: T) -> Result<()>
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: AttributeValue,
+          T: Into<V> {
+        self.retract(e, a.entid(), v.into().into_value())
+    }
 }
 
 impl BuildTerms for TermBuilder {
@@ -118,12 +166,41 @@ impl BuildTerms for TermBuilder {
         self.tempids.intern(TempId::External(name))
     }
 
+    /// Like `named_tempid`, but if the tempid doesn't upsert, allocate it in `partition` rather
+    /// than in `:db.part/user`.
+    fn named_tempid_in_partition(&mut self, name: String, partition: &NamespacedKeyword) -> Result<TempIdHandle> {
+        let tempid = TempId::External(name);
+        request_partition(&mut self.partitions, &tempid, partition.to_string())?;
+        Ok(self.tempids.intern(tempid))
+    }
+
+    /// The transaction itself, so that provenance like an author or a reason can be asserted
+    /// about it, or so that other entities can refer to it.
+    fn tx_tempid(&mut self) -> TempIdHandle {
+        self.tempids.intern(TempId::Tx)
+    }
+
+    /// A tempid for an entity, like a nested component, that is only ever referred to through
+    /// the returned handle.
+    fn new_tempid(&mut self) -> TempIdHandle {
+        let mut id = self.tempids.len() as i64;
+        while self.tempids.inner.contains(&TempId::Internal(id)) {
+            id += 1;
+        }
+        self.tempids.intern(TempId::Internal(id))
+    }
+
     fn describe_tempid(mut self, name: &str) -> EntityBuilder<Self> {
         let e = self.named_tempid(name.into());
         self.describe(e)
     }
 
-    fn describe<E>(self, entity: E) -> EntityBuilder<Self> where E: IntoThing<KnownEntidOr<TempIdHandle>> {
+    fn describe_tx(mut self) -> EntityBuilder<Self> {
+        let e = self.tx_tempid();
+        self.describe(e)
+    }
+
+    fn describe<E>(self, entity: E) -> EntityBuilder<Self> where E: IntoThing<KnownEntidOr<LookupRefOrTempId>> {
         EntityBuilder {
             builder: self,
             entity: entity.into_thing(),
@@ -131,8 +208,8 @@ impl BuildTerms for TermBuilder {
     }
 
     fn add<E, V>(&mut self, e: E, a: KnownEntid, v: V) -> Result<()>
-    where E: IntoThing<KnownEntidOr<TempIdHandle>>,
-          V: IntoThing<TypedValueOr<TempIdHandle>> {
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
         let e = e.into_thing();
         let v = v.into_thing();
         self.terms.push(Term::AddOrRetract(OpType::Add, e, a.into(), v));
@@ -140,8 +217,8 @@ impl BuildTerms for TermBuilder {
     }
 
     fn retract<E, V>(&mut self, e: E, a: KnownEntid, v: V) -> Result<()>
-    where E: IntoThing<KnownEntidOr<TempIdHandle>>,
-          V: IntoThing<TypedValueOr<TempIdHandle>> {
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
         let e = e.into_thing();
         let v = v.into_thing();
         self.terms.push(Term::AddOrRetract(OpType::Retract, e, a.into(), v));
@@ -154,9 +231,14 @@ impl TermBuilder {
         Ok((self.terms, self.tempids))
     }
 
+    pub fn build_in_partitions(self) -> Result<TermsInPartitions> {
+        Ok((self.terms, self.tempids, self.partitions))
+    }
+
     pub fn new() -> TermBuilder {
         TermBuilder {
             tempids: InternSet::new(),
+            partitions: TempIdPartitions::default(),
             terms: vec![],
         }
     }
@@ -167,20 +249,108 @@ impl TermBuilder {
     }
 }
 
+/// Lets a nested `EntityBuilder` borrow the builder that its parent entity is using.
+impl<'b, T> BuildTerms for &'b mut T where T: BuildTerms {
+    fn named_tempid(&mut self, name: String) -> TempIdHandle {
+        (**self).named_tempid(name)
+    }
+
+    fn named_tempid_in_partition(&mut self, name: String, partition: &NamespacedKeyword) -> Result<TempIdHandle> {
+        (**self).named_tempid_in_partition(name, partition)
+    }
+
+    fn tx_tempid(&mut self) -> TempIdHandle {
+        (**self).tx_tempid()
+    }
+
+    fn new_tempid(&mut self) -> TempIdHandle {
+        (**self).new_tempid()
+    }
+
+    fn describe_tempid(self, name: &str) -> EntityBuilder<Self> {
+        let e = (*self).named_tempid(name.into());
+        self.describe(e)
+    }
+
+    fn describe_tx(self) -> EntityBuilder<Self> {
+        let e = (*self).tx_tempid();
+        self.describe(e)
+    }
+
+    fn describe<E>(self, entity: E) -> EntityBuilder<Self> where E: IntoThing<KnownEntidOr<LookupRefOrTempId>> {
+        EntityBuilder {
+            builder: self,
+            entity: entity.into_thing(),
+        }
+    }
+
+    fn add<E, V>(&mut self, e: E, a: KnownEntid, v: V) -> Result<()>
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
+        (**self).add(e, a, v)
+    }
+
+    fn retract<E, V>(&mut self, e: E, a: KnownEntid, v: V) -> Result<()>
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
+        (**self).retract(e, a, v)
+    }
+
+    fn add_many<E, V, I>(&mut self, e: E, a: KnownEntid, vs: I) -> Result<()>
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>>,
+          I: IntoIterator<Item=V> {
+        (**self).add_many(e, a, vs)
+    }
+}
+
 impl<T> EntityBuilder<T> where T: BuildTerms {
-    pub fn finish(self) -> (T, KnownEntidOr<TempIdHandle>) {
+    pub fn finish(self) -> (T, KnownEntidOr<LookupRefOrTempId>) {
         (self.builder, self.entity)
     }
 
     pub fn add<V>(&mut self, a: KnownEntid, v: V) -> Result<()>
-    where V: IntoThing<TypedValueOr<TempIdHandle>> {
+    where V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
         self.builder.add(self.entity.clone(), a, v)
     }
 
     pub fn retract<V>(&mut self, a: KnownEntid, v: V) -> Result<()>
-    where V: IntoThing<TypedValueOr<TempIdHandle>> {
+    where V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
         self.builder.retract(self.entity.clone(), a, v)
     }
+
+    pub fn add_many<V, I>(&mut self, a: KnownEntid, vs: I) -> Result<()>
+    where V: IntoThing<TypedValueOr<LookupRefOrTempId>>,
+          I: IntoIterator<Item=V> {
+        self.builder.add_many(self.entity.clone(), a, vs)
+    }
+
+    pub fn add_typed<V, T>(&mut self, a: &TypedAttribute<V>, v: T) -> Result<()>
+    where V: AttributeValue,
+          T: Into<V> {
+        self.builder.add_typed(self.entity.clone(), a, v)
+    }
+
+    pub fn retract_typed<V, T>(&mut self, a: &TypedAttribute<V>, v: T) -> Result<()>
+    where V: AttributeValue,
+          T: Into<V> {
+        self.builder.retract_typed(self.entity.clone(), a, v)
+    }
+
+    /// Describe a new entity, like a component, that this entity refers to through `a`: the
+    /// builder equivalent of a nested map in `{:db/id "x" :foo/component {:foo/name "y"}}`.
+    /// Returns the nested entity's tempid.
+    pub fn add_nested<F>(&mut self, a: KnownEntid, f: F) -> Result<TempIdHandle>
+    where F: FnOnce(&mut EntityBuilder<&mut T>) -> Result<()> {
+        let e = self.entity.clone();
+        let nested = self.builder.new_tempid();
+        self.builder.add(e, a, nested.clone())?;
+        {
+            let mut entity = (&mut self.builder).describe(nested.clone());
+            f(&mut entity)?;
+        }
+        Ok(nested)
+    }
 }
 
 pub struct InProgressBuilder<'a, 'c> {
@@ -202,9 +372,9 @@ impl<'a, 'c> InProgressBuilder<'a, 'c> {
     pub fn transact(self) -> (InProgress<'a, 'c>, Result<TxReport>)  {
         let mut in_progress = self.in_progress;
         let result = self.builder
-                         .build()
-                         .and_then(|(terms, tempid_set)| {
-                             in_progress.transact_terms(terms, tempid_set)
+                         .build_in_partitions()
+                         .and_then(|(terms, tempid_set, partitions)| {
+                             in_progress.transact_terms_in_partitions(terms, tempid_set, partitions)
                          });
         (in_progress, result)
     }
@@ -214,9 +384,9 @@ impl<'a, 'c> InProgressBuilder<'a, 'c> {
     pub fn commit(self) -> Result<TxReport> {
         let mut in_progress = self.in_progress;
         self.builder
-            .build()
-            .and_then(|(terms, tempid_set)| {
-                in_progress.transact_terms(terms, tempid_set)
+            .build_in_partitions()
+            .and_then(|(terms, tempid_set, partitions)| {
+                in_progress.transact_terms_in_partitions(terms, tempid_set, partitions)
                            .and_then(|report| {
                                in_progress.commit()?;
                                Ok(report)
@@ -230,12 +400,29 @@ impl<'a, 'c> BuildTerms for InProgressBuilder<'a, 'c> {
         self.builder.named_tempid(name)
     }
 
+    fn named_tempid_in_partition(&mut self, name: String, partition: &NamespacedKeyword) -> Result<TempIdHandle> {
+        self.builder.named_tempid_in_partition(name, partition)
+    }
+
+    fn tx_tempid(&mut self) -> TempIdHandle {
+        self.builder.tx_tempid()
+    }
+
+    fn new_tempid(&mut self) -> TempIdHandle {
+        self.builder.new_tempid()
+    }
+
     fn describe_tempid(mut self, name: &str) -> EntityBuilder<InProgressBuilder<'a, 'c>> {
         let e = self.builder.named_tempid(name.into());
         self.describe(e)
     }
 
-    fn describe<E>(self, entity: E) -> EntityBuilder<InProgressBuilder<'a, 'c>> where E: IntoThing<KnownEntidOr<TempIdHandle>> {
+    fn describe_tx(mut self) -> EntityBuilder<InProgressBuilder<'a, 'c>> {
+        let e = self.builder.tx_tempid();
+        self.describe(e)
+    }
+
+    fn describe<E>(self, entity: E) -> EntityBuilder<InProgressBuilder<'a, 'c>> where E: IntoThing<KnownEntidOr<LookupRefOrTempId>> {
         EntityBuilder {
             builder: self,
             entity: entity.into_thing(),
@@ -243,62 +430,154 @@ impl<'a, 'c> BuildTerms for InProgressBuilder<'a, 'c> {
     }
 
     fn add<E, V>(&mut self, e: E, a: KnownEntid, v: V) -> Result<()>
-    where E: IntoThing<KnownEntidOr<TempIdHandle>>,
-          V: IntoThing<TypedValueOr<TempIdHandle>> {
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
         self.builder.add(e, a, v)
     }
 
     fn retract<E, V>(&mut self, e: E, a: KnownEntid, v: V) -> Result<()>
-    where E: IntoThing<KnownEntidOr<TempIdHandle>>,
-          V: IntoThing<TypedValueOr<TempIdHandle>> {
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
         self.builder.retract(e, a, v)
     }
+
+    fn add_many<E, V, I>(&mut self, e: E, a: KnownEntid, vs: I) -> Result<()>
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>>,
+          I: IntoIterator<Item=V> {
+        self.ensure_multival(a)?;
+        self.builder.add_many(e, a, vs)
+    }
 }
 
 impl<'a, 'c> InProgressBuilder<'a, 'c> {
     pub fn add_kw<E, V>(&mut self, e: E, a: &NamespacedKeyword, v: V) -> Result<()>
-    where E: IntoThing<KnownEntidOr<TempIdHandle>>,
-          V: IntoThing<TypedValueOr<TempIdHandle>> {
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
         let (attribute, value) = self.extract_kw_value(a, v.into_thing())?;
         self.add(e, attribute, value)
     }
 
     pub fn retract_kw<E, V>(&mut self, e: E, a: &NamespacedKeyword, v: V) -> Result<()>
-    where E: IntoThing<KnownEntidOr<TempIdHandle>>,
-          V: IntoThing<TypedValueOr<TempIdHandle>> {
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
         let (attribute, value) = self.extract_kw_value(a, v.into_thing())?;
         self.retract(e, attribute, value)
     }
 
-    fn extract_kw_value(&mut self, a: &NamespacedKeyword, v: TypedValueOr<TempIdHandle>) -> Result<(KnownEntid, TypedValueOr<TempIdHandle>)> {
+    pub fn add_kw_many<E, V, I>(&mut self, e: E, a: &NamespacedKeyword, vs: I) -> Result<()>
+    where E: IntoThing<KnownEntidOr<LookupRefOrTempId>>,
+          V: IntoThing<TypedValueOr<LookupRefOrTempId>>,
+          I: IntoIterator<Item=V> {
+        if let Some(attribute) = self.in_progress.get_entid(a) {
+            self.ensure_multival(attribute)?;
+        }
+        let e: KnownEntidOr<LookupRefOrTempId> = e.into_thing();
+        for v in vs {
+            self.add_kw(e.clone(), a, v)?;
+        }
+        Ok(())
+    }
+
+    /// Fails with `NotCardinalityMany` if `a` is a `:db.cardinality/one` attribute.  Attributes
+    /// that aren't in the schema are left for the transactor to reject.
+    fn ensure_multival(&self, a: KnownEntid) -> Result<()> {
+        if let Some(attribute) = self.in_progress.attribute_for_entid(a) {
+            if !attribute.multival {
+                let name = self.in_progress.get_ident(a).map_or_else(|| a.0.to_string(), |ident| ident.to_string());
+                bail!(ErrorKind::NotCardinalityMany(name));
+            }
+        }
+        Ok(())
+    }
+
+    /// The attribute named `a`, checked now to have values of type `V`, so that adding a value of
+    /// any other type is a compile-time error.
+    pub fn typed_attribute<V>(&self, a: &NamespacedKeyword) -> Result<TypedAttribute<V>> where V: AttributeValue {
+        TypedAttribute::for_ident(&self.in_progress, a)
+    }
+
+    /// The lookup ref `[a v]`, naming the entity that has the value `v` for the `:db/unique`
+    /// attribute `a`, so that it can be used wherever an entity is expected.  The transactor
+    /// resolves it, and fails with `LookupRefNotFound` if no such entity exists.
+    pub fn lookup_ref<V>(&self, a: &NamespacedKeyword, v: V) -> Result<LookupRef> where V: Into<TypedValue> {
+        let v: TypedValue = v.into();
+        let (attribute, entid) = self.in_progress.attribute_for_ident(a).ok_or_else(|| ErrorKind::UnknownAttribute(a.to_string()))?;
+        if attribute.unique.is_none() {
+            bail!(ErrorKind::DbError(mentat_db::ErrorKind::NonUniqueLookupRef(a.to_string(), v.to_edn_value_pair().0.to_string())));
+        }
+        if v.value_type() != attribute.value_type {
+            bail!(ErrorKind::ValueTypeMismatch(v.value_type(), attribute.value_type));
+        }
+        Ok(LookupRef::new((entid.0, v)))
+    }
+
+    fn extract_kw_value(&mut self, a: &NamespacedKeyword, v: TypedValueOr<LookupRefOrTempId>) -> Result<(KnownEntid, TypedValueOr<LookupRefOrTempId>)> {
+        let provided = match v {
+            Either::Left(ref tv) => tv.value_type(),
+            Either::Right(_) => ValueType::Ref,
+        };
+        let attribute = self.extract_kw_attribute(a, provided)?;
+        Ok((attribute, v))
+    }
+
+    fn extract_kw_attribute(&self, a: &NamespacedKeyword, provided: ValueType) -> Result<KnownEntid> {
         let attribute: KnownEntid;
         if let Some((attr, aa)) = self.in_progress.attribute_for_ident(a) {
-            if let Either::Left(ref tv) = v {
-                let provided = tv.value_type();
-                let expected = attr.value_type;
-                if provided != expected {
-                    bail!(ErrorKind::ValueTypeMismatch(provided, expected));
-                }
+            let expected = attr.value_type;
+            if provided != expected {
+                bail!(ErrorKind::ValueTypeMismatch(provided, expected));
             }
             attribute = aa;
         } else {
             bail!(ErrorKind::UnknownAttribute(a.to_string()));
         }
-        Ok((attribute, v))
+        Ok(attribute)
     }
 }
 
-impl<'a, 'c> EntityBuilder<InProgressBuilder<'a, 'c>> {
+impl<'a, 'c, B> EntityBuilder<B> where B: BuildTerms + BorrowMut<InProgressBuilder<'a, 'c>> {
     pub fn add_kw<V>(&mut self, a: &NamespacedKeyword, v: V) -> Result<()>
-    where V: IntoThing<TypedValueOr<TempIdHandle>> {
-        self.builder.add_kw(self.entity.clone(), a, v)
+    where V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
+        let e = self.entity.clone();
+        let builder: &mut InProgressBuilder<'a, 'c> = self.builder.borrow_mut();
+        builder.add_kw(e, a, v)
     }
 
     pub fn retract_kw<V>(&mut self, a: &NamespacedKeyword, v: V) -> Result<()>
-    where V: IntoThing<TypedValueOr<TempIdHandle>> {
-        self.builder.retract_kw(self.entity.clone(), a, v)
+    where V: IntoThing<TypedValueOr<LookupRefOrTempId>> {
+        let e = self.entity.clone();
+        let builder: &mut InProgressBuilder<'a, 'c> = self.builder.borrow_mut();
+        builder.retract_kw(e, a, v)
+    }
+
+    pub fn add_kw_many<V, I>(&mut self, a: &NamespacedKeyword, vs: I) -> Result<()>
+    where V: IntoThing<TypedValueOr<LookupRefOrTempId>>,
+          I: IntoIterator<Item=V> {
+        let e = self.entity.clone();
+        let builder: &mut InProgressBuilder<'a, 'c> = self.builder.borrow_mut();
+        builder.add_kw_many(e, a, vs)
     }
 
+    /// Like `add_nested`, but naming the attribute, which must be a `:db.type/ref`.
+    pub fn add_nested_kw<F>(&mut self, a: &NamespacedKeyword, f: F) -> Result<TempIdHandle>
+    where F: FnOnce(&mut EntityBuilder<&mut InProgressBuilder<'a, 'c>>) -> Result<()> {
+        let e = self.entity.clone();
+        let builder: &mut InProgressBuilder<'a, 'c> = self.builder.borrow_mut();
+        // Check the attribute before interning a tempid that nothing would then refer to.
+        let attribute = builder.extract_kw_attribute(a, ValueType::Ref)?;
+        let nested = builder.new_tempid();
+        builder.add(e, attribute, nested.clone())?;
+        {
+            let mut entity = (&mut *builder).describe(nested.clone());
+            f(&mut entity)?;
+        }
+        Ok(nested)
+    }
+}
+
+impl<'a, 'c> EntityBuilder<InProgressBuilder<'a, 'c>> {
+
     /// Build the terms from this builder and transact them against the current
     /// `InProgress`. This method _always_ returns the `InProgress` -- failure doesn't
     /// imply an automatic rollback.
@@ -313,6 +592,120 @@ impl<'a, 'c> EntityBuilder<InProgressBuilder<'a, 'c>> {
     }
 }
 
+/// A Rust type whose values are exactly the values of one Mentat `ValueType`.
+pub trait AttributeValue {
+    fn value_type() -> ValueType;
+    fn into_value(self) -> TypedValueOr<LookupRefOrTempId>;
+}
+
+macro_rules! def_attribute_value {
+    ($t: ty, $value_type: expr) => {
+        impl AttributeValue for $t {
+            fn value_type() -> ValueType {
+                $value_type
+            }
+
+            fn into_value(self) -> TypedValueOr<LookupRefOrTempId> {
+                Either::Left(TypedValue::from(self))
+            }
+        }
+    }
+}
+
+def_attribute_value!(bool, ValueType::Boolean);
+def_attribute_value!(f64, ValueType::Double);
+def_attribute_value!(String, ValueType::String);
+def_attribute_value!(NamespacedKeyword, ValueType::Keyword);
+def_attribute_value!(DateTime<Utc>, ValueType::Instant);
+def_attribute_value!(Uuid, ValueType::Uuid);
+
+// There's no From<i64> for TypedValue; see mentat_core.
+impl AttributeValue for i64 {
+    fn value_type() -> ValueType {
+        ValueType::Long
+    }
+
+    fn into_value(self) -> TypedValueOr<LookupRefOrTempId> {
+        Either::Left(TypedValue::Long(self))
+    }
+}
+
+/// The value of a `:db.type/ref` attribute: an existing entity, one named by a lookup ref, or one
+/// named by a tempid.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EntityRef(pub KnownEntidOr<LookupRefOrTempId>);
+
+impl From<KnownEntid> for EntityRef {
+    fn from(e: KnownEntid) -> EntityRef {
+        EntityRef(Either::Left(e))
+    }
+}
+
+impl From<LookupRef> for EntityRef {
+    fn from(e: LookupRef) -> EntityRef {
+        EntityRef(Either::Right(LookupRefOrTempId::LookupRef(e)))
+    }
+}
+
+impl From<TempIdHandle> for EntityRef {
+    fn from(e: TempIdHandle) -> EntityRef {
+        EntityRef(Either::Right(LookupRefOrTempId::TempId(e)))
+    }
+}
+
+impl<'a> From<&'a TempIdHandle> for EntityRef {
+    fn from(e: &'a TempIdHandle) -> EntityRef {
+        EntityRef(Either::Right(LookupRefOrTempId::TempId(e.clone())))
+    }
+}
+
+impl AttributeValue for EntityRef {
+    fn value_type() -> ValueType {
+        ValueType::Ref
+    }
+
+    fn into_value(self) -> TypedValueOr<LookupRefOrTempId> {
+        match self.0 {
+            Either::Left(e) => Either::Left(e.into()),
+            Either::Right(e) => Either::Right(e),
+        }
+    }
+}
+
+/// An attribute that has been checked against the schema to have values of type `V`.  Adding or
+/// retracting a value of another type through it doesn't compile.
+#[derive(Debug)]
+pub struct TypedAttribute<V> where V: AttributeValue {
+    entid: KnownEntid,
+    value_type: PhantomData<V>,
+}
+
+// Derived implementations would require `V: Clone`.
+impl<V> Clone for TypedAttribute<V> where V: AttributeValue {
+    fn clone(&self) -> TypedAttribute<V> {
+        *self
+    }
+}
+
+impl<V> Copy for TypedAttribute<V> where V: AttributeValue {}
+
+impl<V> TypedAttribute<V> where V: AttributeValue {
+    pub fn for_ident<S>(schema: &S, ident: &NamespacedKeyword) -> Result<TypedAttribute<V>> where S: HasSchema {
+        let (attribute, entid) = schema.attribute_for_ident(ident).ok_or_else(|| ErrorKind::UnknownAttribute(ident.to_string()))?;
+        if attribute.value_type != V::value_type() {
+            bail!(ErrorKind::ValueTypeMismatch(V::value_type(), attribute.value_type));
+        }
+        Ok(TypedAttribute {
+            entid: entid,
+            value_type: PhantomData,
+        })
+    }
+
+    pub fn entid(&self) -> KnownEntid {
+        self.entid
+    }
+}
+
 // Can't implement Into for Rc<T>.
 pub trait IntoThing<T>: Sized {
     fn into_thing(self) -> T;
@@ -334,43 +727,55 @@ impl<I, F> IntoThing<I> for F where I: FromThing<F> {
     }
 }
 
-impl<'a> FromThing<&'a TempIdHandle> for TypedValueOr<TempIdHandle> {
+impl<'a> FromThing<&'a TempIdHandle> for TypedValueOr<LookupRefOrTempId> {
     fn from_thing(v: &'a TempIdHandle) -> Self {
-        Either::Right(v.clone())
+        Either::Right(LookupRefOrTempId::TempId(v.clone()))
     }
 }
 
-impl FromThing<TempIdHandle> for TypedValueOr<TempIdHandle> {
+impl FromThing<TempIdHandle> for TypedValueOr<LookupRefOrTempId> {
     fn from_thing(v: TempIdHandle) -> Self {
-        Either::Right(v)
+        Either::Right(LookupRefOrTempId::TempId(v))
+    }
+}
+
+impl FromThing<LookupRef> for TypedValueOr<LookupRefOrTempId> {
+    fn from_thing(v: LookupRef) -> Self {
+        Either::Right(LookupRefOrTempId::LookupRef(v))
     }
 }
 
-impl FromThing<TypedValue> for TypedValueOr<TempIdHandle> {
+impl FromThing<TypedValue> for TypedValueOr<LookupRefOrTempId> {
     fn from_thing(v: TypedValue) -> Self {
         Either::Left(v)
     }
 }
 
-impl FromThing<TempIdHandle> for KnownEntidOr<TempIdHandle> {
+impl FromThing<TempIdHandle> for KnownEntidOr<LookupRefOrTempId> {
     fn from_thing(v: TempIdHandle) -> Self {
-        Either::Right(v)
+        Either::Right(LookupRefOrTempId::TempId(v))
     }
 }
 
-impl<'a> FromThing<&'a KnownEntid> for KnownEntidOr<TempIdHandle> {
+impl FromThing<LookupRef> for KnownEntidOr<LookupRefOrTempId> {
+    fn from_thing(v: LookupRef) -> Self {
+        Either::Right(LookupRefOrTempId::LookupRef(v))
+    }
+}
+
+impl<'a> FromThing<&'a KnownEntid> for KnownEntidOr<LookupRefOrTempId> {
     fn from_thing(v: &'a KnownEntid) -> Self {
         Either::Left(v.clone())
     }
 }
 
-impl FromThing<KnownEntid> for KnownEntidOr<TempIdHandle> {
+impl FromThing<KnownEntid> for KnownEntidOr<LookupRefOrTempId> {
     fn from_thing(v: KnownEntid) -> Self {
         Either::Left(v)
     }
 }
 
-impl FromThing<KnownEntid> for TypedValueOr<TempIdHandle> {
+impl FromThing<KnownEntid> for TypedValueOr<LookupRefOrTempId> {
     fn from_thing(v: KnownEntid) -> Self {
         Either::Left(v.into())
     }
@@ -394,9 +799,11 @@ mod testing {
         Conn,
         Entid,
         HasSchema,
+        QueryInputs,
         Queryable,
         TypedValue,
         TxReport,
+        Variable,
     };
 
     use super::*;
@@ -468,6 +875,203 @@ mod testing {
         builder.commit().expect("commit succeeded");
     }
 
+    #[test]
+    fn test_in_progress_builder_partitions() {
+        let mut sqlite = mentat_db::db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        conn.transact(&mut sqlite, r#"[
+            [:db/add "p" :db/ident :foo.part/things]
+            [:db/add :db.part/db :db.install/partition "p"]
+            [:db/add "m" :db/ident :foo/many]
+            [:db/add "m" :db/valueType :db.type/string]
+            [:db/add "m" :db/cardinality :db.cardinality/many]
+        ]"#).unwrap();
+
+        let in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+        let mut builder = in_progress.builder();
+        let e_x = builder.named_tempid_in_partition("x".into(), &kw!(:foo.part/things)).expect("partition requested");
+        let e_y = builder.named_tempid("y".into());
+        builder.add_kw(e_x.clone(), &kw!(:foo/many), TypedValue::typed_string("x")).expect("add succeeded");
+        builder.add_kw(e_y.clone(), &kw!(:foo/many), TypedValue::typed_string("y")).expect("add succeeded");
+
+        // A tempid can't ask for two partitions.
+        match builder.named_tempid_in_partition("x".into(), &kw!(:db.part/user)) {
+            Err(Error(ErrorKind::DbError(mentat_db::ErrorKind::ConflictingTempIdPartitions(tempid, _, _)), _)) => assert_eq!(tempid, "x"),
+            x => panic!("expected conflicting partitions, got {:?}", x),
+        }
+
+        let report = builder.commit().expect("commit succeeded");
+        assert_eq!(report.tempids["x"], 1 << 32);
+        assert!(report.tempids["y"] < 1 << 32);
+    }
+
+    #[test]
+    fn test_in_progress_builder_tx_metadata() {
+        let mut sqlite = mentat_db::db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        conn.transact(&mut sqlite, r#"[
+            [:db/add "m" :db/ident :foo/many]
+            [:db/add "m" :db/valueType :db.type/string]
+            [:db/add "m" :db/cardinality :db.cardinality/many]
+            [:db/add "r" :db/ident :foo/ref]
+            [:db/add "r" :db/valueType :db.type/ref]
+            [:db/add "r" :db/cardinality :db.cardinality/one]
+        ]"#).unwrap();
+
+        // Scoped borrow of conn.
+        let report = {
+            let in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            let mut tx = in_progress.builder().describe_tx();
+            tx.add_kw(&kw!(:db/doc), TypedValue::typed_string("imported")).expect("add succeeded");
+            let (mut builder, _) = tx.finish();
+            let e_x = builder.named_tempid("x".into());
+            let e_tx = builder.tx_tempid();
+            builder.add_kw(e_x.clone(), &kw!(:foo/many), TypedValue::typed_string("x")).expect("add succeeded");
+            builder.add_kw(e_x.clone(), &kw!(:foo/ref), e_tx).expect("add succeeded");
+            builder.commit().expect("commit succeeded")
+        };
+
+        let doc = conn.current_schema().get_entid(&kw!(:db/doc)).expect(":db/doc");
+        assert_eq!(report.tx_metadata.len(), 1);
+        assert_eq!(report.tx_metadata.get(&doc.0), Some(&vec![TypedValue::typed_string("imported")]));
+        assert_eq!(conn.lookup_value_for_attribute(&mut sqlite, report.tempids["x"], &kw!(:foo/ref)).expect("lookup succeeded"),
+                   Some(TypedValue::Ref(report.tx_id)));
+
+        // The metadata can be queried back through the transaction that asserted a datom.
+        let reason = conn.q_once(&sqlite, r#"[:find ?reason . :where [?e :foo/many "x" ?tx] [?tx :db/doc ?reason]]"#, None)
+                         .expect("query succeeded")
+                         .into_scalar()
+                         .expect("scalar results");
+        assert_eq!(reason, Some(TypedValue::typed_string("imported")));
+    }
+
+    #[test]
+    fn test_in_progress_builder_nested_entities() {
+        let mut sqlite = mentat_db::db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        conn.transact(&mut sqlite, r#"[
+            [:db/add "n" :db/ident :foo/name]
+            [:db/add "n" :db/valueType :db.type/string]
+            [:db/add "n" :db/cardinality :db.cardinality/one]
+            [:db/add "n" :db/unique :db.unique/identity]
+            [:db/add "t" :db/ident :foo/tag]
+            [:db/add "t" :db/valueType :db.type/keyword]
+            [:db/add "t" :db/cardinality :db.cardinality/many]
+            [:db/add "c" :db/ident :foo/component]
+            [:db/add "c" :db/valueType :db.type/ref]
+            [:db/add "c" :db/cardinality :db.cardinality/many]
+            [:db/add "c" :db/isComponent true]
+            [:db/add "a" :db/ident :foo/age]
+            [:db/add "a" :db/valueType :db.type/long]
+            [:db/add "a" :db/cardinality :db.cardinality/one]
+            [:db/add "f" :db/ident :foo/friend]
+            [:db/add "f" :db/valueType :db.type/ref]
+            [:db/add "f" :db/cardinality :db.cardinality/one]
+            [:db/add "alice" :foo/name "Alice"]
+        ]"#).unwrap();
+
+        let report = {
+            let in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+            let builder = in_progress.builder();
+
+            let name: TypedAttribute<String> = builder.typed_attribute(&kw!(:foo/name)).expect(":foo/name is a string");
+            let age: TypedAttribute<i64> = builder.typed_attribute(&kw!(:foo/age)).expect(":foo/age is a long");
+            let friend: TypedAttribute<EntityRef> = builder.typed_attribute(&kw!(:foo/friend)).expect(":foo/friend is a ref");
+
+            // Attribute handles are checked against the schema when they're made.
+            match builder.typed_attribute::<bool>(&kw!(:foo/age)) {
+                Err(Error(ErrorKind::ValueTypeMismatch(ValueType::Boolean, ValueType::Long), _)) => {},
+                x => panic!("expected value type mismatch, got {:?}", x),
+            }
+
+            // Lookup refs are only for unique attributes, and are resolved when transacted.
+            let alice = builder.lookup_ref(&kw!(:foo/name), TypedValue::typed_string("Alice")).expect("lookup ref");
+            match builder.lookup_ref(&kw!(:foo/age), TypedValue::Long(1)) {
+                Err(Error(ErrorKind::DbError(mentat_db::ErrorKind::NonUniqueLookupRef(attribute, _)), _)) => assert_eq!(attribute, ":foo/age"),
+                x => panic!("expected non-unique lookup ref, got {:?}", x),
+            }
+
+            let mut bob = builder.describe_tempid("bob");
+            bob.add_typed(&name, "Bob").expect("add succeeded");
+            bob.add_typed(&age, 42).expect("add succeeded");
+            bob.add_typed(&friend, alice).expect("add succeeded");
+            bob.add_kw_many(&kw!(:foo/tag), vec![TypedValue::typed_ns_keyword("foo", "x"),
+                                                  TypedValue::typed_ns_keyword("foo", "y")]).expect("add succeeded");
+
+            // Only :db.cardinality/many attributes take many values.
+            match bob.add_kw_many(&kw!(:foo/age), vec![TypedValue::Long(1), TypedValue::Long(2)]) {
+                Err(Error(ErrorKind::NotCardinalityMany(attribute), _)) => assert_eq!(attribute, ":foo/age"),
+                x => panic!("expected cardinality one, got {:?}", x),
+            }
+            match bob.add_many(age.entid(), vec![TypedValue::Long(1), TypedValue::Long(2)]) {
+                Err(Error(ErrorKind::NotCardinalityMany(attribute), _)) => assert_eq!(attribute, ":foo/age"),
+                x => panic!("expected cardinality one, got {:?}", x),
+            }
+
+            // Nested entities are allocated and referred to through the attribute.
+            bob.add_nested_kw(&kw!(:foo/component), |component| {
+                component.add_kw(&kw!(:foo/tag), TypedValue::typed_ns_keyword("foo", "nested"))?;
+                component.add_nested_kw(&kw!(:foo/component), |inner| {
+                    inner.add_kw(&kw!(:foo/tag), TypedValue::typed_ns_keyword("foo", "inner"))
+                })?;
+                Ok(())
+            }).expect("nested succeeded");
+
+            // A tempid can't be the value of an attribute that isn't a ref.
+            match bob.add_nested_kw(&kw!(:foo/age), |_| Ok(())) {
+                Err(Error(ErrorKind::ValueTypeMismatch(ValueType::Ref, ValueType::Long), _)) => {},
+                x => panic!("expected value type mismatch, got {:?}", x),
+            }
+
+            bob.commit().expect("commit succeeded")
+        };
+
+        let bob = report.tempids["bob"];
+        assert_eq!(conn.lookup_value_for_attribute(&sqlite, bob, &kw!(:foo/age)).expect("lookup succeeded"),
+                   Some(TypedValue::Long(42)));
+        let tags = conn.q_once(&sqlite, r#"[:find [?tag ...] :in ?e :where [?e :foo/tag ?tag]]"#,
+                               QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?e"), TypedValue::Ref(bob))]))
+                       .expect("query succeeded")
+                       .into_coll()
+                       .expect("coll results");
+        assert_eq!(tags.len(), 2);
+        let inner = conn.q_once(&sqlite, r#"[:find ?tag . :where [?e :foo/name "Bob"] [?e :foo/friend ?f] [?f :foo/name "Alice"]
+                                                                  [?e :foo/component ?c] [?c :foo/tag :foo/nested]
+                                                                  [?c :foo/component ?i] [?i :foo/tag ?tag]]"#, None)
+                        .expect("query succeeded")
+                        .into_scalar()
+                        .expect("scalar results");
+        assert_eq!(inner, Some(TypedValue::typed_ns_keyword("foo", "inner")));
+    }
+
+    #[test]
+    fn test_lookup_ref_not_found() {
+        let mut sqlite = mentat_db::db::new_connection("").unwrap();
+        let mut conn = Conn::connect(&mut sqlite).unwrap();
+
+        conn.transact(&mut sqlite, r#"[
+            [:db/add "n" :db/ident :foo/name]
+            [:db/add "n" :db/valueType :db.type/string]
+            [:db/add "n" :db/cardinality :db.cardinality/one]
+            [:db/add "n" :db/unique :db.unique/identity]
+            [:db/add "a" :db/ident :foo/age]
+            [:db/add "a" :db/valueType :db.type/long]
+            [:db/add "a" :db/cardinality :db.cardinality/one]
+        ]"#).unwrap();
+
+        let in_progress = conn.begin_transaction(&mut sqlite).expect("begun successfully");
+        let mut builder = in_progress.builder();
+        let bob = builder.lookup_ref(&kw!(:foo/name), TypedValue::typed_string("Bob")).expect("lookup ref");
+        builder.add_kw(bob, &kw!(:foo/age), TypedValue::Long(42)).expect("add succeeded");
+        match builder.transact().1 {
+            Err(Error(ErrorKind::DbError(mentat_db::ErrorKind::LookupRefNotFound(_)), _)) => {},
+            x => panic!("expected lookup ref not found, got {:?}", x),
+        }
+    }
+
     #[test]
     fn test_entity_builder() {
         let mut sqlite = mentat_db::db::new_connection("").unwrap();
@@ -19,6 +19,7 @@ use std::collections::BTreeSet;
 use edn;
 use mentat_core::{
     Attribute,
+    Entid,
     ValueType,
 };
 use mentat_db;
@@ -38,6 +39,7 @@ error_chain! {
 
     foreign_links {
         EdnParseError(edn::ParseError);
+        EdnReadError(edn::reader::ReadError);
         Rusqlite(rusqlite::Error);
         UuidParseError(uuid::ParseError);
         IoError(::std::io::Error);
@@ -109,5 +111,45 @@ error_chain! {
             description("provided value doesn't match value type")
             display("provided value of type {} doesn't match attribute value type {}", provided, expected)
         }
+
+        InvalidExport(message: String) {
+            description("invalid export")
+            display("invalid export: {}", message)
+        }
+
+        UnexportableTxRef(tx: Entid, referring_tx: Entid) {
+            description("transaction refers to another transaction")
+            display("transaction {} refers to transaction {}, which will have a different id when restored", referring_tx, tx)
+        }
+
+        ReservedLocalOnlyPartition(partition: String) {
+            description("partition must be synced")
+            display("partition {} must be synced, so it can't be local-only", partition)
+        }
+
+        StoreNotEmpty {
+            description("store is not empty")
+            display("an export can only be restored into an empty store")
+        }
+
+        ReservedTxFunctionName(name: String) {
+            description("transaction function name is reserved")
+            display("transaction function name {} is in the reserved :db namespace", name)
+        }
+
+        NotCardinalityMany(attribute: String) {
+            description("attribute is not :db.cardinality/many")
+            display("attribute {} is not :db.cardinality/many, so it can't have many values", attribute)
+        }
+
+        BulkLoadFailed(index: usize) {
+            description("bulk load failed")
+            display("bulk load failed at transaction {}", index)
+        }
+
+        InvalidBatchSize(size: usize) {
+            description("invalid batch size")
+            display("invalid batch size {}: batches must hold at least one entity", size)
+        }
     }
 }
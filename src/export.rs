@@ -0,0 +1,271 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Export a whole store as EDN that `InProgress::restore` can replay into an empty store.
+//!
+//! An export is a header map followed by one transaction vector per transaction:
+//!
+//! ```edn
+//! {:mentat.export/version 1
+//!  :mentat.export/history false
+//!  :mentat.export/partitions {:db.part/db 39, :db.part/user 65538}}
+//! [[:db/add :db/tx :db/txInstant #inst "2018-01-01T00:00:00.000Z"]
+//!  [:db/add 65536 :db/ident :person/name]
+//!  ...]
+//! ```
+//!
+//! Entities keep their entids: the header records how far each partition had allocated, so that
+//! replaying can claim the same entids.  Bootstrap entities are written as idents, so that an
+//! export can be read by a store whose bootstrap entids differ.  Each transaction asserts its
+//! original `:db/txInstant`.  Transaction IDs themselves are allocated afresh, so a transaction
+//! can only refer to itself, as `:db/tx`: a store in which one transaction refers to another
+//! can't be exported.
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use rusqlite;
+
+use edn;
+use edn::NamespacedKeyword;
+
+use mentat_core::{
+    Entid,
+    HasSchema,
+    Schema,
+    TypedValue,
+};
+
+use mentat_db::{
+    Partition,
+    PartitionMap,
+    TypedSQLValue,
+    TX0,
+    USER0,
+    to_namespaced_keyword,
+};
+
+use mentat_db::entids::might_update_metadata;
+
+use mentat_db::datoms::{
+    self,
+    Datom,
+    DatomIndex,
+    DatomPattern,
+};
+
+use errors::*;
+
+/// The version of the export format written by `write_export`.
+pub const EXPORT_VERSION: i64 = 1;
+
+const PART_TX: &'static str = ":db.part/tx";
+
+fn export_keyword(name: &str) -> edn::Value {
+    edn::Value::NamespacedKeyword(NamespacedKeyword::new("mentat.export", name))
+}
+
+/// Returns the transactions after the bootstrap transaction that are recorded in the log.
+fn transactions(conn: &rusqlite::Connection) -> Result<Vec<Entid>> {
+    let mut stmt: rusqlite::Statement = conn.prepare("SELECT DISTINCT tx FROM transactions WHERE tx > ? ORDER BY tx")?;
+    let txs: ::std::result::Result<Vec<Entid>, rusqlite::Error> = stmt.query_map(&[&TX0], |row| row.get(0))?.collect();
+    Ok(txs?)
+}
+
+/// Returns whether anything has been transacted since the store was bootstrapped.
+pub fn has_transactions(conn: &rusqlite::Connection) -> Result<bool> {
+    let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM transactions WHERE tx > ?)", &[&TX0], |row| row.get(0))?;
+    Ok(exists)
+}
+
+/// Names `e` by its ident if it is a bootstrap entity, and by its entid otherwise.
+fn entity(schema: &Schema, e: Entid) -> edn::Value {
+    if e < USER0 {
+        if let Some(ident) = schema.get_ident(e) {
+            return edn::Value::NamespacedKeyword(ident.clone());
+        }
+    }
+    edn::Value::Integer(e)
+}
+
+/// Names `e` in a datom written as part of transaction `tx`, which is `:db/tx`.  Fails if `e` is
+/// any other transaction in `txs`.
+fn datom_entity(schema: &Schema, txs: &Partition, tx: Entid, e: Entid) -> Result<edn::Value> {
+    if e == tx {
+        Ok(edn::Value::NamespacedKeyword(NamespacedKeyword::new("db", "tx")))
+    } else if txs.contains_entid(e) {
+        bail!(ErrorKind::UnexportableTxRef(e, tx))
+    } else {
+        Ok(entity(schema, e))
+    }
+}
+
+fn datom_form(schema: &Schema, txs: &Partition, tx: Entid, datom: &Datom) -> Result<edn::Value> {
+    let op = if datom.added { "add" } else { "retract" };
+    let v = match datom.v {
+        TypedValue::Ref(v) => datom_entity(schema, txs, tx, v)?,
+        ref v => v.to_edn_value_pair().0,
+    };
+    Ok(edn::Value::Vector(vec![edn::Value::NamespacedKeyword(NamespacedKeyword::new("db", op)),
+                               datom_entity(schema, txs, tx, datom.e)?,
+                               entity(schema, datom.a),
+                               v]))
+}
+
+/// Writes `datoms` as transaction `tx`, returning the number of transactions written.
+fn write_transaction<W>(out: &mut W, schema: &Schema, txs: &Partition, tx: Entid, datoms: &[Datom]) -> Result<usize>
+where W: Write {
+    // Don't write transactions that only assert their own `:db/txInstant`.
+    if datoms.iter().all(|datom| datom.e == tx) {
+        return Ok(0);
+    }
+    writeln!(out, "[")?;
+    for datom in datoms {
+        writeln!(out, " {}", datom_form(schema, txs, tx, datom)?)?;
+    }
+    writeln!(out, "]")?;
+    Ok(1)
+}
+
+/// Writes every datom in the store, other than those of the bootstrap transaction, to `out`.  If
+/// `history` is set, every transaction in the log is written, retractions and all; otherwise each
+/// current datom is written as part of the transaction that asserted it, except that attributes
+/// are written whole, in the transaction that introduced them.  Returns the number of
+/// transactions written.
+pub fn write_export<W>(conn: &rusqlite::Connection, schema: &Schema, partition_map: &PartitionMap, out: &mut W, history: bool) -> Result<usize>
+where W: Write {
+    let partitions: BTreeMap<edn::Value, edn::Value> =
+        partition_map.iter()
+                     .filter(|&(name, _)| name != PART_TX)
+                     .map(|(name, partition)| -> Result<(edn::Value, edn::Value)> {
+                         Ok((edn::Value::NamespacedKeyword(to_namespaced_keyword(name)?),
+                             edn::Value::Integer(partition.index)))
+                     })
+                     .collect::<Result<_>>()?;
+    let mut header = BTreeMap::new();
+    header.insert(export_keyword("version"), edn::Value::Integer(EXPORT_VERSION));
+    header.insert(export_keyword("history"), edn::Value::Boolean(history));
+    header.insert(export_keyword("partitions"), edn::Value::Map(partitions));
+    writeln!(out, "{}", edn::Value::Map(header))?;
+
+    let txs = partition_map.get(PART_TX).ok_or_else(|| ErrorKind::DbError(::mentat_db::ErrorKind::UnrecognizedPartition(PART_TX.to_string())))?;
+    let mut written = 0;
+    if history {
+        for tx in transactions(conn)? {
+            written += write_transaction(out, schema, txs, tx, &datoms::transaction_datoms(conn, schema, tx)?)?;
+        }
+    } else {
+        let current = datoms::datoms(conn, DatomIndex::EAVT, &DatomPattern::default())?;
+
+        // An altered attribute's current definition is spread over several transactions, and
+        // replaying the first of them alone would install an incomplete attribute.  So each
+        // attribute's whole definition is written in the earliest of them.
+        let mut introduced: BTreeMap<Entid, Entid> = BTreeMap::new();
+        for datom in current.iter() {
+            if datom.tx > TX0 && might_update_metadata(datom.a) && schema.attribute_for_entid(datom.e).is_some() {
+                let tx = introduced.entry(datom.e).or_insert(datom.tx);
+                *tx = cmp::min(*tx, datom.tx);
+            }
+        }
+
+        let mut by_tx: BTreeMap<Entid, Vec<Datom>> = BTreeMap::new();
+        for datom in current {
+            if datom.tx > TX0 {
+                let tx = match introduced.get(&datom.e) {
+                    Some(&tx) if might_update_metadata(datom.a) => tx,
+                    _ => datom.tx,
+                };
+                by_tx.entry(tx).or_insert(vec![]).push(datom);
+            }
+        }
+        for (&tx, datoms) in by_tx.iter() {
+            written += write_transaction(out, schema, txs, tx, datoms)?;
+        }
+    }
+    Ok(written)
+}
+
+/// Returns the partition indices recorded in an export header, or an error if `header` is not one.
+pub fn read_header(header: &edn::Value) -> Result<BTreeMap<String, Entid>> {
+    let map = match header.as_map() {
+        Some(map) if map.contains_key(&export_keyword("version")) => map,
+        _ => bail!(ErrorKind::InvalidExport("expected a header map".to_string())),
+    };
+    match map.get(&export_keyword("version")).and_then(|v| v.as_integer()) {
+        Some(EXPORT_VERSION) => (),
+        version => bail!(ErrorKind::InvalidExport(format!("unsupported version {:?}", version))),
+    }
+    let partitions = match map.get(&export_keyword("partitions")).and_then(|p| p.as_map()) {
+        Some(partitions) => partitions,
+        None => bail!(ErrorKind::InvalidExport("missing :mentat.export/partitions".to_string())),
+    };
+    partitions.iter().map(|(name, index)| -> Result<(String, Entid)> {
+        match (name.as_namespaced_keyword(), index.as_integer()) {
+            (Some(name), Some(index)) => Ok((name.to_string(), index)),
+            _ => bail!(ErrorKind::InvalidExport(format!("invalid partition {} {}", name, index))),
+        }
+    }).collect()
+}
+
+/// Returns whether the file at `path` is an export, rather than a single transaction.
+pub fn is_export<P>(path: P) -> Result<bool>
+where P: AsRef<Path> {
+    match edn::reader::Reader::new(File::open(path)?).next() {
+        Some(form) => Ok(read_header(&form?.without_spans()).is_ok()),
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    use edn;
+
+    #[test]
+    fn test_read_header() {
+        let header = edn::parse::value("{:mentat.export/version 1 :mentat.export/history true :mentat.export/partitions {:db.part/user 65540}}").unwrap().without_spans();
+        let partitions = read_header(&header).expect("header");
+        assert_eq!(partitions.into_iter().collect::<Vec<_>>(),
+                   vec![(":db.part/user".to_string(), 65540)]);
+
+        let transaction = edn::parse::value("[[:db/add 65536 :db/doc \"x\"]]").unwrap().without_spans();
+        assert!(read_header(&transaction).is_err());
+
+        let future = edn::parse::value("{:mentat.export/version 2 :mentat.export/partitions {}}").unwrap().without_spans();
+        assert!(read_header(&future).is_err());
+    }
+
+    #[test]
+    fn test_datom_form() {
+        let schema = Schema::default();
+        let txs = Partition::new(TX0, TX0 + 3);
+        let datom = Datom { e: 65536, a: 65537, v: TypedValue::Ref(65538), tx: TX0 + 1, added: false };
+        assert_eq!(datom_form(&schema, &txs, TX0 + 1, &datom).expect("form").to_string(), "[ :db/retract 65536 65537 65538 ]");
+
+        let datom = Datom { e: TX0 + 1, a: 65537, v: TypedValue::Keyword(Rc::new(NamespacedKeyword::new("foo", "bar"))), tx: TX0 + 1, added: true };
+        assert_eq!(datom_form(&schema, &txs, TX0 + 1, &datom).expect("form").to_string(), "[ :db/add :db/tx 65537 :foo/bar ]");
+
+        let datom = Datom { e: 65536, a: 65537, v: TypedValue::Ref(TX0 + 1), tx: TX0 + 1, added: true };
+        assert_eq!(datom_form(&schema, &txs, TX0 + 1, &datom).expect("form").to_string(), "[ :db/add 65536 65537 :db/tx ]");
+
+        // Transactions other than the one being written get new ids when restored.
+        let datom = Datom { e: TX0 + 1, a: 65537, v: TypedValue::Ref(65536), tx: TX0 + 2, added: true };
+        match datom_form(&schema, &txs, TX0 + 2, &datom) {
+            Err(Error(ErrorKind::UnexportableTxRef(tx, referring_tx), _)) => assert_eq!((tx, referring_tx), (TX0 + 1, TX0 + 2)),
+            x => panic!("expected UnexportableTxRef, got {:?}", x),
+        }
+    }
+}
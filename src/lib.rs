@@ -49,10 +49,20 @@ pub use mentat_query::{
     FindSpec,
 };
 
+pub use mentat_db::datoms::{
+    Datom,
+    DatomIndex,
+    DatomPattern,
+};
+
 pub use mentat_db::{
     CORE_SCHEMA_VERSION,
     DB_SCHEMA_CORE,
+    TxFunction,
+    TxFunctionContext,
     TxReport,
+    UnresolvedRetractions,
+    UpsertConflicts,
     new_connection,
 };
 
@@ -94,6 +104,8 @@ pub mod vocabulary;
 pub mod conn;
 pub mod query;
 pub mod entity_builder;
+pub mod export;
+pub mod tx_observer;
 
 pub use query::{
     IntoResult,
@@ -108,6 +120,12 @@ pub use query::{
     q_once,
 };
 
+pub use mentat_tolstoy::syncer::{
+    SyncOutcome,
+    SyncProgress,
+    SyncReport,
+};
+
 pub use conn::{
     CacheAction,
     CacheDirection,
@@ -119,6 +137,11 @@ pub use conn::{
     Store,
 };
 
+pub use tx_observer::{
+    TxCommitted,
+    TxObserver,
+};
+
 #[cfg(test)]
 mod tests {
     use edn::symbols::Keyword;
@@ -0,0 +1,129 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Observers are told about transactions once they have been committed.
+//!
+//! Register a `TxObserver` with `Conn::register_observer`, naming the attributes it is interested
+//! in. Each time an `InProgress` commits, every observer whose attributes were touched is called
+//! with the committed transactions, in order, and the datoms of those transactions that touch its
+//! attributes. Observers are called on the committing thread, after the commit has completed and
+//! without holding any of the `Conn`'s locks.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+use std::sync::Arc;
+
+use mentat_core::Entid;
+
+use mentat_db::TxReport;
+use mentat_db::datoms::Datom;
+
+/// A committed transaction, as an observer sees it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TxCommitted {
+    pub report: TxReport,
+    /// The datoms asserted and retracted by the transaction, limited to the observer's attributes.
+    pub datoms: Vec<Datom>,
+}
+
+pub struct TxObserver {
+    attributes: Option<BTreeSet<Entid>>,
+    notify_fn: Box<Fn(&str, &[TxCommitted]) + Send + Sync>,
+}
+
+impl TxObserver {
+    /// An observer of the given attributes. `notify_fn` is called with the key the observer was
+    /// registered under, and the committed transactions that touched any of `attributes`.
+    pub fn new<F>(attributes: BTreeSet<Entid>, notify_fn: F) -> TxObserver
+    where F: Fn(&str, &[TxCommitted]) + Send + Sync + 'static {
+        TxObserver {
+            attributes: Some(attributes),
+            notify_fn: Box::new(notify_fn),
+        }
+    }
+
+    /// An observer of every transaction, including those that only describe the transaction
+    /// itself.
+    pub fn all<F>(notify_fn: F) -> TxObserver
+    where F: Fn(&str, &[TxCommitted]) + Send + Sync + 'static {
+        TxObserver {
+            attributes: None,
+            notify_fn: Box::new(notify_fn),
+        }
+    }
+
+    fn applicable(&self, committed: &TxCommitted) -> Option<TxCommitted> {
+        match self.attributes {
+            None => Some(committed.clone()),
+            Some(ref attributes) => {
+                let datoms: Vec<Datom> = committed.datoms
+                                                  .iter()
+                                                  .filter(|datom| attributes.contains(&datom.a))
+                                                  .cloned()
+                                                  .collect();
+                if datoms.is_empty() {
+                    None
+                } else {
+                    Some(TxCommitted {
+                        report: committed.report.clone(),
+                        datoms: datoms,
+                    })
+                }
+            },
+        }
+    }
+
+    fn notify(&self, key: &str, committed: &[TxCommitted]) {
+        let applicable: Vec<TxCommitted> = committed.iter().filter_map(|c| self.applicable(c)).collect();
+        if !applicable.is_empty() {
+            (*self.notify_fn)(key, &applicable);
+        }
+    }
+}
+
+/// The observers registered with a `Conn`, by key.
+#[derive(Default)]
+pub struct TxObservationService {
+    observers: BTreeMap<String, Arc<TxObserver>>,
+}
+
+impl TxObservationService {
+    /// Registers `observer` under `key`, replacing any observer already registered with that key.
+    pub fn register(&mut self, key: String, observer: Arc<TxObserver>) {
+        self.observers.insert(key, observer);
+    }
+
+    pub fn deregister(&mut self, key: &str) {
+        self.observers.remove(key);
+    }
+
+    pub fn is_registered(&self, key: &str) -> bool {
+        self.observers.contains_key(key)
+    }
+
+    pub fn has_observers(&self) -> bool {
+        !self.observers.is_empty()
+    }
+
+    /// The registered observers, so that they can be notified after the service's lock has been
+    /// released.
+    pub fn observers(&self) -> Vec<(String, Arc<TxObserver>)> {
+        self.observers.iter().map(|(key, observer)| (key.clone(), observer.clone())).collect()
+    }
+}
+
+/// Tells each of `observers` about the transactions in `committed` that concern it.
+pub fn notify(observers: &[(String, Arc<TxObserver>)], committed: &[TxCommitted]) {
+    for &(ref key, ref observer) in observers {
+        observer.notify(key, committed);
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+extern crate mentat;
+
+use mentat::{
+    Datom,
+    DatomIndex,
+    DatomPattern,
+    Entid,
+    HasSchema,
+    NamespacedKeyword,
+    Store,
+    TxReport,
+    TypedValue,
+};
+
+fn attribute(store: &Store, name: &str) -> Entid {
+    store.conn().current_schema().get_entid(&NamespacedKeyword::new("test", name)).expect("attribute").into()
+}
+
+fn populate(store: &mut Store) -> TxReport {
+    let mut in_progress = store.begin_transaction().expect("began");
+    in_progress.transact(r#"[
+        {:db/ident :test/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true}
+        {:db/ident :test/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/many}
+        {:db/ident :test/bio :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true}
+    ]"#).expect("schema");
+    let report = in_progress.transact(r#"[
+        {:db/id "a" :test/name "Alice" :test/bio "Likes cats."}
+        {:db/id "b" :test/name "Bob" :test/friend "a"}
+    ]"#).expect("data");
+    in_progress.commit().expect("committed");
+    report
+}
+
+#[test]
+fn test_datoms() {
+    let mut store = Store::open("").expect("opened");
+    let report = populate(&mut store);
+    let alice = report.tempids["a"];
+    let bob = report.tempids["b"];
+    let name = attribute(&store, "name");
+    let friend = attribute(&store, "friend");
+    let bio = attribute(&store, "bio");
+
+    // Alice's own datoms, in attribute order. The fulltext value is returned as text.
+    let forward = store.datoms(DatomIndex::EAVT, &DatomPattern { e: Some(alice), ..Default::default() }).expect("datoms");
+    let mut expected = vec![(name, TypedValue::typed_string("Alice")),
+                            (bio, TypedValue::typed_string("Likes cats."))];
+    expected.sort();
+    assert_eq!(forward.into_iter().map(|d| (d.a, d.v)).collect::<Vec<_>>(),
+               expected);
+
+    // The datoms that refer to Alice.
+    let reverse = store.datoms(DatomIndex::VAET, &DatomPattern { v: Some(TypedValue::Ref(alice)), ..Default::default() }).expect("datoms");
+    assert_eq!(reverse.into_iter().map(|d| (d.e, d.a)).collect::<Vec<_>>(),
+               vec![(bob, friend)]);
+
+    // AVET walks values in order.
+    let names = store.datoms(DatomIndex::AVET, &DatomPattern { a: Some(name), ..Default::default() }).expect("datoms");
+    assert_eq!(names.into_iter().map(|d| d.e).collect::<Vec<_>>(),
+               vec![alice, bob]);
+
+    // `:test/bio` isn't indexed, so it doesn't appear in AVET.
+    let bios = store.datoms(DatomIndex::AVET, &DatomPattern { a: Some(bio), ..Default::default() }).expect("datoms");
+    assert!(bios.is_empty());
+
+    let by_value = store.datoms(DatomIndex::AEVT, &DatomPattern { a: Some(name), v: Some(TypedValue::typed_string("Bob")), ..Default::default() }).expect("datoms");
+    assert_eq!(by_value.into_iter().map(|d| d.e).collect::<Vec<_>>(),
+               vec![bob]);
+}
+
+#[test]
+fn test_transaction_datoms() {
+    let mut store = Store::open("").expect("opened");
+    let report = populate(&mut store);
+    let alice = report.tempids["a"];
+    let name = attribute(&store, "name");
+    let bio = attribute(&store, "bio");
+
+    let logged = store.transaction_datoms(report.tx_id).expect("transaction datoms");
+    assert!(logged.iter().all(|d| d.added && d.tx == report.tx_id));
+    assert!(logged.contains(&Datom { e: alice, a: bio, v: TypedValue::typed_string("Likes cats."), tx: report.tx_id, added: true }));
+
+    let report = {
+        let mut in_progress = store.begin_transaction().expect("began");
+        let report = in_progress.transact(format!("[[:db/add {} :test/name \"Alicia\"]]", alice).as_str()).expect("renamed");
+        in_progress.commit().expect("committed");
+        report
+    };
+
+    let logged: Vec<_> = store.transaction_datoms(report.tx_id)
+                              .expect("transaction datoms")
+                              .into_iter()
+                              .filter(|d| d.e == alice)
+                              .map(|d| (d.a, d.v, d.added))
+                              .collect();
+    assert_eq!(logged,
+               vec![(name, TypedValue::typed_string("Alice"), false),
+                    (name, TypedValue::typed_string("Alicia"), true)]);
+}
@@ -0,0 +1,247 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+extern crate mentat;
+
+use std::env;
+use std::fs::{
+    self,
+    File,
+};
+use std::path::PathBuf;
+
+use mentat::{
+    DatomIndex,
+    DatomPattern,
+    Entid,
+    QueryInputs,
+    Queryable,
+    Store,
+    TypedValue,
+    Variable,
+};
+
+use mentat::errors::ErrorKind;
+
+fn populate(store: &mut Store) -> Entid {
+    let mut in_progress = store.begin_transaction().expect("began");
+    in_progress.transact(r#"[
+        [:db/add :db/tx :db/txInstant #inst "2017-06-16T00:56:41.257Z"]
+        {:db/ident :test/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true}
+        {:db/ident :test/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/many}
+        {:db/ident :test/bio :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/fulltext true}
+    ]"#).expect("schema");
+    let report = in_progress.transact(r#"[
+        [:db/add :db/tx :db/txInstant #inst "2017-06-17T00:00:00.000Z"]
+        {:db/id "a" :test/name "Alice" :test/bio "Likes cats."}
+        {:db/id "b" :test/name "Bob" :test/friend "a"}
+    ]"#).expect("data");
+    let alice = report.tempids["a"];
+    in_progress.transact(format!(r#"[
+        [:db/add :db/tx :db/txInstant #inst "2017-06-18T00:00:00.000Z"]
+        [:db/add {} :test/name "Alicia"]
+    ]"#, alice).as_str()).expect("renamed");
+    in_progress.commit().expect("committed");
+    alice
+}
+
+fn export(store: &Store, name: &str, history: bool) -> PathBuf {
+    let path = env::temp_dir().join(format!("mentat-export-{}.edn", name));
+    let mut file = File::create(&path).expect("created");
+    store.export(&mut file, history).expect("exported");
+    path
+}
+
+fn restore(path: &PathBuf) -> Store {
+    let mut store = Store::open("").expect("opened");
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.restore(path).expect("restored");
+        in_progress.commit().expect("committed");
+    }
+    fs::remove_file(path).expect("removed");
+    store
+}
+
+/// Every datom other than those describing transactions, whose entids differ between stores.
+fn datoms(store: &Store) -> Vec<(Entid, Entid, TypedValue)> {
+    store.datoms(DatomIndex::EAVT, &DatomPattern::default())
+         .expect("datoms")
+         .into_iter()
+         .filter(|d| d.e != d.tx)
+         .map(|d| (d.e, d.a, d.v))
+         .collect()
+}
+
+/// The transaction that asserted `name`.
+fn tx_of_name(store: &Store, name: &str) -> Entid {
+    store.datoms(DatomIndex::AVET, &DatomPattern { v: Some(TypedValue::typed_string(name)), ..Default::default() })
+         .expect("datoms")[0].tx
+}
+
+fn instant_of_name(store: &Store, name: &str) -> TypedValue {
+    let inputs = QueryInputs::with_value_sequence(vec![(Variable::from_valid_name("?tx"), TypedValue::Ref(tx_of_name(store, name)))]);
+    store.q_once(r#"[:find ?when . :in ?tx :where [?tx :db/txInstant ?when]]"#, inputs)
+         .expect("query")
+         .into_scalar()
+         .expect("scalar")
+         .expect("instant")
+}
+
+#[test]
+fn test_export_current() {
+    let mut store = Store::open("").expect("opened");
+    populate(&mut store);
+
+    let restored = restore(&export(&store, "current", false));
+    assert_eq!(datoms(&restored), datoms(&store));
+    assert_eq!(instant_of_name(&restored, "Bob"), instant_of_name(&store, "Bob"));
+    assert_eq!(instant_of_name(&restored, "Alicia"), instant_of_name(&store, "Alicia"));
+
+    // Only current datoms were exported, so the rename is no longer a retraction.
+    assert!(restored.transaction_datoms(tx_of_name(&restored, "Alicia"))
+                    .expect("log")
+                    .iter()
+                    .all(|d| d.added));
+}
+
+#[test]
+fn test_export_history() {
+    let mut store = Store::open("").expect("opened");
+    let alice = populate(&mut store);
+
+    let restored = restore(&export(&store, "history", true));
+    assert_eq!(datoms(&restored), datoms(&store));
+
+    // The rename, with its retraction, was replayed as its own transaction.
+    let logged: Vec<_> = restored.transaction_datoms(tx_of_name(&restored, "Alicia"))
+                                 .expect("log")
+                                 .into_iter()
+                                 .filter(|d| d.e == alice)
+                                 .map(|d| (d.v, d.added))
+                                 .collect();
+    assert_eq!(logged, vec![(TypedValue::typed_string("Alice"), false),
+                            (TypedValue::typed_string("Alicia"), true)]);
+    assert_eq!(instant_of_name(&restored, "Alicia"), instant_of_name(&store, "Alicia"));
+
+    // New entities don't collide with restored ones.
+    let mut restored = restored;
+    let mut in_progress = restored.begin_transaction().expect("began");
+    let report = in_progress.transact(r#"[{:db/id "c" :test/name "Carol"}]"#).expect("transacted");
+    assert!(report.tempids["c"] > alice);
+}
+
+#[test]
+fn test_restore_requires_empty_store() {
+    let mut store = Store::open("").expect("opened");
+    populate(&mut store);
+    let path = export(&store, "nonempty", false);
+
+    let mut in_progress = store.begin_transaction().expect("began");
+    let result = in_progress.restore(&path);
+    fs::remove_file(&path).expect("removed");
+    match result {
+        Err(mentat::errors::Error(ErrorKind::StoreNotEmpty, _)) => (),
+        result => panic!("expected StoreNotEmpty, got {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_export_installed_partition() {
+    let mut store = Store::open("").expect("opened");
+    let thing = {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(r#"[
+            {:db/ident :test/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            [:db/add "p" :db/ident :test.part/things]
+            [:db/add :db.part/db :db.install/partition "p"]
+        ]"#).expect("installed");
+        let report = in_progress.transact(r#"[[:db/add (tempid :test.part/things "t") :test/name "Thing"]]"#).expect("allocated");
+        in_progress.commit().expect("committed");
+        report.tempids["t"]
+    };
+    assert_eq!(thing, 1 << 32);
+
+    let mut restored = restore(&export(&store, "partition", false));
+    assert_eq!(datoms(&restored), datoms(&store));
+
+    // The restored partition allocates after the restored entities.
+    let mut in_progress = restored.begin_transaction().expect("began");
+    let report = in_progress.transact(r#"[[:db/add (tempid :test.part/things "u") :test/name "Other"]]"#).expect("allocated");
+    assert_eq!(report.tempids["u"], thing + 1);
+}
+
+#[test]
+fn test_export_altered_attribute() {
+    let mut store = Store::open("").expect("opened");
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(r#"[
+            {:db/ident :test/tag :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        in_progress.transact(r#"[{:db/id "a" :test/tag "first"}]"#).expect("data");
+        in_progress.transact(r#"[[:db/add :test/tag :db/cardinality :db.cardinality/many]]"#).expect("altered");
+        in_progress.transact(r#"[{:db/id "b" :test/tag ["second" "third"]}]"#).expect("data");
+        in_progress.commit().expect("committed");
+    }
+
+    // The current cardinality was asserted after the attribute was installed, but the attribute is
+    // still installed whole before anything uses it.
+    let restored = restore(&export(&store, "altered-current", false));
+    assert_eq!(datoms(&restored), datoms(&store));
+
+    let restored = restore(&export(&store, "altered-history", true));
+    assert_eq!(datoms(&restored), datoms(&store));
+}
+
+#[test]
+fn test_export_tx_refs() {
+    let mut store = Store::open("").expect("opened");
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(r#"[
+            {:db/ident :test/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/index true}
+            {:db/ident :test/source :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        in_progress.transact(r#"[
+            [:db/add :db/tx :db/doc "imported"]
+            {:db/id "a" :test/name "Alice" :test/source :db/tx}
+        ]"#).expect("data");
+        in_progress.commit().expect("committed");
+    }
+
+    // Metadata about a transaction, and references to it, follow it to its new id.
+    let source = r#"[:find ?doc . :where [?a :test/name "Alice"] [?a :test/source ?tx] [?tx :db/doc ?doc]]"#;
+    for &(name, history) in [("tx-refs-current", false), ("tx-refs-history", true)].iter() {
+        let restored = restore(&export(&store, name, history));
+        assert_eq!(restored.q_once(source, None).expect("query").into_scalar().expect("scalar"),
+                   Some(TypedValue::typed_string("imported")));
+        let alice = tx_of_name(&restored, "Alice");
+        assert_eq!(restored.q_once(r#"[:find ?tx . :where [?a :test/name "Alice"] [?a :test/source ?tx]]"#, None)
+                           .expect("query")
+                           .into_scalar()
+                           .expect("scalar"),
+                   Some(TypedValue::Ref(alice)));
+    }
+
+    // A reference from one transaction to another can't be exported.
+    let alice = tx_of_name(&store, "Alice");
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(format!(r#"[[:db/add {} :db/doc "reviewed"]]"#, alice).as_str()).expect("annotated");
+        in_progress.commit().expect("committed");
+    }
+    for &history in [false, true].iter() {
+        match store.export(&mut Vec::<u8>::new(), history) {
+            Err(mentat::errors::Error(ErrorKind::UnexportableTxRef(tx, _), _)) => assert_eq!(tx, alice),
+            result => panic!("expected UnexportableTxRef, got {:?}", result),
+        }
+    }
+}
@@ -8,13 +8,23 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
+#[macro_use]
 extern crate mentat;
 extern crate mentat_core;
 extern crate mentat_tolstoy;
 
+use std::cell::{
+    Cell,
+    RefCell,
+};
 use std::collections::BTreeMap;
 
 use mentat::conn::Conn;
+use mentat::{
+    Store,
+    Syncable,
+    Uuid,
+};
 
 use mentat::new_connection;
 use mentat_tolstoy::tx_processor::{
@@ -22,7 +32,23 @@ use mentat_tolstoy::tx_processor::{
     TxReceiver,
     TxPart,
 };
-use mentat_tolstoy::errors::Result;
+use mentat_tolstoy::Syncer;
+use mentat_tolstoy::syncer::{
+    GlobalTransactionLog,
+    NoopSyncProgress,
+    SyncOutcome,
+};
+use mentat_tolstoy::errors::{
+    Error,
+    ErrorKind,
+    Result,
+};
+use mentat_tolstoy::metadata::{
+    HeadTrackable,
+    SyncMetadataClient,
+};
+use mentat_tolstoy::tx_mapper::TxMapper;
+use mentat_tolstoy::local_only::LocalOnly;
 use mentat_core::{
     Entid,
     TypedValue,
@@ -156,3 +182,326 @@ fn test_reader() {
         assert_eq!(true, part.added);
     }
 }
+
+#[test]
+fn test_local_only_attributes() {
+    let mut store = Store::open("").expect("opened");
+    let pref = {
+        let mut write = store.begin_transaction().expect("began transaction");
+        let ids = write.transact(r#"[
+            [:db/add "s" :db/ident :foo/pref]
+            [:db/add "s" :db/valueType :db.type/string]
+            [:db/add "s" :db/cardinality :db.cardinality/one]
+        ]"#).expect("successful transaction").tempids;
+        write.commit().expect("committed");
+        *ids.get("s").unwrap()
+    };
+
+    store.set_local_only(&kw!(:foo/pref), true).expect("marked local-only");
+    {
+        let db_tx = store.sqlite_mut().transaction().expect("db tx");
+        let local_only = LocalOnly::read(&db_tx).expect("read");
+        assert!(local_only.contains_attribute(pref));
+    }
+
+    store.set_local_only(&kw!(:foo/pref), false).expect("marked synced");
+    {
+        let db_tx = store.sqlite_mut().transaction().expect("db tx");
+        let local_only = LocalOnly::read(&db_tx).expect("read");
+        assert!(!local_only.contains_attribute(pref));
+    }
+
+    assert!(store.set_local_only(&kw!(:foo/missing), true).is_err());
+}
+
+#[test]
+fn test_local_only_partitions() {
+    let mut store = Store::open("").expect("opened");
+    let device = {
+        let mut write = store.begin_transaction().expect("began transaction");
+        write.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            [:db/add "p" :db/ident :foo.part/device]
+            [:db/add :db.part/db :db.install/partition "p"]
+        ]"#).expect("successful transaction");
+        let ids = write.transact(r#"[[:db/add (tempid :foo.part/device "d") :foo/name "This phone"]]"#).expect("successful transaction").tempids;
+        write.commit().expect("committed");
+        ids["d"]
+    };
+
+    store.set_partition_local_only(&kw!(:foo.part/device), true).expect("marked local-only");
+    {
+        let db_tx = store.sqlite_mut().transaction().expect("db tx");
+        let local_only = LocalOnly::read(&db_tx).expect("read");
+        assert!(local_only.contains_entid(device));
+    }
+
+    store.set_partition_local_only(&kw!(:foo.part/device), false).expect("marked synced");
+    {
+        let db_tx = store.sqlite_mut().transaction().expect("db tx");
+        let local_only = LocalOnly::read(&db_tx).expect("read");
+        assert!(!local_only.contains_entid(device));
+    }
+
+    assert!(store.set_partition_local_only(&kw!(:foo.part/missing), true).is_err());
+    match store.set_partition_local_only(&kw!(:db.part/db), true) {
+        Err(mentat::errors::Error(mentat::errors::ErrorKind::ReservedLocalOnlyPartition(part), _)) => assert_eq!(part, ":db.part/db"),
+        x => panic!("expected a reserved partition error, got {:?}", x),
+    }
+}
+
+/// An in-memory server that fails its `fail_at`th operation after applying it, so that a sync
+/// can be interrupted between any two of its steps.
+struct FlakyServer {
+    head: RefCell<Uuid>,
+    parents: RefCell<BTreeMap<Uuid, Uuid>>,
+    chunks: RefCell<BTreeMap<Uuid, String>>,
+    operations: Cell<usize>,
+    fail_at: Cell<Option<usize>>,
+}
+
+impl FlakyServer {
+    fn new(fail_at: Option<usize>) -> FlakyServer {
+        FlakyServer {
+            head: RefCell::new(Uuid::nil()),
+            parents: RefCell::new(BTreeMap::new()),
+            chunks: RefCell::new(BTreeMap::new()),
+            operations: Cell::new(0),
+            fail_at: Cell::new(fail_at),
+        }
+    }
+
+    fn step(&self) -> Result<()> {
+        let operation = self.operations.get();
+        self.operations.set(operation + 1);
+        if self.fail_at.get() == Some(operation) {
+            return Err(ErrorKind::BadServerResponse(format!("injected failure at operation {}", operation)).into());
+        }
+        Ok(())
+    }
+
+    /// The number of transactions reachable from the head.
+    fn chain_length(&self) -> usize {
+        let parents = self.parents.borrow();
+        let mut length = 0;
+        let mut current = self.head.borrow().clone();
+        while current != Uuid::nil() {
+            length += 1;
+            current = parents.get(&current).expect("parent").clone();
+        }
+        length
+    }
+
+    /// Another client uploads a transaction on top of the head.
+    fn advance_head(&self) -> Uuid {
+        let transaction = Uuid::new_v4();
+        let parent = self.head.borrow().clone();
+        self.parents.borrow_mut().insert(transaction.clone(), parent);
+        *self.head.borrow_mut() = transaction.clone();
+        transaction
+    }
+}
+
+impl GlobalTransactionLog for FlakyServer {
+    fn get_head(&self) -> Result<Uuid> {
+        let head = self.head.borrow().clone();
+        self.step()?;
+        Ok(head)
+    }
+
+    fn put_head(&self, uuid: &Uuid) -> Result<()> {
+        *self.head.borrow_mut() = uuid.clone();
+        self.step()
+    }
+
+    fn put_chunk(&self, chunk_uuid: &Uuid, payload: &String) -> Result<()> {
+        self.chunks.borrow_mut().insert(chunk_uuid.clone(), payload.clone());
+        self.step()
+    }
+
+    fn put_transaction(&self, transaction_uuid: &Uuid, parent_uuid: &Uuid, _chunks: &Vec<Uuid>) -> Result<()> {
+        self.parents.borrow_mut().insert(transaction_uuid.clone(), parent_uuid.clone());
+        self.step()
+    }
+
+    fn get_transactions_after(&self, from_uuid: &Uuid) -> Result<Vec<Uuid>> {
+        let mut transactions = vec![];
+        {
+            let parents = self.parents.borrow();
+            let mut current = self.head.borrow().clone();
+            while current != *from_uuid {
+                if current == Uuid::nil() {
+                    return Err(ErrorKind::BadServerResponse(format!("{} is not an ancestor of the head", from_uuid)).into());
+                }
+                transactions.push(current.clone());
+                current = parents.get(&current).expect("parent").clone();
+            }
+        }
+        transactions.reverse();
+        self.step()?;
+        Ok(transactions)
+    }
+}
+
+fn populated_store() -> Store {
+    let mut store = Store::open("").expect("opened");
+    {
+        let mut write = store.begin_transaction().expect("began transaction");
+        write.transact(r#"[
+            {:db/ident       :foo/numba
+             :db/valueType   :db.type/long
+             :db/cardinality :db.cardinality/one}]"#).expect("transaction expected to succeed");
+        write.transact(r#"[[:db/add "b" :foo/numba 123]]"#).expect("transaction expected to succeed");
+        write.commit().expect("committed");
+    }
+    store
+}
+
+#[test]
+fn test_sync_completes_after_interruption() {
+    // The bootstrap transaction and the two in `populated_store`.
+    let local_tx_count = 3;
+
+    // Count the server operations an uninterrupted sync performs.
+    let operation_count = {
+        let mut store = populated_store();
+        let server = FlakyServer::new(None);
+        let report = Syncer::flow_with_remote(store.sqlite_mut(), &server, &mut NoopSyncProgress).expect("synced");
+        assert_eq!(SyncOutcome::FastForwardedServer, report.outcome);
+        assert_eq!(local_tx_count, report.uploaded_tx_count);
+        assert_eq!(local_tx_count, server.chain_length());
+        server.operations.get()
+    };
+
+    for fail_at in 0..operation_count {
+        let mut store = populated_store();
+        let server = FlakyServer::new(Some(fail_at));
+        assert!(Syncer::flow_with_remote(store.sqlite_mut(), &server, &mut NoopSyncProgress).is_err());
+
+        server.fail_at.set(None);
+        let report = Syncer::flow_with_remote(store.sqlite_mut(), &server, &mut NoopSyncProgress).expect("synced after interruption");
+
+        // Only the last operation, putting the head, advances the server.
+        if fail_at == operation_count - 1 {
+            assert!(report.completed_interrupted_upload);
+            assert_eq!(SyncOutcome::NoChanges, report.outcome);
+            assert_eq!(0, report.uploaded_tx_count);
+        } else {
+            assert!(!report.completed_interrupted_upload);
+            assert_eq!(SyncOutcome::FastForwardedServer, report.outcome);
+            assert_eq!(local_tx_count, report.uploaded_tx_count);
+        }
+        assert_eq!(local_tx_count, server.chain_length());
+        assert_eq!(server.head.borrow().clone(), report.remote_head_after);
+
+        // Once healed, there's nothing left to do.
+        let report = Syncer::flow_with_remote(store.sqlite_mut(), &server, &mut NoopSyncProgress).expect("synced again");
+        assert_eq!(SyncOutcome::NoChanges, report.outcome);
+        assert!(!report.completed_interrupted_upload);
+    }
+}
+
+#[test]
+fn test_sync_completes_interrupted_upload_after_another_client() {
+    let local_tx_count = 3;
+
+    let operation_count = {
+        let mut store = populated_store();
+        let server = FlakyServer::new(None);
+        Syncer::flow_with_remote(store.sqlite_mut(), &server, &mut NoopSyncProgress).expect("synced");
+        server.operations.get()
+    };
+
+    // Interrupt the sync after it puts the head, and let another client build on top of it.
+    let mut store = populated_store();
+    let server = FlakyServer::new(Some(operation_count - 1));
+    assert!(Syncer::flow_with_remote(store.sqlite_mut(), &server, &mut NoopSyncProgress).is_err());
+    let our_head = server.head.borrow().clone();
+    server.advance_head();
+    server.fail_at.set(None);
+    let chunk_count = server.chunks.borrow().len();
+
+    // We can't merge the other client's transaction yet, but ours are recognized as uploaded
+    // rather than uploaded again.
+    match Syncer::flow_with_remote(store.sqlite_mut(), &server, &mut NoopSyncProgress) {
+        Err(Error(ErrorKind::NotYetImplemented(_), _)) => {},
+        x => panic!("expected a NotYetImplemented error, got {:?}", x),
+    }
+    assert_eq!(local_tx_count + 1, server.chain_length());
+    assert_eq!(chunk_count, server.chunks.borrow().len());
+
+    let db_tx = store.sqlite_mut().transaction().expect("db tx");
+    assert_eq!(our_head, SyncMetadataClient::remote_head(&db_tx).expect("remote head"));
+    assert!(TxMapper::pending(&db_tx).expect("pending").is_empty());
+    let mapped = server.parents.borrow().keys()
+        .filter(|uuid| TxMapper::get_tx_for_uuid(&db_tx, uuid).expect("mapping").is_some())
+        .count();
+    assert_eq!(local_tx_count, mapped);
+}
+
+#[test]
+fn test_sync_skips_local_only_attributes() {
+    let mut store = Store::open("").expect("opened");
+    let (name, pref) = {
+        let mut write = store.begin_transaction().expect("began transaction");
+        let ids = write.transact(r#"[
+            {:db/id "n" :db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/id "p" :db/ident :foo/pref :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+        ]"#).expect("successful transaction").tempids;
+        write.commit().expect("committed");
+        (ids["n"], ids["p"])
+    };
+    store.set_local_only(&kw!(:foo/pref), true).expect("marked local-only");
+    {
+        let mut write = store.begin_transaction().expect("began transaction");
+        write.transact(r#"[{:foo/name "Alice" :foo/pref "dark mode"}]"#).expect("successful transaction");
+        write.commit().expect("committed");
+    }
+
+    let server = FlakyServer::new(None);
+    let report = Syncer::flow_with_remote(store.sqlite_mut(), &server, &mut NoopSyncProgress).expect("synced");
+    assert_eq!(SyncOutcome::FastForwardedServer, report.outcome);
+
+    // Each chunk is a single datom, serialized as JSON.
+    let chunks = server.chunks.borrow();
+    let uploaded = |a: Entid| chunks.values().any(|chunk| chunk.contains(&format!("\"a\":{},", a)));
+    assert!(uploaded(name));
+    assert!(!uploaded(pref));
+    assert!(!chunks.values().any(|chunk| chunk.contains("dark mode")));
+}
+
+#[test]
+fn test_sync_skips_local_only_partitions() {
+    let mut store = Store::open("").expect("opened");
+    {
+        let mut write = store.begin_transaction().expect("began transaction");
+        write.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/device :db/valueType :db.type/ref :db/cardinality :db.cardinality/one}
+            [:db/add "p" :db/ident :foo.part/device]
+            [:db/add :db.part/db :db.install/partition "p"]
+        ]"#).expect("successful transaction");
+        write.commit().expect("committed");
+    }
+    store.set_partition_local_only(&kw!(:foo.part/device), true).expect("marked local-only");
+    let (device, alice) = {
+        let mut write = store.begin_transaction().expect("began transaction");
+        let ids = write.transact(r#"[
+            [:db/add (tempid :foo.part/device "d") :foo/name "This phone"]
+            [:db/add "a" :foo/name "Alice"]
+            [:db/add "a" :foo/device "d"]
+        ]"#).expect("successful transaction").tempids;
+        write.commit().expect("committed");
+        (ids["d"], ids["a"])
+    };
+
+    let server = FlakyServer::new(None);
+    let report = Syncer::flow_with_remote(store.sqlite_mut(), &server, &mut NoopSyncProgress).expect("synced");
+    assert_eq!(SyncOutcome::FastForwardedServer, report.outcome);
+
+    // Neither the device entity nor Alice's reference to it is uploaded; Alice's name is.
+    let chunks = server.chunks.borrow();
+    assert!(chunks.values().any(|chunk| chunk.contains(&format!("\"e\":{},", alice)) && chunk.contains("Alice")));
+    assert!(!chunks.values().any(|chunk| chunk.contains(&format!("{}", device))));
+    assert!(!chunks.values().any(|chunk| chunk.contains("This phone")));
+}
@@ -0,0 +1,155 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+extern crate edn;
+
+#[macro_use]
+extern crate mentat;
+extern crate mentat_db;
+extern crate mentat_tx;
+extern crate mentat_tx_parser;
+
+use std::sync::Arc;
+
+use mentat::{
+    Entid,
+    Queryable,
+    Store,
+    TxFunctionContext,
+    TypedValue,
+};
+use mentat::errors::{
+    Error,
+    ErrorKind,
+};
+
+use mentat_tx::entities::Entity;
+
+/// `[:counter/increment e a]` adds one to the long value of `a` on `e`, starting from zero.
+fn increment(context: &TxFunctionContext, args: &[edn::Value]) -> mentat_db::Result<Vec<Entity>> {
+    let e = args.get(0).and_then(|e| e.as_integer()).ok_or("expected an entity")?;
+    let a = args.get(1).and_then(|a| a.as_namespaced_keyword()).ok_or("expected an attribute")?;
+    let current = match context.value_for(e, a)? {
+        Some(TypedValue::Long(n)) => n,
+        _ => 0,
+    };
+    let tx = edn::parse::value(&format!("[[:db/add {} {} {}]]", e, a, current + 1)).map_err(|e| e.to_string())?;
+    Ok(mentat_tx_parser::Tx::parse(&tx).map_err(|e| e.to_string())?)
+}
+
+fn store() -> (Store, Entid) {
+    let mut store = Store::open("").expect("opened");
+    store.register_tx_function(kw!(:counter/increment), Arc::new(increment)).expect("registered");
+
+    let report = {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(r#"[
+            {:db/ident :counter/value :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        let report = in_progress.transact(r#"[{:db/id "c" :counter/value 0}]"#).expect("transacted");
+        in_progress.commit().expect("committed");
+        report
+    };
+    let counter = report.tempids["c"];
+    (store, counter)
+}
+
+fn value(store: &mut Store, counter: Entid) -> Option<TypedValue> {
+    store.begin_read().expect("began")
+         .lookup_value_for_attribute(counter, &kw!(:counter/value))
+         .expect("looked up")
+}
+
+#[test]
+fn test_tx_function() {
+    let (mut store, counter) = store();
+    let increment = format!("[[:counter/increment {} :counter/value]]", counter);
+
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(&increment).expect("incremented");
+        in_progress.commit().expect("committed");
+    }
+    assert_eq!(value(&mut store, counter), Some(TypedValue::Long(1)));
+
+    // The function's entities are transacted alongside the rest of the transaction.
+    let report = {
+        let mut in_progress = store.begin_transaction().expect("began");
+        let report = in_progress.transact(&format!(r#"[
+            [:counter/increment {} :counter/value]
+            {{:db/id "d" :counter/value 10}}
+        ]"#, counter)).expect("incremented");
+        in_progress.commit().expect("committed");
+        report
+    };
+    assert_eq!(value(&mut store, counter), Some(TypedValue::Long(2)));
+    assert_eq!(value(&mut store, report.tempids["d"]), Some(TypedValue::Long(10)));
+
+    // Once unregistered, the function can't be called.
+    store.unregister_tx_function(&kw!(:counter/increment));
+    let mut in_progress = store.begin_transaction().expect("began");
+    match in_progress.transact(&increment).expect_err("unregistered") {
+        Error(ErrorKind::DbError(mentat_db::ErrorKind::UnrecognizedTxFunction(name)), _) => {
+            assert_eq!(name, ":counter/increment");
+        },
+        e => panic!("expected an unrecognized function, got {:?}", e),
+    }
+}
+
+#[test]
+fn test_reserved_tx_function_names() {
+    let (store, _) = store();
+    match store.register_tx_function(kw!(:db/increment), Arc::new(increment)).expect_err("reserved") {
+        Error(ErrorKind::ReservedTxFunctionName(name), _) => assert_eq!(name, ":db/increment"),
+        e => panic!("expected a reserved name, got {:?}", e),
+    }
+}
+
+/// `[:test/recurse]` calls itself forever.
+fn recurse(_context: &TxFunctionContext, _args: &[edn::Value]) -> mentat_db::Result<Vec<Entity>> {
+    Ok(vec![Entity::Call { function: kw!(:test/recurse), args: vec![] }])
+}
+
+#[test]
+fn test_tx_function_depth() {
+    let (mut store, counter) = store();
+    store.register_tx_function(kw!(:test/recurse), Arc::new(recurse)).expect("registered");
+
+    let mut in_progress = store.begin_transaction().expect("began");
+    match in_progress.transact("[[:test/recurse]]").expect_err("too deep") {
+        Error(ErrorKind::DbError(mentat_db::ErrorKind::TxFunctionDepthExceeded(name, depth)), _) => {
+            assert_eq!(name, ":test/recurse");
+            assert_eq!(depth, mentat_db::tx_function::MAX_TX_FUNCTION_DEPTH);
+        },
+        e => panic!("expected too deep a call, got {:?}", e),
+    }
+
+    // Calls side by side aren't nested, however many there are.
+    let increments: Vec<String> = (0..mentat_db::tx_function::MAX_TX_FUNCTION_DEPTH + 1)
+        .map(|_| format!("[:counter/increment {} :counter/value]", counter))
+        .collect();
+    in_progress.transact(&format!("[{}]", increments.join(" "))).expect("incremented");
+}
+
+#[test]
+fn test_tx_functions_read_before_transaction() {
+    let (mut store, counter) = store();
+
+    // Both calls read the value from before the transaction, so both assert 1.
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(&format!(r#"[
+            [:counter/increment {} :counter/value]
+            [:counter/increment {} :counter/value]
+        ]"#, counter, counter)).expect("incremented");
+        in_progress.commit().expect("committed");
+    }
+    assert_eq!(value(&mut store, counter), Some(TypedValue::Long(1)));
+}
@@ -0,0 +1,127 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+#[macro_use]
+extern crate mentat;
+
+use std::collections::BTreeSet;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+use mentat::{
+    Entid,
+    HasSchema,
+    Store,
+    TxCommitted,
+    TxObserver,
+    TypedValue,
+};
+
+type Notifications = Arc<Mutex<Vec<(String, Vec<TxCommitted>)>>>;
+
+fn store() -> (Store, Entid, Entid) {
+    let mut store = Store::open("").expect("opened");
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(r#"[
+            {:db/ident :foo/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}
+            {:db/ident :foo/age :db/valueType :db.type/long :db/cardinality :db.cardinality/one}
+        ]"#).expect("schema");
+        in_progress.commit().expect("committed");
+    }
+    let name = store.conn().current_schema().get_entid(&kw!(:foo/name)).expect("entid").into();
+    let age = store.conn().current_schema().get_entid(&kw!(:foo/age)).expect("entid").into();
+    (store, name, age)
+}
+
+fn observer(notifications: &Notifications, attributes: Option<BTreeSet<Entid>>) -> Arc<TxObserver> {
+    let notifications = notifications.clone();
+    let notify = move |key: &str, committed: &[TxCommitted]| {
+        notifications.lock().unwrap().push((key.to_string(), committed.to_vec()));
+    };
+    Arc::new(match attributes {
+        Some(attributes) => TxObserver::new(attributes, notify),
+        None => TxObserver::all(notify),
+    })
+}
+
+#[test]
+fn test_observer_is_told_of_committed_transactions() {
+    let (mut store, name, _) = store();
+    let notifications: Notifications = Default::default();
+    store.register_observer("names".to_string(), observer(&notifications, Some(vec![name].into_iter().collect())));
+
+    let (named, aged) = {
+        let mut in_progress = store.begin_transaction().expect("began");
+        let named = in_progress.transact(r#"[{:db/id "a" :foo/name "Alice" :foo/age 30}]"#).expect("transacted");
+        let aged = in_progress.transact(r#"[{:db/id "b" :foo/age 40}]"#).expect("transacted");
+
+        // Nothing is committed yet.
+        assert!(notifications.lock().unwrap().is_empty());
+        in_progress.commit().expect("committed");
+        (named, aged)
+    };
+
+    // Only the transaction that touched :foo/name is reported, and only its :foo/name datoms.
+    {
+        let notifications = notifications.lock().unwrap();
+        assert_eq!(notifications.len(), 1);
+        let (ref key, ref committed) = notifications[0];
+        assert_eq!(key, "names");
+        assert_eq!(committed.len(), 1);
+        assert_eq!(committed[0].report, named);
+        let datoms: Vec<_> = committed[0].datoms.iter().map(|d| (d.e, d.a, d.v.clone(), d.added)).collect();
+        assert_eq!(datoms, vec![(named.tempids["a"], name, TypedValue::typed_string("Alice"), true)]);
+    }
+    assert_ne!(named.tx_id, aged.tx_id);
+
+    // Rolled back transactions aren't reported.
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(r#"[{:foo/name "Bob"}]"#).expect("transacted");
+        in_progress.rollback().expect("rolled back");
+    }
+    assert_eq!(notifications.lock().unwrap().len(), 1);
+
+    // Nor are transactions after the observer is unregistered.
+    store.unregister_observer("names");
+    {
+        let mut in_progress = store.begin_transaction().expect("began");
+        in_progress.transact(r#"[{:foo/name "Carol" :foo/age 50}]"#).expect("transacted");
+        in_progress.commit().expect("committed");
+    }
+    assert_eq!(notifications.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_observer_of_all_transactions() {
+    let (mut store, _, age) = store();
+    let notifications: Notifications = Default::default();
+    store.register_observer("all".to_string(), observer(&notifications, None));
+
+    let report = {
+        let mut in_progress = store.begin_transaction().expect("began");
+        let report = in_progress.transact(r#"[{:db/id "b" :foo/age 40}]"#).expect("transacted");
+        in_progress.commit().expect("committed");
+        report
+    };
+
+    let notifications = notifications.lock().unwrap();
+    assert_eq!(notifications.len(), 1);
+    let committed = &notifications[0].1;
+    assert_eq!(committed.len(), 1);
+    assert_eq!(committed[0].report, report);
+
+    // Every datom is reported, including the transaction's instant.
+    assert!(committed[0].datoms.iter().any(|d| d.a == age));
+    assert!(committed[0].datoms.iter().any(|d| d.e == report.tx_id));
+}
@@ -39,6 +39,7 @@ pub mod tx_processor;
 pub mod errors;
 pub mod syncer;
 pub mod tx_mapper;
+pub mod local_only;
 pub use syncer::Syncer;
 pub use errors::{
     Error,
@@ -0,0 +1,167 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::BTreeSet;
+
+use rusqlite;
+
+use mentat_core::{
+    Entid,
+    TypedValue,
+};
+use mentat_db::Partition;
+
+use errors::Result;
+
+use tx_processor::TxPart;
+
+/// Tracks the attributes and partitions whose datoms never leave this device.
+///
+/// Datoms asserting or retracting a local-only attribute are not uploaded. The attribute
+/// definitions themselves are still synced, so other devices know about the attribute but never
+/// see its values.
+///
+/// Datoms about an entity in a local-only partition, or referring to one, are not uploaded either.
+/// The partition itself is still known to other devices.
+///
+/// Both sets live in Tolstoy's own `tolstoy_local_only` and `tolstoy_local_only_parts` tables
+/// rather than in the schema, so only the uploader knows about them: they aren't visible through
+/// the schema, and aren't exported. Downloading isn't affected, because we never download.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LocalOnly {
+    attributes: BTreeSet<Entid>,
+    partitions: Vec<Partition>,
+}
+
+impl LocalOnly {
+    pub fn read(db_tx: &rusqlite::Transaction) -> Result<LocalOnly> {
+        let mut stmt = db_tx.prepare_cached("SELECT a FROM tolstoy_local_only")?;
+        let results = stmt.query_map(&[], |r| r.get(0))?;
+
+        let mut attributes: BTreeSet<Entid> = BTreeSet::new();
+        for a in results {
+            attributes.insert(a?);
+        }
+
+        // A partition's entids are those allocated so far, which are all that datoms can mention.
+        let mut stmt = db_tx.prepare_cached("SELECT parts.start, parts.idx FROM tolstoy_local_only_parts JOIN parts ON tolstoy_local_only_parts.part = parts.part")?;
+        let results = stmt.query_map(&[], |r| Partition::new(r.get(0), r.get(1)))?;
+
+        let mut partitions: Vec<Partition> = vec![];
+        for partition in results {
+            partitions.push(partition?);
+        }
+        Ok(LocalOnly {
+            attributes: attributes,
+            partitions: partitions,
+        })
+    }
+
+    /// Mark `attribute` as local-only, or as synced if `local_only` is `false`.
+    pub fn set_attribute(db_tx: &rusqlite::Transaction, attribute: Entid, local_only: bool) -> Result<()> {
+        if local_only {
+            db_tx.execute("INSERT OR IGNORE INTO tolstoy_local_only (a) VALUES (?)", &[&attribute])?;
+        } else {
+            db_tx.execute("DELETE FROM tolstoy_local_only WHERE a = ?", &[&attribute])?;
+        }
+        Ok(())
+    }
+
+    /// Mark the partition named `partition` as local-only, or as synced if `local_only` is `false`.
+    pub fn set_partition(db_tx: &rusqlite::Transaction, partition: &str, local_only: bool) -> Result<()> {
+        if local_only {
+            db_tx.execute("INSERT OR IGNORE INTO tolstoy_local_only_parts (part) VALUES (?)", &[&partition])?;
+        } else {
+            db_tx.execute("DELETE FROM tolstoy_local_only_parts WHERE part = ?", &[&partition])?;
+        }
+        Ok(())
+    }
+
+    pub fn contains_attribute(&self, attribute: Entid) -> bool {
+        self.attributes.contains(&attribute)
+    }
+
+    pub fn contains_entid(&self, entid: Entid) -> bool {
+        self.partitions.iter().any(|partition| partition.contains_entid(entid))
+    }
+
+    /// `true` if `part` should be uploaded to the server.
+    pub fn should_sync(&self, part: &TxPart) -> bool {
+        if self.contains_attribute(part.a) || self.contains_entid(part.e) {
+            return false;
+        }
+        match part.v {
+            TypedValue::Ref(v) => !self.contains_entid(v),
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schema;
+
+    #[test]
+    fn test_set_and_read() {
+        let mut conn = schema::tests::setup_conn();
+        let tx = conn.transaction().expect("db tx");
+        assert_eq!(LocalOnly::default(), LocalOnly::read(&tx).expect("read"));
+
+        LocalOnly::set_attribute(&tx, 65536, true).expect("set");
+        LocalOnly::set_attribute(&tx, 65536, true).expect("set is idempotent");
+        LocalOnly::set_attribute(&tx, 65537, true).expect("set");
+        LocalOnly::set_attribute(&tx, 65537, false).expect("unset");
+
+        let local_only = LocalOnly::read(&tx).expect("read");
+        assert!(local_only.contains_attribute(65536));
+        assert!(!local_only.contains_attribute(65537));
+
+        let part = TxPart {
+            e: 65538,
+            a: 65536,
+            v: TypedValue::Boolean(true),
+            tx: 268435457,
+            added: true,
+        };
+        assert!(!local_only.should_sync(&part));
+        assert!(local_only.should_sync(&TxPart { a: 65537, ..part }));
+    }
+
+    #[test]
+    fn test_partitions() {
+        let mut conn = schema::tests::setup_conn();
+        conn.execute_batch("
+            CREATE TABLE parts (part TEXT NOT NULL PRIMARY KEY, start INTEGER NOT NULL, idx INTEGER NOT NULL);
+            INSERT INTO parts VALUES (':db.part/user', 65536, 65540), (':test.part/device', 1000000, 1000002);
+        ").expect("parts");
+        let tx = conn.transaction().expect("db tx");
+
+        LocalOnly::set_partition(&tx, ":test.part/device", true).expect("set");
+        LocalOnly::set_partition(&tx, ":test.part/device", true).expect("set is idempotent");
+        LocalOnly::set_partition(&tx, ":db.part/user", true).expect("set");
+        LocalOnly::set_partition(&tx, ":db.part/user", false).expect("unset");
+
+        let local_only = LocalOnly::read(&tx).expect("read");
+        assert!(local_only.contains_entid(1000001));
+        assert!(!local_only.contains_entid(65537));
+
+        let part = TxPart {
+            e: 1000001,
+            a: 65536,
+            v: TypedValue::Boolean(true),
+            tx: 268435457,
+            added: true,
+        };
+        assert!(!local_only.should_sync(&part));
+        assert!(local_only.should_sync(&TxPart { e: 65537, ..part.clone() }));
+        assert!(!local_only.should_sync(&TxPart { e: 65537, v: TypedValue::Ref(1000000), ..part }));
+    }
+}
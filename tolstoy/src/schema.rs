@@ -20,6 +20,9 @@ lazy_static! {
         r#"CREATE TABLE IF NOT EXISTS tolstoy_tu (tx INTEGER PRIMARY KEY, uuid BLOB NOT NULL UNIQUE) WITHOUT ROWID"#,
         r#"CREATE TABLE IF NOT EXISTS tolstoy_metadata (key BLOB NOT NULL UNIQUE, value BLOB NOT NULL)"#,
         r#"CREATE INDEX IF NOT EXISTS idx_tolstoy_tu_ut ON tolstoy_tu (uuid, tx)"#,
+        r#"CREATE TABLE IF NOT EXISTS tolstoy_local_only (a INTEGER PRIMARY KEY) WITHOUT ROWID"#,
+        r#"CREATE TABLE IF NOT EXISTS tolstoy_local_only_parts (part TEXT PRIMARY KEY) WITHOUT ROWID"#,
+        r#"CREATE TABLE IF NOT EXISTS tolstoy_pending_tu (tx INTEGER PRIMARY KEY, uuid BLOB NOT NULL UNIQUE) WITHOUT ROWID"#,
         ]
     };
 }
@@ -9,7 +9,16 @@
 // specific language governing permissions and limitations under the License.
 
 use std;
-use std::collections::HashMap;
+use std::collections::{
+    BTreeMap,
+    HashMap,
+    HashSet,
+};
+use std::fmt;
+use std::time::{
+    Duration,
+    Instant,
+};
 
 use futures::{future, Future, Stream};
 use hyper;
@@ -21,6 +30,7 @@ use hyper::header::{ContentType};
 use rusqlite;
 // TODO: https://github.com/mozilla/mentat/issues/570
 // use serde_cbor;
+use serde::de::DeserializeOwned;
 use serde_json;
 use tokio_core::reactor::Core;
 use uuid::Uuid;
@@ -42,28 +52,80 @@ use tx_processor::{
 };
 
 use tx_mapper::TxMapper;
+use local_only::LocalOnly;
 
 // TODO it would be nice to be able to pass
 // in a logger into Syncer::flow; would allow for a "debug mode"
 // and getting useful logs out of clients.
 // See https://github.com/mozilla/mentat/issues/571
-// Below is some debug Android-friendly logging:
-
-// use std::os::raw::c_char;
-// use std::os::raw::c_int;
-// use std::ffi::CString;
-// pub const ANDROID_LOG_DEBUG: i32 = 3;
-// extern { pub fn __android_log_write(prio: c_int, tag: *const c_char, text: *const c_char) -> c_int; }
-
-pub fn d(message: &str) {
-    println!("d: {}", message);
-    // let message = CString::new(message).unwrap();
-    // let message = message.as_ptr();
-    // let tag = CString::new("RustyToodle").unwrap();
-    // let tag = tag.as_ptr();
-    // unsafe { __android_log_write(ANDROID_LOG_DEBUG, tag, message) };
+
+/// How a successful sync changed the relationship between the local store and the server.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SyncOutcome {
+    /// Neither the server nor the local store changed since the last sync.
+    NoChanges,
+    /// Local transactions were uploaded on top of the server's head.
+    /// An empty server is fast-forwarded from the nil head.
+    FastForwardedServer,
+    // TODO: `Merged`, once we can sync against a server that moved since our last sync.
+}
+
+/// A summary of a single run of `Syncer::flow`.
+///
+/// Syncing only uploads: merging the server's transactions into the local store isn't supported
+/// yet, so a sync against a server that changed since the last sync fails, and nothing is ever
+/// downloaded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SyncReport {
+    pub outcome: SyncOutcome,
+    pub uploaded_tx_count: usize,
+    /// The server's head before we started syncing.
+    pub remote_head_before: Uuid,
+    /// The head the server and the local store agree on after syncing.
+    pub remote_head_after: Uuid,
+    /// `true` if a previous sync had advanced the server's head without recording it locally,
+    /// and this sync recorded it.
+    pub completed_interrupted_upload: bool,
+    pub duration: Duration,
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let outcome = match self.outcome {
+            SyncOutcome::NoChanges => "nothing to do",
+            SyncOutcome::FastForwardedServer => "fast-forwarded server",
+        };
+        let millis = self.duration.as_secs() * 1000 + (self.duration.subsec_nanos() / 1_000_000) as u64;
+        write!(f, "{}: uploaded {} transactions; head {} in {}ms",
+               outcome, self.uploaded_tx_count, self.remote_head_after, millis)?;
+        if self.completed_interrupted_upload {
+            write!(f, " (completed an interrupted sync)")?;
+        }
+        Ok(())
+    }
 }
 
+/// Receives notifications as a sync progresses. All methods default to doing nothing,
+/// so implementors only need to override the events they care about.
+pub trait SyncProgress {
+    /// Called once we know the server's head and the head we recorded at the end of the last sync.
+    fn heads_fetched(&mut self, _remote_head: &Uuid, _locally_known_remote_head: &Uuid) {}
+
+    /// Called after each chunk (a single datom) of a local transaction is uploaded.
+    fn chunk_uploaded(&mut self, _tx: Entid, _chunk: &Uuid) {}
+
+    /// Called after a local transaction and all of its chunks are uploaded.
+    fn tx_uploaded(&mut self, _tx: Entid, _tx_uuid: &Uuid) {}
+
+    /// Called once the sync has been committed locally.
+    fn finished(&mut self, _report: &SyncReport) {}
+}
+
+/// A `SyncProgress` that ignores every event.
+pub struct NoopSyncProgress;
+
+impl SyncProgress for NoopSyncProgress {}
+
 pub struct Syncer {}
 
 // TODO this is sub-optimal, we don't need to walk the table
@@ -97,22 +159,67 @@ impl TxReceiver for InquiringTxReceiver {
     }
 }
 
+/// The operations `Syncer` needs from a server. `RemoteClient` implements them over HTTP;
+/// tests and local servers can provide their own.
+pub trait GlobalTransactionLog {
+    fn get_head(&self) -> Result<Uuid>;
+    fn put_head(&self, uuid: &Uuid) -> Result<()>;
+    fn put_chunk(&self, chunk_uuid: &Uuid, payload: &String) -> Result<()>;
+    fn put_transaction(&self, transaction_uuid: &Uuid, parent_uuid: &Uuid, chunks: &Vec<Uuid>) -> Result<()>;
+    /// The transactions reachable from the head that follow `from_uuid`, oldest first.
+    fn get_transactions_after(&self, from_uuid: &Uuid) -> Result<Vec<Uuid>>;
+}
+
+// Collects the ids of the transactions a `Processor` walks over.
+struct CollectingTxReceiver {
+    pub txs: Vec<Entid>,
+    pub is_done: bool,
+}
+
+impl CollectingTxReceiver {
+    fn new() -> CollectingTxReceiver {
+        CollectingTxReceiver {
+            txs: vec![],
+            is_done: false,
+        }
+    }
+}
+
+impl TxReceiver for CollectingTxReceiver {
+    fn tx<T>(&mut self, tx_id: Entid, _datoms: &mut T) -> Result<()>
+    where T: Iterator<Item=TxPart> {
+        self.txs.push(tx_id);
+        Ok(())
+    }
+
+    fn done(&mut self) -> Result<()> {
+        self.is_done = true;
+        Ok(())
+    }
+}
+
 struct UploadingTxReceiver<'c> {
-    pub tx_temp_uuids: HashMap<Entid, Uuid>,
+    pub uploaded_tx_count: usize,
     pub is_done: bool,
-    remote_client: &'c RemoteClient,
+    remote_client: &'c GlobalTransactionLog,
     remote_head: &'c Uuid,
     rolling_temp_head: Option<Uuid>,
+    tx_temp_uuids: &'c BTreeMap<Entid, Uuid>,
+    local_only: &'c LocalOnly,
+    progress: &'c mut SyncProgress,
 }
 
 impl<'c> UploadingTxReceiver<'c> {
-    fn new(client: &'c RemoteClient, remote_head: &'c Uuid) -> UploadingTxReceiver<'c> {
+    fn new(client: &'c GlobalTransactionLog, remote_head: &'c Uuid, tx_temp_uuids: &'c BTreeMap<Entid, Uuid>, local_only: &'c LocalOnly, progress: &'c mut SyncProgress) -> UploadingTxReceiver<'c> {
         UploadingTxReceiver {
-            tx_temp_uuids: HashMap::new(),
+            uploaded_tx_count: 0,
             remote_client: client,
             remote_head: remote_head,
             rolling_temp_head: None,
-            is_done: false
+            is_done: false,
+            tx_temp_uuids: tx_temp_uuids,
+            local_only: local_only,
+            progress: progress,
         }
     }
 }
@@ -120,29 +227,28 @@ impl<'c> UploadingTxReceiver<'c> {
 impl<'c> TxReceiver for UploadingTxReceiver<'c> {
     fn tx<T>(&mut self, tx_id: Entid, datoms: &mut T) -> Result<()>
     where T: Iterator<Item=TxPart> {
-        // Yes, we generate a new UUID for a given Tx, even if we might
-        // already have one mapped locally. Pre-existing local mapping will
-        // be replaced if this sync succeeds entirely.
-        // If we're seeing this tx again, it implies that previous attempt
-        // to sync didn't update our local head. Something went wrong last time,
-        // and it's unwise to try to re-use these remote tx mappings.
-        // We just leave garbage txs to be GC'd on the server.
-        let tx_uuid = Uuid::new_v4();
-        self.tx_temp_uuids.insert(tx_id, tx_uuid);
+        // The UUID for this tx was generated and recorded as pending before we started uploading.
+        // Pre-existing local mapping will be replaced if this sync succeeds entirely.
+        // Transactions that were committed after we recorded pending UUIDs are left for the next sync.
+        let tx_uuid = match self.tx_temp_uuids.get(&tx_id) {
+            Some(uuid) => uuid.clone(),
+            None => return Ok(()),
+        };
         let mut tx_chunks = vec![];
 
         // TODO separate bits of network work should be combined into single 'future'
 
-        // Upload all chunks.
-        for datom in datoms {
+        // Upload all chunks. Local-only datoms stay on this device.
+        let local_only = self.local_only;
+        for datom in datoms.filter(|datom| local_only.should_sync(datom)) {
             let datom_uuid = Uuid::new_v4();
             tx_chunks.push(datom_uuid);
-            d(&format!("putting chunk: {:?}, {:?}", &datom_uuid, &datom));
             // TODO switch over to CBOR once we're past debugging stuff.
             // See https://github.com/mozilla/mentat/issues/570
             // let cbor_val = serde_cbor::to_value(&datom)?;
             // self.remote_client.put_chunk(&datom_uuid, &serde_cbor::ser::to_vec_sd(&cbor_val)?)?;
             self.remote_client.put_chunk(&datom_uuid, &serde_json::to_string(&datom)?)?;
+            self.progress.chunk_uploaded(tx_id, &datom_uuid);
         }
 
         // Upload tx.
@@ -152,17 +258,17 @@ impl<'c> TxReceiver for UploadingTxReceiver<'c> {
         // Comes at a cost of possibly increasing racing against other clients.
         match self.rolling_temp_head {
             Some(parent) => {
-                d(&format!("putting transaction: {:?}, {:?}, {:?}", &tx_uuid, &parent, &tx_chunks));
                 self.remote_client.put_transaction(&tx_uuid, &parent, &tx_chunks)?;
                 
             },
             None => {
-                d(&format!("putting transaction: {:?}, {:?}, {:?}", &tx_uuid, &self.remote_head, &tx_chunks));
                 self.remote_client.put_transaction(&tx_uuid, self.remote_head, &tx_chunks)?;
             }
         }
 
-        d(&format!("updating rolling head: {:?}", tx_uuid));
+        self.progress.tx_uploaded(tx_id, &tx_uuid);
+        self.uploaded_tx_count += 1;
+
         self.rolling_temp_head = Some(tx_uuid.clone());
 
         Ok(())
@@ -175,106 +281,201 @@ impl<'c> TxReceiver for UploadingTxReceiver<'c> {
 }
 
 impl Syncer {
-    fn upload_ours(db_tx: &mut rusqlite::Transaction, from_tx: Option<Entid>, remote_client: &RemoteClient, remote_head: &Uuid) -> Result<()> {
-        let mut uploader = UploadingTxReceiver::new(remote_client, remote_head);
-        Processor::process(db_tx, from_tx, &mut uploader)?;
-        if !uploader.is_done {
-            bail!(ErrorKind::TxProcessorUnfinished);
+    /// A previous sync might have advanced the server's head, but failed before recording the
+    /// new tx mappings and remote head locally. Each pending upload that the server's log now
+    /// contains completed remotely, even if another client has since built on top of it: we
+    /// record its mapping, and make the last of them our remote head. The remaining pending
+    /// uploads never became reachable from the server's head: we forget them, and their
+    /// transactions are uploaded again.
+    /// Returns `true` if an interrupted sync was completed.
+    fn complete_interrupted_upload(sqlite: &mut rusqlite::Connection, remote_client: &GlobalTransactionLog) -> Result<bool> {
+        let mut db_tx = sqlite.transaction()?;
+        let pending = TxMapper::pending(&db_tx)?;
+        if pending.is_empty() {
+            return Ok(false);
         }
+
+        // Pending uploads were made on top of our remote head, so only the server's
+        // transactions after it can be ours.
+        let locally_known_remote_head = SyncMetadataClient::remote_head(&db_tx)?;
+        let remote_transactions: HashSet<Uuid> = remote_client.get_transactions_after(&locally_known_remote_head)?.into_iter().collect();
+
+        let uploaded: BTreeMap<Entid, Uuid> = pending.into_iter().filter(|&(_, ref uuid)| remote_transactions.contains(uuid)).collect();
+        let completed = match uploaded.values().next_back().cloned() {
+            Some(last_uploaded) => {
+                let mappings: HashMap<Entid, Uuid> = uploaded.into_iter().collect();
+                TxMapper::set_bulk(&mut db_tx, &mappings)?;
+                SyncMetadataClient::set_remote_head(&db_tx, &last_uploaded)?;
+                true
+            },
+            None => false,
+        };
+
+        TxMapper::clear_pending(&db_tx)?;
+        db_tx.commit()?;
+        Ok(completed)
+    }
+
+    /// Upload local transactions after `from_tx` on top of `remote_head`.
+    /// Returns the number of uploaded transactions and the new remote head.
+    fn upload_ours(sqlite: &mut rusqlite::Connection, from_tx: Option<Entid>, remote_client: &GlobalTransactionLog, remote_head: &Uuid, progress: &mut SyncProgress) -> Result<(usize, Uuid)> {
+        // Decide on the UUIDs for our transactions and durably record them before touching the
+        // server, so that a sync interrupted after advancing the server's head can be recognized.
+        let tx_temp_uuids: BTreeMap<Entid, Uuid> = {
+            let db_tx = sqlite.transaction()?;
+            let mut collector = CollectingTxReceiver::new();
+            Processor::process(&db_tx, from_tx, &mut collector)?;
+            if !collector.is_done {
+                bail!(ErrorKind::TxProcessorUnfinished);
+            }
+            // Yes, we generate a new UUID for a given Tx, even if we might
+            // already have one mapped locally.
+            // If we're seeing this tx again, it implies that previous attempt
+            // to sync didn't update our local head. Something went wrong last time,
+            // and it's unwise to try to re-use these remote tx mappings.
+            // We just leave garbage txs to be GC'd on the server.
+            let tx_temp_uuids: BTreeMap<Entid, Uuid> = collector.txs.into_iter().map(|tx| (tx, Uuid::new_v4())).collect();
+            TxMapper::set_pending(&db_tx, &tx_temp_uuids)?;
+            db_tx.commit()?;
+            tx_temp_uuids
+        };
+
+        let mut db_tx = sqlite.transaction()?;
+        let local_only = LocalOnly::read(&db_tx)?;
+
+        let (uploaded_tx_count, rolling_temp_head) = {
+            let mut uploader = UploadingTxReceiver::new(remote_client, remote_head, &tx_temp_uuids, &local_only, progress);
+            Processor::process(&db_tx, from_tx, &mut uploader)?;
+            if !uploader.is_done {
+                bail!(ErrorKind::TxProcessorUnfinished);
+            }
+            (uploader.uploaded_tx_count, uploader.rolling_temp_head)
+        };
+
         // Last tx uuid uploaded by the tx receiver.
         // It's going to be our new head.
-        if let Some(last_tx_uploaded) = uploader.rolling_temp_head {
-            // Upload remote head.
-            remote_client.put_head(&last_tx_uploaded)?;
-
-            // On succes:
-            // - persist local mappings from the receiver
-            // - update our local "remote head".
-            TxMapper::set_bulk(db_tx, &uploader.tx_temp_uuids)?;
-            SyncMetadataClient::set_remote_head(db_tx, &last_tx_uploaded)?;
-        }
+        let new_head = match rolling_temp_head {
+            Some(last_tx_uploaded) => {
+                // Upload remote head.
+                remote_client.put_head(&last_tx_uploaded)?;
+
+                // On succes:
+                // - persist local mappings from the receiver
+                // - update our local "remote head".
+                let mappings: HashMap<Entid, Uuid> = tx_temp_uuids.into_iter().collect();
+                TxMapper::set_bulk(&mut db_tx, &mappings)?;
+                SyncMetadataClient::set_remote_head(&db_tx, &last_tx_uploaded)?;
+                last_tx_uploaded
+            },
+            None => remote_head.clone(),
+        };
 
-        Ok(())
+        TxMapper::clear_pending(&db_tx)?;
+        db_tx.commit()?;
+
+        Ok((uploaded_tx_count, new_head))
     }
 
-    pub fn flow(sqlite: &mut rusqlite::Connection, server_uri: &String, user_uuid: &Uuid) -> Result<()> {
-        d(&format!("sync flowing"));
+    pub fn flow(sqlite: &mut rusqlite::Connection, server_uri: &String, user_uuid: &Uuid) -> Result<SyncReport> {
+        Syncer::flow_with_progress(sqlite, server_uri, user_uuid, &mut NoopSyncProgress)
+    }
 
-        ensure_current_version(sqlite)?;
-        
+    pub fn flow_with_progress(sqlite: &mut rusqlite::Connection, server_uri: &String, user_uuid: &Uuid, progress: &mut SyncProgress) -> Result<SyncReport> {
         // TODO configure this sync with some auth data
         let remote_client = RemoteClient::new(server_uri.clone(), user_uuid.clone());
-        let mut db_tx = sqlite.transaction()?;
+        Syncer::flow_with_remote(sqlite, &remote_client, progress)
+    }
+
+    pub fn flow_with_remote(sqlite: &mut rusqlite::Connection, remote_client: &GlobalTransactionLog, progress: &mut SyncProgress) -> Result<SyncReport> {
+        let start = Instant::now();
+
+        ensure_current_version(sqlite)?;
 
         let remote_head = remote_client.get_head()?;
-        d(&format!("remote head {:?}", remote_head));
 
-        let locally_known_remote_head = SyncMetadataClient::remote_head(&db_tx)?;
-        d(&format!("local head {:?}", locally_known_remote_head));
-
-        // Local head: latest transaction that we have in the store,
-        // but with one caveat: its tx might will not be mapped if it's
-        // never been synced successfully.
-        // In other words: if latest tx isn't mapped, then HEAD moved
-        // since last sync and server needs to be updated.
-        let mut inquiring_tx_receiver = InquiringTxReceiver::new();
-        // TODO don't just start from the beginning... but then again, we should do this
-        // without walking the table at all, and use the tx index.
-        Processor::process(&db_tx, None, &mut inquiring_tx_receiver)?;
-        if !inquiring_tx_receiver.is_done {
-            bail!(ErrorKind::TxProcessorUnfinished);
-        }
-        let have_local_changes = match inquiring_tx_receiver.last_tx {
-            Some(tx) => {
-                match TxMapper::get(&db_tx, tx)? {
-                    Some(_) => false,
-                    None => true
-                }
-            },
-            None => false
+        let completed_interrupted_upload = Syncer::complete_interrupted_upload(sqlite, remote_client)?;
+
+        let (locally_known_remote_head, upload_from_tx, have_local_changes) = {
+            let db_tx = sqlite.transaction()?;
+
+            let locally_known_remote_head = SyncMetadataClient::remote_head(&db_tx)?;
+
+            // Local head: latest transaction that we have in the store,
+            // but with one caveat: its tx might will not be mapped if it's
+            // never been synced successfully.
+            // In other words: if latest tx isn't mapped, then HEAD moved
+            // since last sync and server needs to be updated.
+            let mut inquiring_tx_receiver = InquiringTxReceiver::new();
+            // TODO don't just start from the beginning... but then again, we should do this
+            // without walking the table at all, and use the tx index.
+            Processor::process(&db_tx, None, &mut inquiring_tx_receiver)?;
+            if !inquiring_tx_receiver.is_done {
+                bail!(ErrorKind::TxProcessorUnfinished);
+            }
+            let have_local_changes = match inquiring_tx_receiver.last_tx {
+                Some(tx) => {
+                    match TxMapper::get(&db_tx, tx)? {
+                        Some(_) => false,
+                        None => true
+                    }
+                },
+                None => false
+            };
+
+            let upload_from_tx = TxMapper::get_tx_for_uuid(&db_tx, &locally_known_remote_head)?;
+
+            (locally_known_remote_head, upload_from_tx, have_local_changes)
+        };
+
+        progress.heads_fetched(&remote_head, &locally_known_remote_head);
+
+        let mut report = SyncReport {
+            outcome: SyncOutcome::NoChanges,
+            uploaded_tx_count: 0,
+            remote_head_before: remote_head.clone(),
+            remote_head_after: remote_head.clone(),
+            completed_interrupted_upload: completed_interrupted_upload,
+            duration: Duration::from_secs(0),
         };
 
         // Check if the server is empty - populate it.
         if remote_head == Uuid::nil() {
-            d(&format!("empty server!"));
-            Syncer::upload_ours(&mut db_tx, None, &remote_client, &remote_head)?;
-        
+            let (uploaded, new_head) = Syncer::upload_ours(sqlite, None, remote_client, &remote_head, progress)?;
+            if uploaded > 0 {
+                report.outcome = SyncOutcome::FastForwardedServer;
+            }
+            report.uploaded_tx_count = uploaded;
+            report.remote_head_after = new_head;
+
         // Check if the server is the same as us, and if our HEAD moved.
         } else if locally_known_remote_head == remote_head {
-            d(&format!("server unchanged since last sync."));
-            
             if !have_local_changes {
-                d(&format!("local HEAD did not move. Nothing to do!"));
-                return Ok(());
+                report.duration = start.elapsed();
+                progress.finished(&report);
+                return Ok(report);
             }
 
-            d(&format!("local HEAD moved."));
-            // TODO it's possible that we've successfully advanced remote head previously,
-            // but failed to advance our own local head. If that's the case, and we can recognize it,
-            // our sync becomes just bumping our local head. AFAICT below would currently fail.
-            if let Some(upload_from_tx) = TxMapper::get_tx_for_uuid(&db_tx, &locally_known_remote_head)? {
-                d(&format!("Fast-forwarding the server."));
-                Syncer::upload_ours(&mut db_tx, Some(upload_from_tx), &remote_client, &remote_head)?;
+            if let Some(upload_from_tx) = upload_from_tx {
+                let (uploaded, new_head) = Syncer::upload_ours(sqlite, Some(upload_from_tx), remote_client, &remote_head, progress)?;
+                report.outcome = SyncOutcome::FastForwardedServer;
+                report.uploaded_tx_count = uploaded;
+                report.remote_head_after = new_head;
             } else {
-                d(&format!("Unable to fast-forward the server; missing local tx mapping"));
                 bail!(ErrorKind::TxIncorrectlyMapped(0));
             }
             
         // We diverged from the server.
         // We'll need to rebase/merge ourselves on top of it.
         } else {
-            d(&format!("server changed since last sync."));
-
             bail!(ErrorKind::NotYetImplemented(
                 format!("Can't yet sync against changed server. Local head {:?}, remote head {:?}", locally_known_remote_head, remote_head)
             ));
         }
 
-        // Commit everything, if there's anything to commit!
-        // Any new tx->uuid mappings and the new HEAD. We're synced!
-        db_tx.commit()?;
-
-        Ok(())
+        // Any new tx->uuid mappings and the new HEAD were committed by `upload_ours`. We're synced!
+        report.duration = start.elapsed();
+        progress.finished(&report);
+        Ok(report)
     }
 }
 
@@ -283,6 +484,11 @@ struct SerializedHead {
     head: Uuid
 }
 
+#[derive(Serialize,Deserialize)]
+struct SerializedTransactions {
+    transactions: Vec<Uuid>
+}
+
 #[derive(Serialize)]
 struct SerializedTransaction<'a> {
     parent: &'a Uuid,
@@ -309,6 +515,12 @@ impl RemoteClient {
     }
 
     fn get_uuid(&self, uri: String) -> Result<Uuid> {
+        let head_json: SerializedHead = self.get(uri)?;
+        Ok(head_json.head)
+    }
+
+    fn get<T>(&self, uri: String) -> Result<T>
+    where T: DeserializeOwned + 'static {
         let mut core = Core::new()?;
         // TODO enable TLS, see https://github.com/mozilla/mentat/issues/569
         // let client = hyper::Client::configure()
@@ -316,28 +528,19 @@ impl RemoteClient {
         //     .build(&core.handle());
         let client = hyper::Client::new(&core.handle());
 
-        d(&format!("client"));
-
         let uri = uri.parse()?;
 
-        d(&format!("parsed uri {:?}", uri));
-        
         let work = client.get(uri).and_then(|res| {
-            println!("Response: {}", res.status());
-
             res.body().concat2().and_then(move |body| {
-                let head_json: SerializedHead = serde_json::from_slice(&body).map_err(|e| {
+                let json: T = serde_json::from_slice(&body).map_err(|e| {
                     std::io::Error::new(std::io::ErrorKind::Other, e)
                 })?;
-                Ok(head_json)
+                Ok(json)
             })
         });
 
-        d(&format!("running..."));
-
-        let head_json = core.run(work)?;
-        d(&format!("got head: {:?}", &head_json.head));
-        Ok(head_json.head)
+        let json = core.run(work)?;
+        Ok(json)
     }
 
     fn put<T>(&self, uri: String, payload: T, expected: StatusCode) -> Result<()>
@@ -351,8 +554,6 @@ impl RemoteClient {
 
         let uri = uri.parse()?;
 
-        d(&format!("PUT {:?}", uri));
-
         let mut req = Request::new(Method::Put, uri);
         req.headers_mut().set(ContentType::json());
         req.set_body(payload);
@@ -361,7 +562,6 @@ impl RemoteClient {
             let status_code = res.status();
 
             if status_code != expected {
-                d(&format!("bad put response: {:?}", status_code));
                 future::err(HyperError::Status)
             } else {
                 future::ok(())
@@ -371,7 +571,9 @@ impl RemoteClient {
         core.run(put)?;
         Ok(())
     }
+}
 
+impl GlobalTransactionLog for RemoteClient {
     fn put_transaction(&self, transaction_uuid: &Uuid, parent_uuid: &Uuid, chunks: &Vec<Uuid>) -> Result<()> {
         // {"parent": uuid, "chunks": [chunk1, chunk2...]}
         let transaction = SerializedTransaction {
@@ -381,7 +583,6 @@ impl RemoteClient {
 
         let uri = format!("{}/transactions/{}", self.bound_base_uri(), transaction_uuid);
         let json = serde_json::to_string(&transaction)?;
-        d(&format!("serialized transaction: {:?}", json));
         self.put(uri, json, StatusCode::Created)
     }
 
@@ -398,16 +599,20 @@ impl RemoteClient {
 
         let uri = format!("{}/head", self.bound_base_uri());
         let json = serde_json::to_string(&head)?;
-        d(&format!("serialized head: {:?}", json));
         self.put(uri, json, StatusCode::NoContent)
     }
 
     fn put_chunk(&self, chunk_uuid: &Uuid, payload: &String) -> Result<()> {
         let uri = format!("{}/chunks/{}", self.bound_base_uri(), chunk_uuid);
-        d(&format!("serialized chunk: {:?}", payload));
         // TODO don't want to clone every datom!
         self.put(uri, payload.clone(), StatusCode::Created)
     }
+
+    fn get_transactions_after(&self, from_uuid: &Uuid) -> Result<Vec<Uuid>> {
+        let uri = format!("{}/transactions?from={}", self.bound_base_uri(), from_uuid);
+        let transactions_json: SerializedTransactions = self.get(uri)?;
+        Ok(transactions_json.transactions)
+    }
 }
 
 #[cfg(test)]
@@ -422,4 +627,19 @@ mod tests {
         let remote_client = RemoteClient::new(server_uri, user_uuid);
         assert_eq!("https://example.com/api/0.1/316ea470-ce35-4adf-9c61-e0de6e289c59", remote_client.bound_base_uri());
     }
+
+    #[test]
+    fn test_sync_report_display() {
+        let head = Uuid::from_str(&"316ea470-ce35-4adf-9c61-e0de6e289c59").expect("uuid");
+        let report = SyncReport {
+            outcome: SyncOutcome::FastForwardedServer,
+            uploaded_tx_count: 2,
+            remote_head_before: Uuid::nil(),
+            remote_head_after: head,
+            completed_interrupted_upload: false,
+            duration: Duration::from_millis(1500),
+        };
+        assert_eq!("fast-forwarded server: uploaded 2 transactions; head 316ea470-ce35-4adf-9c61-e0de6e289c59 in 1500ms",
+                   format!("{}", report));
+    }
 }
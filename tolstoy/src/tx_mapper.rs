@@ -8,7 +8,10 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::collections::HashMap;
+use std::collections::{
+    BTreeMap,
+    HashMap,
+};
 use rusqlite;
 use uuid::Uuid;
 use mentat_core::Entid;
@@ -83,6 +86,44 @@ impl TxMapper {
         }
         Ok(Some(uuids.remove(0)?))
     }
+
+    /// Record the UUIDs we're about to upload transactions under, replacing any previously
+    /// pending mappings. These are committed before talking to the server, so that a sync which
+    /// advanced the server's head but failed to commit locally can be completed later.
+    pub fn set_pending(db_tx: &rusqlite::Transaction, tx_uuid_map: &BTreeMap<Entid, Uuid>) -> Result<()> {
+        TxMapper::clear_pending(db_tx)?;
+        let mut stmt = db_tx.prepare_cached(
+            "INSERT INTO tolstoy_pending_tu (tx, uuid) VALUES (?, ?)"
+        )?;
+        for (tx, uuid) in tx_uuid_map.iter() {
+            let uuid_bytes = uuid.as_bytes().to_vec();
+            stmt.execute(&[tx, &uuid_bytes])?;
+        }
+        Ok(())
+    }
+
+    pub fn pending(db_tx: &rusqlite::Transaction) -> Result<BTreeMap<Entid, Uuid>> {
+        let mut stmt = db_tx.prepare_cached(
+            "SELECT tx, uuid FROM tolstoy_pending_tu"
+        )?;
+
+        let results = stmt.query_and_then(&[], |r| -> Result<(Entid, Uuid)> {
+            let bytes: Vec<u8> = r.get(1);
+            Ok((r.get(0), Uuid::from_bytes(bytes.as_slice())?))
+        })?;
+
+        let mut pending = BTreeMap::new();
+        for result in results {
+            let (tx, uuid) = result?;
+            pending.insert(tx, uuid);
+        }
+        Ok(pending)
+    }
+
+    pub fn clear_pending(db_tx: &rusqlite::Transaction) -> Result<()> {
+        db_tx.execute("DELETE FROM tolstoy_pending_tu", &[])?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -125,4 +166,29 @@ pub mod tests {
         assert_eq!(Some(uuid1), TxMapper::get(&mut tx, 1).expect("success"));
         assert_eq!(Some(new_uuid2), TxMapper::get(&mut tx, 2).expect("success"));
     }
+
+    #[test]
+    fn test_pending() {
+        let mut conn = schema::tests::setup_conn();
+        let tx = conn.transaction().expect("db tx");
+        assert!(TxMapper::pending(&tx).expect("success").is_empty());
+
+        let mut map = BTreeMap::new();
+        map.insert(1, Uuid::new_v4());
+        map.insert(2, Uuid::new_v4());
+        TxMapper::set_pending(&tx, &map).expect("success");
+        assert_eq!(map, TxMapper::pending(&tx).expect("success"));
+
+        // Setting pending mappings replaces the previous ones.
+        let mut replacement = BTreeMap::new();
+        replacement.insert(3, Uuid::new_v4());
+        TxMapper::set_pending(&tx, &replacement).expect("success");
+        assert_eq!(replacement, TxMapper::pending(&tx).expect("success"));
+
+        TxMapper::clear_pending(&tx).expect("success");
+        assert!(TxMapper::pending(&tx).expect("success").is_empty());
+
+        // Pending mappings aren't visible as real mappings.
+        assert_eq!(None, TxMapper::get(&tx, 3).expect("success"));
+    }
 }
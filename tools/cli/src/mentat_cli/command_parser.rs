@@ -13,6 +13,7 @@ use combine::{
     any,
     eof,
     look_ahead,
+    many,
     many1,
     satisfy,
     sep_end_by,
@@ -36,17 +37,25 @@ use edn;
 
 use mentat::{
     CacheDirection,
+    DatomIndex,
+    Entid,
 };
 
+use output::OutputFormat;
+
 pub static COMMAND_CACHE: &'static str = &"cache";
 pub static COMMAND_CLOSE: &'static str = &"close";
+pub static COMMAND_DATOMS: &'static str = &"datoms";
+pub static COMMAND_ENTITY: &'static str = &"entity";
 pub static COMMAND_EXIT_LONG: &'static str = &"exit";
+pub static COMMAND_EXPORT: &'static str = &"export";
 pub static COMMAND_EXIT_SHORT: &'static str = &"e";
 pub static COMMAND_HELP: &'static str = &"help";
 pub static COMMAND_IMPORT_LONG: &'static str = &"import";
 pub static COMMAND_IMPORT_SHORT: &'static str = &"i";
 pub static COMMAND_OPEN: &'static str = &"open";
 pub static COMMAND_OPEN_EMPTY: &'static str = &"empty";
+pub static COMMAND_OUTPUT: &'static str = &"output";
 pub static COMMAND_QUERY_LONG: &'static str = &"query";
 pub static COMMAND_QUERY_SHORT: &'static str = &"q";
 pub static COMMAND_QUERY_EXPLAIN_LONG: &'static str = &"explain_query";
@@ -57,16 +66,21 @@ pub static COMMAND_SYNC: &'static str = &"sync";
 pub static COMMAND_TIMER_LONG: &'static str = &"timer";
 pub static COMMAND_TRANSACT_LONG: &'static str = &"transact";
 pub static COMMAND_TRANSACT_SHORT: &'static str = &"t";
+pub static COMMAND_TX: &'static str = &"tx";
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Command {
     Cache(String, CacheDirection),
     Close,
+    Datoms(DatomIndex, Vec<edn::Value>),
+    Entity(edn::Value),
     Exit,
+    Export(String, bool),
     Help(Vec<String>),
     Import(String),
     Open(String),
     OpenEmpty(String),
+    Output(OutputFormat),
     Query(String),
     QueryExplain(String),
     QueryPrepared(String),
@@ -74,6 +88,7 @@ pub enum Command {
     Sync(Vec<String>),
     Timer(bool),
     Transact(String),
+    Tx(Entid),
 }
 
 impl Command {
@@ -92,24 +107,33 @@ impl Command {
             },
             &Command::Cache(_, _) |
             &Command::Close |
+            &Command::Datoms(_, _) |
+            &Command::Entity(_) |
             &Command::Exit |
+            &Command::Export(_, _) |
             &Command::Help(_) |
             &Command::Import(_) |
             &Command::Open(_) |
             &Command::OpenEmpty(_) |
+            &Command::Output(_) |
             &Command::Timer(_) |
             &Command::Schema |
-            &Command::Sync(_)
+            &Command::Sync(_) |
+            &Command::Tx(_)
             => true,
         }
     }
 
     pub fn is_timed(&self) -> bool {
         match self {
+            &Command::Datoms(_, _) |
+            &Command::Entity(_) |
+            &Command::Export(_, _) |
             &Command::Import(_) |
             &Command::Query(_) |
             &Command::QueryPrepared(_) |
-            &Command::Transact(_)
+            &Command::Transact(_) |
+            &Command::Tx(_)
             => true,
 
             &Command::Cache(_, _) |
@@ -118,6 +142,7 @@ impl Command {
             &Command::Help(_) |
             &Command::Open(_) |
             &Command::OpenEmpty(_) |
+            &Command::Output(_) |
             &Command::QueryExplain(_) |
             &Command::Timer(_) |
             &Command::Schema |
@@ -134,9 +159,23 @@ impl Command {
             &Command::Close => {
                 format!(".{}", COMMAND_CLOSE)
             },
+            &Command::Datoms(index, ref components) => {
+                let components: Vec<String> = components.iter().map(|c| c.to_string()).collect();
+                format!(".{} {} {}", COMMAND_DATOMS, index, components.join(" "))
+            },
+            &Command::Entity(ref e) => {
+                format!(".{} {}", COMMAND_ENTITY, e)
+            },
             &Command::Exit => {
                 format!(".{}", COMMAND_EXIT_LONG)
             },
+            &Command::Export(ref path, history) => {
+                if history {
+                    format!(".{} --history {}", COMMAND_EXPORT, path)
+                } else {
+                    format!(".{} {}", COMMAND_EXPORT, path)
+                }
+            },
             &Command::Help(ref args) => {
                 format!(".{} {:?}", COMMAND_HELP, args)
             },
@@ -149,6 +188,9 @@ impl Command {
             &Command::OpenEmpty(ref args) => {
                 format!(".{} {}", COMMAND_OPEN_EMPTY, args)
             },
+            &Command::Output(format) => {
+                format!(".{} {}", COMMAND_OUTPUT, format)
+            },
             &Command::Query(ref args) => {
                 format!(".{} {}", COMMAND_QUERY_LONG, args)
             },
@@ -170,10 +212,21 @@ impl Command {
             &Command::Transact(ref args) => {
                 format!(".{} {}", COMMAND_TRANSACT_LONG, args)
             },
+            &Command::Tx(tx) => {
+                format!(".{} {}", COMMAND_TX, tx)
+            },
         }
     }
 }
 
+/// Parses a sequence of whitespace-separated EDN values.
+fn edn_values(s: &str) -> Result<Vec<edn::Value>, cli::Error> {
+    match edn::parse::value(&format!("[{}]", s)).map(|v| v.without_spans()) {
+        Ok(edn::Value::Vector(values)) => Ok(values),
+        _ => bail!(cli::ErrorKind::CommandParse(format!("Invalid arguments {:?}", s.trim()))),
+    }
+}
+
 pub fn command(s: &str) -> Result<Command, cli::Error> {
     let path = || many1::<String, _>(satisfy(|c: char| !c.is_whitespace()));
     let argument = || many1::<String, _>(satisfy(|c: char| !c.is_whitespace()));
@@ -207,6 +260,40 @@ pub fn command(s: &str) -> Result<Command, cli::Error> {
                     }));
 
 
+    let datoms_parser = string(COMMAND_DATOMS)
+                    .with(spaces())
+                    .with(argument())
+                    .and(many::<String, _>(any()))
+                    .map(|(index, rest)| -> Result<Command, cli::Error> {
+                        let index = index.parse::<DatomIndex>().map_err(|e| cli::Error::from(cli::ErrorKind::CommandParse(e)))?;
+                        Ok(Command::Datoms(index, edn_values(&rest)?))
+                    });
+
+    let entity_parser = string(COMMAND_ENTITY)
+                    .with(many::<String, _>(any()))
+                    .map(|rest| -> Result<Command, cli::Error> {
+                        let mut values = edn_values(&rest)?;
+                        if values.len() != 1 {
+                            bail!(cli::ErrorKind::CommandParse("Expected a single entid, ident or lookup ref".to_string()));
+                        }
+                        Ok(Command::Entity(values.remove(0)))
+                    });
+
+    let export_parser = string(COMMAND_EXPORT)
+                    .with(spaces())
+                    .with(arguments())
+                    .map(|args| {
+                        let history = args.first().map_or(false, |arg| arg == "--history");
+                        let paths = if history { &args[1..] } else { &args[..] };
+                        if paths.len() < 1 {
+                            bail!(cli::ErrorKind::CommandParse("Missing required argument".to_string()));
+                        }
+                        if paths.len() > 1 {
+                            bail!(cli::ErrorKind::CommandParse(format!("Unrecognized argument {:?}", paths[1])));
+                        }
+                        Ok(Command::Export(paths[0].clone(), history))
+                    });
+
     let close_parser = string(COMMAND_CLOSE)
                     .with(no_arg_parser())
                     .map(|args| {
@@ -272,6 +359,19 @@ pub fn command(s: &str) -> Result<Command, cli::Error> {
                         Ok(Command::OpenEmpty(args[0].clone()))
                     });
 
+    let output_parser = string(COMMAND_OUTPUT)
+                    .with(spaces())
+                    .with(arguments())
+                    .map(|args| -> Result<Command, cli::Error> {
+                        if args.len() < 1 {
+                            bail!(cli::ErrorKind::CommandParse("Missing required argument".to_string()));
+                        }
+                        if args.len() > 1 {
+                            bail!(cli::ErrorKind::CommandParse(format!("Unrecognized argument {:?}", args[1])));
+                        }
+                        Ok(Command::Output(args[0].parse()?))
+                    });
+
     let query_parser = try(string(COMMAND_QUERY_LONG)).or(try(string(COMMAND_QUERY_SHORT)))
                         .with(edn_arg_parser())
                         .map(|x| {
@@ -313,6 +413,19 @@ pub fn command(s: &str) -> Result<Command, cli::Error> {
                         Ok(Command::Timer(args))
                     });
 
+    let tx_parser = string(COMMAND_TX)
+                    .with(spaces())
+                    .with(arguments())
+                    .map(|args| {
+                        if args.len() != 1 {
+                            bail!(cli::ErrorKind::CommandParse("Expected a single transaction ID".to_string()));
+                        }
+                        match args[0].parse::<Entid>() {
+                            Ok(tx) => Ok(Command::Tx(tx)),
+                            Err(_) => bail!(cli::ErrorKind::CommandParse(format!("Invalid transaction ID {:?}", args[0]))),
+                        }
+                    });
+
     let transact_parser = try(string(COMMAND_TRANSACT_LONG)).or(try(string(COMMAND_TRANSACT_SHORT)))
                     .with(edn_arg_parser())
                     .map(|x| {
@@ -321,13 +434,17 @@ pub fn command(s: &str) -> Result<Command, cli::Error> {
 
     spaces()
     .skip(token('.'))
-    .with(choice::<[&mut Parser<Input = _, Output = Result<Command, cli::Error>>; 14], _>
+    .with(choice::<[&mut Parser<Input = _, Output = Result<Command, cli::Error>>; 19], _>
           ([&mut try(help_parser),
             &mut try(import_parser),
             &mut try(timer_parser),
             &mut try(cache_parser),
+            &mut try(datoms_parser),
+            &mut try(entity_parser),
+            &mut try(export_parser),
             &mut try(open_parser),
             &mut try(open_empty_parser),
+            &mut try(output_parser),
             &mut try(close_parser),
             &mut try(explain_query_parser),
             &mut try(exit_parser),
@@ -335,6 +452,7 @@ pub fn command(s: &str) -> Result<Command, cli::Error> {
             &mut try(query_parser),
             &mut try(schema_parser),
             &mut try(sync_parser),
+            &mut try(tx_parser),
             &mut try(transact_parser)]))
         .parse(s)
         .unwrap_or((Err(cli::ErrorKind::CommandParse(format!("Invalid command {:?}", s)).into()), "")).0
@@ -464,6 +582,94 @@ mod tests {
         assert_eq!(err.to_string(), "Missing required argument");
     }
 
+    #[test]
+    fn test_output_parser() {
+        let input = ".output csv";
+        let cmd = command(&input).expect("Expected output command");
+        match cmd {
+            Command::Output(format) => {
+                assert_eq!(format, OutputFormat::Csv);
+            },
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn test_output_parser_unknown_format() {
+        let input = ".output xml";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Unknown output format \"xml\"; expected one of table, edn, json or csv");
+    }
+
+    #[test]
+    fn test_output_parser_no_args() {
+        let input = ".output";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Missing required argument");
+    }
+
+    #[test]
+    fn test_entity_parser() {
+        let input = ".entity [:person/email \"alice@example.com\"]";
+        let cmd = command(&input).expect("Expected entity command");
+        match cmd {
+            Command::Entity(e) => {
+                assert_eq!(e, edn::Value::Vector(vec![edn::Value::NamespacedKeyword(edn::NamespacedKeyword::new("person", "email")),
+                                                      edn::Value::Text("alice@example.com".to_string())]));
+            },
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn test_entity_parser_multiple_args() {
+        let input = ".entity 65536 65537";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Expected a single entid, ident or lookup ref");
+    }
+
+    #[test]
+    fn test_datoms_parser() {
+        let input = ".datoms avet :person/name \"Alice Smith\"";
+        let cmd = command(&input).expect("Expected datoms command");
+        match cmd {
+            Command::Datoms(index, components) => {
+                assert_eq!(index, DatomIndex::AVET);
+                assert_eq!(components, vec![edn::Value::NamespacedKeyword(edn::NamespacedKeyword::new("person", "name")),
+                                            edn::Value::Text("Alice Smith".to_string())]);
+            },
+            _ => assert!(false)
+        }
+    }
+
+    #[test]
+    fn test_datoms_parser_no_components() {
+        let input = ".datoms eavt";
+        let cmd = command(&input).expect("Expected datoms command");
+        assert_eq!(cmd, Command::Datoms(DatomIndex::EAVT, vec![]));
+    }
+
+    #[test]
+    fn test_datoms_parser_unknown_index() {
+        let input = ".datoms evat 65536";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "unknown index evat; expected one of eavt, aevt, avet or vaet");
+    }
+
+    #[test]
+    fn test_tx_parser() {
+        let input = ".tx 268435457";
+        let cmd = command(&input).expect("Expected tx command");
+        assert_eq!(cmd, Command::Tx(268435457));
+    }
+
+    #[test]
+    fn test_tx_parser_invalid_id() {
+        let input = ".tx latest";
+        let err = command(&input).expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Invalid transaction ID \"latest\"");
+    }
+
     #[test]
     fn test_close_parser_with_args() {
         let input = ".close arg1";
@@ -619,6 +825,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_export_parser() {
+        match command(".export /foo/bar.edn").expect("Expected export command") {
+            Command::Export(path, history) => {
+                assert_eq!(path, "/foo/bar.edn");
+                assert!(!history);
+            },
+            _ => panic!("Wrong command!")
+        }
+
+        match command(".export --history /foo/bar.edn").expect("Expected export command") {
+            Command::Export(path, history) => {
+                assert_eq!(path, "/foo/bar.edn");
+                assert!(history);
+            },
+            _ => panic!("Wrong command!")
+        }
+
+        let err = command(".export").expect_err("Expected an error");
+        assert_eq!(err.to_string(), "Missing required argument");
+    }
+
     #[test]
     fn test_transact_parser_complete_edn() {
         let input = ".t [[:db/add \"s\" :db/ident :foo/uuid] [:db/add \"r\" :db/ident :bar/uuid]]";
@@ -0,0 +1,219 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Tab completion of dot-commands, idents and query variables.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+use linefeed::{
+    Reader,
+    Terminal,
+};
+use linefeed::complete::{
+    Completer,
+    Completion,
+};
+
+use command_parser::{
+    COMMAND_CACHE,
+    COMMAND_CLOSE,
+    COMMAND_DATOMS,
+    COMMAND_ENTITY,
+    COMMAND_EXIT_LONG,
+    COMMAND_EXPORT,
+    COMMAND_HELP,
+    COMMAND_IMPORT_LONG,
+    COMMAND_OPEN,
+    COMMAND_OPEN_EMPTY,
+    COMMAND_OUTPUT,
+    COMMAND_QUERY_LONG,
+    COMMAND_QUERY_EXPLAIN_LONG,
+    COMMAND_QUERY_PREPARED_LONG,
+    COMMAND_SCHEMA,
+    COMMAND_SYNC,
+    COMMAND_TIMER_LONG,
+    COMMAND_TRANSACT_LONG,
+    COMMAND_TX,
+};
+
+lazy_static! {
+    static ref COMMANDS: Vec<&'static str> = {
+        vec![
+            COMMAND_CACHE,
+            COMMAND_CLOSE,
+            COMMAND_DATOMS,
+            COMMAND_ENTITY,
+            COMMAND_EXIT_LONG,
+            COMMAND_EXPORT,
+            COMMAND_HELP,
+            COMMAND_IMPORT_LONG,
+            COMMAND_OPEN,
+            COMMAND_OPEN_EMPTY,
+            COMMAND_OUTPUT,
+            COMMAND_QUERY_LONG,
+            COMMAND_QUERY_EXPLAIN_LONG,
+            COMMAND_QUERY_PREPARED_LONG,
+            COMMAND_SCHEMA,
+            COMMAND_SYNC,
+            COMMAND_TIMER_LONG,
+            COMMAND_TRANSACT_LONG,
+            COMMAND_TX,
+        ]
+    };
+}
+
+/// What the completer knows beyond the line being edited. The REPL and the `InputReader` keep
+/// this up to date; the completer only reads it.
+#[derive(Default)]
+pub struct CompletionContext {
+    /// The idents in the open store's schema, like `:db/ident`.
+    idents: RefCell<Vec<String>>,
+    /// The lines of a multi-line command entered before the current line.
+    pending: RefCell<String>,
+}
+
+impl CompletionContext {
+    pub fn set_idents(&self, idents: Vec<String>) {
+        *self.idents.borrow_mut() = idents;
+    }
+
+    pub fn set_pending(&self, pending: &str) {
+        let mut p = self.pending.borrow_mut();
+        p.clear();
+        p.push_str(pending);
+    }
+}
+
+/// A `linefeed` completer for the REPL.
+pub struct MentatCompleter {
+    context: Rc<CompletionContext>,
+}
+
+impl MentatCompleter {
+    pub fn new(context: Rc<CompletionContext>) -> MentatCompleter {
+        MentatCompleter {
+            context: context,
+        }
+    }
+}
+
+impl<Term: Terminal> Completer<Term> for MentatCompleter {
+    fn complete(&self, word: &str, reader: &Reader<Term>, start: usize, end: usize) -> Option<Vec<Completion>> {
+        let pending = self.context.pending.borrow();
+        let line = reader.buffer();
+
+        // The rest of the command, for finding the variables already used in it.
+        let mut text = pending.clone();
+        text.push('\n');
+        text.push_str(&line[..start]);
+        text.push(' ');
+        text.push_str(&line[end..]);
+
+        let at_command_start = pending.is_empty() && line[..start].trim().is_empty();
+        let completions = complete_word(word, at_command_start, &text, &self.context.idents.borrow());
+        if completions.is_empty() {
+            None
+        } else {
+            Some(completions.into_iter().map(Completion::simple).collect())
+        }
+    }
+
+    fn word_start(&self, line: &str, end: usize, _reader: &Reader<Term>) -> usize {
+        word_start(line, end)
+    }
+}
+
+/// Whether `c` can appear in a command name, keyword or symbol.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || ".:/?-_*+!<>=$%&'".contains(c)
+}
+
+/// Finds the start of the word that ends at `end`. Keywords like `:foo/bar` and variables like
+/// `?x` are single words, unlike in `linefeed`'s default.
+fn word_start(line: &str, end: usize) -> usize {
+    line[..end].char_indices()
+               .rev()
+               .take_while(|&(_, c)| is_word_char(c))
+               .last()
+               .map_or(end, |(i, _)| i)
+}
+
+/// Returns the sorted completions of `word`. `text` is the rest of the command being entered.
+fn complete_word(word: &str, at_command_start: bool, text: &str, idents: &[String]) -> Vec<String> {
+    let candidates: BTreeSet<String> = if word.starts_with('.') && at_command_start {
+        COMMANDS.iter().map(|c| format!(".{}", c)).collect()
+    } else if word.starts_with(':') {
+        idents.iter().cloned().collect()
+    } else if word.starts_with('?') {
+        text.split(|c: char| !is_word_char(c))
+            .filter(|w| w.starts_with('?') && w.len() > 1)
+            .map(|w| w.to_string())
+            .collect()
+    } else {
+        BTreeSet::new()
+    };
+    candidates.into_iter().filter(|c| c.starts_with(word)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn idents() -> Vec<String> {
+        vec![":db/ident".to_string(), ":db/doc".to_string(), ":person/name".to_string()]
+    }
+
+    #[test]
+    fn test_word_start() {
+        let line = "[:find ?person :where [?person :person/na";
+        assert_eq!(&line[word_start(line, line.len())..], ":person/na");
+
+        let line = ".q [:find ?p";
+        assert_eq!(&line[word_start(line, line.len())..], "?p");
+        assert_eq!(&line[word_start(line, 2)..2], ".q");
+
+        let line = "[";
+        assert_eq!(word_start(line, 1), 1);
+    }
+
+    #[test]
+    fn test_complete_commands() {
+        assert_eq!(complete_word(".q", true, "", &idents()),
+                   vec![".query", ".query_prepared"]);
+        assert_eq!(complete_word(".ex", true, "", &idents()),
+                   vec![".exit", ".explain_query", ".export"]);
+        assert_eq!(complete_word(".t", true, "", &idents()),
+                   vec![".timer", ".transact", ".tx"]);
+
+        // Not at the start of a command.
+        assert!(complete_word(".q", false, "", &idents()).is_empty());
+    }
+
+    #[test]
+    fn test_complete_idents() {
+        assert_eq!(complete_word(":db/", false, "", &idents()),
+                   vec![":db/doc", ":db/ident"]);
+        assert_eq!(complete_word(":p", false, "", &idents()),
+                   vec![":person/name"]);
+        assert!(complete_word(":x", false, "", &idents()).is_empty());
+    }
+
+    #[test]
+    fn test_complete_variables() {
+        let text = ".q [:find ?person ?name\n :where [?person :person/name ?name] [?person ?p";
+        assert_eq!(complete_word("?p", false, text, &idents()),
+                   vec!["?p", "?person"]);
+        assert_eq!(complete_word("?n", false, text, &idents()),
+                   vec!["?name"]);
+        assert!(complete_word("?z", false, text, &idents()).is_empty());
+    }
+}
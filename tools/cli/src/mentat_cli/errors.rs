@@ -33,5 +33,15 @@ error_chain! {
             description("An error occured parsing the entered command")
             display("{}", message)
         }
+
+        UnknownEntity(entity: String) {
+            description("No entity was found")
+            display("No entity found for {}", entity)
+        }
+
+        NotTransactional(command: String) {
+            description("A command can't be run inside a single transaction")
+            display("{} can't be run inside a single transaction", command)
+        }
     }
 }
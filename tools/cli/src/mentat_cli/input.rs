@@ -8,7 +8,11 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::io::stdin;
+use std::io::{
+    BufRead,
+    stdin,
+};
+use std::rc::Rc;
 
 use linefeed::{
     DefaultTerminal,
@@ -28,6 +32,11 @@ use command_parser::{
     command,
 };
 
+use completion::{
+    CompletionContext,
+    MentatCompleter,
+};
+
 use errors as cli;
 
 /// Starting prompt
@@ -49,11 +58,13 @@ pub enum InputResult {
     Eof,
 }
 
-/// Reads input from `stdin`
+/// Reads input from `stdin`, or from a script.
 pub struct InputReader {
     buffer: String,
     reader: Option<Reader<DefaultTerminal>>,
+    script: Option<Box<BufRead>>,
     in_process_cmd: Option<Command>,
+    completion: Rc<CompletionContext>,
 }
 
 enum UserAction {
@@ -72,11 +83,13 @@ enum UserAction {
 impl InputReader {
     /// Constructs a new `InputReader` reading from `stdin`.
     pub fn new() -> InputReader {
+        let completion = Rc::new(CompletionContext::default());
         let r = match Reader::new("mentat") {
             Ok(mut r) => {
                 // Handle SIGINT (Ctrl-C)
                 r.set_report_signal(Signal::Interrupt, true);
                 r.set_word_break_chars(" \t\n!\"#$%&'(){}*+,-./:;<=>?@[\\]^`");
+                r.set_completer(Rc::new(MentatCompleter::new(completion.clone())));
                 Some(r)
             },
             Err(_) => None,
@@ -85,15 +98,38 @@ impl InputReader {
         InputReader{
             buffer: String::new(),
             reader: r,
+            script: None,
             in_process_cmd: None,
+            completion: completion,
         }
     }
 
+    /// Constructs a new `InputReader` reading commands from `script`, without prompting.
+    pub fn with_script(script: Box<BufRead>) -> InputReader {
+        InputReader {
+            buffer: String::new(),
+            reader: None,
+            script: Some(script),
+            in_process_cmd: None,
+            completion: Rc::new(CompletionContext::default()),
+        }
+    }
+
+    /// Sets the idents offered when completing keywords, usually those of the open store.
+    pub fn set_idents(&self, idents: Vec<String>) {
+        self.completion.set_idents(idents);
+    }
+
     /// Returns whether the `InputReader` is reading from a TTY.
     pub fn is_tty(&self) -> bool {
         self.reader.is_some()
     }
 
+    /// Returns whether a command has been started but not yet completed.
+    pub fn is_incomplete(&self) -> bool {
+        self.in_process_cmd.is_some()
+    }
+
     /// Reads a single command, item, or statement from `stdin`.
     /// Returns `More` if further input is required for a complete result.
     /// In this case, the input received so far is buffered internally.
@@ -116,6 +152,11 @@ impl InputReader {
             _ => return Ok(Eof),
         };
 
+        // Blank lines between commands, as found in scripts, are not errors.
+        if self.in_process_cmd.is_none() && line.trim().is_empty() {
+            return Ok(Empty);
+        }
+
         if !self.buffer.is_empty() {
             self.buffer.push('\n');
         }
@@ -181,6 +222,8 @@ impl InputReader {
     fn read_line(&mut self, prompt: &str) -> UserAction {
         match self.reader {
             Some(ref mut r) => {
+                // Let the completer see the earlier lines of a multi-line command.
+                self.completion.set_pending(&self.buffer);
                 r.set_prompt(prompt);
                 r.read_line().ok().map_or(UserAction::Quit, |line|
                     match line {
@@ -195,12 +238,21 @@ impl InputReader {
         }
     }
 
-    fn read_stdin(&self) -> UserAction {
+    fn read_stdin(&mut self) -> UserAction {
         let mut s = String::new();
 
-        match stdin().read_line(&mut s) {
+        let read = match self.script {
+            Some(ref mut script) => script.read_line(&mut s),
+            None => stdin().read_line(&mut s),
+        };
+        match read {
             Ok(0) | Err(_) => UserAction::Quit,
-            Ok(_) => UserAction::TextInput(s)
+            Ok(_) => {
+                // Like `linefeed`, don't include the line terminator.
+                let len = s.trim_right_matches(|c: char| c == '\n' || c == '\r').len();
+                s.truncate(len);
+                UserAction::TextInput(s)
+            },
         }
     }
 
@@ -210,3 +262,55 @@ impl InputReader {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn script(text: &str) -> InputReader {
+        InputReader::with_script(Box::new(Cursor::new(text.as_bytes().to_vec())))
+    }
+
+    #[test]
+    fn test_script_multiline_command() {
+        let mut input = script(".t [[:db/add \"a\"\n  :db/ident :foo/bar]]\n\n.q [:find ?e :where [?e :db/ident :foo/bar]]\n");
+        match input.read_input().expect("read") {
+            More => (),
+            r => panic!("expected More, got {:?}", r),
+        }
+        assert!(input.is_incomplete());
+        match input.read_input().expect("read") {
+            MetaCommand(Command::Transact(t)) => assert_eq!(t, "[[:db/add \"a\"   :db/ident :foo/bar]]"),
+            r => panic!("expected a transact, got {:?}", r),
+        }
+        match input.read_input().expect("read") {
+            Empty => (),
+            r => panic!("expected Empty, got {:?}", r),
+        }
+        match input.read_input().expect("read") {
+            MetaCommand(Command::Query(_)) => (),
+            r => panic!("expected a query, got {:?}", r),
+        }
+        match input.read_input().expect("read") {
+            Eof => (),
+            r => panic!("expected Eof, got {:?}", r),
+        }
+        assert!(!input.is_incomplete());
+    }
+
+    #[test]
+    fn test_script_incomplete_command() {
+        let mut input = script(".t [[:db/add \"a\"");
+        match input.read_input().expect("read") {
+            More => (),
+            r => panic!("expected More, got {:?}", r),
+        }
+        match input.read_input().expect("read") {
+            Eof => (),
+            r => panic!("expected Eof, got {:?}", r),
+        }
+        assert!(input.is_incomplete());
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! Support for `.entity`, `.datoms` and `.tx`, which show raw datoms rather than query results.
+
+use std::rc::Rc;
+
+use edn;
+
+use mentat::{
+    Datom,
+    DatomIndex,
+    DatomPattern,
+    Entid,
+    HasSchema,
+    Schema,
+    Store,
+    TypedValue,
+    ValueType,
+};
+
+use mentat_db::TypedSQLValue;
+
+use errors as cli;
+
+/// Resolves an entid, an ident, or a lookup ref like `[:person/email "alice@example.com"]`, to an
+/// entid.
+pub fn resolve_entity(store: &Store, schema: &Schema, value: &edn::Value) -> Result<Entid, cli::Error> {
+    match *value {
+        edn::Value::Integer(e) => Ok(e),
+        edn::Value::NamespacedKeyword(ref ident) => {
+            schema.get_entid(ident)
+                  .map(|e| e.into())
+                  .ok_or_else(|| cli::ErrorKind::UnknownEntity(value.to_string()).into())
+        },
+        edn::Value::Vector(ref lookup_ref) if lookup_ref.len() == 2 => {
+            let a = resolve_attribute(schema, &lookup_ref[0])?;
+            if schema.attribute_for_entid(a).map_or(true, |attribute| attribute.unique.is_none()) {
+                bail!(cli::ErrorKind::CommandParse(format!("{} is not a unique attribute, so can't be used in a lookup ref", lookup_ref[0])));
+            }
+            let v = resolve_value(store, schema, a, &lookup_ref[1])?;
+            let pattern = DatomPattern { a: Some(a), v: Some(v), ..Default::default() };
+            store.datoms(DatomIndex::AVET, &pattern)?
+                 .first()
+                 .map(|datom| datom.e)
+                 .ok_or_else(|| cli::ErrorKind::UnknownEntity(value.to_string()).into())
+        },
+        _ => bail!(cli::ErrorKind::CommandParse(format!("Expected an entid, an ident or a lookup ref, got {}", value))),
+    }
+}
+
+fn resolve_attribute(schema: &Schema, value: &edn::Value) -> Result<Entid, cli::Error> {
+    let a = match *value {
+        edn::Value::Integer(a) => Some(a),
+        edn::Value::NamespacedKeyword(ref ident) => schema.get_entid(ident).map(|a| a.into()),
+        _ => None,
+    };
+    match a {
+        Some(a) if schema.is_attribute(a) => Ok(a),
+        _ => bail!(cli::ErrorKind::CommandParse(format!("Expected an attribute, got {}", value))),
+    }
+}
+
+/// Interprets `value` as a value of the attribute `a`, resolving idents and lookup refs for
+/// `:db.type/ref` attributes.
+fn resolve_value(store: &Store, schema: &Schema, a: Entid, value: &edn::Value) -> Result<TypedValue, cli::Error> {
+    let value_type = schema.attribute_for_entid(a).map(|attribute| attribute.value_type);
+    if value_type == Some(ValueType::Ref) {
+        return resolve_entity(store, schema, value).map(TypedValue::Ref);
+    }
+    match TypedValue::from_edn_value(value) {
+        Some(v) => {
+            if value_type.map_or(true, |t| v.matches_type(t)) {
+                Ok(v)
+            } else {
+                bail!(cli::ErrorKind::CommandParse(format!("{} is not a value of type {:?}", value, value_type.unwrap())))
+            }
+        },
+        None => bail!(cli::ErrorKind::CommandParse(format!("{} is not a value that can be stored", value))),
+    }
+}
+
+/// Returns the datoms in `index` whose leading components are `components`, like Datomic's
+/// `datoms`: `.datoms aevt :person/name 65536` walks `:person/name` datoms for entity 65536.
+pub fn datoms(store: &Store, schema: &Schema, index: DatomIndex, components: &[edn::Value]) -> Result<Vec<Datom>, cli::Error> {
+    if components.len() > 4 {
+        bail!(cli::ErrorKind::CommandParse(format!("Expected at most four components, got {}", components.len())));
+    }
+
+    let mut pattern = DatomPattern::default();
+    let order: &[char] = match index {
+        DatomIndex::EAVT => &['e', 'a', 'v', 't'],
+        DatomIndex::AEVT => &['a', 'e', 'v', 't'],
+        DatomIndex::AVET => &['a', 'v', 'e', 't'],
+        DatomIndex::VAET => &['v', 'a', 'e', 't'],
+    };
+    for (component, value) in order.iter().zip(components) {
+        match *component {
+            'e' => pattern.e = Some(resolve_entity(store, schema, value)?),
+            'a' => pattern.a = Some(resolve_attribute(schema, value)?),
+            't' => pattern.tx = Some(resolve_entity(store, schema, value)?),
+            _ => {
+                // Every index that leads with `v` only covers refs, and otherwise `a` precedes `v`.
+                pattern.v = Some(match pattern.a {
+                    Some(a) => resolve_value(store, schema, a, value)?,
+                    None => TypedValue::Ref(resolve_entity(store, schema, value)?),
+                });
+            },
+        }
+    }
+    Ok(store.datoms(index, &pattern)?)
+}
+
+/// Returns rows of `[attribute value]` for everything known about `e`. Datoms that refer to `e`
+/// are included as reverse attributes, like `[:person/_friend 65537]`.
+pub fn entity(store: &Store, schema: &Schema, e: Entid) -> Result<Vec<Vec<TypedValue>>, cli::Error> {
+    let forward = store.datoms(DatomIndex::EAVT, &DatomPattern { e: Some(e), ..Default::default() })?;
+    let reverse = store.datoms(DatomIndex::VAET, &DatomPattern { v: Some(TypedValue::Ref(e)), ..Default::default() })?;
+
+    let forward = forward.into_iter().map(|datom| vec![ident_or_entid(schema, datom.a), value(schema, datom.v)]);
+    let reverse = reverse.into_iter().map(|datom| {
+        let a = match schema.get_ident(datom.a) {
+            Some(ident) => TypedValue::Keyword(Rc::new(ident.to_reversed())),
+            None => TypedValue::Ref(datom.a),
+        };
+        vec![a, ident_or_entid(schema, datom.e)]
+    });
+    Ok(forward.chain(reverse).collect())
+}
+
+/// Turns datoms into rows of `[e a v tx]`, followed by `added` if `with_added` is set.
+pub fn datom_rows(schema: &Schema, datoms: Vec<Datom>, with_added: bool) -> Vec<Vec<TypedValue>> {
+    datoms.into_iter().map(|datom| {
+        let mut row = vec![ident_or_entid(schema, datom.e),
+                           ident_or_entid(schema, datom.a),
+                           value(schema, datom.v),
+                           TypedValue::Ref(datom.tx)];
+        if with_added {
+            row.push(TypedValue::Boolean(datom.added));
+        }
+        row
+    }).collect()
+}
+
+/// Names an entity by its ident, if it has one.
+fn ident_or_entid(schema: &Schema, e: Entid) -> TypedValue {
+    schema.get_ident(e)
+          .map(|ident| TypedValue::Keyword(Rc::new(ident.clone())))
+          .unwrap_or(TypedValue::Ref(e))
+}
+
+fn value(schema: &Schema, v: TypedValue) -> TypedValue {
+    match v {
+        TypedValue::Ref(e) => ident_or_entid(schema, e),
+        v => v,
+    }
+}
@@ -29,6 +29,14 @@ extern crate mentat_query;
 extern crate mentat_core;
 extern crate mentat_db;
 
+use std::fs::File;
+use std::io::{
+    BufRead,
+    BufReader,
+    Cursor,
+    Read,
+};
+
 use getopts::Options;
 
 use termion::{
@@ -39,7 +47,10 @@ static BLUE: color::Rgb = color::Rgb(0x99, 0xaa, 0xFF);
 static GREEN: color::Rgb = color::Rgb(0x77, 0xFF, 0x99);
 
 pub mod command_parser;
+pub mod completion;
 pub mod input;
+pub mod inspect;
+pub mod output;
 pub mod repl;
 pub mod errors;
 
@@ -54,6 +65,10 @@ pub fn run() -> i32 {
     opts.optmulti("q", "query", "Execute a query on startup. Queries are executed after any transacts.", "QUERY");
     opts.optmulti("t", "transact", "Execute a transact on startup. Transacts are executed before queries.", "TRANSACT");
     opts.optmulti("i", "import", "Execute an import on startup. Imports are executed before queries.", "PATH");
+    opts.optopt("f", "file", "Execute the commands in a script, then exit. Exits with a non-zero status on the first error.", "PATH");
+    opts.optmulti("e", "execute", "Execute a command, then exit. Commands are executed after any script. Exits with a non-zero status on the first error.", "COMMAND");
+    opts.optflag("1", "single-transaction", "Execute the commands given by -f and -e in a single transaction, which is committed only if all of them succeed.");
+    opts.optopt("o", "output", "The format in which to print query results: table (the default), edn, json or csv", "FORMAT");
     opts.optflag("v", "version", "Print version and exit");
 
     let matches = match opts.parse(&args[1..]) {
@@ -74,6 +89,15 @@ pub fn run() -> i32 {
         return 0;
     }
 
+    let output_format = match matches.opt_str("output").map(|f| f.parse::<output::OutputFormat>()) {
+        None => output::OutputFormat::default(),
+        Some(Ok(format)) => format,
+        Some(Err(e)) => {
+            println!("{}: {}", args[0], e);
+            return 1;
+        },
+    };
+
     let mut last_arg: Option<&str> = None;
     let cmds:Vec<command_parser::Command> = args.iter().filter_map(|arg| {
         match last_arg {
@@ -103,8 +127,24 @@ pub fn run() -> i32 {
 
     let repl = repl::Repl::new();
     if repl.is_ok() {
-        repl.unwrap().run(Some(cmds));
-
+        let mut repl = repl.unwrap();
+        repl.set_output_format(output_format);
+
+        if matches.opt_present("file") || matches.opt_present("execute") {
+            let script = match script(matches.opt_str("file"), matches.opt_strs("execute")) {
+                Ok(script) => script,
+                Err(e) => {
+                    println!("{}: {}", args[0], e);
+                    return 1;
+                },
+            };
+            if let Err(e) = repl.run_script(cmds, script, matches.opt_present("single-transaction")) {
+                repl::eprint_error(&e);
+                return 1;
+            }
+        } else {
+            repl.run(Some(cmds));
+        }
     } else {
         println!("{}", repl.err().unwrap());
     }
@@ -112,6 +152,15 @@ pub fn run() -> i32 {
     0
 }
 
+/// Concatenates the script at `path`, if any, and `commands` into a single script.
+fn script(path: Option<String>, commands: Vec<String>) -> std::io::Result<Box<BufRead>> {
+    let commands = Cursor::new(commands.join("\n").into_bytes());
+    Ok(match path {
+        Some(path) => Box::new(BufReader::new(File::open(path)?).chain(Cursor::new(b"\n".to_vec())).chain(commands)),
+        None => Box::new(commands),
+    })
+}
+
 /// Returns a version string.
 pub fn version() -> &'static str {
     env!("CARGO_PKG_VERSION")
@@ -0,0 +1,263 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use tabwriter::TabWriter;
+
+use edn;
+use edn::serde_support::json;
+
+use mentat::{
+    QueryOutput,
+    QueryResults,
+    TypedValue,
+};
+
+use mentat_db::TypedSQLValue;
+
+use errors as cli;
+
+/// How the REPL prints query results.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// An aligned table with a header row, for reading at a terminal.
+    Table,
+    /// The results as a single pretty-printed EDN value, shaped like the `:find` spec.
+    Edn,
+    /// One JSON object per row, keyed by the `:find` column names.
+    Json,
+    /// Comma-separated values with a header row.
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OutputFormat::Table => "table",
+            OutputFormat::Edn => "edn",
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+        }
+    }
+}
+
+impl Default for OutputFormat {
+    fn default() -> OutputFormat {
+        OutputFormat::Table
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = cli::Error;
+
+    fn from_str(s: &str) -> Result<OutputFormat, cli::Error> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "edn" => Ok(OutputFormat::Edn),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => bail!(cli::ErrorKind::CommandParse(format!("Unknown output format {:?}; expected one of table, edn, json or csv", s))),
+        }
+    }
+}
+
+/// Writes `query_output` to `out` in the given format.
+pub fn write_results<W: Write>(out: W, format: OutputFormat, query_output: QueryOutput) -> Result<(), cli::Error> {
+    let headers: Vec<String> = query_output.spec.columns().map(|e| e.to_string()).collect();
+    match format {
+        OutputFormat::Edn => write_edn(out, query_output.results),
+        _ => write_rows(out, format, &headers, rows(query_output.results)),
+    }
+}
+
+/// Writes rows of values to `out` in the given format, labelled with `headers`.
+pub fn write_rows<W: Write>(out: W, format: OutputFormat, headers: &[String], rows: Vec<Vec<TypedValue>>) -> Result<(), cli::Error> {
+    match format {
+        OutputFormat::Table => write_table(out, headers, rows),
+        OutputFormat::Edn => write_edn(out, QueryResults::Rel(rows)),
+        OutputFormat::Json => write_json(out, headers, rows),
+        OutputFormat::Csv => write_csv(out, headers, rows),
+    }
+}
+
+/// Flattens any shape of results into rows. A missing scalar or tuple is no rows at all.
+fn rows(results: QueryResults) -> Vec<Vec<TypedValue>> {
+    match results {
+        QueryResults::Scalar(v) => v.into_iter().map(|v| vec![v]).collect(),
+        QueryResults::Tuple(vv) => vv.into_iter().collect(),
+        QueryResults::Coll(vv) => vv.into_iter().map(|v| vec![v]).collect(),
+        QueryResults::Rel(vvv) => vvv,
+    }
+}
+
+fn write_table<W: Write>(out: W, headers: &[String], rows: Vec<Vec<TypedValue>>) -> Result<(), cli::Error> {
+    let mut output = TabWriter::new(out);
+
+    // Print the column headers.
+    for header in headers {
+        write!(output, "| {}\t", header)?;
+    }
+    writeln!(output, "|")?;
+    for _ in headers {
+        write!(output, "---\t")?;
+    }
+    writeln!(output, "")?;
+
+    for row in rows {
+        for v in row {
+            write!(output, "| {}\t", typed_value_as_string(v))?;
+        }
+        writeln!(output, "|")?;
+    }
+
+    for _ in headers {
+        write!(output, "---\t")?;
+    }
+    writeln!(output, "")?;
+    output.flush()?;
+    Ok(())
+}
+
+fn write_edn<W: Write>(mut out: W, results: QueryResults) -> Result<(), cli::Error> {
+    let vector = |vs: Vec<TypedValue>| edn::Value::Vector(vs.iter().map(typed_value_as_edn).collect());
+    let value = match results {
+        QueryResults::Scalar(v) => v.as_ref().map(typed_value_as_edn).unwrap_or(edn::Value::Nil),
+        QueryResults::Tuple(vv) => vv.map(&vector).unwrap_or(edn::Value::Nil),
+        QueryResults::Coll(vv) => vector(vv),
+        QueryResults::Rel(vvv) => edn::Value::Vector(vvv.into_iter().map(&vector).collect()),
+    };
+    writeln!(out, "{}", value.to_pretty(120)?)?;
+    Ok(())
+}
+
+fn write_json<W: Write>(mut out: W, headers: &[String], rows: Vec<Vec<TypedValue>>) -> Result<(), cli::Error> {
+    for row in rows {
+        let object: BTreeMap<edn::Value, edn::Value> =
+            headers.iter()
+                   .zip(row.iter())
+                   .map(|(header, v)| (edn::Value::Text(header.clone()), typed_value_as_edn(v)))
+                   .collect();
+        writeln!(out, "{}", json::to_json_string(&edn::Value::Map(object)))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_csv<W: Write>(mut out: W, headers: &[String], rows: Vec<Vec<TypedValue>>) -> Result<(), cli::Error> {
+    let header_fields: Vec<String> = headers.iter().map(|h| csv_field(h)).collect();
+    writeln!(out, "{}", header_fields.join(","))?;
+    for row in rows {
+        let fields: Vec<String> = row.into_iter().map(|v| {
+            match v {
+                // Strings are written raw rather than in their quoted, escaped EDN form.
+                TypedValue::String(s) => csv_field(s.as_str()),
+                v => csv_field(&typed_value_as_string(v)),
+            }
+        }).collect();
+        writeln!(out, "{}", fields.join(","))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Quotes a CSV field if it contains a delimiter, a quote or a line break, as per RFC 4180.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn typed_value_as_edn(value: &TypedValue) -> edn::Value {
+    value.to_edn_value_pair().0
+}
+
+fn typed_value_as_string(value: TypedValue) -> String {
+    match value {
+        TypedValue::Boolean(b) => if b { "true".to_string() } else { "false".to_string() },
+        TypedValue::Double(d) => format!("{}", d),
+        TypedValue::Instant(i) => format!("{}", i),
+        TypedValue::Keyword(k) => format!("{}", k),
+        TypedValue::Long(l) => format!("{}", l),
+        TypedValue::Ref(r) => format!("{}", r),
+        TypedValue::String(s) => format!("{:?}", s.to_string()),
+        TypedValue::Uuid(u) => format!("{}", u),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::rc::Rc;
+
+    fn write(format: OutputFormat, headers: &[&str], rows: Vec<Vec<TypedValue>>) -> String {
+        let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+        let mut out = Vec::new();
+        match format {
+            OutputFormat::Table => write_table(&mut out, &headers, rows),
+            OutputFormat::Json => write_json(&mut out, &headers, rows),
+            OutputFormat::Csv => write_csv(&mut out, &headers, rows),
+            OutputFormat::Edn => write_edn(&mut out, QueryResults::Rel(rows)),
+        }.expect("to write");
+        String::from_utf8(out).expect("UTF-8")
+    }
+
+    fn rows() -> Vec<Vec<TypedValue>> {
+        vec![vec![TypedValue::Ref(65536), TypedValue::String(Rc::new("Alice".to_string()))],
+             vec![TypedValue::Ref(65537), TypedValue::String(Rc::new("Bob \"the\" builder, esq.".to_string()))]]
+    }
+
+    #[test]
+    fn test_output_format_from_str() {
+        for format in &[OutputFormat::Table, OutputFormat::Edn, OutputFormat::Json, OutputFormat::Csv] {
+            assert_eq!(format.name().parse::<OutputFormat>().expect("to parse"), *format);
+        }
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(write(OutputFormat::Csv, &["?e", "?name"], rows()),
+                   "?e,?name\n65536,Alice\n65537,\"Bob \"\"the\"\" builder, esq.\"\n");
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(write(OutputFormat::Json, &["?e", "?name"], rows()),
+                   "{\"?e\":65536,\"?name\":\"Alice\"}\n{\"?e\":65537,\"?name\":\"Bob \\\"the\\\" builder, esq.\"}\n");
+    }
+
+    #[test]
+    fn test_edn() {
+        assert_eq!(write(OutputFormat::Edn, &["?e", "?name"], rows()),
+                   "[[65536 \"Alice\"] [65537 \"Bob \\\"the\\\" builder, esq.\"]]\n");
+    }
+
+    #[test]
+    fn test_rows() {
+        assert_eq!(super::rows(QueryResults::Scalar(None)).len(), 0);
+        assert_eq!(super::rows(QueryResults::Tuple(Some(vec![TypedValue::Long(1), TypedValue::Long(2)]))),
+                   vec![vec![TypedValue::Long(1), TypedValue::Long(2)]]);
+        assert_eq!(super::rows(QueryResults::Coll(vec![TypedValue::Long(1), TypedValue::Long(2)])),
+                   vec![vec![TypedValue::Long(1)], vec![TypedValue::Long(2)]]);
+    }
+}
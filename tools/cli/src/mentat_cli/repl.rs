@@ -8,7 +8,12 @@
 // CONDITIONS OF ANY KIND, either express or implied. See the License for the
 // specific language governing permissions and limitations under the License.
 
-use std::io::Write;
+use std::fs::File;
+use std::io::{
+    BufRead,
+    BufWriter,
+    Write,
+};
 use std::process;
 
 use tabwriter::TabWriter;
@@ -25,15 +30,18 @@ use time::{
 
 use mentat::{
     CacheDirection,
+    InProgress,
     NamespacedKeyword,
     Queryable,
     QueryExplanation,
     QueryOutput,
-    QueryResults,
+    Entid,
     Store,
+    SyncProgress,
     Syncable,
     TxReport,
     TypedValue,
+    Uuid,
 };
 
 use command_parser::{
@@ -42,11 +50,15 @@ use command_parser::{
 
 use command_parser::{
     COMMAND_CACHE,
+    COMMAND_DATOMS,
+    COMMAND_ENTITY,
     COMMAND_EXIT_LONG,
     COMMAND_EXIT_SHORT,
+    COMMAND_EXPORT,
     COMMAND_HELP,
     COMMAND_IMPORT_LONG,
     COMMAND_OPEN,
+    COMMAND_OUTPUT,
     COMMAND_QUERY_LONG,
     COMMAND_QUERY_SHORT,
     COMMAND_QUERY_EXPLAIN_LONG,
@@ -57,8 +69,20 @@ use command_parser::{
     COMMAND_TIMER_LONG,
     COMMAND_TRANSACT_LONG,
     COMMAND_TRANSACT_SHORT,
+    COMMAND_TX,
+};
+
+use inspect;
+
+use output::{
+    OutputFormat,
+    write_results,
+    write_rows,
 };
 
+use errors as cli;
+use errors::ResultExt;
+
 use input::InputReader;
 use input::InputResult::{
     Empty,
@@ -67,6 +91,9 @@ use input::InputResult::{
     More,
 };
 
+/// How many entities `.import` transacts at a time, so that large files import in bounded memory.
+const IMPORT_BATCH_SIZE: usize = 1000;
+
 lazy_static! {
     static ref HELP_COMMANDS: Vec<(&'static str, &'static str)> = {
         vec![
@@ -79,7 +106,10 @@ lazy_static! {
 
             (COMMAND_SCHEMA, "Output the schema for the current open database."),
 
-            (COMMAND_IMPORT_LONG, "Transact the contents of a file against the current open database."),
+            (COMMAND_OUTPUT, "Choose how query results are printed. Usage: `.output table|edn|json|csv`"),
+
+            (COMMAND_IMPORT_LONG, "Transact the contents of a file against the current open database, 1000 entities at a time, or restore an export into an empty database. Tempids are only shared within each batch of 1000."),
+            (COMMAND_EXPORT, "Write the schema and current datoms to a file that `.import` can restore into an empty database. Usage: `.export [--history] path`; `--history` writes every transaction, retractions included."),
 
             (COMMAND_QUERY_LONG, "Execute a query against the current open database."),
             (COMMAND_QUERY_SHORT, "Shortcut for `.query`. Execute a query against the current open database."),
@@ -95,6 +125,10 @@ lazy_static! {
             (COMMAND_TIMER_LONG, "Enable or disable timing of query and transact operations."),
 
             (COMMAND_CACHE, "Cache an attribute. Usage: `.cache :foo/bar reverse`"),
+
+            (COMMAND_ENTITY, "Show every attribute of an entity, including reverse attributes. Usage: `.entity 65536`, `.entity :foo/bar` or `.entity [:foo/email \"a@b.com\"]`"),
+            (COMMAND_DATOMS, "Walk an index: eavt, aevt, avet or vaet, optionally limited by leading components. Usage: `.datoms aevt :foo/bar 65536`"),
+            (COMMAND_TX, "Show the datoms asserted and retracted by a transaction. Usage: `.tx 268435457`"),
             (COMMAND_SYNC, "Synchronize the database against a Sync Server URL for a provided user UUID."),
         ]
     };
@@ -156,11 +190,158 @@ fn format_time(duration: Duration) {
               reset = style::Reset);
 }
 
+/// Prints a line to stderr for each transaction uploaded during `.sync`.
+struct SyncProgressPrinter;
+
+impl SyncProgress for SyncProgressPrinter {
+    fn heads_fetched(&mut self, remote_head: &Uuid, locally_known_remote_head: &Uuid) {
+        eprintln!("Remote head {}, last synced head {}", remote_head, locally_known_remote_head);
+    }
+
+    fn tx_uploaded(&mut self, tx: Entid, tx_uuid: &Uuid) {
+        eprintln!("Uploaded transaction {} as {}", tx, tx_uuid);
+    }
+}
+
+/// Prints `e` and its causes to stderr.
+pub fn eprint_error(e: &cli::Error) {
+    eprint!("Error: {}", e);
+    for cause in e.iter().skip(1) {
+        eprint!(": {}", cause);
+    }
+    eprintln!();
+}
+
+/// Reads the next complete command from a script. Returns `None` at the end of the script, or
+/// when the script says `.exit`.
+fn next_script_command(input: &mut InputReader) -> Result<Option<Command>, cli::Error> {
+    loop {
+        match input.read_input()? {
+            MetaCommand(Command::Exit) => return Ok(None),
+            MetaCommand(cmd) => return Ok(Some(cmd)),
+            Empty |
+            More => (),
+            Eof => {
+                if input.is_incomplete() {
+                    bail!(cli::ErrorKind::CommandParse("Incomplete command at end of input".to_string()));
+                }
+                return Ok(None);
+            },
+        }
+    }
+}
+
+/// Runs a single command inside `in_progress`. Commands that open, close, cache or sync would
+/// need the transaction to be committed first, and so are rejected.
+fn handle_command_in_progress(in_progress: &mut InProgress, output_format: &mut OutputFormat, cmd: Command) -> Result<(), cli::Error> {
+    match cmd {
+        Command::Help(args) => {
+            help_command(args)?;
+        },
+        Command::Import(path) => {
+            if ::mentat::export::is_export(&path)? {
+                let transactions = in_progress.restore(&path)?;
+                eprintln!("Restored {} transactions from {}", transactions, path);
+            } else {
+                let transactions = in_progress.import_in_batches(&path, IMPORT_BATCH_SIZE)?;
+                eprintln!("Imported {} transactions from {}", transactions, path);
+            }
+        },
+        Command::Output(format) => {
+            *output_format = format;
+        },
+        Command::Query(query) => {
+            let results = in_progress.q_once(query.as_str(), None)?;
+            write_results(::std::io::stdout().lock(), *output_format, results)?;
+        },
+        Command::QueryExplain(query) => {
+            print_explanation(in_progress.q_explain(query.as_str(), None)?);
+        },
+        Command::QueryPrepared(query) => {
+            let results = in_progress.q_prepare(query.as_str(), None)?.run(None)?;
+            write_results(::std::io::stdout().lock(), *output_format, results)?;
+        },
+        Command::Transact(transaction) => {
+            println!("{:?}", in_progress.transact(&transaction)?);
+        },
+        cmd => {
+            bail!(cli::ErrorKind::NotTransactional(cmd.output()));
+        },
+    }
+    Ok(())
+}
+
+fn help_command(args: Vec<String>) -> Result<(), cli::Error> {
+    let stdout = ::std::io::stdout();
+    let mut output = TabWriter::new(stdout.lock());
+    if args.is_empty() {
+        for &(cmd, msg) in HELP_COMMANDS.iter() {
+            write!(output, ".{}\t", cmd)?;
+            writeln!(output, "{}", msg)?;
+        }
+    } else {
+        for mut arg in args {
+            if arg.chars().nth(0).unwrap() == '.' {
+                arg.remove(0);
+            }
+            if let Some(&(cmd, msg)) = HELP_COMMANDS.iter()
+                                                   .filter(|&&(c, _)| c == arg.as_str())
+                                                   .next() {
+                write!(output, ".{}\t", cmd)?;
+                writeln!(output, "{}", msg)?;
+            } else {
+                bail!(cli::ErrorKind::CommandParse(format!("Unrecognised command {}", arg)));
+            }
+        }
+    }
+    writeln!(output, "")?;
+    output.flush()?;
+    Ok(())
+}
+
+fn print_explanation(explanation: QueryExplanation) {
+    match explanation {
+        QueryExplanation::KnownConstant =>
+            println!("Query is known constant!"),
+        QueryExplanation::KnownEmpty(empty_because) =>
+            println!("Query is known empty: {:?}", empty_because),
+        QueryExplanation::ExecutionPlan { query, steps } => {
+            println!("SQL: {}", query.sql);
+            if !query.args.is_empty() {
+                println!("  Bindings:");
+                for (arg_name, value) in query.args {
+                    println!("    {} = {:?}", arg_name, *value)
+                }
+            }
+
+            println!("Plan: select id | order | from | detail");
+            // Compute the number of columns we need for order, select id, and from,
+            // so that longer query plans don't become misaligned.
+            let (max_select_id, max_order, max_from) = steps.iter().fold((0, 0, 0), |acc, step|
+                (acc.0.max(step.select_id), acc.1.max(step.order), acc.2.max(step.from)));
+            // This is less efficient than computing it via the logarithm base 10,
+            // but it's clearer and doesn't have require special casing "0"
+            let max_select_digits = max_select_id.to_string().len();
+            let max_order_digits = max_order.to_string().len();
+            let max_from_digits = max_from.to_string().len();
+            for step in steps {
+                // Note: > is right align.
+                println!("  {:>sel_cols$}|{:>ord_cols$}|{:>from_cols$}|{}",
+                         step.select_id, step.order, step.from, step.detail,
+                         sel_cols = max_select_digits,
+                         ord_cols = max_order_digits,
+                         from_cols = max_from_digits);
+            }
+        }
+    };
+}
+
 /// Executes input and maintains state of persistent items.
 pub struct Repl {
     path: String,
     store: Store,
     timer_on: bool,
+    output_format: OutputFormat,
 }
 
 impl Repl {
@@ -179,27 +360,44 @@ impl Repl {
             path: "".to_string(),
             store: store,
             timer_on: false,
+            output_format: OutputFormat::default(),
         })
     }
 
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.output_format = format;
+    }
+
+    /// The idents in the open store, for completion.
+    fn idents(&self) -> Vec<String> {
+        self.store.conn().current_schema().ident_map.keys().map(|ident| ident.to_string()).collect()
+    }
+
     /// Runs the REPL interactively.
     pub fn run(&mut self, startup_commands: Option<Vec<Command>>) {
         let mut input = InputReader::new();
 
         if let Some(cmds) = startup_commands {
             for command in cmds.iter() {
-                println!("{}", command.output());
-                self.handle_command(command.clone());
+                // Echo to stderr, so that piped output contains only results.
+                eprintln!("{}", command.output());
+                if let Err(e) = self.handle_command(command.clone()) {
+                    eprint_error(&e);
+                }
             }
         }
 
         loop {
+            // Opening a store or transacting can change the idents on offer.
+            input.set_idents(self.idents());
             let res = input.read_input();
 
             match res {
                 Ok(MetaCommand(cmd)) => {
                     debug!("read command: {:?}", cmd);
-                    self.handle_command(cmd);
+                    if let Err(e) = self.handle_command(cmd) {
+                        eprint_error(&e);
+                    }
                 },
                 Ok(Empty) |
                 Ok(More) => (),
@@ -214,19 +412,44 @@ impl Repl {
         }
     }
 
-    fn cache(&mut self, attr: String, direction: CacheDirection) {
+    /// Runs `startup_commands`, then the commands read from `script`, stopping at the first error.
+    /// If `single_transaction` is set, the commands from `script` run in one transaction that is
+    /// committed only if all of them succeed.
+    pub fn run_script(&mut self, startup_commands: Vec<Command>, script: Box<BufRead>, single_transaction: bool) -> Result<(), cli::Error> {
+        for command in startup_commands {
+            self.handle_command(command)?;
+        }
+
+        let mut input = InputReader::with_script(script);
+        if single_transaction {
+            let mut output_format = self.output_format;
+            {
+                let mut in_progress = self.store.begin_transaction()?;
+                while let Some(cmd) = next_script_command(&mut input)? {
+                    handle_command_in_progress(&mut in_progress, &mut output_format, cmd)?;
+                }
+                in_progress.commit()?;
+            }
+            self.output_format = output_format;
+        } else {
+            while let Some(cmd) = next_script_command(&mut input)? {
+                self.handle_command(cmd)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cache(&mut self, attr: String, direction: CacheDirection) -> Result<(), cli::Error> {
         if let Some(kw) = parse_namespaced_keyword(attr.as_str()) {
-            match self.store.cache(&kw, direction) {
-                Result::Ok(_) => (),
-                Result::Err(e) => eprintln!("Couldn't cache attribute: {}", e),
-            };
+            self.store.cache(&kw, direction)?;
+            Ok(())
         } else {
-            eprintln!("Invalid attribute {}", attr);
+            bail!(cli::ErrorKind::CommandParse(format!("Invalid attribute {}", attr)));
         }
     }
 
     /// Runs a single command input.
-    fn handle_command(&mut self, cmd: Command) {
+    fn handle_command(&mut self, cmd: Command) -> Result<(), cli::Error> {
         let should_print_times = self.timer_on && cmd.is_timed();
 
         let mut start = PreciseTime::now();
@@ -234,90 +457,96 @@ impl Repl {
 
         match cmd {
             Command::Cache(attr, direction) => {
-                self.cache(attr, direction);
+                self.cache(attr, direction)?;
             },
             Command::Close => {
-                self.close();
+                self.close()?;
+            },
+            Command::Datoms(index, components) => {
+                let schema = self.store.conn().current_schema();
+                let datoms = inspect::datoms(&self.store, &schema, index, &components)?;
+                end = Some(PreciseTime::now());
+                self.print_rows(&["e", "a", "v", "tx"], inspect::datom_rows(&schema, datoms, false))?;
+            },
+            Command::Entity(e) => {
+                let schema = self.store.conn().current_schema();
+                let e = inspect::resolve_entity(&self.store, &schema, &e)?;
+                let rows = inspect::entity(&self.store, &schema, e)?;
+                end = Some(PreciseTime::now());
+                self.print_rows(&["a", "v"], rows)?;
             },
             Command::Exit => {
-                self.close();
+                self.close()?;
                 eprintln!("Exiting…");
                 process::exit(0);
             },
             Command::Help(args) => {
-                self.help_command(args);
+                help_command(args)?;
+            },
+            Command::Export(path, history) => {
+                let mut out = BufWriter::new(File::create(&path)?);
+                let transactions = self.store.export(&mut out, history).chain_err(|| format!("Error exporting to {}", path))?;
+                out.flush()?;
+                eprintln!("Exported {} transactions to {}", transactions, path);
             },
             Command::Import(path) => {
-                self.execute_import(path);
+                self.execute_import(path)?;
             },
             Command::Open(db) => {
-                match self.open(db) {
-                    Ok(_) => println!("Database {:?} opened", self.db_name()),
-                    Err(e) => eprintln!("{}", e.to_string()),
-                };
+                self.open(db)?;
+                eprintln!("Database {:?} opened", self.db_name());
             },
             Command::OpenEmpty(db) => {
-                match self.open_empty(db) {
-                    Ok(_) => println!("Empty database {:?} opened", self.db_name()),
-                    Err(e) => eprintln!("{}", e.to_string()),
-                };
+                self.open_empty(db)?;
+                eprintln!("Empty database {:?} opened", self.db_name());
+            },
+            Command::Output(format) => {
+                self.set_output_format(format);
             },
             Command::Query(query) => {
-                self.store
-                    .q_once(query.as_str(), None)
-                    .map_err(|e| e.into())
-                    .and_then(|o| {
-                        end = Some(PreciseTime::now());
-                        self.print_results(o)
-                    })
-                    .map_err(|err| {
-                        eprintln!("{:?}.", err);
-                    })
-                    .ok();
+                let results = self.store.q_once(query.as_str(), None)?;
+                end = Some(PreciseTime::now());
+                self.print_results(results)?;
             },
             Command::QueryExplain(query) => {
-                self.explain_query(query);
+                self.explain_query(query)?;
             },
             Command::QueryPrepared(query) => {
-                self.store
-                    .q_prepare(query.as_str(), None)
-                    .and_then(|mut p| {
-                        let prepare_end = PreciseTime::now();
-                        if should_print_times {
-                            eprint_out("Prepare time");
-                            eprint!(": ");
-                            format_time(start.to(prepare_end));
-                        }
-                        // This is a hack.
-                        start = PreciseTime::now();
-                        let r = p.run(None);
-                        end = Some(PreciseTime::now());
-                        return r;
-                    })
-                    .map(|o| self.print_results(o))
-                    .map_err(|err| {
-                        eprintln!("{:?}.", err);
-                    })
-                    .ok();
+                let results = {
+                    let mut p = self.store.q_prepare(query.as_str(), None)?;
+                    let prepare_end = PreciseTime::now();
+                    if should_print_times {
+                        eprint_out("Prepare time");
+                        eprint!(": ");
+                        format_time(start.to(prepare_end));
+                    }
+                    // This is a hack.
+                    start = PreciseTime::now();
+                    let r = p.run(None)?;
+                    end = Some(PreciseTime::now());
+                    r
+                };
+                self.print_results(results)?;
             },
             Command::Schema => {
                 let edn = self.store.conn().current_schema().to_edn_value();
-                match edn.to_pretty(120) {
-                    Ok(s) => println!("{}", s),
-                    Err(e) => eprintln!("{}", e)
-                };
+                println!("{}", edn.to_pretty(120)?);
             },
             Command::Sync(args) => {
-                match self.store.sync(&args[0], &args[1]) {
-                    Ok(_) => println!("Synced!"),
-                    Err(e) => eprintln!("{:?}", e)
-                };
+                let report = self.store.sync_with_progress(&args[0], &args[1], &mut SyncProgressPrinter)?;
+                println!("Synced! {}", report);
             }
             Command::Timer(on) => {
                 self.toggle_timer(on);
             },
             Command::Transact(transaction) => {
-                self.execute_transact(transaction);
+                self.execute_transact(transaction)?;
+            },
+            Command::Tx(tx) => {
+                let schema = self.store.conn().current_schema();
+                let datoms = self.store.transaction_datoms(tx)?;
+                end = Some(PreciseTime::now());
+                self.print_rows(&["e", "a", "v", "tx", "added"], inspect::datom_rows(&schema, datoms, true))?;
             },
         }
 
@@ -327,17 +556,23 @@ impl Repl {
             eprint!(": ");
             format_time(start.to(end));
         }
+        Ok(())
     }
 
-    fn execute_import<T>(&mut self, path: T)
+    fn execute_import<T>(&mut self, path: T) -> Result<(), cli::Error>
     where T: Into<String> {
-        use ::std::io::Read;
         let path = path.into();
-        let mut content: String = "".to_string();
-        match ::std::fs::File::open(path.clone()).and_then(|mut f| f.read_to_string(&mut content)) {
-            Ok(_) => self.execute_transact(content),
-            Err(e) => eprintln!("Error reading file {}: {}", path, e)
+        let mut tx = self.store.begin_transaction()?;
+        if ::mentat::export::is_export(&path)? {
+            let transactions = tx.restore(&path).chain_err(|| format!("Error restoring file {}", path))?;
+            tx.commit()?;
+            eprintln!("Restored {} transactions from {}", transactions, path);
+        } else {
+            let transactions = tx.import_in_batches(&path, IMPORT_BATCH_SIZE).chain_err(|| format!("Error importing file {}", path))?;
+            tx.commit()?;
+            eprintln!("Imported {} transactions from {}", transactions, path);
         }
+        Ok(())
     }
 
     fn open<T>(&mut self, path: T) -> ::mentat::errors::Result<()>
@@ -365,143 +600,37 @@ impl Repl {
     }
 
     // Close the current store by opening a new in-memory store in its place.
-    fn close(&mut self) {
+    fn close(&mut self) -> Result<(), cli::Error> {
         let old_db_name = self.db_name();
-        match self.open("") {
-            Ok(_) => println!("Database {:?} closed.", old_db_name),
-            Err(e) => eprintln!("{}", e),
-        };
+        self.open("")?;
+        eprintln!("Database {:?} closed.", old_db_name);
+        Ok(())
     }
 
     fn toggle_timer(&mut self, on: bool) {
         self.timer_on = on;
     }
 
-    fn help_command(&self, args: Vec<String>) {
+    fn print_results(&self, query_output: QueryOutput) -> Result<(), cli::Error> {
         let stdout = ::std::io::stdout();
-        let mut output = TabWriter::new(stdout.lock());
-        if args.is_empty() {
-            for &(cmd, msg) in HELP_COMMANDS.iter() {
-                write!(output, ".{}\t", cmd).unwrap();
-                writeln!(output, "{}", msg).unwrap();
-            }
-        } else {
-            for mut arg in args {
-                if arg.chars().nth(0).unwrap() == '.' {
-                    arg.remove(0);
-                }
-                if let Some(&(cmd, msg)) = HELP_COMMANDS.iter()
-                                                       .filter(|&&(c, _)| c == arg.as_str())
-                                                       .next() {
-                    write!(output, ".{}\t", cmd).unwrap();
-                    writeln!(output, "{}", msg).unwrap();
-                } else {
-                    eprintln!("Unrecognised command {}", arg);
-                    return;
-                }
-            }
-        }
-        writeln!(output, "").unwrap();
-        output.flush().unwrap();
+        write_results(stdout.lock(), self.output_format, query_output)
     }
 
-    fn print_results(&self, query_output: QueryOutput) -> Result<(), ::errors::Error> {
+    fn print_rows(&self, headers: &[&str], rows: Vec<Vec<TypedValue>>) -> Result<(), cli::Error> {
+        let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
         let stdout = ::std::io::stdout();
-        let mut output = TabWriter::new(stdout.lock());
-
-        // Print the column headers.
-        for e in query_output.spec.columns() {
-            write!(output, "| {}\t", e)?;
-        }
-        writeln!(output, "|")?;
-        for _ in 0..query_output.spec.expected_column_count() {
-            write!(output, "---\t")?;
-        }
-        writeln!(output, "")?;
-
-        match query_output.results {
-            QueryResults::Scalar(v) => {
-                if let Some(val) = v {
-                    writeln!(output, "| {}\t |", &self.typed_value_as_string(val))?;
-                }
-            },
-
-            QueryResults::Tuple(vv) => {
-                if let Some(vals) = vv {
-                    for val in vals {
-                        write!(output, "| {}\t", self.typed_value_as_string(val))?;
-                    }
-                    writeln!(output, "|")?;
-                }
-            },
-
-            QueryResults::Coll(vv) => {
-                for val in vv {
-                    writeln!(output, "| {}\t|", self.typed_value_as_string(val))?;
-                }
-            },
-
-            QueryResults::Rel(vvv) => {
-                for vv in vvv {
-                    for v in vv {
-                        write!(output, "| {}\t", self.typed_value_as_string(v))?;
-                    }
-                    writeln!(output, "|")?;
-                }
-            },
-        }
-        for _ in 0..query_output.spec.expected_column_count() {
-            write!(output, "---\t")?;
-        }
-        writeln!(output, "")?;
-        output.flush()?;
-        Ok(())
+        write_rows(stdout.lock(), self.output_format, &headers, rows)
     }
 
-    pub fn explain_query(&self, query: String) {
-        match self.store.q_explain(query.as_str(), None) {
-            Result::Err(err) =>
-                println!("{:?}.", err),
-            Result::Ok(QueryExplanation::KnownConstant) =>
-                println!("Query is known constant!"),
-            Result::Ok(QueryExplanation::KnownEmpty(empty_because)) =>
-                println!("Query is known empty: {:?}", empty_because),
-            Result::Ok(QueryExplanation::ExecutionPlan { query, steps }) => {
-                println!("SQL: {}", query.sql);
-                if !query.args.is_empty() {
-                    println!("  Bindings:");
-                    for (arg_name, value) in query.args {
-                        println!("    {} = {:?}", arg_name, *value)
-                    }
-                }
-
-                println!("Plan: select id | order | from | detail");
-                // Compute the number of columns we need for order, select id, and from,
-                // so that longer query plans don't become misaligned.
-                let (max_select_id, max_order, max_from) = steps.iter().fold((0, 0, 0), |acc, step|
-                    (acc.0.max(step.select_id), acc.1.max(step.order), acc.2.max(step.from)));
-                // This is less efficient than computing it via the logarithm base 10,
-                // but it's clearer and doesn't have require special casing "0"
-                let max_select_digits = max_select_id.to_string().len();
-                let max_order_digits = max_order.to_string().len();
-                let max_from_digits = max_from.to_string().len();
-                for step in steps {
-                    // Note: > is right align.
-                    println!("  {:>sel_cols$}|{:>ord_cols$}|{:>from_cols$}|{}",
-                             step.select_id, step.order, step.from, step.detail,
-                             sel_cols = max_select_digits,
-                             ord_cols = max_order_digits,
-                             from_cols = max_from_digits);
-                }
-            }
-        };
+    pub fn explain_query(&self, query: String) -> Result<(), cli::Error> {
+        print_explanation(self.store.q_explain(query.as_str(), None)?);
+        Ok(())
     }
 
-    pub fn execute_transact(&mut self, transaction: String) {
-        match self.transact(transaction) {
-            Result::Ok(report) => println!("{:?}", report),
-            Result::Err(err) => eprintln!("Error: {:?}.", err),
-        }
+    pub fn execute_transact(&mut self, transaction: String) -> Result<(), cli::Error> {
+        let report = self.transact(transaction)?;
+        println!("{:?}", report);
+        Ok(())
     }
 
     fn transact(&mut self, transaction: String) -> ::mentat::errors::Result<TxReport> {
@@ -510,17 +639,4 @@ impl Repl {
         tx.commit()?;
         Ok(report)
     }
-
-    fn typed_value_as_string(&self, value: TypedValue) -> String {
-        match value {
-            TypedValue::Boolean(b) => if b { "true".to_string() } else { "false".to_string() },
-            TypedValue::Double(d) => format!("{}", d),
-            TypedValue::Instant(i) => format!("{}", i),
-            TypedValue::Keyword(k) => format!("{}", k),
-            TypedValue::Long(l) => format!("{}", l),
-            TypedValue::Ref(r) => format!("{}", r),
-            TypedValue::String(s) => format!("{:?}", s.to_string()),
-            TypedValue::Uuid(u) => format!("{}", u),
-        }
-    }
 }
@@ -0,0 +1,418 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! The operations behind each endpoint, independent of HTTP.  Requests and responses are EDN
+//! values; `main` converts them to and from EDN or JSON text.
+
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+use std::fmt;
+
+use edn;
+use edn::{
+    Keyword,
+    NamespacedKeyword,
+};
+
+use mentat::{
+    DatomIndex,
+    DatomPattern,
+    Entid,
+    HasSchema,
+    QueryInputs,
+    QueryResults,
+    Queryable,
+    Schema,
+    Store,
+    TxCommitted,
+    TxReport,
+    TypedValue,
+    Variable,
+};
+use mentat::errors::{
+    Error as MentatError,
+    ErrorKind as MentatErrorKind,
+};
+
+use mentat_db::ErrorKind as DbErrorKind;
+use mentat_db::TypedSQLValue;
+
+/// Why a request failed: the client asked for something invalid, or for something that doesn't
+/// exist, or the server couldn't read or write its own storage, or is too busy to serve it.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    Internal(String),
+    Unavailable(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApiError::BadRequest(ref message) |
+            ApiError::NotFound(ref message) |
+            ApiError::Internal(ref message) |
+            ApiError::Unavailable(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Whether `error` is the client's fault: a query or transaction that doesn't parse, that names
+/// something the schema doesn't have or asserts schema it can't, or that gives a value of the
+/// wrong type.
+fn is_bad_request(error: &MentatErrorKind) -> bool {
+    match *error {
+        MentatErrorKind::EdnParseError(_) |
+        MentatErrorKind::QueryParseError(_) |
+        MentatErrorKind::QueryError(_) |
+        MentatErrorKind::TxParseError(_) |
+        MentatErrorKind::UnboundVariables(_) |
+        MentatErrorKind::InvalidArgumentName(_) |
+        MentatErrorKind::UnknownAttribute(_) |
+        MentatErrorKind::ValueTypeMismatch(_, _) => true,
+        MentatErrorKind::DbError(ref error) => match *error {
+            DbErrorKind::TxParseError(_) |
+            DbErrorKind::BadEDNValuePair(_, _) |
+            DbErrorKind::BadSchemaAssertion(_) |
+            DbErrorKind::UnrecognizedIdent(_) |
+            DbErrorKind::UnrecognizedEntid(_) |
+            DbErrorKind::UnknownAttribute(_) => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+impl From<MentatError> for ApiError {
+    fn from(error: MentatError) -> ApiError {
+        if is_bad_request(error.kind()) {
+            ApiError::BadRequest(error.to_string())
+        } else {
+            ApiError::Internal(error.to_string())
+        }
+    }
+}
+
+pub type Result<T> = ::std::result::Result<T, ApiError>;
+
+impl ApiError {
+    /// The body of an error response: `{:error "message"}`.
+    pub fn to_edn_value(&self) -> edn::Value {
+        let mut map = BTreeMap::new();
+        map.insert(keyword("error"), edn::Value::Text(self.to_string()));
+        edn::Value::Map(map)
+    }
+}
+
+fn keyword(name: &str) -> edn::Value {
+    edn::Value::Keyword(Keyword::new(name))
+}
+
+/// Looks up `name` in a request map, which may use either a keyword key like `:query` or, for
+/// JSON clients, a string key like `"query"`.
+fn get<'a>(map: &'a BTreeMap<edn::Value, edn::Value>, name: &str) -> Option<&'a edn::Value> {
+    map.get(&keyword(name)).or_else(|| map.get(&edn::Value::Text(name.to_string())))
+}
+
+fn typed_value_as_edn(value: &TypedValue) -> edn::Value {
+    value.to_edn_value_pair().0
+}
+
+/// Names an entity by its ident, if it has one.
+fn ident_or_entid(schema: &Schema, e: Entid) -> edn::Value {
+    schema.get_ident(e)
+          .map(|ident| edn::Value::NamespacedKeyword(ident.clone()))
+          .unwrap_or(edn::Value::Integer(e))
+}
+
+/// Turns the `:inputs` of a query request, a map from variables like `?name` to values, into
+/// `QueryInputs`.  Variables may be symbols or, for JSON clients, strings.
+fn query_inputs(inputs: &edn::Value) -> Result<QueryInputs> {
+    let inputs = match inputs.as_map() {
+        Some(inputs) => inputs,
+        None => return Err(ApiError::BadRequest(format!("Expected a map of inputs, got {}", inputs))),
+    };
+    let mut values = Vec::with_capacity(inputs.len());
+    for (name, value) in inputs {
+        let symbol = match *name {
+            edn::Value::Text(ref text) => edn::parse::value(text).ok().map(|v| v.without_spans()),
+            ref name => Some(name.clone()),
+        };
+        let var = match symbol {
+            Some(edn::Value::PlainSymbol(ref symbol)) => Variable::from_symbol(symbol),
+            _ => None,
+        };
+        let var = var.ok_or_else(|| ApiError::BadRequest(format!("Expected a variable like ?name, got {}", name)))?;
+        let value = TypedValue::from_edn_value(value)
+            .ok_or_else(|| ApiError::BadRequest(format!("{} is not a value that can be bound to {}", value, name)))?;
+        values.push((var, value));
+    }
+    Ok(QueryInputs::with_value_sequence(values))
+}
+
+/// Runs a query.  `request` is either the query itself, or a map with a `:query` and optional
+/// `:inputs`; the query may be given as EDN or as a string.  The results keep the shape of the
+/// query's find spec, like `[[1 "a"] [2 "b"]]` for a relation.
+pub fn query(store: &Store, request: &edn::Value) -> Result<edn::Value> {
+    let (query, inputs) = match request.as_map() {
+        Some(map) => {
+            let query = get(map, "query").ok_or_else(|| ApiError::BadRequest("Missing :query".to_string()))?;
+            let inputs = match get(map, "inputs") {
+                Some(inputs) => Some(query_inputs(inputs)?),
+                None => None,
+            };
+            (query, inputs)
+        },
+        None => (request, None),
+    };
+    let query = match *query {
+        edn::Value::Text(ref text) => text.clone(),
+        ref form => form.to_string(),
+    };
+
+    let vector = |vs: Vec<TypedValue>| edn::Value::Vector(vs.iter().map(typed_value_as_edn).collect());
+    let results = match store.q_once(query.as_str(), inputs)?.results {
+        QueryResults::Scalar(v) => v.as_ref().map(typed_value_as_edn).unwrap_or(edn::Value::Nil),
+        QueryResults::Tuple(vv) => vv.map(&vector).unwrap_or(edn::Value::Nil),
+        QueryResults::Coll(vv) => vector(vv),
+        QueryResults::Rel(vvv) => edn::Value::Vector(vvv.into_iter().map(&vector).collect()),
+    };
+    Ok(results)
+}
+
+/// Describes a committed transaction: `{:tx-id 268435457 :tx-instant #inst "…" :tempids {"a" 65536}}`.
+pub fn tx_report(report: &TxReport) -> edn::Value {
+    let tempids = report.tempids
+                        .iter()
+                        .map(|(tempid, e)| (edn::Value::Text(tempid.clone()), edn::Value::Integer(*e)))
+                        .collect();
+    let mut map = BTreeMap::new();
+    map.insert(keyword("tx-id"), edn::Value::Integer(report.tx_id));
+    map.insert(keyword("tx-instant"), edn::Value::Instant(report.tx_instant));
+    map.insert(keyword("tempids"), edn::Value::Map(tempids));
+    edn::Value::Map(map)
+}
+
+/// Describes a transaction to a subscriber: like `tx_report`, with the `:datoms` it asserted and
+/// retracted, each as `[e a v tx added]`.
+pub fn tx_committed(schema: &Schema, committed: &TxCommitted) -> edn::Value {
+    let datoms = committed.datoms.iter().map(|datom| {
+        let v = match datom.v {
+            TypedValue::Ref(v) => ident_or_entid(schema, v),
+            ref v => typed_value_as_edn(v),
+        };
+        edn::Value::Vector(vec![edn::Value::Integer(datom.e),
+                                ident_or_entid(schema, datom.a),
+                                v,
+                                edn::Value::Integer(datom.tx),
+                                edn::Value::Boolean(datom.added)])
+    }).collect();
+    let mut value = tx_report(&committed.report);
+    if let edn::Value::Map(ref mut map) = value {
+        map.insert(keyword("datoms"), edn::Value::Vector(datoms));
+    }
+    value
+}
+
+/// Resolves a subscription's attributes, given as idents separated by commas or spaces, like
+/// `:person/name,:person/friend`.
+pub fn attributes(store: &Store, names: &str) -> Result<BTreeSet<Entid>> {
+    let schema = store.conn().current_schema();
+    names.split(|c: char| c == ',' || c.is_whitespace())
+         .filter(|name| !name.is_empty())
+         .map(|name| {
+             let e = resolve_entity(&schema, name)?;
+             if schema.attribute_for_entid(e).is_none() {
+                 return Err(ApiError::BadRequest(format!("{} is not an attribute", name)));
+             }
+             Ok(e)
+         })
+         .collect()
+}
+
+/// Transacts `transaction`, a vector of entities, and commits it.
+pub fn transact(store: &mut Store, transaction: &edn::Value) -> Result<edn::Value> {
+    if !transaction.is_vector() {
+        return Err(ApiError::BadRequest(format!("Expected a vector of entities, got {}", transaction)));
+    }
+    let mut in_progress = store.begin_transaction()?;
+    let report = in_progress.transact(transaction.to_string().as_str())?;
+    in_progress.commit()?;
+    Ok(tx_report(&report))
+}
+
+/// The current schema, as it is stored.
+pub fn schema(store: &Store) -> edn::Value {
+    store.conn().current_schema().to_edn_value()
+}
+
+/// Resolves `id`, an entid like `65536` or an ident like `:foo/bar` or `foo/bar`, to an entid.
+fn resolve_entity(schema: &Schema, id: &str) -> Result<Entid> {
+    if let Ok(e) = id.parse::<Entid>() {
+        return Ok(e);
+    }
+    let name = id.trim_left_matches(':');
+    let ident = match name.find('/') {
+        Some(i) if i > 0 && i < name.len() - 1 => NamespacedKeyword::new(&name[..i], &name[i + 1..]),
+        _ => return Err(ApiError::BadRequest(format!("Expected an entid or an ident, got {:?}", id))),
+    };
+    schema.get_entid(&ident)
+          .map(|e| e.into())
+          .ok_or_else(|| ApiError::NotFound(format!("No entity found for {}", id)))
+}
+
+/// Every attribute of the entity `id`, as a map like `{:db/id 65536 :person/name "Alice"}`.
+/// Cardinality-many attributes map to vectors, and refs to idents where the referenced entity has
+/// one.
+pub fn entity(store: &Store, id: &str) -> Result<edn::Value> {
+    let schema = store.conn().current_schema();
+    let e = resolve_entity(&schema, id)?;
+    let datoms = store.datoms(DatomIndex::EAVT, &DatomPattern { e: Some(e), ..Default::default() })?;
+    if datoms.is_empty() {
+        return Err(ApiError::NotFound(format!("No entity found for {}", id)));
+    }
+
+    let mut map = BTreeMap::new();
+    map.insert(edn::Value::NamespacedKeyword(NamespacedKeyword::new("db", "id")), edn::Value::Integer(e));
+    for datom in datoms {
+        let a = ident_or_entid(&schema, datom.a);
+        let v = match datom.v {
+            TypedValue::Ref(v) => ident_or_entid(&schema, v),
+            ref v => typed_value_as_edn(v),
+        };
+        if schema.attribute_for_entid(datom.a).map_or(false, |attribute| attribute.multival) {
+            if let edn::Value::Vector(ref mut vs) = *map.entry(a).or_insert(edn::Value::Vector(vec![])) {
+                vs.push(v);
+            }
+        } else {
+            map.insert(a, v);
+        }
+    }
+    Ok(edn::Value::Map(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> Store {
+        let mut store = Store::open("").expect("opened");
+        transact(&mut store, &edn::parse::value(r#"[
+            {:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one :db/unique :db.unique/identity}
+            {:db/ident :person/friend :db/valueType :db.type/ref :db/cardinality :db.cardinality/many}
+        ]"#).unwrap().without_spans()).expect("schema");
+        store
+    }
+
+    fn parse(text: &str) -> edn::Value {
+        edn::parse::value(text).expect("EDN").without_spans()
+    }
+
+    #[test]
+    fn test_query_and_transact() {
+        let mut store = store();
+        let report = transact(&mut store, &parse(r#"[{:db/id "a" :person/name "Alice"} {:db/id "b" :person/name "Bob" :person/friend "a"}]"#)).expect("transacted");
+        let alice = get(report.as_map().unwrap(), "tempids").and_then(|t| t.as_map()).and_then(|t| t.get(&edn::Value::Text("a".to_string()))).cloned().expect("tempid");
+
+        // A bare query.
+        let names = query(&store, &parse(r#"[:find [?name ...] :where [_ :person/name ?name]]"#)).expect("queried");
+        assert_eq!(names, parse(r#"["Alice" "Bob"]"#));
+
+        // A query with inputs, as a JSON client would send it.
+        let request = parse(r#"{"query" "[:find ?e . :in ?name :where [?e :person/name ?name]]" "inputs" {"?name" "Alice"}}"#);
+        assert_eq!(query(&store, &request).expect("queried"), alice);
+
+        match query(&store, &parse(r#"{:inputs {?name "Alice"}}"#)) {
+            Err(ApiError::BadRequest(message)) => assert_eq!(message, "Missing :query"),
+            result => panic!("expected a bad request, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_error_status() {
+        let mut store = store();
+        let bad_requests = vec![
+            query(&store, &parse(r#""[:find ?e :where""#)).map(|_| ()),
+            transact(&mut store, &parse(r#"[[:db/add "a" :person/name 42]]"#)).map(|_| ()),
+            transact(&mut store, &parse(r#"[[:db/add "a" :person/age 42]]"#)).map(|_| ()),
+        ];
+        for result in bad_requests {
+            match result {
+                Err(ApiError::BadRequest(_)) => (),
+                result => panic!("expected a bad request, got {:?}", result),
+            }
+        }
+
+        // Anything else is the server's fault.
+        match ApiError::from(MentatError::from(MentatErrorKind::PathAlreadyExists("/tmp".to_string()))) {
+            ApiError::Internal(_) => (),
+            error => panic!("expected an internal error, got {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_entity() {
+        let mut store = store();
+        let report = transact(&mut store, &parse(r#"[{:db/id "a" :person/name "Alice"} {:db/id "b" :person/name "Bob" :person/friend "a"}]"#)).expect("transacted");
+        let tempids = get(report.as_map().unwrap(), "tempids").and_then(|t| t.as_map()).cloned().expect("tempids");
+        let alice = tempids[&edn::Value::Text("a".to_string())].clone();
+        let bob = tempids[&edn::Value::Text("b".to_string())].as_integer().expect("entid");
+
+        let expected = format!(r#"{{:db/id {} :person/name "Bob" :person/friend [{}]}}"#, bob, alice);
+        assert_eq!(entity(&store, &bob.to_string()).expect("entity"), parse(&expected));
+
+        let attribute = entity(&store, ":person/name").expect("entity");
+        assert_eq!(attribute.as_map().unwrap().get(&parse(":db/valueType")), Some(&parse(":db.type/string")));
+
+        match entity(&store, "person/nobody") {
+            Err(ApiError::NotFound(_)) => (),
+            result => panic!("expected not found, got {:?}", result),
+        }
+        match entity(&store, "nonsense") {
+            Err(ApiError::BadRequest(_)) => (),
+            result => panic!("expected a bad request, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_tx_committed() {
+        let mut store = store();
+        let schema = store.conn().current_schema();
+        let name: Entid = schema.get_entid(&NamespacedKeyword::new("person", "name")).expect("entid").into();
+        let friend: Entid = schema.get_entid(&NamespacedKeyword::new("person", "friend")).expect("entid").into();
+        assert_eq!(attributes(&store, ":person/name, person/friend").expect("attributes"),
+                   vec![name, friend].into_iter().collect());
+        match attributes(&store, ":person/name,:db.type/string") {
+            Err(ApiError::BadRequest(_)) => (),
+            result => panic!("expected a bad request, got {:?}", result),
+        }
+
+        let report = {
+            let mut in_progress = store.begin_transaction().expect("began");
+            let report = in_progress.transact(r#"[{:db/id "a" :person/name "Alice" :person/friend :person/name}]"#).expect("transacted");
+            in_progress.commit().expect("committed");
+            report
+        };
+        let alice = report.tempids["a"];
+        let committed = TxCommitted {
+            report: report.clone(),
+            datoms: store.transaction_datoms(report.tx_id).expect("datoms").into_iter().filter(|d| d.e == alice).collect(),
+        };
+        let value = tx_committed(&store.conn().current_schema(), &committed);
+        let datoms = value.as_map().and_then(|map| map.get(&keyword("datoms"))).cloned().expect("datoms");
+        let expected = format!(r#"[[{alice} :person/name "Alice" {tx} true] [{alice} :person/friend :person/name {tx} true]]"#,
+                               alice=alice, tx=report.tx_id);
+        assert_eq!(datoms, parse(&expected));
+        assert_eq!(value.as_map().and_then(|map| map.get(&keyword("tx-id"))), Some(&edn::Value::Integer(report.tx_id)));
+    }
+}
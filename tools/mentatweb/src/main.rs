@@ -13,14 +13,415 @@ extern crate clap;
 #[macro_use]
 extern crate nickel;
 
+extern crate edn;
 extern crate mentat;
+extern crate mentat_db;
 
+use std::collections::BTreeMap;
+use std::io::{
+    Read,
+    Write,
+};
+use std::path::PathBuf;
 use std::u16;
 use std::str::FromStr;
+use std::sync::{
+    Arc,
+    Mutex,
+    MutexGuard,
+};
+use std::sync::atomic::{
+    AtomicUsize,
+    ATOMIC_USIZE_INIT,
+    Ordering,
+};
+use std::sync::mpsc::{
+    self,
+    RecvTimeoutError,
+};
+use std::time::Duration;
 
 use clap::{App, Arg, SubCommand, AppSettings};
 
-use nickel::{Nickel, HttpRouter};
+use nickel::{
+    Action,
+    HttpRouter,
+    MediaType,
+    MiddlewareResult,
+    Nickel,
+    Options,
+    QueryString,
+    Request,
+    Response,
+};
+use nickel::hyper::header::{
+    Accept,
+    ContentType,
+};
+use nickel::hyper::mime::{
+    Mime,
+    SubLevel,
+};
+use nickel::status::StatusCode;
+
+use edn::serde_support::json;
+
+use mentat::{
+    Store,
+    TxCommitted,
+    TxObserver,
+    Uuid,
+};
+
+mod api;
+mod sync_server;
+
+use api::ApiError;
+use sync_server::{
+    SyncServer,
+    Transaction,
+};
+
+/// The state shared by every request.
+struct ServerData {
+    store: Mutex<Store>,
+    /// Serves Tolstoy's sync protocol under `/sync`, if the server was given a directory to keep
+    /// transaction logs in.
+    sync: Option<Mutex<SyncServer>>,
+    /// How many clients are subscribed to `/subscribe`.
+    subscriptions: AtomicUsize,
+}
+
+impl ServerData {
+    fn new(store: Store, sync: Option<SyncServer>) -> ServerData {
+        ServerData {
+            store: Mutex::new(store),
+            sync: sync.map(Mutex::new),
+            subscriptions: AtomicUsize::new(0),
+        }
+    }
+
+    fn store(&self) -> MutexGuard<Store> {
+        self.store.lock().unwrap()
+    }
+
+    fn sync(&self) -> api::Result<MutexGuard<SyncServer>> {
+        match self.sync {
+            Some(ref sync) => Ok(sync.lock().unwrap()),
+            None => Err(ApiError::NotFound("This server doesn't serve sync; start it with --sync-dir".to_string())),
+        }
+    }
+
+    /// Takes one of the `MAX_SUBSCRIPTIONS` subscriptions, if there's one free.
+    fn claim_subscription(&self) -> bool {
+        if self.subscriptions.fetch_add(1, Ordering::SeqCst) < MAX_SUBSCRIPTIONS {
+            return true;
+        }
+        self.subscriptions.fetch_sub(1, Ordering::SeqCst);
+        false
+    }
+
+    fn release_subscription(&self) {
+        self.subscriptions.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Whether to respond with JSON rather than EDN: either `?format=json`, or an `Accept` header that
+/// asks for `application/json`.
+fn wants_json(req: &mut Request<ServerData>) -> bool {
+    if let Some(format) = req.query().get("format") {
+        return format == "json";
+    }
+    req.origin.headers.get::<Accept>().map_or(false, |accept| {
+        accept.iter().any(|quality_item| quality_item.item.1 == SubLevel::Json)
+    })
+}
+
+fn read_text(req: &mut Request<ServerData>) -> api::Result<String> {
+    let mut body = String::new();
+    req.origin.read_to_string(&mut body).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    Ok(body)
+}
+
+fn read_json(req: &mut Request<ServerData>) -> api::Result<edn::Value> {
+    json::from_json_str(&read_text(req)?).map_err(|e| ApiError::BadRequest(format!("Invalid JSON: {}", e)))
+}
+
+/// Reads the body of `req` as EDN, or as JSON if it is sent as `application/json`.
+fn read_body(req: &mut Request<ServerData>) -> api::Result<edn::Value> {
+    let is_json = req.origin.headers.get::<ContentType>().map_or(false, |content_type| (content_type.0).1 == SubLevel::Json);
+    if is_json {
+        return read_json(req);
+    }
+    let body = read_text(req)?;
+    edn::parse::value(&body).map(|v| v.without_spans()).map_err(|e| ApiError::BadRequest(format!("Invalid EDN: {}", e)))
+}
+
+fn error_status(error: &ApiError) -> StatusCode {
+    match *error {
+        ApiError::BadRequest(_) => StatusCode::BadRequest,
+        ApiError::NotFound(_) => StatusCode::NotFound,
+        ApiError::Internal(_) => StatusCode::InternalServerError,
+        ApiError::Unavailable(_) => StatusCode::ServiceUnavailable,
+    }
+}
+
+/// Sends `result` as EDN or JSON, as the client prefers.
+fn respond<'mw>(req: &mut Request<ServerData>, mut res: Response<'mw, ServerData>, result: api::Result<edn::Value>) -> MiddlewareResult<'mw, ServerData> {
+    let (status, value) = match result {
+        Ok(value) => (StatusCode::Ok, value),
+        Err(ref e) => (error_status(e), e.to_edn_value()),
+    };
+    res.set(status);
+    if wants_json(req) {
+        res.set(MediaType::Json);
+        res.send(json::to_json_string(&value))
+    } else {
+        res.set(ContentType("application/edn".parse::<Mime>().unwrap()));
+        res.send(value.to_string())
+    }
+}
+
+fn post_query<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let result = read_body(req).and_then(|request| {
+        let store = req.server_data().store();
+        api::query(&store, &request)
+    });
+    respond(req, res, result)
+}
+
+fn post_transact<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let result = read_body(req).and_then(|transaction| {
+        let mut store = req.server_data().store();
+        api::transact(&mut store, &transaction)
+    });
+    respond(req, res, result)
+}
+
+fn get_schema<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let result = Ok(api::schema(&req.server_data().store()));
+    respond(req, res, result)
+}
+
+fn get_entity<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let id = req.param("id").unwrap_or("").to_string();
+    let result = api::entity(&req.server_data().store(), &id);
+    respond(req, res, result)
+}
+
+/// Sends the result of a sync request.  Tolstoy's client only speaks JSON, and expects a particular
+/// status on success: `body` is the JSON to send, if any.
+fn respond_sync<'mw>(mut res: Response<'mw, ServerData>, success: StatusCode, result: api::Result<Option<String>>) -> MiddlewareResult<'mw, ServerData> {
+    let (status, body) = match result {
+        Ok(body) => (success, body.unwrap_or_default()),
+        Err(ref e) => (error_status(e), json::to_json_string(&e.to_edn_value())),
+    };
+    res.set(status);
+    res.set(MediaType::Json);
+    res.send(body)
+}
+
+fn sync_param(req: &Request<ServerData>, name: &str) -> api::Result<Uuid> {
+    sync_server::parse_uuid(req.param(name).unwrap_or(""))
+}
+
+fn get_sync_head<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let result = sync_param(req, "user").and_then(|user| {
+        let head = req.server_data().sync()?.head(&user)?;
+        let mut map = BTreeMap::new();
+        map.insert(edn::Value::Text("head".to_string()), sync_server::uuid_value(&head));
+        Ok(Some(json::to_json_string(&edn::Value::Map(map))))
+    });
+    respond_sync(res, StatusCode::Ok, result)
+}
+
+fn put_sync_head<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let result = sync_param(req, "user").and_then(|user| {
+        let body = read_json(req)?;
+        let head = body.as_map()
+                       .and_then(|map| map.get(&edn::Value::Text("head".to_string())))
+                       .and_then(|head| head.as_text())
+                       .ok_or_else(|| ApiError::BadRequest(format!("Expected {{\"head\": <uuid>}}, got {}", body)))
+                       .and_then(|head| sync_server::parse_uuid(head))?;
+        req.server_data().sync()?.put_head(&user, &head)?;
+        Ok(None)
+    });
+    respond_sync(res, StatusCode::NoContent, result)
+}
+
+fn get_sync_transactions<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let result = sync_param(req, "user").and_then(|user| {
+        let from = match req.query().get("from") {
+            Some(from) => sync_server::parse_uuid(from)?,
+            None => Uuid::nil(),
+        };
+        let transactions = req.server_data().sync()?.transactions_after(&user, &from)?;
+        let mut map = BTreeMap::new();
+        map.insert(edn::Value::Text("transactions".to_string()),
+                   edn::Value::Vector(transactions.iter().map(sync_server::uuid_value).collect()));
+        Ok(Some(json::to_json_string(&edn::Value::Map(map))))
+    });
+    respond_sync(res, StatusCode::Ok, result)
+}
+
+fn get_sync_transaction<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let result = sync_param(req, "user").and_then(|user| {
+        let tx = sync_param(req, "uuid")?;
+        let transaction = req.server_data().sync()?.transaction(&user, &tx)?;
+        Ok(Some(json::to_json_string(&transaction.to_edn_value())))
+    });
+    respond_sync(res, StatusCode::Ok, result)
+}
+
+fn put_sync_transaction<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let result = sync_param(req, "user").and_then(|user| {
+        let tx = sync_param(req, "uuid")?;
+        let transaction = Transaction::from_edn_value(&read_json(req)?)?;
+        req.server_data().sync()?.put_transaction(&user, &tx, &transaction)?;
+        Ok(None)
+    });
+    respond_sync(res, StatusCode::Created, result)
+}
+
+fn get_sync_chunk<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let result = sync_param(req, "user").and_then(|user| {
+        let chunk = sync_param(req, "uuid")?;
+        let payload = req.server_data().sync()?.chunk(&user, &chunk)?;
+        Ok(Some(payload))
+    });
+    respond_sync(res, StatusCode::Ok, result)
+}
+
+/// Chunks are stored as uploaded; the server doesn't need to understand them.
+fn put_sync_chunk<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let result = sync_param(req, "user").and_then(|user| {
+        let chunk = sync_param(req, "uuid")?;
+        let payload = read_text(req)?;
+        req.server_data().sync()?.put_chunk(&user, &chunk, &payload)?;
+        Ok(None)
+    });
+    respond_sync(res, StatusCode::Created, result)
+}
+
+fn server(data: ServerData) -> Nickel<ServerData> {
+    let mut server = Nickel::with_data(data);
+    // Each subscriber keeps a thread for as long as it's connected.
+    server.options = Options::default().thread_count(Some(MAX_SUBSCRIPTIONS + REQUEST_THREADS));
+    server.get("/", middleware!("Mentat: POST /query or /transact, or GET /schema, /entity/:id or /subscribe"));
+    server.post("/query", post_query);
+    server.post("/transact", post_transact);
+    server.get("/schema", get_schema);
+    server.get("/entity/:id", get_entity);
+    server.get("/subscribe", get_subscribe);
+
+    // Tolstoy's protocol, relative to a server URI of `/sync`.
+    server.get("/sync/:user/head", get_sync_head);
+    server.put("/sync/:user/head", put_sync_head);
+    server.get("/sync/:user/transactions", get_sync_transactions);
+    server.get("/sync/:user/transactions/:uuid", get_sync_transaction);
+    server.put("/sync/:user/transactions/:uuid", put_sync_transaction);
+    server.get("/sync/:user/chunks/:uuid", get_sync_chunk);
+    server.put("/sync/:user/chunks/:uuid", put_sync_chunk);
+    server
+}
+
+/// Each subscription registers an observer under its own key.
+static NEXT_SUBSCRIPTION: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// How many clients may subscribe at once.  Each subscriber holds one of the server's threads until
+/// it disconnects, so further subscribers are turned away with 503 Service Unavailable.
+const MAX_SUBSCRIPTIONS: usize = 16;
+
+/// How many threads are left to serve other requests when every subscription is taken.
+const REQUEST_THREADS: usize = 8;
+
+/// How often to write to an idle subscription, so that we notice when its client goes away.
+const SUBSCRIPTION_KEEPALIVE_SECS: u64 = 15;
+
+/// Formats a server-sent event, whose data may span several lines.
+fn sse_event(event: &str, data: &str) -> String {
+    let mut text = format!("event: {}\n", event);
+    for line in data.lines() {
+        text.push_str("data: ");
+        text.push_str(line);
+        text.push('\n');
+    }
+    text.push('\n');
+    text
+}
+
+/// Streams committed transactions as server-sent events named `transaction`, each described as
+/// by `api::tx_committed`, in EDN or JSON.  `?attributes=:a/b,:c/d` limits the stream to
+/// transactions that touch those attributes, and their datoms to those attributes' datoms.  At most
+/// `MAX_SUBSCRIPTIONS` clients may subscribe at once.
+fn get_subscribe<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    if !req.server_data().claim_subscription() {
+        let error = ApiError::Unavailable(format!("This server already has {} subscribers", MAX_SUBSCRIPTIONS));
+        return respond(req, res, Err(error));
+    }
+    let result = subscribe(req, res);
+    req.server_data().release_subscription();
+    result
+}
+
+fn subscribe<'mw, 'conn>(req: &mut Request<'mw, 'conn, ServerData>, mut res: Response<'mw, ServerData>) -> MiddlewareResult<'mw, ServerData> {
+    let json = wants_json(req);
+    let names = req.query().get("attributes").map(|names| names.to_string());
+    let key = format!("mentatweb-subscription-{}", NEXT_SUBSCRIPTION.fetch_add(1, Ordering::SeqCst));
+
+    // Observers must be `Sync`, and so can't hold a bare `Sender`.
+    let (sender, receiver) = mpsc::channel::<Vec<TxCommitted>>();
+    let sender = Mutex::new(sender);
+    let notify = move |_key: &str, committed: &[TxCommitted]| {
+        let _ = sender.lock().unwrap().send(committed.to_vec());
+    };
+    let registered = {
+        let store = req.server_data().store();
+        let observer = match names {
+            Some(ref names) => api::attributes(&store, names).map(|attributes| TxObserver::new(attributes, notify)),
+            None => Ok(TxObserver::all(notify)),
+        };
+        observer.map(|observer| store.register_observer(key.clone(), Arc::new(observer)))
+    };
+    if let Err(e) = registered {
+        return respond(req, res, Err(e));
+    }
+
+    res.set(ContentType("text/event-stream".parse::<Mime>().unwrap()));
+    let mut stream = match res.start() {
+        Ok(stream) => stream,
+        Err(e) => {
+            req.server_data().store().unregister_observer(&key);
+            return Err(e);
+        },
+    };
+    // Send the headers now, rather than with the first event, so the client knows it's subscribed.
+    if stream.write_all(b":\n\n").and_then(|_| stream.flush()).is_err() {
+        req.server_data().store().unregister_observer(&key);
+        return Ok(Action::Halt(stream));
+    }
+    loop {
+        let text = match receiver.recv_timeout(Duration::from_secs(SUBSCRIPTION_KEEPALIVE_SECS)) {
+            Ok(committed) => {
+                let schema = req.server_data().store().conn().current_schema();
+                committed.iter().map(|committed| {
+                    let value = api::tx_committed(&schema, committed);
+                    let data = if json { json::to_json_string(&value) } else { value.to_string() };
+                    sse_event("transaction", &data)
+                }).collect()
+            },
+            // A comment, which clients ignore.
+            Err(RecvTimeoutError::Timeout) => ":\n\n".to_string(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if stream.write_all(text.as_bytes()).and_then(|_| stream.flush()).is_err() {
+            // The client went away.
+            break;
+        }
+    }
+    req.server_data().store().unregister_observer(&key);
+    Ok(Action::Halt(stream))
+}
 
 fn main() {
     let app = App::new("Mentat").setting(AppSettings::ArgRequiredElseHelp);
@@ -36,6 +437,11 @@ fn main() {
                 .help("Path to the Mentat database to serve")
                 .default_value("")
                 .takes_value(true))
+            .arg(Arg::with_name("sync-dir")
+                .long("sync-dir")
+                .value_name("DIR")
+                .help("Directory in which to store synced transactions; serves sync from `localhost:PORT/sync`")
+                .takes_value(true))
             .arg(Arg::with_name("port")
                 .short("p")
                 .long("port")
@@ -46,16 +452,114 @@ fn main() {
         .get_matches();
     if let Some(ref matches) = matches.subcommand_matches("serve") {
         let debug = matches.is_present("debug");
+        let database = matches.value_of("database").unwrap();
         let port = u16::from_str(matches.value_of("port").unwrap()).expect("Port must be an integer");
         if debug {
-            println!("This doesn't do anything yet, but it will eventually serve up the following database: {} \
-                      on port: {}.",
-                     matches.value_of("database").unwrap(),
-                     matches.value_of("port").unwrap());
+            println!("Serving database {:?} on port {}.", database, port);
         }
 
-        let mut server = Nickel::new();
-        server.get("/", middleware!("This doesn't do anything yet"));
+        let sync_dir = matches.value_of("sync-dir").map(PathBuf::from);
+        if debug {
+            if let Some(ref dir) = sync_dir {
+                println!("Serving sync from {:?}.", dir);
+            }
+        }
+
+        let store = Store::open(database).expect("Failed to open database");
+        let server = server(ServerData::new(store, sync_dir.map(SyncServer::new)));
         server.listen(("127.0.0.1", port)).expect("Failed to launch server");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::net::{
+        SocketAddr,
+        TcpStream,
+    };
+
+    use mentat::{
+        SyncOutcome,
+        Syncable,
+    };
+
+    /// Sends a bare HTTP request, and reads the response up to the end of its headers.
+    fn request(socket: SocketAddr, request: &str) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(socket).expect("connected");
+        stream.write_all(request.as_bytes()).expect("sent");
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).expect("read");
+            head.push(byte[0]);
+        }
+        (stream, String::from_utf8(head).expect("headers"))
+    }
+
+    #[test]
+    fn test_query_while_subscribed() {
+        let listening = server(ServerData::new(Store::open("").expect("opened"), None))
+            .listen(("127.0.0.1", 0)).expect("listening");
+        let socket = listening.socket();
+        listening.detach();
+
+        // Subscribers hold their threads until they disconnect.
+        let subscribers: Vec<(TcpStream, String)> = (0..MAX_SUBSCRIPTIONS).map(|_| {
+            request(socket, "GET /subscribe HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        }).collect();
+        for &(_, ref head) in subscribers.iter() {
+            assert!(head.starts_with("HTTP/1.1 200"), "subscribed: {}", head);
+        }
+
+        // Queries are still served.
+        let query = "[:find ?e . :where [?e :db/ident :db/ident]]";
+        let (mut stream, head) = request(socket, &format!("POST /query HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", query.len(), query));
+        assert!(head.starts_with("HTTP/1.1 200"), "queried: {}", head);
+        let mut body = String::new();
+        stream.read_to_string(&mut body).expect("read");
+        assert_eq!(body, "1");
+
+        // But there's no room for another subscriber.
+        let (_, head) = request(socket, "GET /subscribe HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 503"), "turned away: {}", head);
+    }
+
+    #[test]
+    fn test_sync_end_to_end() {
+        let dir = env::temp_dir().join(format!("mentatweb-sync-end-to-end-{}", Uuid::new_v4()));
+        let store = Store::open("").expect("opened");
+        let listening = server(ServerData::new(store, Some(SyncServer::new(dir.clone()))))
+            .listen(("127.0.0.1", 0)).expect("listening");
+        let server_uri = format!("http://{}/sync", listening.socket());
+        listening.detach();
+
+        let mut store = Store::open("").expect("opened");
+        {
+            let mut in_progress = store.begin_transaction().expect("began");
+            in_progress.transact(r#"[{:db/ident :person/name :db/valueType :db.type/string :db/cardinality :db.cardinality/one}]"#).expect("schema");
+            in_progress.transact(r#"[{:person/name "Alice"} {:person/name "Bob"}]"#).expect("data");
+            in_progress.commit().expect("committed");
+        }
+
+        let user = Uuid::new_v4();
+        let report = store.sync(&server_uri, &user.hyphenated().to_string()).expect("synced");
+        assert_eq!(report.outcome, SyncOutcome::FastForwardedServer);
+        assert!(report.uploaded_tx_count > 0);
+
+        // The server's log ends at the head the client uploaded.
+        let sync = SyncServer::new(dir.clone());
+        assert_eq!(sync.head(&user).expect("head"), report.remote_head_after);
+        let transactions = sync.transactions_after(&user, &Uuid::nil()).expect("transactions");
+        assert_eq!(transactions.len(), report.uploaded_tx_count);
+
+        // Nothing changed since, so there's nothing to do.
+        let report = store.sync(&server_uri, &user.hyphenated().to_string()).expect("synced");
+        assert_eq!(report.outcome, SyncOutcome::NoChanges);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,264 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License"); you may not use
+// this file except in compliance with the License. You may obtain a copy of the
+// License at http://www.apache.org/licenses/LICENSE-2.0
+// Unless required by applicable law or agreed to in writing, software distributed
+// under the License is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR
+// CONDITIONS OF ANY KIND, either express or implied. See the License for the
+// specific language governing permissions and limitations under the License.
+
+//! The server side of Tolstoy's sync protocol, storing each user's transaction log in a directory:
+//!
+//! ```text
+//! <root>/<user>/head                  the UUID of the user's head transaction
+//! <root>/<user>/transactions/<uuid>   {"parent": <uuid>, "chunks": [<uuid>, ...]}
+//! <root>/<user>/chunks/<uuid>         a chunk's payload, as uploaded
+//! ```
+//!
+//! A user's transactions form a chain from their head back to the nil UUID, through each
+//! transaction's parent.
+
+use std::collections::BTreeMap;
+use std::fs::{
+    self,
+    File,
+};
+use std::io::{
+    self,
+    Read,
+    Write,
+};
+use std::path::PathBuf;
+
+use edn;
+use edn::serde_support::json;
+
+use mentat::Uuid;
+
+use api::{
+    ApiError,
+    Result,
+};
+
+impl From<io::Error> for ApiError {
+    fn from(error: io::Error) -> ApiError {
+        ApiError::Internal(error.to_string())
+    }
+}
+
+/// A transaction as stored: its parent, and the chunks that make it up.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Transaction {
+    pub parent: Uuid,
+    pub chunks: Vec<Uuid>,
+}
+
+impl Transaction {
+    pub fn to_edn_value(&self) -> edn::Value {
+        let mut map = BTreeMap::new();
+        map.insert(edn::Value::Text("parent".to_string()), uuid_value(&self.parent));
+        map.insert(edn::Value::Text("chunks".to_string()), edn::Value::Vector(self.chunks.iter().map(uuid_value).collect()));
+        edn::Value::Map(map)
+    }
+
+    /// Reads `{"parent": <uuid>, "chunks": [<uuid>, ...]}`.
+    pub fn from_edn_value(value: &edn::Value) -> Result<Transaction> {
+        let map = value.as_map().ok_or_else(|| ApiError::BadRequest(format!("Expected a transaction, got {}", value)))?;
+        let parent = map.get(&edn::Value::Text("parent".to_string()))
+                        .ok_or_else(|| ApiError::BadRequest("Missing parent".to_string()))
+                        .and_then(parse_uuid_value)?;
+        let chunks = match map.get(&edn::Value::Text("chunks".to_string())).and_then(|chunks| chunks.as_vector()) {
+            Some(chunks) => chunks.iter().map(parse_uuid_value).collect::<Result<Vec<_>>>()?,
+            None => return Err(ApiError::BadRequest("Missing chunks".to_string())),
+        };
+        Ok(Transaction {
+            parent: parent,
+            chunks: chunks,
+        })
+    }
+}
+
+/// UUIDs are exchanged as JSON strings.
+pub fn uuid_value(uuid: &Uuid) -> edn::Value {
+    edn::Value::Text(uuid.hyphenated().to_string())
+}
+
+pub fn parse_uuid(text: &str) -> Result<Uuid> {
+    Uuid::parse_str(text).map_err(|_| ApiError::BadRequest(format!("Invalid UUID {:?}", text)))
+}
+
+fn parse_uuid_value(value: &edn::Value) -> Result<Uuid> {
+    match value.as_text() {
+        Some(text) => parse_uuid(text),
+        None => Err(ApiError::BadRequest(format!("Expected a UUID, got {}", value))),
+    }
+}
+
+pub struct SyncServer {
+    root: PathBuf,
+}
+
+impl SyncServer {
+    pub fn new<P>(root: P) -> SyncServer where P: Into<PathBuf> {
+        SyncServer {
+            root: root.into(),
+        }
+    }
+
+    fn user_dir(&self, user: &Uuid) -> PathBuf {
+        self.root.join(user.hyphenated().to_string())
+    }
+
+    fn transaction_path(&self, user: &Uuid, tx: &Uuid) -> PathBuf {
+        self.user_dir(user).join("transactions").join(tx.hyphenated().to_string())
+    }
+
+    fn chunk_path(&self, user: &Uuid, chunk: &Uuid) -> PathBuf {
+        self.user_dir(user).join("chunks").join(chunk.hyphenated().to_string())
+    }
+
+    /// Writes `contents` to `path` by way of a temporary file, so that readers never see a
+    /// partially written file.
+    fn write(path: PathBuf, contents: &str) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temporary = path.with_extension("tmp");
+        File::create(&temporary)?.write_all(contents.as_bytes())?;
+        fs::rename(&temporary, &path)?;
+        Ok(())
+    }
+
+    fn read(path: PathBuf, what: &str) -> Result<String> {
+        let mut contents = String::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                file.read_to_string(&mut contents)?;
+                Ok(contents)
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Err(ApiError::NotFound(format!("No such {}", what))),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The user's head transaction, or the nil UUID if nothing has been uploaded.
+    pub fn head(&self, user: &Uuid) -> Result<Uuid> {
+        match SyncServer::read(self.user_dir(user).join("head"), "head") {
+            Ok(head) => parse_uuid(head.trim()),
+            Err(ApiError::NotFound(_)) => Ok(Uuid::nil()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Moves the user's head to `head`, which must be the nil UUID or an uploaded transaction.
+    pub fn put_head(&self, user: &Uuid, head: &Uuid) -> Result<()> {
+        if !head.is_nil() && !self.transaction_path(user, head).exists() {
+            return Err(ApiError::BadRequest(format!("Unknown transaction {}", head)));
+        }
+        SyncServer::write(self.user_dir(user).join("head"), &head.hyphenated().to_string())
+    }
+
+    pub fn put_chunk(&self, user: &Uuid, chunk: &Uuid, payload: &str) -> Result<()> {
+        SyncServer::write(self.chunk_path(user, chunk), payload)
+    }
+
+    pub fn chunk(&self, user: &Uuid, chunk: &Uuid) -> Result<String> {
+        SyncServer::read(self.chunk_path(user, chunk), "chunk")
+    }
+
+    /// Stores a transaction.  Its parent, if any, and its chunks must already have been uploaded.
+    pub fn put_transaction(&self, user: &Uuid, tx: &Uuid, transaction: &Transaction) -> Result<()> {
+        if !transaction.parent.is_nil() && !self.transaction_path(user, &transaction.parent).exists() {
+            return Err(ApiError::BadRequest(format!("Unknown parent transaction {}", transaction.parent)));
+        }
+        if let Some(chunk) = transaction.chunks.iter().find(|chunk| !self.chunk_path(user, chunk).exists()) {
+            return Err(ApiError::BadRequest(format!("Unknown chunk {}", chunk)));
+        }
+        SyncServer::write(self.transaction_path(user, tx), &json::to_json_string(&transaction.to_edn_value()))
+    }
+
+    pub fn transaction(&self, user: &Uuid, tx: &Uuid) -> Result<Transaction> {
+        let text = SyncServer::read(self.transaction_path(user, tx), "transaction")?;
+        let value = json::from_json_str(&text).map_err(|e| ApiError::Internal(e.to_string()))?;
+        Transaction::from_edn_value(&value)
+    }
+
+    /// The transactions after `from`, oldest first, up to and including the user's head.  `from`
+    /// may be the nil UUID, to list every transaction.
+    pub fn transactions_after(&self, user: &Uuid, from: &Uuid) -> Result<Vec<Uuid>> {
+        let mut transactions = vec![];
+        let mut tx = self.head(user)?;
+        while tx != *from {
+            if tx.is_nil() {
+                return Err(ApiError::NotFound(format!("Transaction {} is not an ancestor of the head", from)));
+            }
+            transactions.push(tx);
+            tx = self.transaction(user, &tx)?.parent;
+        }
+        transactions.reverse();
+        Ok(transactions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    /// A server in a directory of its own, so that tests running at the same time don't share it.
+    fn server(name: &str) -> SyncServer {
+        SyncServer::new(env::temp_dir().join(format!("mentatweb-sync-{}-{}", name, Uuid::new_v4())))
+    }
+
+    #[test]
+    fn test_transaction_chain() {
+        let server = server("chain");
+        let user = Uuid::new_v4();
+        assert_eq!(server.head(&user).expect("head"), Uuid::nil());
+
+        let (chunk, tx1, tx2) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        server.put_chunk(&user, &chunk, "{\"e\": 65536}").expect("put chunk");
+        assert_eq!(server.chunk(&user, &chunk).expect("chunk"), "{\"e\": 65536}");
+
+        let first = Transaction { parent: Uuid::nil(), chunks: vec![chunk] };
+        server.put_transaction(&user, &tx1, &first).expect("put transaction");
+        server.put_transaction(&user, &tx2, &Transaction { parent: tx1, chunks: vec![] }).expect("put transaction");
+        assert_eq!(server.transaction(&user, &tx1).expect("transaction"), first);
+
+        // The head only moves when asked.
+        assert_eq!(server.transactions_after(&user, &Uuid::nil()).expect("transactions"), vec![]);
+        server.put_head(&user, &tx2).expect("put head");
+        assert_eq!(server.head(&user).expect("head"), tx2);
+        assert_eq!(server.transactions_after(&user, &Uuid::nil()).expect("transactions"), vec![tx1, tx2]);
+        assert_eq!(server.transactions_after(&user, &tx1).expect("transactions"), vec![tx2]);
+
+        // Other users have their own logs.
+        assert_eq!(server.head(&Uuid::new_v4()).expect("head"), Uuid::nil());
+    }
+
+    #[test]
+    fn test_rejects_dangling_references() {
+        let server = server("dangling");
+        let user = Uuid::new_v4();
+        let tx = Uuid::new_v4();
+
+        match server.put_transaction(&user, &tx, &Transaction { parent: Uuid::new_v4(), chunks: vec![] }) {
+            Err(ApiError::BadRequest(_)) => (),
+            result => panic!("expected a bad request, got {:?}", result),
+        }
+        match server.put_transaction(&user, &tx, &Transaction { parent: Uuid::nil(), chunks: vec![Uuid::new_v4()] }) {
+            Err(ApiError::BadRequest(_)) => (),
+            result => panic!("expected a bad request, got {:?}", result),
+        }
+        match server.put_head(&user, &tx) {
+            Err(ApiError::BadRequest(_)) => (),
+            result => panic!("expected a bad request, got {:?}", result),
+        }
+        match server.chunk(&user, &Uuid::new_v4()) {
+            Err(ApiError::NotFound(_)) => (),
+            result => panic!("expected not found, got {:?}", result),
+        }
+    }
+}
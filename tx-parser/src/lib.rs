@@ -88,9 +88,20 @@ def_parser!(Tx, lookup_ref, LookupRef, {
             .map(|(a, v)| LookupRef { a: a, v: v.clone().without_spans() }))
 });
 
+def_matches_plain_symbol!(Tx, literal_tempid, "tempid");
+
+def_parser!(Tx, temp_id_in_partition, (TempId, edn::NamespacedKeyword), {
+    list().of_exactly(
+        Tx::literal_tempid()
+            .with((namespaced_keyword(),
+                   Tx::temp_id()))
+            .map(|(partition, temp_id)| (temp_id, partition.clone())))
+});
+
 def_parser!(Tx, entid_or_lookup_ref_or_temp_id, EntidOrLookupRefOrTempId, {
     Tx::db_tx().map(EntidOrLookupRefOrTempId::TempId)
         .or(Tx::entid().map(EntidOrLookupRefOrTempId::Entid))
+        .or(try(Tx::temp_id_in_partition()).map(|(temp_id, partition)| EntidOrLookupRefOrTempId::TempIdInPartition(temp_id, partition)))
         .or(Tx::lookup_ref().map(EntidOrLookupRefOrTempId::LookupRef))
         .or(Tx::temp_id().map(EntidOrLookupRefOrTempId::TempId))
 });
@@ -147,6 +158,52 @@ def_parser!(Tx, add_or_retract, Entity, {
             }))
 });
 
+def_matches_namespaced_keyword!(Tx, literal_db_cas, "db", "cas");
+
+def_parser!(Tx, cas, Entity, {
+    vector().of_exactly(
+        Tx::literal_db_cas()
+            .with((Tx::entid_or_lookup_ref_or_temp_id(),
+                   Tx::forward_entid(),
+                   Tx::atom(),
+                   Tx::atom_or_lookup_ref_or_vector()))
+            .map(|(e, a, old, new)| Entity::Cas { e: e, a: a, old: old.clone(), new: new }))
+});
+
+def_matches_namespaced_keyword!(Tx, literal_db_retract_attribute, "db", "retractAttribute");
+
+def_matches_namespaced_keyword!(Tx, literal_db_retract_entity, "db", "retractEntity");
+
+def_parser!(Tx, retract_attribute, Entity, {
+    vector().of_exactly(
+        Tx::literal_db_retract_attribute()
+            .with((Tx::entid_or_lookup_ref_or_temp_id(),
+                   Tx::forward_entid()))
+            .map(|(e, a)| Entity::RetractAttribute { e: e, a: a }))
+});
+
+def_parser!(Tx, retract_entity, Entity, {
+    vector().of_exactly(
+        Tx::literal_db_retract_entity()
+            .with(Tx::entid_or_lookup_ref_or_temp_id())
+            .map(|e| Entity::RetractEntity { e: e }))
+});
+
+// Accepts any keyword that isn't in the reserved :db namespace.
+def_parser!(Tx, tx_function_name, edn::NamespacedKeyword, {
+    satisfy_map(|x: &'a edn::ValueAndSpan| x.inner.as_namespaced_keyword().and_then(|k| if k.namespace == "db" { None } else { Some(k.clone()) }))
+});
+
+def_parser!(Tx, any_value, edn::Value, {
+    satisfy_map(|x: &'a edn::ValueAndSpan| Some(x.clone().without_spans()))
+});
+
+def_parser!(Tx, call, Entity, {
+    vector().of_exactly(
+        (Tx::tx_function_name(), many(Tx::any_value()))
+            .map(|(function, args)| Entity::Call { function: function, args: args }))
+});
+
 def_parser!(Tx, map_notation, MapNotation, {
     map()
         .of_exactly(many((Tx::entid(), Tx::atom_or_lookup_ref_or_vector())))
@@ -156,8 +213,14 @@ def_parser!(Tx, map_notation, MapNotation, {
 });
 
 def_parser!(Tx, entity, Entity, {
-    Tx::add_or_retract()
-        .or(Tx::map_notation().map(Entity::MapNotation))
+    choice::<[&mut Parser<Input = _, Output = Entity>; 6], _>
+        ([&mut try(Tx::cas()),
+          &mut try(Tx::retract_attribute()),
+          &mut try(Tx::retract_entity()),
+          &mut try(Tx::call()),
+          &mut Tx::add_or_retract(),
+          &mut Tx::map_notation().map(Entity::MapNotation),
+        ])
 });
 
 def_parser!(Tx, entities, Vec<Entity>, {
@@ -173,6 +236,16 @@ impl<'a> Tx<'a> {
             .map_err(|e| Error::from_kind(ErrorKind::ParseError(e.into())))
     }
 
+    /// Parse a single entity, like `[:db/add e a v]` or `{:db/id e a v}`, as found in the vector
+    /// of entities that `parse` accepts.
+    pub fn parse_entity(input: &'a edn::ValueAndSpan) -> std::result::Result<Entity, errors::Error> {
+        Tx::entity()
+            .skip(eof())
+            .parse(input.atom_stream())
+            .map(|x| x.0)
+            .map_err(|e| Error::from_kind(ErrorKind::ParseError(e.into())))
+    }
+
     fn parse_entid_or_lookup_ref_or_temp_id(input: edn::ValueAndSpan) -> std::result::Result<EntidOrLookupRefOrTempId, errors::Error> {
         Tx::entid_or_lookup_ref_or_temp_id()
             .skip(eof())
@@ -231,6 +304,7 @@ mod tests {
         Entity,
         OpType,
         AtomOrLookupRefOrVectorOrMapNotation,
+        TempId,
     };
 
     fn kw(namespace: &str, name: &str) -> Value {
@@ -303,6 +377,29 @@ mod tests {
                    }));
     }
 
+    #[test]
+    fn test_temp_id_in_partition() {
+        let input = Value::Vector(vec![kw("db", "add"),
+                                       Value::List(vec![Value::PlainSymbol(PlainSymbol::new("tempid")),
+                                                        kw("test.part", "a"),
+                                                        Value::Text("t".into())].into_iter().collect()),
+                                       kw("test", "a"),
+                                       Value::Text("v".into())]);
+
+        let input = input.with_spans();
+        let stream = input.atom_stream();
+        let result = Tx::entity().parse(stream).map(|x| x.0);
+
+        assert_eq!(result,
+                   Ok(Entity::AddOrRetract {
+                       op: OpType::Add,
+                       e: EntidOrLookupRefOrTempId::TempIdInPartition(TempId::External("t".into()),
+                                                                      NamespacedKeyword::new("test.part", "a")),
+                       a: Entid::Ident(NamespacedKeyword::new("test", "a")),
+                       v: AtomOrLookupRefOrVectorOrMapNotation::Atom(ValueAndSpan::new(SpannedValue::Text("v".into()), Span(43, 46))),
+                   }));
+    }
+
     #[test]
     fn test_nested_vector() {
         let input = Value::Vector(vec![kw("db", "add"),
@@ -329,6 +426,111 @@ mod tests {
                    }));
     }
 
+    #[test]
+    fn test_cas() {
+        let input = Value::Vector(vec![kw("db", "cas"),
+                                       Value::Integer(101),
+                                       kw("test", "a"),
+                                       Value::Nil,
+                                       Value::Text("v".into())]);
+
+        let input = input.with_spans();
+        let stream = input.atom_stream();
+        let result = Tx::entity().parse(stream).map(|x| x.0);
+
+        assert_eq!(result,
+                   Ok(Entity::Cas {
+                       e: EntidOrLookupRefOrTempId::Entid(Entid::Entid(101)),
+                       a: Entid::Ident(NamespacedKeyword::new("test", "a")),
+                       old: ValueAndSpan::new(SpannedValue::Nil, Span(21, 24)),
+                       new: AtomOrLookupRefOrVectorOrMapNotation::Atom(ValueAndSpan::new(SpannedValue::Text("v".into()), Span(25, 28))),
+                   }));
+
+        // The old value must be an atom.
+        let input = Value::Vector(vec![kw("db", "cas"),
+                                       Value::Integer(101),
+                                       kw("test", "a"),
+                                       Value::Vector(vec![Value::Integer(1)]),
+                                       Value::Integer(2)]);
+        let input = input.with_spans();
+        assert!(Tx::entity().parse(input.atom_stream()).is_err());
+    }
+
+    #[test]
+    fn test_call() {
+        let input = Value::Vector(vec![kw("counter", "increment"),
+                                       Value::Integer(101),
+                                       Value::Vector(vec![kw("test", "a"), Value::Text("v".into())])]);
+
+        let input = input.with_spans();
+        let stream = input.atom_stream();
+        let result = Tx::entity().parse(stream).map(|x| x.0);
+
+        assert_eq!(result,
+                   Ok(Entity::Call {
+                       function: NamespacedKeyword::new("counter", "increment"),
+                       args: vec![Value::Integer(101),
+                                  Value::Vector(vec![kw("test", "a"), Value::Text("v".into())])],
+                   }));
+
+        // The :db namespace is reserved, so malformed operations aren't mistaken for calls.
+        let input = Value::Vector(vec![kw("db", "add"),
+                                       Value::Integer(101)]);
+        let input = input.with_spans();
+        assert!(Tx::entity().parse(input.atom_stream()).is_err());
+    }
+
+    #[test]
+    fn test_retract_attribute() {
+        let input = Value::Vector(vec![kw("db", "retractAttribute"),
+                                       Value::Integer(101),
+                                       kw("test", "a")]);
+
+        let input = input.with_spans();
+        let stream = input.atom_stream();
+        let result = Tx::entity().parse(stream).map(|x| x.0);
+
+        assert_eq!(result,
+                   Ok(Entity::RetractAttribute {
+                       e: EntidOrLookupRefOrTempId::Entid(Entid::Entid(101)),
+                       a: Entid::Ident(NamespacedKeyword::new("test", "a")),
+                   }));
+
+        // Reversed attributes aren't allowed.
+        let input = Value::Vector(vec![kw("db", "retractAttribute"),
+                                       Value::Integer(101),
+                                       kw("test", "_a")]);
+        let input = input.with_spans();
+        assert!(Tx::entity().parse(input.atom_stream()).is_err());
+    }
+
+    #[test]
+    fn test_retract_entity() {
+        let input = Value::Vector(vec![kw("db", "retractEntity"),
+                                       Value::List(vec![Value::PlainSymbol(PlainSymbol::new("lookup-ref")),
+                                                        kw("test", "a1"),
+                                                        Value::Text("v1".into())].into_iter().collect())]);
+
+        let input = input.with_spans();
+        let stream = input.atom_stream();
+        let result = Tx::entity().parse(stream).map(|x| x.0);
+
+        assert_eq!(result,
+                   Ok(Entity::RetractEntity {
+                       e: EntidOrLookupRefOrTempId::LookupRef(LookupRef {
+                           a: Entid::Ident(NamespacedKeyword::new("test", "a1")),
+                           v: Value::Text("v1".into()),
+                       }),
+                   }));
+
+        // Exactly one entity.
+        let input = Value::Vector(vec![kw("db", "retractEntity"),
+                                       Value::Integer(101),
+                                       Value::Integer(102)]);
+        let input = input.with_spans();
+        assert!(Tx::entity().parse(input.atom_stream()).is_err());
+    }
+
     #[test]
     fn test_map_notation() {
         let mut expected: MapNotation = BTreeMap::default();
@@ -84,6 +84,9 @@ pub enum EntidOrLookupRefOrTempId {
     Entid(Entid),
     LookupRef(LookupRef),
     TempId(TempId),
+    // Like (tempid :my.part/name "tempid"): a tempid to be allocated in the given partition, should
+    // it not upsert.
+    TempIdInPartition(TempId, NamespacedKeyword),
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialOrd, PartialEq)]
@@ -103,4 +106,29 @@ pub enum Entity {
     },
     // Like {:db/id "tempid" a1 v1 a2 v2}.
     MapNotation(MapNotation),
+    // Like [:db/cas e a old new]: assert `new` as the value of the cardinality-one attribute `a`
+    // on `e`, but only if its current value is `old`.  An `old` of `nil` means `e` has no value
+    // for `a`.
+    Cas {
+        e: EntidOrLookupRefOrTempId,
+        a: Entid,
+        old: edn::ValueAndSpan, // An atom.
+        new: AtomOrLookupRefOrVectorOrMapNotation,
+    },
+    // Like [:db/retractAttribute e a]: retract every value of `a` on `e`.
+    RetractAttribute {
+        e: EntidOrLookupRefOrTempId,
+        a: Entid,
+    },
+    // Like [:my.fn/name arg1 arg2]: call the transaction function registered as `function`, and
+    // transact the entities it returns.
+    Call {
+        function: NamespacedKeyword,
+        args: Vec<edn::Value>,
+    },
+    // Like [:db/retractEntity e]: retract every datom about `e`, every reference to `e`, and, in
+    // turn, every entity `e` refers to through a component attribute.
+    RetractEntity {
+        e: EntidOrLookupRefOrTempId,
+    },
 }